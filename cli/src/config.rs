@@ -0,0 +1,98 @@
+use rsrpc::detection::DetectableActivity;
+use serde::Deserialize;
+use std::{
+  error::Error,
+  net::IpAddr,
+  path::{Path, PathBuf},
+};
+
+/**
+ * What can be set in the file passed to --config. Everything is optional, flags win over the file, and lists from both are combined.
+ */
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+  pub offline: Option<bool>,
+  pub process_scanning: Option<bool>,
+  // Seconds between process scans
+  pub scan_interval: Option<u64>,
+
+  pub connector_port: Option<u16>,
+  pub connector_fallback_ports: Option<Vec<u16>>,
+  pub connector_address: Option<IpAddr>,
+  pub websocket_address: Option<IpAddr>,
+  pub websocket_port: Option<u16>,
+  pub websocket_port_count: Option<u8>,
+  pub ipc_socket_count: Option<u8>,
+  pub ipc_base_name: Option<String>,
+  pub control_socket: Option<String>,
+  // Where --daemon writes its pid
+  pub pidfile: Option<PathBuf>,
+
+  // Relative paths are relative to the config file
+  pub detectable_files: Vec<PathBuf>,
+  pub plugins: Vec<PathBuf>,
+  pub proc_roots: Vec<PathBuf>,
+  // Detectables merged over Discord's list, in its format, eg. [[overrides]] id = "..." name = "..."
+  pub overrides: Vec<DetectableActivity>,
+  // Never detected, like --ignore
+  pub blacklist: Vec<String>,
+
+  pub user: FileUser,
+  pub log: FileLog,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileUser {
+  pub id: Option<String>,
+  pub username: Option<String>,
+  pub global_name: Option<String>,
+  pub avatar: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileLog {
+  // Print logs at all, on by default
+  pub enabled: Option<bool>,
+  // error, warn, info, debug or trace
+  pub level: Option<String>,
+  // text or json
+  pub format: Option<String>,
+  // Where --daemon writes logs
+  pub file: Option<PathBuf>,
+}
+
+impl FileConfig {
+  pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut config: FileConfig = toml::from_str(&text)?;
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+
+    for path in [&mut config.pidfile, &mut config.log.file]
+      .iter_mut()
+      .filter_map(|path| path.as_mut())
+    {
+      if path.is_relative() {
+        *path = base.join(&path);
+      }
+    }
+
+    for paths in [
+      &mut config.detectable_files,
+      &mut config.plugins,
+      &mut config.proc_roots,
+    ]
+    .iter_mut()
+    {
+      for path in paths.iter_mut() {
+        if path.is_relative() {
+          *path = base.join(&path);
+        }
+      }
+    }
+
+    Ok(config)
+  }
+}
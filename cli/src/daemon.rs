@@ -0,0 +1,156 @@
+use std::{
+  error::Error,
+  fs,
+  path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use std::{os::unix::io::AsRawFd, time::Duration};
+
+// How long `stop` waits for the server to exit
+#[cfg(unix)]
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn default_pidfile() -> PathBuf {
+  runtime_dir().join("rsrpc.pid")
+}
+
+pub fn default_log_file() -> PathBuf {
+  runtime_dir().join("rsrpc.log")
+}
+
+fn runtime_dir() -> PathBuf {
+  std::env::var_os("XDG_RUNTIME_DIR")
+    .filter(|dir| !dir.is_empty())
+    .map(PathBuf::from)
+    .unwrap_or_else(std::env::temp_dir)
+}
+
+fn read_pid(pidfile: &Path) -> Option<i32> {
+  fs::read_to_string(pidfile).ok()?.trim().parse().ok()
+}
+
+#[cfg(unix)]
+fn is_running(pid: i32) -> bool {
+  unsafe { libc::kill(pid, 0) == 0 }
+}
+
+/**
+ * Fork into the background, detached from the terminal, with the child's pid in `pidfile` and its output appended to `log_file`.
+ * Only the child returns. This has to happen before any thread is started, since they don't survive the fork.
+ */
+#[cfg(unix)]
+pub fn daemonize(pidfile: &Path, log_file: &Path) -> Result<(), Box<dyn Error>> {
+  if let Some(pid) = read_pid(pidfile).filter(|pid| is_running(*pid)) {
+    return Err(format!("Already running as pid {} (see {})", pid, pidfile.display()).into());
+  }
+
+  // Opened first, so a bad path is reported to the terminal
+  let log = fs::OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(log_file)?;
+  let null = fs::File::open("/dev/null")?;
+
+  match unsafe { libc::fork() } {
+    -1 => return Err(std::io::Error::last_os_error().into()),
+    0 => {}
+    pid => {
+      println!(
+        "Running in the background as pid {}, logging to {}",
+        pid,
+        log_file.display()
+      );
+      std::process::exit(0);
+    }
+  }
+
+  // A session of its own, so closing the terminal doesn't stop it
+  unsafe { libc::setsid() };
+  fs::write(pidfile, format!("{}\n", std::process::id()))?;
+
+  unsafe {
+    libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO);
+    libc::dup2(log.as_raw_fd(), libc::STDOUT_FILENO);
+    libc::dup2(log.as_raw_fd(), libc::STDERR_FILENO);
+  }
+
+  Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn daemonize(_pidfile: &Path, _log_file: &Path) -> Result<(), Box<dyn Error>> {
+  Err("--daemon is only supported on Unix".into())
+}
+
+/**
+ * `rsrpc-cli stop`: ask the server in `pidfile` to exit, wait for it to, and remove the pidfile
+ */
+#[cfg(unix)]
+pub fn stop(pidfile: &Path) -> Result<(), Box<dyn Error>> {
+  let pid =
+    read_pid(pidfile).ok_or_else(|| format!("No pid in {}, is it running?", pidfile.display()))?;
+
+  if !is_running(pid) {
+    fs::remove_file(pidfile).ok();
+    return Err(format!("pid {} already exited, removed {}", pid, pidfile.display()).into());
+  }
+
+  if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+    return Err(std::io::Error::last_os_error().into());
+  }
+
+  let deadline = std::time::Instant::now() + STOP_TIMEOUT;
+
+  while is_running(pid) {
+    if std::time::Instant::now() >= deadline {
+      return Err(format!("pid {} didn't exit within {:?}", pid, STOP_TIMEOUT).into());
+    }
+
+    std::thread::sleep(Duration::from_millis(100));
+  }
+
+  fs::remove_file(pidfile).ok();
+  println!("Stopped pid {}", pid);
+
+  Ok(())
+}
+
+/**
+ * From a thread of its own, call `on_hangup` whenever the process gets SIGHUP, which asks daemons to reload their config,
+ * and `on_terminate` on SIGTERM (from `stop`) or SIGINT, which no longer exit on their own. Does nothing off Unix.
+ */
+#[cfg(unix)]
+pub fn on_signals(on_hangup: impl Fn() + Send + 'static, on_terminate: impl Fn() + Send + 'static) {
+  use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+
+  let mut signals = match signal_hook::iterator::Signals::new(&[SIGHUP, SIGTERM, SIGINT]) {
+    Ok(signals) => signals,
+    Err(err) => {
+      eprintln!("Failed to listen for signals: {}", err);
+      return;
+    }
+  };
+
+  std::thread::spawn(move || {
+    for signal in signals.forever() {
+      if signal == SIGHUP {
+        on_hangup();
+      } else {
+        on_terminate();
+      }
+    }
+  });
+}
+
+#[cfg(not(unix))]
+pub fn on_signals(
+  _on_hangup: impl Fn() + Send + 'static,
+  _on_terminate: impl Fn() + Send + 'static,
+) {
+}
+
+#[cfg(not(unix))]
+pub fn stop(_pidfile: &Path) -> Result<(), Box<dyn Error>> {
+  Err("stop is only supported on Unix".into())
+}
@@ -1,33 +1,499 @@
-use clap::{command, Parser};
+use clap::{command, Parser, Subcommand};
 use rsrpc;
-use rsrpc::RPCConfig;
-use std::path::PathBuf;
+use rsrpc::{
+  cmd::UserConfig,
+  detection::{merge_detectables, DetectableActivity},
+  RPCConfig, RPCServer,
+};
+use std::{
+  net::IpAddr,
+  path::{Path, PathBuf},
+  sync::mpsc,
+  time::Duration,
+};
+
+mod config;
+mod daemon;
+mod scan;
+mod send;
+mod service;
+mod status;
+#[cfg(feature = "tray")]
+mod tray;
+
+use config::FileConfig;
+
+// What the control socket, that `status` asks, is called unless --control-socket says otherwise
+const DEFAULT_CONTROL_SOCKET: &str = "rsrpc-control";
+
+// What the main thread, which owns the server, is asked to do by signals and the control socket
+pub enum Request {
+  Reload,
+  Stop,
+}
+
+/**
+ * Stop the server, so its sockets are cleaned up, and remove the pidfile if it was started with --daemon
+ */
+fn shut_down(client: &mut RPCServer, pidfile: Option<&Path>) {
+  client.stop();
+
+  if let Some(pidfile) = pidfile {
+    std::fs::remove_file(pidfile).ok();
+  }
+}
 
 pub fn main() {
+  #[derive(Subcommand, Debug)]
+  enum Command {
+    #[command(about = "Scan for processes once, print which games match and why, and exit")]
+    Scan,
+    #[command(about = "Set an activity on a running server, like an RPC client, until stopped")]
+    Send(send::SendArgs),
+    #[command(about = "Show the uptime, clients and activities of the running server")]
+    Status {
+      /// Print the whole state as JSON instead of tables
+      #[arg(long)]
+      json: bool,
+    },
+    #[command(about = "Make the running server read its config file again, like SIGHUP")]
+    Reload,
+    #[command(about = "Stop the server started with --daemon")]
+    Stop,
+    #[command(about = "Run as a Windows service, started at boot for every user")]
+    Service {
+      #[command(subcommand)]
+      action: ServiceAction,
+    },
+  }
+
+  #[derive(Subcommand, Debug)]
+  enum ServiceAction {
+    #[command(about = "Register the service, run with the same --config")]
+    Install,
+    #[command(about = "Stop and remove the service")]
+    Uninstall,
+    #[command(about = "What the service manager runs")]
+    Run,
+  }
+
   #[derive(Parser, Debug)]
   #[command(author, version, about, long_about = None)]
   struct Args {
+    // Runs the server when not given
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// A TOML file with any of these settings, see README.md. Flags win over it.
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+    /// Extra detectable lists, merged over Discord's. Later files win when ids clash.
     #[arg(short, long)]
-    detectable_file: Option<PathBuf>,
+    detectable_file: Vec<PathBuf>,
+    /// Don't download Discord's detectable list, only use the --detectable-file ones
+    #[arg(long)]
+    offline: bool,
+    /// Seconds between process scans, when the OS can't report processes starting
+    #[arg(long)]
+    scan_interval: Option<u64>,
+    /// Only serve RPC clients, without detecting games
+    #[arg(long)]
+    no_process_scanning: bool,
+    /// Never detect this application id, executable name or path. Can be passed several times.
+    #[arg(long)]
+    ignore: Vec<String>,
+    /// A WASM plugin, or a directory of them, to load (requires the `wasm-plugins` feature). Can be passed several times.
+    #[arg(long)]
+    plugin: Vec<PathBuf>,
+    /// Another procfs to scan for games, eg. a container's /proc (Linux only). Can be passed several times.
+    #[arg(long)]
+    proc_root: Vec<PathBuf>,
+
+    /// The id of the user presented to RPC clients
+    #[arg(long)]
+    user_id: Option<String>,
+    /// Their username
+    #[arg(long)]
+    username: Option<String>,
+    /// Their display name
+    #[arg(long)]
+    global_name: Option<String>,
+    /// Their avatar hash
+    #[arg(long)]
+    avatar: Option<String>,
+
+    /// Where the client connector listens, eg. 0.0.0.0 to allow remote clients (or :: for IPv4 and IPv6)
+    #[arg(long)]
+    connector_address: Option<IpAddr>,
+    /// Where the RPC websocket server listens, like --connector-address
+    #[arg(long)]
+    websocket_address: Option<IpAddr>,
+    /// The client connector's port, 1337 by default
+    #[arg(long)]
+    connector_port: Option<u16>,
+    /// The first port the RPC websocket server tries, 6463 by default
+    #[arg(long)]
+    ws_port: Option<u16>,
+    /// Name the IPC sockets <name>-N instead of discord-ipc-N, eg. to run next to Discord or another server
+    #[arg(long)]
+    ipc_base_name: Option<String>,
+
+    /// The most verbose log messages printed, info by default
+    #[arg(long, value_parser = ["error", "warn", "info", "debug", "trace"])]
+    log_level: Option<String>,
+
+    /// Fork to the background, writing the pid to --pidfile and logs to --log-file (Unix only)
+    #[arg(long)]
+    daemon: bool,
+    /// Where --daemon writes its pid and `stop` looks for it, $XDG_RUNTIME_DIR/rsrpc.pid by default, or in the temp directory
+    #[arg(long, global = true)]
+    pidfile: Option<PathBuf>,
+    /// Where --daemon and the Windows service write logs, rsrpc.log next to the default pidfile otherwise
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+    /// What the local socket `status` talks to is called, so several servers can run at once
+    #[arg(long, global = true)]
+    control_socket: Option<String>,
+
+    /// text, or json for every log message as a JSON object on its own line, for journald, Loki and the like
+    #[arg(long, value_parser = ["text", "json"])]
+    log_format: Option<String>,
+
+    /// Show a tray icon with the server's status, and a menu to pause detection, copy the port and quit
+    #[cfg(feature = "tray")]
+    #[arg(long)]
+    tray: bool,
+  }
+
+  /**
+   * The server's settings, from the flags and then the config file
+   */
+  fn server_config(args: &Args, file: &FileConfig, control_socket: &str) -> RPCConfig {
+    let default_user = UserConfig::default();
+    let default_config = RPCConfig::default();
+
+    RPCConfig {
+      user: UserConfig {
+        id: args
+          .user_id
+          .clone()
+          .or_else(|| file.user.id.clone())
+          .unwrap_or(default_user.id),
+        username: args
+          .username
+          .clone()
+          .or_else(|| file.user.username.clone())
+          .unwrap_or(default_user.username),
+        global_name: args
+          .global_name
+          .clone()
+          .or_else(|| file.user.global_name.clone())
+          .or(default_user.global_name),
+        avatar: args
+          .avatar
+          .clone()
+          .or_else(|| file.user.avatar.clone())
+          .or(default_user.avatar),
+        ..UserConfig::default()
+      },
+      enable_process_scanner: !args.no_process_scanning
+        && file
+          .process_scanning
+          .unwrap_or(default_config.enable_process_scanner),
+      connector_port: args
+        .connector_port
+        .or(file.connector_port)
+        .unwrap_or(default_config.connector_port),
+      connector_fallback_ports: file
+        .connector_fallback_ports
+        .clone()
+        .unwrap_or(default_config.connector_fallback_ports),
+      connector_bind_address: args
+        .connector_address
+        .or(file.connector_address)
+        .unwrap_or(default_config.connector_bind_address),
+      websocket_bind_address: args
+        .websocket_address
+        .or(file.websocket_address)
+        .unwrap_or(default_config.websocket_bind_address),
+      websocket_base_port: args
+        .ws_port
+        .or(file.websocket_port)
+        .unwrap_or(default_config.websocket_base_port),
+      websocket_port_count: file
+        .websocket_port_count
+        .unwrap_or(default_config.websocket_port_count),
+      ipc_socket_count: file
+        .ipc_socket_count
+        .unwrap_or(default_config.ipc_socket_count),
+      ipc_base_name: args
+        .ipc_base_name
+        .clone()
+        .or_else(|| file.ipc_base_name.clone())
+        .unwrap_or(default_config.ipc_base_name),
+      // The file's come first, so the ones passed as flags win when ids clash
+      detectable_files: [&file.detectable_files[..], &args.detectable_file[..]].concat(),
+      ignored_processes: [&file.blacklist[..], &args.ignore[..]].concat(),
+      plugins: [&file.plugins[..], &args.plugin[..]].concat(),
+      extra_proc_roots: [&file.proc_roots[..], &args.proc_root[..]].concat(),
+      scan_interval: args
+        .scan_interval
+        .or(file.scan_interval)
+        .map(Duration::from_secs)
+        .unwrap_or(default_config.scan_interval),
+      control_socket: Some(control_socket.to_string()),
+      // The service runs as LocalSystem, so games of the logged-in users have to be let in explicitly
+      ipc_pipe_all_users: matches!(
+        args.command,
+        Some(Command::Service {
+          action: ServiceAction::Run
+        })
+      ),
+      // Nothing changes unless it is run by systemd
+      systemd: true,
+      ..default_config
+    }
+  }
+
+  /**
+   * Discord's detectable list with the config file's overrides merged over it
+   */
+  fn with_overrides(discord: &[DetectableActivity], file: &FileConfig) -> Vec<DetectableActivity> {
+    let mut detectable = discord.to_vec();
+    merge_detectables(&mut detectable, file.overrides.clone());
+    detectable
+  }
+
+  /**
+   * Pass the log settings on to rsrpc, which checks them for every message, so they can change while running
+   */
+  fn set_log_settings(client: &RPCServer, args: &Args, file: &FileConfig) {
+    client.set_logs_enabled(file.log.enabled.unwrap_or(true));
+    // Back to the default when a reload leaves it out
+    client.set_log_level(
+      args
+        .log_level
+        .as_ref()
+        .or(file.log.level.as_ref())
+        .and_then(|level| level.parse().ok()),
+    );
+    client.set_log_json(
+      args
+        .log_format
+        .as_ref()
+        .or(file.log.format.as_ref())
+        .map_or(false, |format| format == "json"),
+    );
+  }
+
+  /**
+   * Read the config file again and switch the running server over to it, without dropping its clients
+   */
+  fn reload_config(
+    client: &mut RPCServer,
+    args: &Args,
+    discord: &[DetectableActivity],
+    control_socket: &str,
+  ) {
+    let file = match args.config.as_ref() {
+      Some(path) => match FileConfig::load(path) {
+        Ok(file) => file,
+        Err(err) => {
+          eprintln!(
+            "Keeping the current config, failed to load {}: {}",
+            path.display(),
+            err
+          );
+          return;
+        }
+      },
+      None => FileConfig::default(),
+    };
+
+    set_log_settings(client, args, &file);
+    client.reload_config(
+      server_config(args, &file, control_socket),
+      Some(with_overrides(discord, &file)),
+    );
   }
 
   let args = Args::parse();
-  let mut client = if let Some(file) = args.detectable_file {
-    rsrpc::RPCServer::from_file(file, RPCConfig::default()).expect("Failed to create RPCServer")
+
+  // Only talks to a server that is already running
+  if let Some(Command::Send(send_args)) = args.command {
+    send::run(send_args);
+    return;
+  }
+
+  let file = match args.config.as_ref() {
+    Some(path) => FileConfig::load(path).unwrap_or_else(|err| {
+      eprintln!("Failed to load {}: {}", path.display(), err);
+      std::process::exit(1);
+    }),
+    None => FileConfig::default(),
+  };
+
+  let control_socket = args
+    .control_socket
+    .clone()
+    .or_else(|| file.control_socket.clone())
+    .unwrap_or_else(|| DEFAULT_CONTROL_SOCKET.to_string());
+
+  if let Some(Command::Status { json }) = args.command {
+    status::run(&control_socket, json);
+    return;
+  }
+
+  if let Some(Command::Reload) = args.command {
+    if let Err(err) = rsrpc::query_control_socket(None, &control_socket, "reload") {
+      eprintln!("Failed to reload, is the server running? {}", err);
+      std::process::exit(1);
+    }
+    println!("Reloading the config");
+    return;
+  }
+
+  let pidfile = args
+    .pidfile
+    .clone()
+    .or_else(|| file.pidfile.clone())
+    .unwrap_or_else(daemon::default_pidfile);
+
+  if let Some(Command::Stop) = args.command {
+    if let Err(err) = daemon::stop(&pidfile) {
+      eprintln!("Failed to stop the server: {}", err);
+      std::process::exit(1);
+    }
+    return;
+  }
+
+  let service_setup = match &args.command {
+    Some(Command::Service {
+      action: ServiceAction::Install,
+    }) => Some(service::install(args.config.as_deref())),
+    Some(Command::Service {
+      action: ServiceAction::Uninstall,
+    }) => Some(service::uninstall()),
+    _ => None,
+  };
+
+  if let Some(result) = service_setup {
+    if let Err(err) = result {
+      eprintln!("Failed to set up the service: {}", err);
+      std::process::exit(1);
+    }
+    return;
+  }
+
+  let log_file = args
+    .log_file
+    .clone()
+    .or_else(|| file.log.file.clone())
+    .unwrap_or_else(daemon::default_log_file);
+
+  // Before anything starts a thread, which wouldn't survive the fork
+  if args.daemon {
+    if let Err(err) = daemon::daemonize(&pidfile, &log_file) {
+      eprintln!("Failed to start in the background: {}", err);
+      std::process::exit(1);
+    }
+  }
+
+  // Kept without the overrides, so reloading can merge them in again
+  let discord: Vec<DetectableActivity> = if args.offline || file.offline == Some(true) {
+    vec![]
   } else {
-    let detectable = reqwest::blocking::get("https://discord.com/api/v9/applications/detectable");
-    rsrpc::RPCServer::from_json_str(detectable.unwrap().text().unwrap(), RPCConfig::default())
-      .expect("Failed to create RPCServer")
+    reqwest::blocking::get("https://discord.com/api/v9/applications/detectable")
+      .and_then(|response| response.json())
+      .unwrap_or_else(|err| {
+        eprintln!(
+          "Failed to download the detectable list, try --offline: {}",
+          err
+        );
+        std::process::exit(1);
+      })
   };
 
+  let mut client = RPCServer::from_detectables(
+    with_overrides(&discord, &file),
+    server_config(&args, &file, &control_socket),
+  )
+  .unwrap_or_else(|err| {
+    eprintln!("Failed to create RPCServer: {}", err);
+    std::process::exit(1);
+  });
+
+  // Before logs are enabled, so only the results are printed
+  if let Some(Command::Scan) = args.command {
+    scan::run(&client);
+    return;
+  }
+
   // When running as a binary, enable logs
-  std::env::set_var("RSRPC_LOGS_ENABLED", "1");
+  set_log_settings(&client, &args, &file);
+
+  // The service starts the server itself, once the service manager is ready for it
+  if let Some(Command::Service {
+    action: ServiceAction::Run,
+  }) = args.command
+  {
+    if let Err(err) = service::run(client, log_file) {
+      eprintln!("Failed to run the service: {}", err);
+      std::process::exit(1);
+    }
+    return;
+  }
+
+  // `rsrpc-cli reload` and SIGHUP both end up reloading on the main thread, which owns the server, and SIGTERM and SIGINT stop it there
+  let (request_sender, requests) = mpsc::channel();
+  let hangup_sender = request_sender.clone();
+  let terminate_sender = request_sender.clone();
+
+  client.on_reload_requested(move || {
+    request_sender.send(Request::Reload).ok();
+  });
+  daemon::on_signals(
+    move || {
+      hangup_sender.send(Request::Reload).ok();
+    },
+    move || {
+      terminate_sender.send(Request::Stop).ok();
+    },
+  );
 
   // Starts the other threads (process detector, client connector, etc)
-  client.start();
+  if let Err(err) = client.start() {
+    eprintln!("Failed to start RPCServer: {}", err);
+    std::process::exit(1);
+  }
+
+  // let 'er run forever, printing anything that stops working along the way
+  let errors = client.errors().unwrap();
+  std::thread::spawn(move || {
+    for err in errors {
+      eprintln!("RPCServer error: {}", err);
+    }
+  });
+
+  #[cfg(feature = "tray")]
+  let show_tray = args.tray;
+  let daemon_pidfile = if args.daemon { Some(pidfile) } else { None };
+  let reload =
+    move |client: &mut RPCServer| reload_config(client, &args, &discord, &control_socket);
+  let stop = move |client: &mut RPCServer| shut_down(client, daemon_pidfile.as_deref());
 
-  // let 'er run forever
-  loop {
-    std::thread::sleep(std::time::Duration::from_millis(10));
+  // The tray needs the main thread, so it reloads and stops from its event loop
+  #[cfg(feature = "tray")]
+  if show_tray {
+    tray::run(client, requests, reload, stop);
   }
+
+  for request in requests {
+    match request {
+      Request::Reload => reload(&mut client),
+      Request::Stop => break,
+    }
+  }
+
+  stop(&mut client);
 }
@@ -0,0 +1,49 @@
+use rsrpc::RPCServer;
+
+/**
+ * `rsrpc-cli scan`: scan for processes once and print what was detected, and why
+ */
+pub fn run(client: &RPCServer) {
+  let detectable_count = client.get_detectables().len();
+  let detected = client.scan_once().unwrap_or_else(|err| {
+    eprintln!("Failed to scan for processes: {}", err);
+    std::process::exit(1);
+  });
+
+  if detected.is_empty() {
+    println!(
+      "No running game was detected, out of {} detectables",
+      detectable_count
+    );
+    return;
+  }
+
+  println!(
+    "Detected {} of {} detectables:",
+    detected.len(),
+    detectable_count
+  );
+
+  for activity in detected {
+    println!();
+    println!("{} ({})", activity.name, activity.id);
+
+    match activity.process {
+      Some(process) => {
+        let pids: Vec<String> = process.pids.iter().map(|pid| pid.to_string()).collect();
+
+        println!("  process: {}", process.path);
+        println!("  pids: {}", pids.join(", "));
+        println!("  matched by: {}", process.reason);
+      }
+      // Detection providers don't say which process it was
+      None => {
+        if let Some(pid) = activity.pid {
+          println!("  pid: {}", pid);
+        }
+
+        println!("  matched by: a detection provider or plugin");
+      }
+    }
+  }
+}
@@ -0,0 +1,165 @@
+use rsrpc::cmd::{Activity, ActivityCmd, ActivityCmdArgs, Assets, TimeoutValue, Timestamps};
+use serde_json::Value;
+use std::{
+  error::Error,
+  net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream},
+  time::{SystemTime, UNIX_EPOCH},
+};
+use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+
+// Where Discord's RPC websocket server can be, the first free one is used
+const RPC_PORTS: std::ops::RangeInclusive<u16> = 6463..=6472;
+
+#[derive(clap::Args, Debug)]
+pub struct SendArgs {
+  /// The application the activity is for
+  #[arg(long)]
+  client_id: String,
+  /// The activity's second line
+  #[arg(long)]
+  state: Option<String>,
+  /// The activity's first line
+  #[arg(long)]
+  details: Option<String>,
+  /// An asset key of the application, or an image URL
+  #[arg(long)]
+  large_image: Option<String>,
+  /// Shown when hovering over the large image
+  #[arg(long)]
+  large_text: Option<String>,
+  /// Drawn over the corner of the large image, like --large-image
+  #[arg(long)]
+  small_image: Option<String>,
+  /// Shown when hovering over the small image
+  #[arg(long)]
+  small_text: Option<String>,
+  /// Show the time since the activity was sent
+  #[arg(long)]
+  elapsed: bool,
+
+  /// The instance to send it to, this machine by default
+  #[arg(long, default_value_t = IpAddr::V4(Ipv4Addr::LOCALHOST))]
+  host: IpAddr,
+  /// Its RPC websocket port, otherwise the first of 6463 - 6472 that answers
+  #[arg(long)]
+  port: Option<u16>,
+}
+
+/**
+ * `rsrpc-cli send`: connect to a running server as an RPC client, set an activity and keep it until stopped
+ */
+pub fn run(args: SendArgs) {
+  let mut socket = connect(&args).unwrap_or_else(|err| {
+    eprintln!("Failed to connect to {}: {}", args.host, err);
+    std::process::exit(1);
+  });
+
+  if let Err(err) = set_activity(&mut socket, &args) {
+    eprintln!("Failed to set the activity: {}", err);
+    std::process::exit(1);
+  }
+
+  println!("Activity set, press Ctrl+C to clear it");
+
+  // The activity is cleared when the connection closes, so hold it open
+  while socket.read().is_ok() {}
+
+  println!("The server closed the connection");
+}
+
+fn connect(args: &SendArgs) -> Result<WebSocket<MaybeTlsStream<TcpStream>>, Box<dyn Error>> {
+  let ports = match args.port {
+    Some(port) => port..=port,
+    None => RPC_PORTS,
+  };
+  let mut last_error: Box<dyn Error> = "no ports to try".into();
+
+  for port in ports {
+    let url = format!(
+      "ws://{}/?v=1&encoding=json&client_id={}",
+      SocketAddr::new(args.host, port),
+      args.client_id
+    );
+
+    match tungstenite::connect(url) {
+      Ok((mut socket, _)) => {
+        // The server says READY first, or why it doesn't want the connection
+        let ready = read_json(&mut socket)?;
+
+        if ready["evt"] != "READY" {
+          return Err(format!("the server rejected the connection: {}", ready).into());
+        }
+
+        return Ok(socket);
+      }
+      Err(err) => last_error = err.into(),
+    }
+  }
+
+  Err(last_error)
+}
+
+fn set_activity(
+  socket: &mut WebSocket<MaybeTlsStream<TcpStream>>,
+  args: &SendArgs,
+) -> Result<(), Box<dyn Error>> {
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_millis() as i64;
+  let has_assets = args.large_image.is_some()
+    || args.large_text.is_some()
+    || args.small_image.is_some()
+    || args.small_text.is_some();
+  let assets = Assets {
+    large_image: args.large_image.clone(),
+    large_text: args.large_text.clone(),
+    small_image: args.small_image.clone(),
+    small_text: args.small_text.clone(),
+  };
+
+  let mut cmd = ActivityCmd::empty();
+  cmd.cmd = "SET_ACTIVITY".to_string();
+  cmd.nonce = Some(now.to_string());
+  cmd.args = Some(ActivityCmdArgs {
+    pid: Some(std::process::id() as u64),
+    activity: Some(Activity {
+      state: args.state.clone(),
+      details: args.details.clone(),
+      assets: if has_assets { Some(assets) } else { None },
+      timestamps: if args.elapsed {
+        Some(Timestamps {
+          start: Some(TimeoutValue(now)),
+          end: None,
+        })
+      } else {
+        None
+      },
+      ..Activity::default()
+    }),
+    ..ActivityCmdArgs::default()
+  });
+
+  socket.send(Message::text(serde_json::to_string(&cmd)?))?;
+
+  let response = read_json(socket)?;
+
+  if response["evt"] == "ERROR" {
+    let message = response["data"]["message"]
+      .as_str()
+      .unwrap_or("unknown error");
+    return Err(message.into());
+  }
+
+  Ok(())
+}
+
+fn read_json(socket: &mut WebSocket<MaybeTlsStream<TcpStream>>) -> Result<Value, Box<dyn Error>> {
+  loop {
+    match socket.read()? {
+      Message::Text(text) => return Ok(serde_json::from_str(&text)?),
+      Message::Close(frame) => return Err(format!("connection closed: {:?}", frame).into()),
+      _ => continue,
+    }
+  }
+}
@@ -0,0 +1,266 @@
+use rsrpc::RPCServer;
+use std::{
+  error::Error,
+  path::{Path, PathBuf},
+};
+
+// What the service is registered as
+#[cfg(windows)]
+const SERVICE_NAME: &str = "rsrpc";
+
+/**
+ * `rsrpc-cli service install`: register a service that starts at boot and runs `rsrpc-cli service run`, with the same --config
+ */
+pub fn install(config: Option<&Path>) -> Result<(), Box<dyn Error>> {
+  platform::install(config)
+}
+
+/**
+ * `rsrpc-cli service uninstall`: stop the service if it is running, and remove it
+ */
+pub fn uninstall() -> Result<(), Box<dyn Error>> {
+  platform::uninstall()
+}
+
+/**
+ * `rsrpc-cli service run`: what the service manager starts. Runs `client` until the service is stopped, writing its logs to `log_file`.
+ */
+pub fn run(client: RPCServer, log_file: PathBuf) -> Result<(), Box<dyn Error>> {
+  platform::run(client, log_file)
+}
+
+#[cfg(windows)]
+mod platform {
+  use rsrpc::RPCServer;
+  use std::{
+    error::Error,
+    ffi::OsString,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{mpsc, Mutex},
+    time::Duration,
+  };
+  use windows_service::{
+    define_windows_service,
+    service::{
+      ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+      ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandle, ServiceControlHandlerResult},
+    service_dispatcher,
+    service_manager::{ServiceManager, ServiceManagerAccess},
+  };
+
+  use super::SERVICE_NAME;
+
+  // Handed from run() to the service's thread, which the dispatcher starts
+  static SERVER: Mutex<Option<(RPCServer, PathBuf)>> = Mutex::new(None);
+
+  define_windows_service!(ffi_service_main, service_main);
+
+  pub fn install(config: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let manager = ServiceManager::local_computer(
+      None::<&str>,
+      ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+    let mut launch_arguments = vec![OsString::from("service"), OsString::from("run")];
+
+    // The service doesn't start where this was run from
+    if let Some(config) = config {
+      launch_arguments.push("--config".into());
+      launch_arguments.push(std::fs::canonicalize(config)?.into_os_string());
+    }
+
+    let info = ServiceInfo {
+      name: SERVICE_NAME.into(),
+      display_name: "rsRPC".into(),
+      service_type: ServiceType::OWN_PROCESS,
+      start_type: ServiceStartType::AutoStart,
+      error_control: ServiceErrorControl::Normal,
+      executable_path: std::env::current_exe()?,
+      launch_arguments,
+      dependencies: vec![],
+      account_name: None,
+      account_password: None,
+    };
+
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description("Discord RPC server, detecting games and serving RPC clients")?;
+
+    println!(
+      "Installed the {} service, start it with `sc start {}` or reboot",
+      SERVICE_NAME, SERVICE_NAME
+    );
+
+    Ok(())
+  }
+
+  pub fn uninstall() -> Result<(), Box<dyn Error>> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(
+      SERVICE_NAME,
+      ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+    )?;
+
+    if service.query_status()?.current_state != ServiceState::Stopped {
+      service.stop()?;
+    }
+
+    service.delete()?;
+    println!("Uninstalled the {} service", SERVICE_NAME);
+
+    Ok(())
+  }
+
+  pub fn run(client: RPCServer, log_file: PathBuf) -> Result<(), Box<dyn Error>> {
+    *SERVER.lock().unwrap() = Some((client, log_file));
+
+    // Blocks until the service stops
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main)?;
+
+    Ok(())
+  }
+
+  fn service_main(_arguments: Vec<OsString>) {
+    let Some((client, log_file)) = SERVER.lock().unwrap().take() else {
+      return;
+    };
+
+    if let Err(err) = run_service(client, &log_file) {
+      // Nobody sees stderr, so this is the only place it can go
+      if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&log_file) {
+        writeln!(file, "Service failed: {}", err).ok();
+      }
+    }
+  }
+
+  fn run_service(mut client: RPCServer, log_file: &Path) -> Result<(), Box<dyn Error>> {
+    let (sender, receiver) = mpsc::channel();
+    let handle = service_control_handler::register(SERVICE_NAME, move |control| match control {
+      ServiceControl::Stop
+      | ServiceControl::Shutdown
+      | ServiceControl::Pause
+      | ServiceControl::Continue => {
+        sender.send(control).ok();
+        ServiceControlHandlerResult::NoError
+      }
+      ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+      _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+
+    set_state(&handle, ServiceState::StartPending, 0)?;
+    log_to_file(&client, log_file)?;
+
+    if let Err(err) = client.start() {
+      set_state(&handle, ServiceState::Stopped, 1)?;
+      return Err(err.into());
+    }
+
+    set_state(&handle, ServiceState::Running, 0)?;
+
+    for control in receiver {
+      match control {
+        ServiceControl::Pause => {
+          client.pause_scanning();
+          set_state(&handle, ServiceState::Paused, 0)?;
+        }
+        ServiceControl::Continue => {
+          client.resume_scanning();
+          set_state(&handle, ServiceState::Running, 0)?;
+        }
+        _ => break,
+      }
+    }
+
+    set_state(&handle, ServiceState::StopPending, 0)?;
+    client.stop();
+    set_state(&handle, ServiceState::Stopped, 0)?;
+
+    Ok(())
+  }
+
+  /**
+   * A service has no console, so logs at the configured level (info by default) and above are appended to `log_file`
+   */
+  fn log_to_file(client: &RPCServer, log_file: &Path) -> Result<(), Box<dyn Error>> {
+    let file = Mutex::new(
+      OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)?,
+    );
+    let max_level = client.log_level();
+
+    client.set_log_sink(
+      move |record| {
+        if record.level > max_level {
+          return;
+        }
+
+        writeln!(
+          file.lock().unwrap(),
+          "[{}] {} {}",
+          record.timestamp.format("%Y-%m-%d %H:%M:%S"),
+          record.level,
+          record.message
+        )
+        .ok();
+      },
+      false,
+    );
+
+    Ok(())
+  }
+
+  fn set_state(
+    handle: &ServiceControlHandle,
+    state: ServiceState,
+    exit_code: u32,
+  ) -> Result<(), Box<dyn Error>> {
+    let controls_accepted = match state {
+      ServiceState::Running | ServiceState::Paused => {
+        ServiceControlAccept::STOP
+          | ServiceControlAccept::SHUTDOWN
+          | ServiceControlAccept::PAUSE_CONTINUE
+      }
+      _ => ServiceControlAccept::empty(),
+    };
+
+    handle.set_service_status(ServiceStatus {
+      service_type: ServiceType::OWN_PROCESS,
+      current_state: state,
+      controls_accepted,
+      exit_code: match exit_code {
+        0 => ServiceExitCode::Win32(0),
+        code => ServiceExitCode::ServiceSpecific(code),
+      },
+      checkpoint: 0,
+      wait_hint: Duration::from_secs(10),
+      process_id: None,
+    })?;
+
+    Ok(())
+  }
+}
+
+#[cfg(not(windows))]
+mod platform {
+  use rsrpc::RPCServer;
+  use std::{
+    error::Error,
+    path::{Path, PathBuf},
+  };
+
+  pub fn install(_config: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    Err("Services are only supported on Windows, see the README for systemd".into())
+  }
+
+  pub fn uninstall() -> Result<(), Box<dyn Error>> {
+    Err("Services are only supported on Windows, see the README for systemd".into())
+  }
+
+  pub fn run(_client: RPCServer, _log_file: PathBuf) -> Result<(), Box<dyn Error>> {
+    Err("Services are only supported on Windows, see the README for systemd".into())
+  }
+}
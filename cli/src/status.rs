@@ -0,0 +1,161 @@
+use serde_json::Value;
+
+/**
+ * `rsrpc-cli status`: ask the running server for its state over the control socket, and print it
+ */
+pub fn run(control_socket: &str, json: bool) {
+  let state = rsrpc::query_control_socket(None, control_socket, "status").unwrap_or_else(|err| {
+    eprintln!("Failed to get the status, is the server running? {}", err);
+    std::process::exit(1);
+  });
+
+  if json {
+    println!(
+      "{}",
+      serde_json::to_string_pretty(&state).unwrap_or_default()
+    );
+    return;
+  }
+
+  let scanner = &state["scanner"];
+  let detected: Vec<String> = list(&scanner["detected"])
+    .iter()
+    .map(|activity| text(&activity["name"]))
+    .collect();
+
+  println!(
+    "Uptime: {}",
+    duration(state["uptime_secs"].as_u64().unwrap_or(0))
+  );
+  println!("Client connector port: {}", state["connector_port"]);
+  println!(
+    "Process scanner: {}, {} detectables, detected: {}",
+    match (scanner["enabled"].as_bool(), scanner["paused"].as_bool()) {
+      (Some(false), _) => "disabled",
+      (_, Some(true)) => "paused",
+      _ => "running",
+    },
+    scanner["detectable_count"],
+    if detected.is_empty() {
+      "nothing".to_string()
+    } else {
+      detected.join(", ")
+    }
+  );
+
+  println!();
+  println!("Connector clients:");
+  print_table(
+    &["ID", "ADDRESS", "SUBSCRIPTION", "IDLE"],
+    list(&state["connector_clients"])
+      .iter()
+      .map(|client| {
+        vec![
+          text(&client["client_id"]),
+          text(&client["peer_address"]),
+          text(&client["subscription"]),
+          duration(client["idle_secs"].as_u64().unwrap_or(0)),
+        ]
+      })
+      .collect(),
+  );
+
+  println!();
+  println!("RPC clients:");
+  print_table(
+    &["SOCKET", "SUBSCRIPTIONS"],
+    list(&state["rpc_clients"])
+      .iter()
+      .map(|client| {
+        let subscriptions: Vec<String> = list(&client["subscriptions"]).iter().map(text).collect();
+
+        vec![text(&client["socket_id"]), subscriptions.join(", ")]
+      })
+      .collect(),
+  );
+
+  println!();
+  println!("Activities:");
+  print_table(
+    &["SOCKET", "SOURCE", "APPLICATION", "PID", "ACTIVITY"],
+    list(&state["activities"])
+      .iter()
+      .map(|shown| {
+        vec![
+          text(&shown["socket_id"]),
+          text(&shown["source"]),
+          text(&shown["application_id"]),
+          text(&shown["pid"]),
+          describe_activity(&shown["activity"]),
+        ]
+      })
+      .collect(),
+  );
+}
+
+fn list(value: &Value) -> Vec<Value> {
+  value.as_array().cloned().unwrap_or_default()
+}
+
+/**
+ * A value as it is shown in the table, strings without their quotes and nothing as "-"
+ */
+fn text(value: &Value) -> String {
+  match value {
+    Value::Null => "-".to_string(),
+    Value::String(text) => text.clone(),
+    value => value.to_string(),
+  }
+}
+
+fn describe_activity(activity: &Value) -> String {
+  if activity.is_null() {
+    return "(cleared)".to_string();
+  }
+
+  let parts: Vec<&str> = ["name", "details", "state"]
+    .iter()
+    .filter_map(|field| activity[*field].as_str())
+    .collect();
+
+  if parts.is_empty() {
+    "(empty)".to_string()
+  } else {
+    parts.join(" - ")
+  }
+}
+
+fn duration(secs: u64) -> String {
+  match secs {
+    0..=59 => format!("{}s", secs),
+    60..=3599 => format!("{}m {}s", secs / 60, secs % 60),
+    _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+  }
+}
+
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+  if rows.is_empty() {
+    println!("  none");
+    return;
+  }
+
+  let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+
+  for row in &rows {
+    for (width, cell) in widths.iter_mut().zip(row) {
+      *width = (*width).max(cell.chars().count());
+    }
+  }
+
+  let headers: Vec<String> = headers.iter().map(|header| header.to_string()).collect();
+
+  for row in std::iter::once(&headers).chain(&rows) {
+    let cells: Vec<String> = row
+      .iter()
+      .zip(&widths)
+      .map(|(cell, width)| format!("{:width$}", cell, width = width))
+      .collect();
+
+    println!("  {}", cells.join("  ").trim_end());
+  }
+}
@@ -0,0 +1,176 @@
+use crate::Request;
+use rsrpc::{RPCServer, ServerState};
+use std::{
+  sync::mpsc,
+  time::{Duration, Instant},
+};
+use tao::{
+  event::{Event, StartCause},
+  event_loop::{ControlFlow, EventLoopBuilder},
+};
+use tray_icon::{
+  menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem},
+  Icon, TrayIcon, TrayIconBuilder,
+};
+
+// How often the status in the tray is refreshed
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+// Green while a game is detected or a client is connected, grey otherwise
+const ACTIVE_COLOR: [u8; 3] = [59, 165, 93];
+const IDLE_COLOR: [u8; 3] = [128, 132, 142];
+const ICON_SIZE: u32 = 32;
+
+/**
+ * Show a tray icon with what the server is doing, and a menu to pause detection, copy the client connector's port and quit.
+ * `reload` and `stop` are called for the requests on `requests`, and `stop` on quit too. Has to run on the main thread, and only returns by exiting the process.
+ */
+pub fn run(
+  mut client: RPCServer,
+  requests: mpsc::Receiver<Request>,
+  reload: impl Fn(&mut RPCServer) + 'static,
+  stop: impl Fn(&mut RPCServer) + 'static,
+) -> ! {
+  let event_loop = EventLoopBuilder::new().build();
+  let status = MenuItem::new("Starting...", false, None);
+  let pause = CheckMenuItem::new("Pause detection", true, false, None);
+  let copy_port = MenuItem::new("Copy port", true, None);
+  let quit = MenuItem::new("Quit", true, None);
+  let menu = Menu::new();
+
+  if let Err(err) = menu.append_items(&[
+    &status,
+    &PredefinedMenuItem::separator(),
+    &pause,
+    &copy_port,
+    &PredefinedMenuItem::separator(),
+    &quit,
+  ]) {
+    eprintln!("Failed to create the tray menu: {}", err);
+  }
+
+  let mut tray: Option<TrayIcon> = None;
+  let mut active = false;
+  // Kept around, since on Linux what was copied is gone once the clipboard is dropped
+  let mut clipboard = arboard::Clipboard::new().ok();
+
+  event_loop.run(move |event, _, control_flow| {
+    *control_flow = ControlFlow::WaitUntil(Instant::now() + REFRESH_INTERVAL);
+
+    match event {
+      // Not before the event loop runs, GTK has to be set up first on Linux
+      Event::NewEvents(StartCause::Init) => {
+        let mut builder = TrayIconBuilder::new()
+          .with_menu(Box::new(menu.clone()))
+          .with_tooltip("rsRPC");
+
+        if let Some(icon) = icon(IDLE_COLOR) {
+          builder = builder.with_icon(icon);
+        }
+
+        tray = builder
+          .build()
+          .map_err(|err| eprintln!("Failed to create the tray icon: {}", err))
+          .ok();
+      }
+      Event::NewEvents(StartCause::ResumeTimeReached { .. }) => {
+        if let (Some(tray), Some(state)) = (tray.as_ref(), client.state()) {
+          let summary = summary(&state);
+          let now_active = !state.scanner.detected.is_empty()
+            || !state.rpc_clients.is_empty()
+            || !state.connector_clients.is_empty();
+
+          status.set_text(&summary);
+          copy_port.set_text(format!("Copy port ({})", state.connector_port));
+          tray.set_tooltip(Some(format!("rsRPC: {}", summary))).ok();
+
+          if now_active != active {
+            active = now_active;
+            tray
+              .set_icon(icon(if active { ACTIVE_COLOR } else { IDLE_COLOR }))
+              .ok();
+          }
+        }
+      }
+      _ => {}
+    }
+
+    while let Ok(request) = requests.try_recv() {
+      match request {
+        Request::Reload => reload(&mut client),
+        Request::Stop => {
+          tray.take();
+          stop(&mut client);
+          *control_flow = ControlFlow::Exit;
+          return;
+        }
+      }
+    }
+
+    while let Ok(event) = MenuEvent::receiver().try_recv() {
+      if event.id == *pause.id() {
+        // Checked by the click itself
+        if pause.is_checked() {
+          client.pause_scanning();
+        } else {
+          client.resume_scanning();
+        }
+      } else if event.id == *copy_port.id() {
+        let port = client.connector_port().map(|port| port.to_string());
+
+        if let (Some(clipboard), Some(port)) = (clipboard.as_mut(), port) {
+          if let Err(err) = clipboard.set_text(port) {
+            eprintln!("Failed to copy the port: {}", err);
+          }
+        }
+      } else if event.id == *quit.id() {
+        tray.take();
+        stop(&mut client);
+        *control_flow = ControlFlow::Exit;
+      }
+    }
+  })
+}
+
+/**
+ * What the server is doing, eg. "2 clients connected, playing Minecraft"
+ */
+fn summary(state: &ServerState) -> String {
+  let clients = state.rpc_clients.len() + state.connector_clients.len();
+  let detection = match state.scanner.detected.first() {
+    Some(game) => format!("playing {}", game.name),
+    None if !state.scanner.enabled => "detection off".to_string(),
+    None if state.scanner.paused => "detection paused".to_string(),
+    None => "nothing detected".to_string(),
+  };
+
+  format!(
+    "{} client{} connected, {}",
+    clients,
+    if clients == 1 { "" } else { "s" },
+    detection
+  )
+}
+
+/**
+ * A filled circle, since the binary doesn't ship any images
+ */
+fn icon(color: [u8; 3]) -> Option<Icon> {
+  let center = (ICON_SIZE as f32 - 1.0) / 2.0;
+  let radius = ICON_SIZE as f32 / 2.0 - 1.0;
+  let mut rgba = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+
+  for y in 0..ICON_SIZE {
+    for x in 0..ICON_SIZE {
+      let (dx, dy) = (x as f32 - center, y as f32 - center);
+      let alpha = if dx * dx + dy * dy <= radius * radius {
+        255
+      } else {
+        0
+      };
+
+      rgba.extend_from_slice(&[color[0], color[1], color[2], alpha]);
+    }
+  }
+
+  Icon::from_rgba(rgba, ICON_SIZE, ICON_SIZE).ok()
+}
@@ -52,7 +52,11 @@ pub fn main() {
   });
 
   // Starts the other threads (process detector, client connector, etc)
-  client.lock().unwrap().start();
+  client
+    .lock()
+    .unwrap()
+    .start()
+    .expect("Failed to start RPCServer");
 
   // let 'er run forever
   loop {
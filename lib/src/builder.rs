@@ -0,0 +1,328 @@
+use std::{net::IpAddr, path::PathBuf, time::Duration};
+
+use crate::{
+  cmd::UserConfig,
+  detection::{DetectableActivity, DetectionProvider},
+  ActivityEvent, ConnectorClientEvent, Error, LogRecord, ProcessScanState, RPCConfig, RPCServer,
+  Result,
+};
+
+enum DetectableSource {
+  Json(String),
+  File(PathBuf),
+  List(Vec<DetectableActivity>),
+}
+
+/**
+ * Configures an RPCServer step by step, instead of filling in an RPCConfig by hand. Anything that isn't set keeps its RPCConfig default.
+ */
+pub struct RPCServerBuilder {
+  config: RPCConfig,
+  detectable: Option<DetectableSource>,
+  logging: Option<bool>,
+  log_sink: Option<(Box<dyn Fn(&LogRecord) + Send + Sync>, bool)>,
+  on_process_scan_complete: Option<Box<dyn FnMut(ProcessScanState) + Send + Sync>>,
+  on_connector_client: Option<Box<dyn FnMut(ConnectorClientEvent) + Send + Sync>>,
+  on_activity_set: Option<Box<dyn FnMut(ActivityEvent) + Send + Sync>>,
+  on_activity_cleared: Option<Box<dyn FnMut(ActivityEvent) + Send + Sync>>,
+  on_process_detected: Option<Box<dyn FnMut(DetectableActivity) + Send + Sync>>,
+  providers: Vec<Box<dyn DetectionProvider>>,
+}
+
+impl Default for RPCServerBuilder {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl RPCServerBuilder {
+  pub fn new() -> Self {
+    Self::from_config(RPCConfig::default())
+  }
+
+  /**
+   * Start from an existing config, and change it from there
+   */
+  pub fn from_config(config: RPCConfig) -> Self {
+    RPCServerBuilder {
+      config,
+      detectable: None,
+      logging: None,
+      log_sink: None,
+      on_process_scan_complete: None,
+      on_connector_client: None,
+      on_activity_set: None,
+      on_activity_cleared: None,
+      on_process_detected: None,
+      providers: vec![],
+    }
+  }
+
+  /**
+   * The detectable list, as JSON. Without one, only the detectable files are scanned for.
+   */
+  pub fn detectable_json(mut self, json: impl Into<String>) -> Self {
+    self.detectable = Some(DetectableSource::Json(json.into()));
+    self
+  }
+
+  /**
+   * Read the detectable list from a file
+   */
+  pub fn detectable_file(mut self, path: impl Into<PathBuf>) -> Self {
+    self.detectable = Some(DetectableSource::File(path.into()));
+    self
+  }
+
+  pub fn detectables(mut self, detectable: Vec<DetectableActivity>) -> Self {
+    self.detectable = Some(DetectableSource::List(detectable));
+    self
+  }
+
+  /**
+   * Merge another detectable file over the list, later ones winning when ids clash
+   */
+  pub fn extra_detectable_file(mut self, path: impl Into<PathBuf>) -> Self {
+    self.config.detectable_files.push(path.into());
+    self
+  }
+
+  /**
+   * Fetch Discord's detectable list this often and merge it in
+   */
+  pub fn detectable_updates(mut self, interval: Duration) -> Self {
+    self.config.detectable_update_interval = Some(interval);
+    self
+  }
+
+  pub fn user(mut self, user: UserConfig) -> Self {
+    self.config.user = user;
+    self
+  }
+
+  /**
+   * The port the client connector listens on, followed by the ones tried when it is taken
+   */
+  pub fn connector_port(mut self, port: u16) -> Self {
+    self.config.connector_port = port;
+    self
+  }
+
+  pub fn connector_fallback_ports(mut self, ports: Vec<u16>) -> Self {
+    self.config.connector_fallback_ports = ports;
+    self
+  }
+
+  pub fn connector_address(mut self, address: IpAddr) -> Self {
+    self.config.connector_bind_address = address;
+    self
+  }
+
+  /**
+   * The first port the RPC websocket server tries instead of 6463
+   */
+  pub fn websocket_port(mut self, port: u16) -> Self {
+    self.config.websocket_base_port = port;
+    self
+  }
+
+  /**
+   * How many ports of the 10 from the websocket port (6463 - 6472 by default) the RPC websocket server listens on
+   */
+  pub fn websocket_port_count(mut self, count: u8) -> Self {
+    self.config.websocket_port_count = count;
+    self
+  }
+
+  pub fn websocket_address(mut self, address: IpAddr) -> Self {
+    self.config.websocket_bind_address = address;
+    self
+  }
+
+  pub fn ipc_socket_count(mut self, count: u8) -> Self {
+    self.config.ipc_socket_count = count;
+    self
+  }
+
+  /**
+   * Name the IPC sockets `<name>-N` instead of `discord-ipc-N`
+   */
+  pub fn ipc_base_name(mut self, name: impl Into<String>) -> Self {
+    self.config.ipc_base_name = name.into();
+    self
+  }
+
+  /**
+   * Answer `status` queries on the local control socket `name`, eg. the "rsrpc-control" that `rsrpc-cli status` asks
+   */
+  pub fn control_socket(mut self, name: impl Into<String>) -> Self {
+    self.config.control_socket = Some(name.into());
+    self
+  }
+
+  /**
+   * Integrate with systemd when run as a service: serve the websocket sockets it passes in, and notify it when ready (Linux only)
+   */
+  pub fn systemd(mut self, enabled: bool) -> Self {
+    self.config.systemd = enabled;
+    self
+  }
+
+  pub fn scan_interval(mut self, interval: Duration) -> Self {
+    self.config.scan_interval = interval;
+    self
+  }
+
+  pub fn ignore_process(mut self, entry: impl Into<String>) -> Self {
+    self.config.ignored_processes.push(entry.into());
+    self
+  }
+
+  pub fn process_scanner(mut self, enabled: bool) -> Self {
+    self.config.enable_process_scanner = enabled;
+    self
+  }
+
+  pub fn ipc_connector(mut self, enabled: bool) -> Self {
+    self.config.enable_ipc_connector = enabled;
+    self
+  }
+
+  pub fn websocket_connector(mut self, enabled: bool) -> Self {
+    self.config.enable_websocket_connector = enabled;
+    self
+  }
+
+  pub fn secondary_events(mut self, enabled: bool) -> Self {
+    self.config.enable_secondary_events = enabled;
+    self
+  }
+
+  /**
+   * Print logs to stdout. Logging is process wide, so this affects every RPCServer.
+   */
+  pub fn logging(mut self, enabled: bool) -> Self {
+    self.logging = Some(enabled);
+    self
+  }
+
+  /**
+   * Deliver every log message to `sink`, printing it to stdout as well only if `stdout` is set. Process wide like logging().
+   */
+  pub fn log_sink(
+    mut self,
+    sink: impl Fn(&LogRecord) + Send + Sync + 'static,
+    stdout: bool,
+  ) -> Self {
+    self.log_sink = Some((Box::new(sink), stdout));
+    self
+  }
+
+  /**
+   * Change anything else in the config
+   */
+  pub fn configure(mut self, configure: impl FnOnce(&mut RPCConfig)) -> Self {
+    configure(&mut self.config);
+    self
+  }
+
+  pub fn on_process_scan_complete(
+    mut self,
+    callback: impl FnMut(ProcessScanState) + Send + Sync + 'static,
+  ) -> Self {
+    self.on_process_scan_complete = Some(Box::new(callback));
+    self
+  }
+
+  pub fn on_connector_client(
+    mut self,
+    callback: impl FnMut(ConnectorClientEvent) + Send + Sync + 'static,
+  ) -> Self {
+    self.on_connector_client = Some(Box::new(callback));
+    self
+  }
+
+  pub fn on_activity_set(
+    mut self,
+    callback: impl FnMut(ActivityEvent) + Send + Sync + 'static,
+  ) -> Self {
+    self.on_activity_set = Some(Box::new(callback));
+    self
+  }
+
+  pub fn on_activity_cleared(
+    mut self,
+    callback: impl FnMut(ActivityEvent) + Send + Sync + 'static,
+  ) -> Self {
+    self.on_activity_cleared = Some(Box::new(callback));
+    self
+  }
+
+  pub fn on_process_detected(
+    mut self,
+    callback: impl FnMut(DetectableActivity) + Send + Sync + 'static,
+  ) -> Self {
+    self.on_process_detected = Some(Box::new(callback));
+    self
+  }
+
+  pub fn detection_provider(mut self, provider: impl DetectionProvider + 'static) -> Self {
+    self.providers.push(Box::new(provider));
+    self
+  }
+
+  /**
+   * Create the RPCServer. Fails if the detectable list can't be read or parsed, or a detectable file can't be loaded.
+   */
+  pub fn build(self) -> Result<RPCServer> {
+    if let Some(enabled) = self.logging {
+      crate::logger::set_enabled(enabled);
+    }
+
+    if let Some((sink, stdout)) = self.log_sink {
+      crate::logger::set_sink(sink, stdout);
+    }
+
+    let detectable = match self.detectable {
+      Some(DetectableSource::Json(json)) => serde_json::from_str(&json)?,
+      Some(DetectableSource::File(path)) => {
+        let json = std::fs::read_to_string(&path).map_err(|err| {
+          Error::Detectables(format!("Could not read {}: {}", path.display(), err))
+        })?;
+        serde_json::from_str(&json)?
+      }
+      Some(DetectableSource::List(detectable)) => detectable,
+      None => vec![],
+    };
+
+    let mut server = RPCServer::from_detectables(detectable, self.config)?;
+
+    if let Some(callback) = self.on_process_scan_complete {
+      server.on_process_scan_complete(callback);
+    }
+
+    if let Some(callback) = self.on_connector_client {
+      server.on_connector_client(callback);
+    }
+
+    if let Some(callback) = self.on_activity_set {
+      server.on_activity_set(callback);
+    }
+
+    if let Some(callback) = self.on_activity_cleared {
+      server.on_activity_cleared(callback);
+    }
+
+    if let Some(callback) = self.on_process_detected {
+      server.on_process_detected(callback);
+    }
+
+    server
+      .detection_providers
+      .lock()
+      .unwrap()
+      .extend(self.providers);
+
+    Ok(server)
+  }
+}
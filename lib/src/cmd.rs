@@ -3,6 +3,126 @@ use serde_json::Value;
 use serde_with::skip_serializing_none;
 use std::collections::HashMap;
 
+// https://docs.discord.sex/resources/presence#activity-flags
+pub const ACTIVITY_FLAG_INSTANCE: u32 = 1 << 0;
+pub const ACTIVITY_FLAG_JOIN: u32 = 1 << 1;
+pub const ACTIVITY_FLAG_SPECTATE: u32 = 1 << 2;
+
+// https://discord.com/developers/docs/topics/opcodes-and-status-codes#rpc-rpc-error-codes
+pub const RPC_ERROR_UNKNOWN: u32 = 1000;
+pub const RPC_ERROR_INVALID_PAYLOAD: u32 = 4000;
+pub const RPC_ERROR_INVALID_COMMAND: u32 = 4002;
+pub const RPC_ERROR_INVALID_EVENT: u32 = 4004;
+pub const RPC_ERROR_INVALID_INVITE: u32 = 4011;
+pub const RPC_ERROR_INVALID_LOBBY: u32 = 4013;
+pub const RPC_ERROR_INVALID_LOBBY_SECRET: u32 = 4014;
+pub const RPC_ERROR_INVALID_GUILD_TEMPLATE: u32 = 4017;
+pub const RPC_ERROR_OAUTH2: u32 = 5000;
+
+// https://discord.com/developers/docs/topics/opcodes-and-status-codes#rpc-rpc-close-event-codes
+pub const RPC_CLOSE_NORMAL: u32 = 1000;
+pub const RPC_CLOSE_UNSUPPORTED: u32 = 1003;
+// Not one of Discord's, but the same as websockets use
+pub const RPC_CLOSE_TOO_LARGE: u32 = 1009;
+pub const RPC_CLOSE_INVALID_CLIENT_ID: u32 = 4000;
+pub const RPC_CLOSE_INVALID_ORIGIN: u32 = 4001;
+pub const RPC_CLOSE_RATE_LIMITED: u32 = 4002;
+pub const RPC_CLOSE_INVALID_VERSION: u32 = 4004;
+pub const RPC_CLOSE_INVALID_ENCODING: u32 = 4005;
+
+// https://discord.com/developers/docs/events/gateway-events#activity-object
+const MAX_TEXT_LENGTH: usize = 128;
+const MAX_BUTTONS: usize = 2;
+const MAX_BUTTON_LABEL_LENGTH: usize = 32;
+const MAX_BUTTON_URL_LENGTH: usize = 512;
+
+// https://docs.discord.sex/resources/presence#activity-type
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ActivityType {
+  Playing = 0,
+  Streaming = 1,
+  Listening = 2,
+  Watching = 3,
+  Custom = 4,
+  Competing = 5,
+}
+
+impl ActivityType {
+  pub fn from_u32(value: u32) -> Option<Self> {
+    match value {
+      0 => Some(ActivityType::Playing),
+      1 => Some(ActivityType::Streaming),
+      2 => Some(ActivityType::Listening),
+      3 => Some(ActivityType::Watching),
+      4 => Some(ActivityType::Custom),
+      5 => Some(ActivityType::Competing),
+      _ => None,
+    }
+  }
+}
+
+/**
+ * The (fake) user rsRPC presents itself as to RPC clients
+ */
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct UserConfig {
+  pub id: String,
+  pub username: String,
+  pub discriminator: String,
+  pub global_name: Option<String>,
+  pub avatar: Option<String>,
+  pub flags: u64,
+  pub premium_type: u32,
+}
+
+impl Default for UserConfig {
+  fn default() -> Self {
+    Self {
+      id: "1045800378228281345".to_string(),
+      username: "arRPC".to_string(),
+      discriminator: "0000".to_string(),
+      global_name: None,
+      avatar: Some("cfefa4d9839fb4bdf030f91c2a13e95c".to_string()),
+      flags: 0,
+      premium_type: 0,
+    }
+  }
+}
+
+/**
+ * What GET_VOICE_SETTINGS returns unless configured otherwise
+ * https://discord.com/developers/docs/topics/rpc#getvoicesettings
+ */
+pub fn default_voice_settings() -> Value {
+  serde_json::json!({
+    "input": {
+      "available_devices": [],
+      "device_id": "default",
+      "volume": 100.0,
+    },
+    "output": {
+      "available_devices": [],
+      "device_id": "default",
+      "volume": 100.0,
+    },
+    "mode": {
+      "type": "VOICE_ACTIVITY",
+      "auto_threshold": true,
+      "threshold": -60.0,
+      "shortcut": [],
+      "delay": 20.0,
+    },
+    "automatic_gain_control": true,
+    "echo_cancellation": true,
+    "noise_suppression": true,
+    "qos": false,
+    "silence_warning": false,
+    "deaf": false,
+    "mute": false,
+  })
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ActivityPayload {
   pub activity: Option<Activity>,
@@ -18,9 +138,13 @@ pub struct ActivityCmd {
   pub application_id: Option<String>,
   pub cmd: String,
   pub args: Option<ActivityCmdArgs>,
-  pub data: Option<HashMap<String, String>>,
+  pub data: Option<Value>,
   pub evt: Option<String>,
-  pub nonce: String,
+  #[serde(default)]
+  pub nonce: Option<String>,
+  // The connection this command came from, never sent over the wire
+  #[serde(skip)]
+  pub socket_id: Option<String>,
 }
 
 impl ActivityCmd {
@@ -31,16 +155,133 @@ impl ActivityCmd {
       args: None,
       data: None,
       evt: None,
-      nonce: "".to_string(),
+      nonce: None,
+      socket_id: None,
     }
   }
 
+  /**
+   * Create an error response frame for a command
+   */
+  pub fn error(cmd: &str, nonce: Option<String>, code: u32, message: impl AsRef<str>) -> Self {
+    Self {
+      cmd: cmd.to_string(),
+      evt: Some("ERROR".to_string()),
+      data: Some(serde_json::json!({
+        "code": code,
+        "message": message.as_ref(),
+      })),
+      nonce,
+      ..Self::empty()
+    }
+  }
+
+  /**
+   * Check the activity against Discord's limits, so we don't broadcast something clients will reject anyway
+   */
+  pub fn validate(&self) -> Result<(), String> {
+    let Some(activity) = self.args.as_ref().and_then(|args| args.activity.as_ref()) else {
+      return Ok(());
+    };
+
+    let mut text_fields = vec![("state", &activity.state), ("details", &activity.details)];
+
+    if let Some(assets) = activity.assets.as_ref() {
+      text_fields.push(("large_text", &assets.large_text));
+      text_fields.push(("small_text", &assets.small_text));
+    }
+
+    for (field, value) in text_fields {
+      if let Some(value) = value {
+        if value.chars().count() > MAX_TEXT_LENGTH {
+          return Err(format!(
+            "{} must be at most {} characters",
+            field, MAX_TEXT_LENGTH
+          ));
+        }
+      }
+    }
+
+    if let Some(buttons) = activity.buttons.as_ref() {
+      if buttons.len() > MAX_BUTTONS {
+        return Err(format!("at most {} buttons are allowed", MAX_BUTTONS));
+      }
+
+      for button in buttons {
+        // Label-only buttons were already converted, and have nothing else to check
+        let (label, url) = match button {
+          Value::String(label) => (label.clone(), None),
+          _ => match serde_json::from_value::<Button>(button.clone()) {
+            Ok(button) => (button.label, Some(button.url)),
+            Err(_) => return Err("buttons must have a label and url".to_string()),
+          },
+        };
+
+        if label.is_empty() || label.chars().count() > MAX_BUTTON_LABEL_LENGTH {
+          return Err(format!(
+            "button labels must be 1 to {} characters",
+            MAX_BUTTON_LABEL_LENGTH
+          ));
+        }
+
+        if url.is_some_and(|url| url.chars().count() > MAX_BUTTON_URL_LENGTH) {
+          return Err(format!(
+            "button urls must be at most {} characters",
+            MAX_BUTTON_URL_LENGTH
+          ));
+        }
+      }
+    }
+
+    if let Some(timestamps) = activity.timestamps.as_ref() {
+      let start = timestamps.start.as_ref().map(|start| start.0);
+      let end = timestamps.end.as_ref().map(|end| end.0);
+
+      if start.is_some_and(|start| start < 0) || end.is_some_and(|end| end < 0) {
+        return Err("timestamps must be positive".to_string());
+      }
+
+      if let (Some(start), Some(end)) = (start, end) {
+        if end < start {
+          return Err("end timestamp must be after the start timestamp".to_string());
+        }
+      }
+    }
+
+    if let Some(size) = activity
+      .party
+      .as_ref()
+      .and_then(|party| party.size.as_ref())
+    {
+      if size.len() != 2 || size[1] == 0 || size[0] > size[1] {
+        return Err("party size must be [current, max] with current <= max".to_string());
+      }
+    }
+
+    Ok(())
+  }
+
   pub fn fix(&mut self) {
+    self.fix_type();
     self.fix_timestamps();
     self.fix_buttons();
     self.fix_flags();
   }
 
+  pub fn fix_type(&mut self) {
+    if let Some(activity) = self.args.as_mut().and_then(|args| args.activity.as_mut()) {
+      let activity_type = match ActivityType::from_u32(activity.r#type) {
+        // Streaming only works with a stream URL
+        Some(ActivityType::Streaming) if activity.url.is_none() => ActivityType::Playing,
+        // Custom statuses cannot be set by applications
+        Some(ActivityType::Custom) | None => ActivityType::Playing,
+        Some(activity_type) => activity_type,
+      };
+
+      activity.r#type = activity_type as u32;
+    }
+  }
+
   pub fn fix_timestamps(&mut self) {
     if let Some(timestamps) = self
       .args
@@ -79,20 +320,28 @@ impl ActivityCmd {
         let mut button_urls: Vec<String> = vec![];
         let mut button_labels: Vec<Value> = vec![];
 
-        for b in buttons {
-          if let Some(label) = b.get("label") {
-            // Unless the provider of the actvity REALLY screwed up, we can safely assume this is a string
-            button_labels.push(label.clone());
-          }
-          if let Some(url) = b.get("url") {
-            button_urls.push(url.as_str().unwrap_or("").to_string());
+        for b in buttons.iter() {
+          match b {
+            // Already converted (for example, the activity was sent by something that speaks the client format), leave it be
+            Value::String(_) => button_labels.push(b.clone()),
+            _ => {
+              let Ok(button) = serde_json::from_value::<Button>(b.clone()) else {
+                continue;
+              };
+
+              button_labels.push(Value::String(button.label));
+              button_urls.push(button.url);
+            }
           }
         }
 
-        activity.metadata = Some(Metadata {
-          button_urls: Some(button_urls),
-          ..activity.metadata.clone().unwrap_or_default()
-        });
+        // Only overwrite the urls if we actually converted anything, otherwise we would wipe the ones that came with pre-converted buttons
+        if !button_urls.is_empty() {
+          activity.metadata = Some(Metadata {
+            button_urls: Some(button_urls),
+            ..activity.metadata.clone().unwrap_or_default()
+          });
+        }
 
         activity.buttons = Some(button_labels);
       }
@@ -101,20 +350,141 @@ impl ActivityCmd {
 
   pub fn fix_flags(&mut self) {
     if let Some(activity) = self.args.as_mut().and_then(|args| args.activity.as_mut()) {
-      if activity.instance.unwrap_or(false) && activity.flags.is_none() {
-        activity.flags = Some(1);
+      // Respect flags that were explicitly provided
+      if activity.flags.is_some() {
+        return;
+      }
+
+      let mut flags = 0;
+
+      if activity.instance.unwrap_or(false) {
+        flags |= ACTIVITY_FLAG_INSTANCE;
+      }
+
+      // Clients only show the join/spectate buttons if these flags are set
+      if let Some(secrets) = activity.secrets.as_ref() {
+        if secrets.join.is_some() {
+          flags |= ACTIVITY_FLAG_JOIN;
+        }
+
+        if secrets.spectate.is_some() {
+          flags |= ACTIVITY_FLAG_SPECTATE;
+        }
+      }
+
+      if flags != 0 {
+        activity.flags = Some(flags);
       }
     }
   }
 }
 
+/**
+ * An event sent by a connector client, to be dispatched to the RPC client that owns `socket_id`
+ */
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct InboundEvent {
+  pub cmd: String,
+  pub evt: String,
+  #[serde(rename = "socketId")]
+  pub socket_id: String,
+  #[serde(default)]
+  pub data: Option<Value>,
+}
+
+impl InboundEvent {
+  /**
+   * Check that the event data has the shape RPC clients expect for the event
+   */
+  pub fn validate(&self) -> Result<(), String> {
+    let data = self.data.clone().unwrap_or(Value::Null);
+    let result = match self.evt.as_str() {
+      "ACTIVITY_JOIN" | "ACTIVITY_SPECTATE" => {
+        serde_json::from_value::<ActivitySecretEvent>(data).map(|_| ())
+      }
+      "ACTIVITY_JOIN_REQUEST" => {
+        serde_json::from_value::<ActivityJoinRequestEvent>(data).map(|_| ())
+      }
+      "ACTIVITY_INVITE" => serde_json::from_value::<ActivityInviteEvent>(data).map(|_| ()),
+      _ => Ok(()),
+    };
+
+    result.map_err(|err| format!("Invalid {} data: {}", self.evt, err))
+  }
+}
+
+// Data of ACTIVITY_JOIN and ACTIVITY_SPECTATE
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ActivitySecretEvent {
+  pub secret: String,
+}
+
+// Data of ACTIVITY_JOIN_REQUEST
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ActivityJoinRequestEvent {
+  pub user: Value,
+}
+
+// Data of ACTIVITY_INVITE
 #[skip_serializing_none]
 #[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ActivityInviteEvent {
+  pub user: Value,
+  pub activity: Value,
+  // 1 = join, 2 = spectate
+  pub r#type: u32,
+  pub channel_id: Option<String>,
+  pub message_id: Option<String>,
+}
+
+/**
+ * Sent to connector clients when an RPC client answers a join request (SEND_ACTIVITY_JOIN_INVITE) or ignores it (CLOSE_ACTIVITY_REQUEST)
+ */
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ActivityRequestPayload {
+  pub cmd: String,
+  pub application_id: Option<String>,
+  pub user_id: String,
+  #[serde(rename = "socketId")]
+  pub socket_id: Option<String>,
+}
+
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct ActivityCmdArgs {
   pub pid: Option<u64>,
   pub activity: Option<Activity>,
   // For INVITE_BROWSER
   pub code: Option<String>,
+  // For AUTHORIZE/AUTHENTICATE
+  pub client_id: Option<String>,
+  pub scopes: Option<Vec<String>>,
+  pub access_token: Option<String>,
+  // For SET_CERTIFIED_DEVICES
+  pub devices: Option<Vec<Value>>,
+  // For SEND_ACTIVITY_JOIN_INVITE/CLOSE_ACTIVITY_REQUEST
+  pub user_id: Option<String>,
+  // Anything else, for commands whose arguments we don't model (eg. SET_VOICE_SETTINGS)
+  #[serde(flatten)]
+  pub extra: HashMap<String, Value>,
+  // For DEEP_LINK (a string) and lobbies (a number)
+  pub r#type: Option<Value>,
+  pub params: Option<DeepLinkParams>,
+}
+
+// https://docs.discord.sex/topics/rpc#deep-link
+#[skip_serializing_none]
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DeepLinkParams {
+  pub guild_id: Option<String>,
+  pub channel_id: Option<String>,
+  pub message_id: Option<String>,
+  pub search: Option<String>,
+  pub fingerprint: Option<String>,
+  // Anything else the link type uses
+  #[serde(flatten)]
+  pub extra: HashMap<String, Value>,
 }
 
 #[skip_serializing_none]
@@ -122,6 +492,11 @@ pub struct ActivityCmdArgs {
 pub struct Party {
   pub id: Option<String>,
   pub size: Option<Vec<u32>>,
+  // 0 = private, 1 = public
+  pub privacy: Option<u32>,
+  // Anything else, so nothing a client relies on gets lost on the way through
+  #[serde(flatten)]
+  pub extra: HashMap<String, Value>,
 }
 
 #[skip_serializing_none]
@@ -138,6 +513,9 @@ pub struct Assets {
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Secrets {
   pub join: Option<String>,
+  pub spectate: Option<String>,
+  #[serde(rename = "match")]
+  pub r#match: Option<String>,
 }
 
 #[skip_serializing_none]
@@ -157,10 +535,14 @@ pub struct Metadata {
   pub artist_ids: Option<Vec<String>>,
   pub album_id: Option<String>,
   pub context_uri: Option<String>,
+  // eg. "track" or "episode", for listening activities
+  pub r#type: Option<String>,
+  #[serde(flatten)]
+  pub extra: HashMap<String, Value>,
 }
 
 #[skip_serializing_none]
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
 #[serde(rename_all = "snake_case")]
 pub struct Activity {
   pub id: Option<String>,
@@ -170,6 +552,7 @@ pub struct Activity {
   pub r#type: u32,
   pub url: Option<String>,
   pub created_at: Option<u64>,
+  // Listening activities use the session, sync (track) and party ids for "Listen Along"
   pub session_id: Option<String>,
   pub platform: Option<String>,
   pub supported_platforms: Option<Vec<String>>,
@@ -185,14 +568,16 @@ pub struct Activity {
   pub assets: Option<Assets>,
   pub secrets: Option<Secrets>,
   pub metadata: Option<Metadata>,
+  #[serde(flatten)]
+  pub extra: HashMap<String, Value>,
 }
 
 #[skip_serializing_none]
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
-pub struct TimeoutValue(i64);
+pub struct TimeoutValue(pub i64);
 
 #[skip_serializing_none]
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct Timestamps {
   #[serde(default)]
   pub start: Option<TimeoutValue>,
@@ -1,5 +1,9 @@
+use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
+use std::{collections::HashMap, error::Error, path::Path};
+
+use crate::log;
 
 #[skip_serializing_none]
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -19,6 +23,7 @@ pub struct DetectableActivity {
   pub flags: Option<i64>,
   #[serde(rename = "guild_id")]
   pub guild_id: Option<String>,
+  #[serde(default)]
   pub hook: bool,
   pub icon: Option<String>,
   pub id: String,
@@ -54,8 +59,107 @@ pub struct DetectableActivity {
   pub deeplink_uri: Option<String>,
   #[serde(default)]
   pub tags: Option<Vec<String>>,
+  // What is shown when this is detected, on top of the name and timer
+  #[serde(default)]
+  pub presence: Option<PresenceOverride>,
   pub pid: Option<u64>,
   pub timestamp: Option<String>,
+  // Set by the process scanner for what it detected
+  #[serde(skip)]
+  pub process: Option<ProcessDetails>,
+}
+
+/**
+ * The process an activity was detected in
+ */
+#[derive(Clone, Debug, Default)]
+pub struct ProcessDetails {
+  // For Wine games, the Windows executable that is run
+  pub path: String,
+  pub cmd: Vec<String>,
+  pub parent_pid: Option<u64>,
+  // Every running process that was matched, `pid` first
+  pub pids: Vec<u64>,
+  // Why it was detected, eg. `executable "game.exe" (win32)`
+  pub reason: String,
+}
+
+impl DetectableActivity {
+  /**
+   * An activity with nothing but an id and name
+   */
+  pub fn empty(id: &str, name: &str) -> Self {
+    DetectableActivity {
+      bot_public: None,
+      bot_require_code_grant: None,
+      cover_image: None,
+      description: None,
+      developers: None,
+      executables: None,
+      flags: None,
+      guild_id: None,
+      hook: false,
+      icon: None,
+      id: id.to_string(),
+      name: name.to_string(),
+      publishers: None,
+      rpc_origins: None,
+      splash: None,
+      third_party_skus: None,
+      type_field: None,
+      verify_key: None,
+      primary_sku_id: None,
+      slug: None,
+      aliases: None,
+      overlay: None,
+      overlay_compatibility_hook: None,
+      privacy_policy_url: None,
+      terms_of_service_url: None,
+      eula_id: None,
+      deeplink_uri: None,
+      tags: None,
+      presence: None,
+      pid: None,
+      timestamp: None,
+      process: None,
+    }
+  }
+}
+
+/**
+ * Overrides for the activity of a detected game. Text fields are templates, where `{name}` is the name of the game and `{pid}` the process it was found in.
+ */
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PresenceOverride {
+  // Shown instead of the name of the game
+  pub name: Option<String>,
+  pub details: Option<String>,
+  pub state: Option<String>,
+  // Asset keys of the application, or image URLs
+  pub large_image: Option<String>,
+  pub large_text: Option<String>,
+  pub small_image: Option<String>,
+  pub small_text: Option<String>,
+}
+
+impl PresenceOverride {
+  /**
+   * Fill in the placeholders of `template` for `activity`
+   */
+  pub fn render(template: &str, activity: &DetectableActivity) -> String {
+    template.replace("{name}", &activity.name).replace(
+      "{pid}",
+      &activity.pid.map(|pid| pid.to_string()).unwrap_or_default(),
+    )
+  }
+
+  pub fn has_assets(&self) -> bool {
+    self.large_image.is_some()
+      || self.large_text.is_some()
+      || self.small_image.is_some()
+      || self.small_text.is_some()
+  }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -72,6 +176,115 @@ pub struct Executable {
   pub name: String,
   pub os: String,
   pub arguments: Option<String>,
+  // How `name` is matched. Discord's entries are always exact, custom ones can use patterns for eg. version-stamped names.
+  #[serde(default, rename = "match", skip_serializing_if = "NameMatch::is_exact")]
+  pub name_match: NameMatch,
+  // `name` compiled, for glob and regex names
+  #[serde(skip)]
+  pub pattern: Option<Regex>,
+  // The SHA-256 of the file, in hex. Executables with one are matched by it instead of their name, so renamed copies are found too.
+  #[serde(default)]
+  pub sha256: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NameMatch {
+  #[default]
+  Exact,
+  // `*` and `?` wildcards, eg. "game-*.exe". Names with a slash match the end of the path, others the file name.
+  Glob,
+  // Searched for in the whole (lowercase, forward slashed) path
+  Regex,
+}
+
+impl NameMatch {
+  pub fn is_exact(&self) -> bool {
+    *self == NameMatch::Exact
+  }
+}
+
+impl Executable {
+  /**
+   * What matching this executable looks like, as the reason a game was detected
+   */
+  pub fn describe(&self) -> String {
+    let mut description = match (&self.sha256, self.name_match) {
+      (Some(_), _) => format!("SHA-256 of executable {:?}", self.name),
+      (None, NameMatch::Exact) => format!("executable {:?}", self.name),
+      (None, NameMatch::Glob) => format!("executable glob {:?}", self.name),
+      (None, NameMatch::Regex) => format!("executable regex {:?}", self.name),
+    };
+
+    description.push_str(&format!(" ({})", self.os));
+
+    if let Some(arguments) = &self.arguments {
+      description.push_str(&format!(" with arguments {:?}", arguments));
+    }
+
+    description
+  }
+
+  /**
+   * Compile `name` if it is a pattern. Paths are compared lowercase and with forward slashes, so patterns are too.
+   */
+  pub fn compile(&mut self) -> Result<(), regex::Error> {
+    let name = self.name.replace('\\', "/");
+    let pattern = match self.name_match {
+      NameMatch::Exact => return Ok(()),
+      NameMatch::Glob => {
+        let glob: String = name
+          .split('*')
+          .map(|part| {
+            part
+              .split('?')
+              .map(regex::escape)
+              .collect::<Vec<_>>()
+              .join("[^/]")
+          })
+          .collect::<Vec<_>>()
+          .join("[^/]*");
+
+        if name.contains('/') {
+          format!("(^|/){}$", glob)
+        } else {
+          format!("^{}$", glob)
+        }
+      }
+      NameMatch::Regex => name,
+    };
+
+    self.pattern = Some(RegexBuilder::new(&pattern).case_insensitive(true).build()?);
+
+    Ok(())
+  }
+
+  /**
+   * Whether this is an executable for `os` ("win32", "linux" or "darwin", like Discord's list). Executables that don't say are for every OS.
+   */
+  pub fn is_for_os(&self, os: &str) -> bool {
+    match self.os.to_lowercase().as_str() {
+      "" => true,
+      "windows" | "win64" => os == "win32",
+      "macos" | "osx" => os == "darwin",
+      own => own == os,
+    }
+  }
+
+  /**
+   * Whether this is the executable at `path` (lowercase, with forward slashes)
+   */
+  pub fn matches_path(&self, path: &str) -> Option<bool> {
+    let pattern = self.pattern.as_ref()?;
+    let target = match self.name_match {
+      NameMatch::Glob if !self.name.contains('/') && !self.name.contains('\\') => {
+        path.split('/').last().unwrap_or_default()
+      }
+      _ => path,
+    };
+
+    Some(!path.is_empty() && pattern.is_match(target))
+  }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -87,3 +300,77 @@ pub struct ThirdPartySku {
   pub id: Option<String>,
   pub sku: Option<String>,
 }
+
+/**
+ * A source of detected activities besides the process scanner, eg. an app tracking its own mini-games. Polled on every scan, and what it returns goes through the same ignore list, priority and broadcasting as detected processes.
+ */
+pub trait DetectionProvider: Send {
+  /**
+   * What is running right now. Each activity needs an `id` and `name` (DetectableActivity::empty() is enough), `pid` and `timestamp` are optional.
+   */
+  fn poll(&mut self) -> Vec<DetectableActivity>;
+}
+
+/**
+ * Read a detectable list, in the same format as Discord's
+ */
+pub fn read_detectables(path: &Path) -> Result<Vec<DetectableActivity>, Box<dyn Error>> {
+  let detectable = std::fs::read_to_string(path)?;
+
+  Ok(serde_json::from_str(&detectable)?)
+}
+
+/**
+ * Compile the executable patterns of every detectable, dropping the executables with invalid ones
+ */
+pub fn compile_patterns(detectable: &mut [DetectableActivity]) {
+  for activity in detectable {
+    let name = &activity.name;
+    let Some(executables) = activity.executables.as_mut() else {
+      continue;
+    };
+
+    executables.retain_mut(|executable| {
+      if executable.name_match.is_exact() || executable.pattern.is_some() {
+        return true;
+      }
+
+      match executable.compile() {
+        Ok(()) => true,
+        Err(err) => {
+          log!(
+            warn,
+            "[Detection] Ignoring executable {:?} of {}: {}",
+            executable.name,
+            name,
+            err
+          );
+          false
+        }
+      }
+    });
+  }
+}
+
+/**
+ * Add `extra` to `detectable`, replacing the entries that have the same id
+ */
+pub fn merge_detectables(detectable: &mut Vec<DetectableActivity>, extra: Vec<DetectableActivity>) {
+  // id -> index in `detectable`, so merging doesn't scan the whole list for every entry. Reversed, so of duplicate ids the first is the one replaced.
+  let mut indices: HashMap<String, usize> = detectable
+    .iter()
+    .enumerate()
+    .rev()
+    .map(|(index, activity)| (activity.id.clone(), index))
+    .collect();
+
+  for activity in extra {
+    match indices.get(&activity.id) {
+      Some(&index) => detectable[index] = activity,
+      None => {
+        indices.insert(activity.id.clone(), detectable.len());
+        detectable.push(activity);
+      }
+    }
+  }
+}
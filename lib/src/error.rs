@@ -0,0 +1,72 @@
+use std::fmt;
+
+/**
+ * Everything that can go wrong creating, starting or running an RPCServer
+ */
+#[derive(Debug)]
+pub enum Error {
+  Io(std::io::Error),
+  Json(serde_json::Error),
+  // A detectable list or file couldn't be read or parsed
+  Detectables(String),
+  // A server couldn't listen on any of its ports or sockets
+  Listen(String),
+  // A background worker panicked, it isn't running anymore
+  Worker(String),
+  Other(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::Io(err) => write!(f, "{}", err),
+      Error::Json(err) => write!(f, "Invalid JSON: {}", err),
+      Error::Detectables(message) => write!(f, "{}", message),
+      Error::Listen(message) => write!(f, "Could not listen: {}", message),
+      Error::Worker(message) => write!(f, "Worker stopped: {}", message),
+      Error::Other(message) => write!(f, "{}", message),
+    }
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::Io(err) => Some(err),
+      Error::Json(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<std::io::Error> for Error {
+  fn from(err: std::io::Error) -> Self {
+    Error::Io(err)
+  }
+}
+
+impl From<serde_json::Error> for Error {
+  fn from(err: serde_json::Error) -> Self {
+    Error::Json(err)
+  }
+}
+
+impl From<Box<dyn std::error::Error>> for Error {
+  fn from(err: Box<dyn std::error::Error>) -> Self {
+    Error::Other(err.to_string())
+  }
+}
+
+impl From<String> for Error {
+  fn from(message: String) -> Self {
+    Error::Other(message)
+  }
+}
+
+impl From<&str> for Error {
+  fn from(message: &str) -> Self {
+    Error::Other(message.to_string())
+  }
+}
@@ -1,24 +1,62 @@
-use detection::DetectableActivity;
+use cmd::{default_voice_settings, Activity, UserConfig};
+use detection::{merge_detectables, read_detectables, DetectableActivity, DetectionProvider};
 use serde_json::Value;
+#[cfg(feature = "async")]
+use server::runtime::TaskGroup;
 use server::{
-  client_connector::ClientConnector,
+  assets::AssetResolver,
+  client_connector::{ClientConnector, ConnectorOptions},
+  commands::{CommandConfig, CommandHandler},
+  connector_types::{IpcFacilitator, IpcOptions, WebsocketOptions},
+  control,
+  detectable_updater::DetectableUpdater,
+  detectable_watcher::watch_files,
   ipc::IpcConnector,
-  ipc_utils::IpcFacilitator,
-  process::{ProcessEventListeners, ProcessScanState, ProcessServer},
+  plugins::Plugins,
+  process::ProcessServer,
+  process_types::{DetectionProviders, ProcessEventListeners, ProcessOptions, ProcessScanState},
+  rpc_clients::RpcClients,
+  shutdown::Shutdown,
+  systemd,
   websocket::WebsocketConnector,
 };
 use std::{
+  net::{IpAddr, Ipv4Addr},
   path::PathBuf,
   sync::{mpsc, Arc, Mutex},
+  time::{Duration, Instant},
 };
 
+pub use builder::RPCServerBuilder;
+pub use error::{Error, Result};
+pub use logger::{Level as LogLevel, LogRecord};
+pub use server::{
+  arbitration::ActivitySource,
+  client_connector::{ActivityEvent, ConnectorClientEvent},
+  control::query as query_control_socket,
+  process_types::ProcessPriority,
+  state::{ConnectorClientState, RpcClientState, ScannerState, ServerState, ShownActivity},
+  tls::TlsConfig,
+};
+
+pub mod builder;
 pub mod cmd;
 pub mod detection;
+mod error;
 mod logger;
 mod server;
+#[cfg(any(feature = "client-connector", feature = "rpc-websocket"))]
 mod url_params;
 
+// How long stop_async() waits for the server loops to finish
+#[cfg(feature = "async")]
+const ASYNC_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub type ProcessCallback = dyn FnMut(ProcessScanState) + Send + Sync;
+pub type ConnectorCallback = dyn FnMut(ConnectorClientEvent) + Send + Sync;
+pub type ActivityCallback = dyn FnMut(ActivityEvent) + Send + Sync;
+pub type DetectedCallback = dyn FnMut(DetectableActivity) + Send + Sync;
+pub type ReloadCallback = dyn FnMut() + Send + Sync;
 
 #[derive(Clone, Debug)]
 pub struct RPCConfig {
@@ -26,6 +64,101 @@ pub struct RPCConfig {
   pub enable_ipc_connector: bool,
   pub enable_websocket_connector: bool,
   pub enable_secondary_events: bool,
+  // Resolve activity asset keys to asset IDs using the Discord API
+  pub resolve_assets: bool,
+  // Convert image URLs used as asset keys to media proxy (mp:external) keys
+  pub convert_external_assets: bool,
+  // Minimum time between activity updates from the same application, zero to disable
+  pub activity_rate_limit: Duration,
+  // Answer AUTHORIZE/AUTHENTICATE with fake codes and tokens, for SDKs that won't set activities without them
+  pub enable_auth_stub: bool,
+  // The user presented to RPC clients
+  pub user: UserConfig,
+  // Returned by GET_VOICE_SETTINGS
+  pub voice_settings: Value,
+  // Keep track of GameSDK lobbies in memory, instead of responding that they are unsupported
+  pub enable_lobbies: bool,
+  // When several sources have an activity, the one listed first is shown
+  pub activity_priority: Vec<ActivitySource>,
+  // How many of the discord-ipc-0..9 sockets to bind. Indices that are taken are skipped.
+  pub ipc_socket_count: u8,
+  // What the IPC sockets (or pipes) are called before their index. Clients only look for "discord-ipc", so this is for running alongside another server.
+  pub ipc_base_name: String,
+  // Create the IPC sockets here instead of the platform's temp/runtime directory (not used on Windows)
+  pub ipc_socket_dir: Option<PathBuf>,
+  // Link the IPC sockets into the Flatpak/Snap Discord runtime directories (Linux only)
+  pub ipc_sandbox_links: bool,
+  // Named pipe instances per socket, so several clients can connect at the same time (Windows only)
+  pub ipc_pipe_instances: u8,
+  // Let every logged-in user's processes connect to the named pipes, not only this user's, eg. when running as a service (Windows only)
+  pub ipc_pipe_all_users: bool,
+  // Largest IPC frame accepted, in bytes
+  pub ipc_max_frame_size: usize,
+  // The port the client connector listens on (requires the `client-connector` feature)
+  pub connector_port: u16,
+  // Tried in order when `connector_port` is taken. Use `RPCServer::connector_port()` to see which one was used.
+  pub connector_fallback_ports: Vec<u16>,
+  // The address the client connector listens on. `::` listens on both IPv4 and IPv6.
+  pub connector_bind_address: IpAddr,
+  // The address the RPC websocket servers listen on, `::1` for IPv6 only hosts
+  pub websocket_bind_address: IpAddr,
+  // The first port the RPC websocket server tries, clients look from 6463 on
+  pub websocket_base_port: u16,
+  // How many ports of the 10 from `websocket_base_port` the RPC websocket server listens on
+  pub websocket_port_count: u8,
+  // Browser origins allowed to connect to the websocket servers, on top of Discord and local pages
+  pub allowed_origins: Vec<String>,
+  // Reject IPC handshakes from applications that aren't in the detectable list
+  pub validate_client_ids: bool,
+  // Drop IPC connections sending more than `ipc_message_limit` frames per `ipc_message_window`, zero to disable
+  pub ipc_message_limit: u32,
+  pub ipc_message_window: Duration,
+  // Serve the client connector over TLS with this certificate and key (requires the `tls` feature)
+  pub connector_tls: Option<TlsConfig>,
+  // Only let client connector clients that present this token (as `?token=` or a first `{"token": ...}` message) receive activities. Clients that haven't sent it within 10 seconds of connecting are disconnected
+  pub connector_token: Option<String>,
+  // Ping client connector clients this often, and disconnect the ones that don't answer. They have to reply with {"cmd": "PONG"}.
+  pub connector_keepalive: Option<Duration>,
+  // How many frames are held for a client connector client that isn't keeping up, before the oldest activity updates are dropped
+  pub connector_queue_size: usize,
+  // Send client connector clients the bare READY event on connect, instead of the versioned HELLO message wrapping it
+  pub connector_legacy_hello: bool,
+  // Extra detectable lists merged over the one the server is created with, later files win when ids clash
+  pub detectable_files: Vec<PathBuf>,
+  // Reload the detectable files when they change, without restarting
+  pub watch_detectable_files: bool,
+  // Fetch Discord's detectable list this often and merge it in, caching it between runs
+  pub detectable_update_interval: Option<Duration>,
+  // Detect games by the Steam library they are installed in, when Discord only knows them by their Steam app id
+  pub steam_detection: bool,
+  // Detect games run through Heroic and Lutris by title, which their wrappers otherwise hide
+  pub launcher_detection: bool,
+  // Show the game and system RetroArch is running, eg. "Super Metroid (SNES)", from its command line or its network commands if they are enabled
+  pub emulator_detection: bool,
+  // When nothing is detected by executable, look for windows titled like a detectable game (requires the `window-titles` feature)
+  pub window_title_detection: bool,
+  // Scan as soon as the OS reports a process starting or exiting, instead of only every few seconds. Falls back to polling when the events aren't available.
+  pub event_driven_scanning: bool,
+  // How often processes are scanned, when process events aren't available or are turned off
+  pub scan_interval: Duration,
+  // Never detect these, each one an application id, an executable name (eg. "cmd.exe") or a path (ending in a slash for a whole directory)
+  pub ignored_processes: Vec<String>,
+  // How long a game stays detected after a scan stops finding it, so its activity and timer survive a missed scan
+  pub detection_grace_period: Duration,
+  // Which game is shown when several are running
+  pub process_priority: ProcessPriority,
+  // Show the game whose window has focus, like Discord does (requires the `window-titles` feature)
+  pub follow_focus: bool,
+  // Clear the activity of a game within a few hundred milliseconds of it exiting, instead of on the next scan
+  pub fast_exit_detection: bool,
+  // WASM plugins to load, each one a .wasm file or a directory of them (requires the `wasm-plugins` feature)
+  pub plugins: Vec<PathBuf>,
+  // Also scan the processes of these procfs mounts, eg. a container's /proc or the host's when running in one (Linux only)
+  pub extra_proc_roots: Vec<PathBuf>,
+  // Answer `status` queries on a local socket of this name, next to the IPC sockets (a named pipe on Windows). See `query_control_socket()`. Requires the `ipc` feature.
+  pub control_socket: Option<String>,
+  // Take the websocket listeners from systemd socket activation if it passed any, and report READY=1 and watchdog pings through sd_notify (Linux only)
+  pub systemd: bool,
 }
 
 impl Default for RPCConfig {
@@ -35,6 +168,59 @@ impl Default for RPCConfig {
       enable_ipc_connector: true,
       enable_websocket_connector: true,
       enable_secondary_events: true,
+      resolve_assets: true,
+      convert_external_assets: true,
+      activity_rate_limit: Duration::from_secs(15),
+      enable_auth_stub: true,
+      user: UserConfig::default(),
+      voice_settings: default_voice_settings(),
+      enable_lobbies: true,
+      activity_priority: vec![
+        ActivitySource::Api,
+        ActivitySource::Ipc,
+        ActivitySource::Websocket,
+        ActivitySource::Process,
+      ],
+      ipc_socket_count: 1,
+      ipc_base_name: "discord-ipc".to_string(),
+      ipc_socket_dir: None,
+      ipc_sandbox_links: true,
+      ipc_pipe_instances: 4,
+      ipc_pipe_all_users: false,
+      ipc_max_frame_size: 64 * 1024,
+      connector_port: 1337,
+      connector_fallback_ports: vec![],
+      connector_bind_address: Ipv4Addr::LOCALHOST.into(),
+      websocket_bind_address: Ipv4Addr::LOCALHOST.into(),
+      websocket_base_port: *server::connector_types::RPC_PORT_RANGE.start(),
+      websocket_port_count: 1,
+      allowed_origins: vec![],
+      validate_client_ids: false,
+      ipc_message_limit: 100,
+      ipc_message_window: Duration::from_secs(1),
+      connector_tls: None,
+      connector_token: None,
+      connector_keepalive: None,
+      connector_queue_size: 64,
+      connector_legacy_hello: false,
+      detectable_files: vec![],
+      watch_detectable_files: true,
+      detectable_update_interval: None,
+      steam_detection: true,
+      launcher_detection: false,
+      emulator_detection: false,
+      window_title_detection: false,
+      event_driven_scanning: true,
+      scan_interval: Duration::from_secs(10),
+      ignored_processes: vec![],
+      detection_grace_period: Duration::from_secs(10),
+      process_priority: ProcessPriority::FirstDetected,
+      follow_focus: false,
+      fast_exit_detection: true,
+      plugins: vec![],
+      extra_proc_roots: vec![],
+      control_socket: None,
+      systemd: false,
     }
   }
 }
@@ -45,24 +231,131 @@ pub struct Connectors {
   client_connector: Arc<Mutex<ClientConnector>>,
   ipc_connector: Arc<Mutex<IpcConnector>>,
   ws_connector: Arc<Mutex<WebsocketConnector>>,
+  // Stops the threads that don't belong to any connector, like the detectable file watcher
+  shutdown: Shutdown,
+  started: Instant,
+  scanner_enabled: bool,
+}
+
+impl Connectors {
+  fn state(&self) -> ServerState {
+    let client_connector = self.client_connector.lock().unwrap();
+    let process_server = self.process_server.lock().unwrap();
+    let commands = self.ipc_connector.lock().unwrap().commands();
+
+    ServerState {
+      uptime_secs: self.started.elapsed().as_secs(),
+      connector_port: client_connector.port,
+      connector_clients: client_connector.client_states(),
+      rpc_clients: commands.rpc_clients.list(),
+      activities: client_connector.shown_activities(),
+      scanner: ScannerState {
+        enabled: self.scanner_enabled,
+        paused: process_server.is_paused(),
+        detectable_count: process_server.detectables().len(),
+        detected: process_server.detected(),
+      },
+      certified_devices: commands.certified_devices(),
+    }
+  }
+}
+
+/**
+ * Everything needed to rebuild the scanned detectable list from another thread
+ */
+#[derive(Clone)]
+struct DetectableReloader {
+  base: Arc<Mutex<Vec<DetectableActivity>>>,
+  files: Arc<Mutex<Vec<PathBuf>>>,
+  detectable: Arc<Mutex<Vec<DetectableActivity>>>,
+  process_server: ProcessServer,
+}
+
+impl DetectableReloader {
+  /**
+   * Merge the detectable files over the base list again, and scan for the result from then on
+   */
+  fn reload(&self) {
+    let files = self.files.lock().unwrap().clone();
+    let merged = match load_detectables(&self.base.lock().unwrap(), &files) {
+      Ok(merged) => merged,
+      Err(err) => {
+        log!(
+          warn,
+          "[RPC Server] Keeping the current detectable list: {}",
+          err
+        );
+        return;
+      }
+    };
+
+    log!("[RPC Server] Now detecting {} games", merged.len());
+    *self.detectable.lock().unwrap() = merged.clone();
+    self.process_server.replace_detectables(merged);
+  }
+}
+
+/**
+ * `base` with every detectable file merged over it, in order
+ */
+fn load_detectables(
+  base: &[DetectableActivity],
+  files: &[PathBuf],
+) -> Result<Vec<DetectableActivity>> {
+  let mut detectable = base.to_vec();
+
+  for file in files {
+    let extra = read_detectables(file).map_err(|err| {
+      Error::Detectables(format!(
+        "Could not load detectable file {}: {}",
+        file.display(),
+        err
+      ))
+    })?;
+
+    log!(
+      "[RPC Server] Loaded {} detectables from {}",
+      extra.len(),
+      file.display()
+    );
+    merge_detectables(&mut detectable, extra);
+  }
+
+  Ok(detectable)
 }
 
 pub struct RPCServer {
+  // The list the server was created with, before the detectable files are merged over it
+  base_detectable: Arc<Mutex<Vec<DetectableActivity>>>,
   detectable: Arc<Mutex<Vec<DetectableActivity>>>,
+  // config.detectable_files, shared with the threads that merge them in, since reload_config() can change them
+  detectable_files: Arc<Mutex<Vec<PathBuf>>>,
+  // Whether a detectable file watcher is running, so reload_config() starts one if the server started without files
+  watching_files: bool,
   connectors: Option<Connectors>,
   config: RPCConfig,
 
   on_process_scan_complete: Option<Arc<Mutex<ProcessCallback>>>,
+  on_connector_client: Option<Arc<Mutex<ConnectorCallback>>>,
+  on_activity_set: Option<Arc<Mutex<ActivityCallback>>>,
+  on_activity_cleared: Option<Arc<Mutex<ActivityCallback>>>,
+  on_process_detected: Option<Arc<Mutex<DetectedCallback>>>,
+  on_reload_requested: Option<Arc<Mutex<ReloadCallback>>>,
+  detection_providers: DetectionProviders,
+  // Where the server loops report errors, see errors()
+  errors: mpsc::Sender<Error>,
+  error_receiver: Option<mpsc::Receiver<Error>>,
+  // Everything spawned by start_async(), waited on by stop_async()
+  #[cfg(feature = "async")]
+  tasks: Option<TaskGroup>,
 }
 
 impl RPCServer {
-  pub fn from_json_str(
-    detectable: impl AsRef<str>,
-    config: RPCConfig,
-  ) -> Result<Self, Box<dyn std::error::Error>> {
-    // Parse as JSON, panic if invalid
-    let detectable: Value =
-      serde_json::from_str(detectable.as_ref()).expect("Invalid JSON provided to RPCServer");
+  /**
+   * Create a new RPCServer from the detectable games list as JSON. Fails if it isn't valid JSON, or a detectable in it is malformed.
+   */
+  pub fn from_json_str(detectable: impl AsRef<str>, config: RPCConfig) -> Result<Self> {
+    let detectable: Value = serde_json::from_str(detectable.as_ref())?;
 
     // Turn detectable into a vector of DetectableActivity
     let detectable_arr = detectable.as_array();
@@ -71,15 +364,31 @@ impl RPCServer {
     if let Some(detectable_arr) = detectable_arr {
       detectable = detectable_arr
         .iter()
-        .map(|x| serde_json::from_value(x.clone()).expect("Detectable list malformed!"))
-        .collect();
+        .map(|x| {
+          serde_json::from_value(x.clone())
+            .map_err(|err| Error::Detectables(format!("Detectable list malformed: {}", err)))
+        })
+        .collect::<Result<_>>()?;
     } else {
-      log!("Detectable list empty!");
+      log!(warn, "Detectable list empty!");
       detectable = vec![];
     }
 
+    Self::from_detectables(detectable, config)
+  }
+
+  /**
+   * Create a new RPCServer from an already parsed detectable games list
+   */
+  pub fn from_detectables(detectable: Vec<DetectableActivity>, config: RPCConfig) -> Result<Self> {
+    let merged = load_detectables(&detectable, &config.detectable_files)?;
+    let (errors, error_receiver) = mpsc::channel();
+
     Ok(Self {
-      detectable: Arc::new(Mutex::new(detectable)),
+      base_detectable: Arc::new(Mutex::new(detectable)),
+      detectable: Arc::new(Mutex::new(merged)),
+      detectable_files: Arc::new(Mutex::new(config.detectable_files.clone())),
+      watching_files: false,
 
       // Default to empty servers
       connectors: None,
@@ -87,16 +396,26 @@ impl RPCServer {
 
       // Event listeners
       on_process_scan_complete: None,
+      on_connector_client: None,
+      on_activity_set: None,
+      on_activity_cleared: None,
+      on_process_detected: None,
+      on_reload_requested: None,
+      detection_providers: DetectionProviders::default(),
+      errors,
+      error_receiver: Some(error_receiver),
+      #[cfg(feature = "async")]
+      tasks: None,
     })
   }
 
   /**
    * Create a new RPCServer and read the detectable games list from file.
    */
-  pub fn from_file(file: PathBuf, config: RPCConfig) -> Result<Self, Box<dyn std::error::Error>> {
+  pub fn from_file(file: PathBuf, config: RPCConfig) -> Result<Self> {
     // Read the detectable games list from file.
     let detectable = std::fs::read_to_string(&file)
-      .unwrap_or_else(|_| panic!("RPCServer could not find file: {:?}", file.display()));
+      .map_err(|err| Error::Detectables(format!("Could not read {}: {}", file.display(), err)))?;
 
     Self::from_json_str(detectable.as_str(), config)
   }
@@ -106,7 +425,10 @@ impl RPCServer {
    */
   pub fn append_detectables(&mut self, detectable: Vec<DetectableActivity>) {
     if self.connectors.is_none() {
-      log!("[RPC Server] Cannot append detectables, connectors are not initialized");
+      log!(
+        warn,
+        "[RPC Server] Cannot append detectables, connectors are not initialized"
+      );
       return;
     }
 
@@ -125,7 +447,10 @@ impl RPCServer {
    */
   pub fn remove_detectable_by_name(&mut self, name: String) {
     if self.connectors.is_none() {
-      log!("[RPC Server] Cannot remove detectable, connectors are not initialized");
+      log!(
+        warn,
+        "[RPC Server] Cannot remove detectable, connectors are not initialized"
+      );
       return;
     }
 
@@ -139,12 +464,65 @@ impl RPCServer {
       .remove_detectable_by_name(name);
   }
 
+  /**
+   * Remove a detectable by id, whether it was in the initial list, a detectable file or appended. This should be run AFTER start().
+   */
+  pub fn remove_detectable(&mut self, id: &str) {
+    let Some(connectors) = self.connectors.as_ref() else {
+      log!(
+        warn,
+        "[RPC Server] Cannot remove detectable, connectors are not initialized"
+      );
+      return;
+    };
+
+    self.base_detectable.lock().unwrap().retain(|x| x.id != id);
+    self.detectable.lock().unwrap().retain(|x| x.id != id);
+    connectors
+      .process_server
+      .lock()
+      .unwrap()
+      .remove_detectable(id);
+  }
+
+  /**
+   * Replace the detectable list the server was created with, dropping any appended detectables. The detectable files are still merged over it. This should be run AFTER start().
+   */
+  pub fn replace_detectables(&mut self, detectable: Vec<DetectableActivity>) {
+    let Some(connectors) = self.connectors.as_ref() else {
+      log!(
+        warn,
+        "[RPC Server] Cannot replace detectables, connectors are not initialized"
+      );
+      return;
+    };
+
+    *self.base_detectable.lock().unwrap() = detectable;
+
+    let reloader = self.reloader(connectors);
+    reloader.process_server.clear_appended_detectables();
+    reloader.reload();
+  }
+
+  /**
+   * Every detectable that is being scanned for, or will be once started
+   */
+  pub fn get_detectables(&self) -> Vec<DetectableActivity> {
+    match self.connectors.as_ref() {
+      Some(connectors) => connectors.process_server.lock().unwrap().detectables(),
+      None => self.detectable.lock().unwrap().clone(),
+    }
+  }
+
   /**
    * Manually trigger a scan for processes. This should be run AFTER start().
    */
   pub fn scan_for_processes(&mut self) {
     if self.connectors.is_none() {
-      log!("[RPC Server] Cannot scan processes, connectors are not initialized");
+      log!(
+        warn,
+        "[RPC Server] Cannot scan processes, connectors are not initialized"
+      );
       return;
     }
 
@@ -159,27 +537,421 @@ impl RPCServer {
     match process_server.scan_for_processes() {
       Ok(_) => {}
       Err(err) => {
-        log!("[RPC Server] Error while scanning processes: {}", err);
+        log!(warn, "[RPC Server] Error while scanning processes: {}", err);
       }
     }
   }
 
+  /**
+   * Stop detecting processes and clear the activity they set, eg. while the user is streaming. Other connectors keep working. This should be run AFTER start().
+   */
+  pub fn pause_scanning(&self) {
+    let Some(connectors) = self.connectors.as_ref() else {
+      log!(
+        warn,
+        "[RPC Server] Cannot pause scanning, connectors are not initialized"
+      );
+      return;
+    };
+
+    connectors.process_server.lock().unwrap().pause();
+  }
+
+  /**
+   * Start detecting processes again after pause_scanning(). This should be run AFTER start().
+   */
+  pub fn resume_scanning(&self) {
+    let Some(connectors) = self.connectors.as_ref() else {
+      log!(
+        warn,
+        "[RPC Server] Cannot resume scanning, connectors are not initialized"
+      );
+      return;
+    };
+
+    connectors.process_server.lock().unwrap().resume();
+  }
+
+  pub fn is_scanning_paused(&self) -> bool {
+    self
+      .connectors
+      .as_ref()
+      .map(|connectors| connectors.process_server.lock().unwrap().is_paused())
+      .unwrap_or(false)
+  }
+
+  /**
+   * Never detect `entry`, an application id, an executable name or a path. Can be run before or after start().
+   */
+  pub fn ignore_process(&mut self, entry: impl Into<String>) {
+    let entry = entry.into();
+
+    if !self.config.ignored_processes.contains(&entry) {
+      self.config.ignored_processes.push(entry);
+      self.update_ignored();
+    }
+  }
+
+  /**
+   * Detect `entry` again after ignore_process()
+   */
+  pub fn unignore_process(&mut self, entry: &str) {
+    self.config.ignored_processes.retain(|x| x != entry);
+    self.update_ignored();
+  }
+
+  pub fn ignored_processes(&self) -> Vec<String> {
+    self.config.ignored_processes.clone()
+  }
+
+  /**
+   * Switch to `config` without restarting, keeping every client connected. The detectable files and ignored processes take effect
+   * right away, and `detectable`, if given, replaces the list the server was created with like replace_detectables(). Everything else,
+   * like the ports, only changes the next time the server is started. Can be run before or after start().
+   */
+  pub fn reload_config(&mut self, config: RPCConfig, detectable: Option<Vec<DetectableActivity>>) {
+    let files_changed = config.detectable_files != self.config.detectable_files;
+    self.config = config;
+
+    *self.detectable_files.lock().unwrap() = self.config.detectable_files.clone();
+    self.update_ignored();
+
+    if let Some(detectable) = detectable.as_ref() {
+      *self.base_detectable.lock().unwrap() = detectable.clone();
+    }
+
+    let Some(connectors) = self.connectors.clone() else {
+      if files_changed || detectable.is_some() {
+        match load_detectables(
+          &self.base_detectable.lock().unwrap(),
+          &self.config.detectable_files,
+        ) {
+          Ok(merged) => *self.detectable.lock().unwrap() = merged,
+          Err(err) => log!(
+            warn,
+            "[RPC Server] Keeping the current detectable list: {}",
+            err
+          ),
+        }
+      }
+
+      return;
+    };
+
+    if files_changed || detectable.is_some() {
+      let reloader = self.reloader(&connectors);
+
+      if detectable.is_some() {
+        reloader.process_server.clear_appended_detectables();
+      }
+
+      reloader.reload();
+    }
+
+    if files_changed
+      && self.config.watch_detectable_files
+      && !self.config.detectable_files.is_empty()
+      && !self.watching_files
+    {
+      log!("[RPC Server] Watching detectable files for changes...");
+      self.watch_detectable_files(&connectors);
+    }
+
+    log!("[RPC Server] Reloaded the config, changes to anything but the detectables and ignored processes apply once restarted");
+  }
+
+  fn update_ignored(&self) {
+    if let Some(connectors) = self.connectors.as_ref() {
+      connectors
+        .process_server
+        .lock()
+        .unwrap()
+        .set_ignored(&self.config.ignored_processes);
+    }
+  }
+
   pub fn on_process_scan_complete(
     &mut self,
     callback: impl FnMut(ProcessScanState) + Send + Sync + 'static,
   ) {
     if self.connectors.is_some() {
-      log!("[RPC Server] Cannot set on_process_scan_complete, connectors are already initialized");
+      log!(
+        warn,
+        "[RPC Server] Cannot set on_process_scan_complete, connectors are already initialized"
+      );
       return;
     }
 
     self.on_process_scan_complete = Some(Arc::new(Mutex::new(callback)));
   }
 
-  pub fn start(&mut self) {
+  /**
+   * Detect activities from `provider` alongside the process scanner. Can be run before or after start().
+   */
+  pub fn add_detection_provider(&mut self, provider: impl DetectionProvider + 'static) {
+    self
+      .detection_providers
+      .lock()
+      .unwrap()
+      .push(Box::new(provider));
+  }
+
+  /**
+   * Called whenever a client connector client connects (after presenting its token, if one is needed) or disconnects
+   */
+  pub fn on_connector_client(
+    &mut self,
+    callback: impl FnMut(ConnectorClientEvent) + Send + Sync + 'static,
+  ) {
+    if self.connectors.is_some() {
+      log!(
+        warn,
+        "[RPC Server] Cannot set on_connector_client, connectors are already initialized"
+      );
+      return;
+    }
+
+    self.on_connector_client = Some(Arc::new(Mutex::new(callback)));
+  }
+
+  /**
+   * Called whenever an activity is shown, whichever source it came from. Activities that lose arbitration to another source aren't shown, so they aren't passed on.
+   */
+  pub fn on_activity_set(&mut self, callback: impl FnMut(ActivityEvent) + Send + Sync + 'static) {
+    if self.connectors.is_some() {
+      log!(
+        warn,
+        "[RPC Server] Cannot set on_activity_set, connectors are already initialized"
+      );
+      return;
+    }
+
+    self.on_activity_set = Some(Arc::new(Mutex::new(callback)));
+  }
+
+  /**
+   * Called whenever a shown activity goes away, eg. the game exited or the client cleared it
+   */
+  pub fn on_activity_cleared(
+    &mut self,
+    callback: impl FnMut(ActivityEvent) + Send + Sync + 'static,
+  ) {
+    if self.connectors.is_some() {
+      log!(
+        warn,
+        "[RPC Server] Cannot set on_activity_cleared, connectors are already initialized"
+      );
+      return;
+    }
+
+    self.on_activity_cleared = Some(Arc::new(Mutex::new(callback)));
+  }
+
+  /**
+   * Called once whenever the process scanner starts detecting a game, whether or not it ends up shown
+   */
+  pub fn on_process_detected(
+    &mut self,
+    callback: impl FnMut(DetectableActivity) + Send + Sync + 'static,
+  ) {
+    if self.connectors.is_some() {
+      log!(
+        warn,
+        "[RPC Server] Cannot set on_process_detected, connectors are already initialized"
+      );
+      return;
+    }
+
+    self.on_process_detected = Some(Arc::new(Mutex::new(callback)));
+  }
+
+  /**
+   * Called when a `reload` command comes in on the control socket, eg. to read the host's config again and pass it to reload_config().
+   * The control socket answers `reload` with an error unless this is set. This should be run BEFORE start().
+   */
+  pub fn on_reload_requested(&mut self, callback: impl FnMut() + Send + Sync + 'static) {
+    if self.connectors.is_some() {
+      log!(
+        warn,
+        "[RPC Server] Cannot set on_reload_requested, connectors are already initialized"
+      );
+      return;
+    }
+
+    self.on_reload_requested = Some(Arc::new(Mutex::new(callback)));
+  }
+
+  /**
+   * Show `activity` for `application_id`, like an IPC or websocket client setting it would. It is fixed up, has its assets resolved and goes through the plugins the same way.
+   * These activities are the `api` source, which wins arbitration by default. This should be run AFTER start().
+   */
+  pub fn set_activity(&self, activity: Activity, application_id: impl AsRef<str>) {
+    let Some(connectors) = self.connectors.as_ref() else {
+      log!(
+        warn,
+        "[RPC Server] Cannot set activity, connectors are not initialized"
+      );
+      return;
+    };
+
+    connectors
+      .client_connector
+      .lock()
+      .unwrap()
+      .set_api_activity(application_id.as_ref(), activity);
+  }
+
+  /**
+   * Clear the activity set with set_activity(), leaving the ones clients and the process scanner set alone. This should be run AFTER start().
+   */
+  pub fn clear_activity(&self) {
+    let Some(connectors) = self.connectors.as_ref() else {
+      log!(
+        warn,
+        "[RPC Server] Cannot clear activity, connectors are not initialized"
+      );
+      return;
+    };
+
+    connectors
+      .client_connector
+      .lock()
+      .unwrap()
+      .clear_api_activity();
+  }
+
+  /**
+   * What the server is doing right now: who is connected, which activities are shown and what the scanner found. None if it isn't started.
+   */
+  pub fn state(&self) -> Option<ServerState> {
+    self
+      .connectors
+      .as_ref()
+      .map(|connectors| connectors.state())
+  }
+
+  /**
+   * The port the client connector ended up listening on. This should be run AFTER start(), and is None without the `client-connector` feature.
+   */
+  pub fn connector_port(&self) -> Option<u16> {
+    self
+      .connectors
+      .as_ref()
+      .map(|connectors| connectors.client_connector.lock().unwrap().port)
+      .filter(|port| *port != 0)
+  }
+
+  /**
+   * Deliver every log message to `sink` instead of only printing it, eg. to show it in the host's own UI. Messages are still printed to stdout if `stdout` is set.
+   * Logging is process wide, so this affects every RPCServer and replaces any previous sink.
+   */
+  pub fn set_log_sink(&self, sink: impl Fn(&LogRecord) + Send + Sync + 'static, stdout: bool) {
+    logger::set_sink(sink, stdout);
+  }
+
+  /**
+   * Stop delivering log messages to the sink set with set_log_sink()
+   */
+  pub fn clear_log_sink(&self) {
+    logger::clear_sink();
+  }
+
+  /**
+   * Print log messages to stdout or not, in place of RSRPC_LOGS_ENABLED. Like the sink, this is process wide, and can be changed while running.
+   */
+  pub fn set_logs_enabled(&self, enabled: bool) {
+    logger::set_enabled(enabled);
+  }
+
+  /**
+   * Only print log messages up to `level`, in place of RSRPC_LOG_LEVEL. None goes back to RSRPC_LOG_LEVEL, or info.
+   */
+  pub fn set_log_level(&self, level: Option<LogLevel>) {
+    logger::set_level(level);
+  }
+
+  /**
+   * Print log messages as JSON objects instead of text, in place of RSRPC_LOG_FORMAT
+   */
+  pub fn set_log_json(&self, json: bool) {
+    logger::set_json(json);
+  }
+
+  /**
+   * The most verbose level that is printed, for sinks that want to filter the same way
+   */
+  pub fn log_level(&self) -> LogLevel {
+    logger::max_level()
+  }
+
+  /**
+   * Errors from the server loops after start(), like a worker that panicked. The receiver can only be taken once.
+   */
+  pub fn errors(&mut self) -> Option<mpsc::Receiver<Error>> {
+    self.error_receiver.take()
+  }
+
+  /**
+   * Scan for processes once, without starting anything, and return every game that was found. The `process` of each says which
+   * processes matched and why. A dry run of what the server would detect, eg. to find out why a game isn't detected.
+   */
+  pub fn scan_once(&self) -> Result<Vec<DetectableActivity>> {
+    let (event_sender, _) = mpsc::channel();
+    let detectable = self.detectable.lock().unwrap().to_vec();
+    let process_server = ProcessServer::new(
+      detectable.clone(),
+      event_sender,
+      8,
+      ProcessEventListeners::default(),
+      self.detection_providers.clone(),
+      self.process_options(Plugins::load(&self.config.plugins)),
+    );
+
+    process_server.replace_detectables(detectable);
+    Ok(process_server.scan_for_processes()?)
+  }
+
+  fn process_options(&self, plugins: Plugins) -> ProcessOptions {
+    ProcessOptions {
+      steam_detection: self.config.steam_detection,
+      launcher_detection: self.config.launcher_detection,
+      emulator_detection: self.config.emulator_detection,
+      window_title_detection: self.config.window_title_detection,
+      event_driven: self.config.event_driven_scanning,
+      scan_interval: self.config.scan_interval,
+      ignored: self.config.ignored_processes.clone(),
+      grace_period: self.config.detection_grace_period,
+      priority: self.config.process_priority.clone(),
+      follow_focus: self.config.follow_focus,
+      watch_exits: self.config.fast_exit_detection,
+      plugins,
+      proc_roots: self.config.extra_proc_roots.clone(),
+    }
+  }
+
+  /**
+   * Start every server and connector. Fails if the client connector or websocket servers can't listen on any of their ports.
+   */
+  pub fn start(&mut self) -> Result<()> {
+    server::runtime::with_errors(self.errors.clone(), || self.start_connectors())
+  }
+
+  fn start_connectors(&mut self) -> Result<()> {
     let (proc_event_sender, proc_event_receiver) = mpsc::channel();
     let (ipc_event_sender, ipc_event_receiver) = mpsc::channel();
     let (ws_event_sender, ws_event_reciever) = mpsc::channel();
+    let rpc_clients = RpcClients::default();
+    let commands = CommandHandler::new(
+      rpc_clients.clone(),
+      CommandConfig {
+        auth_stub: self.config.enable_auth_stub,
+        user: self.config.user.clone(),
+        voice_settings: self.config.voice_settings.clone(),
+        lobbies: self.config.enable_lobbies,
+      },
+    );
+
+    let plugins = Plugins::load(&self.config.plugins);
 
     let connectors = Connectors {
       process_server: Arc::new(Mutex::new(ProcessServer::new(
@@ -188,19 +960,87 @@ impl RPCServer {
         8,
         ProcessEventListeners {
           on_process_scan_complete: self.on_process_scan_complete.clone(),
+          on_process_detected: self.on_process_detected.clone(),
         },
+        self.detection_providers.clone(),
+        self.process_options(plugins.clone()),
       ))),
       client_connector: Arc::new(Mutex::new(ClientConnector::new(
-        1337,
-        server::utils::CONNECTION_REPONSE.to_string(),
+        self.config.connector_port,
+        server::utils::connection_response(&self.config.user),
         ipc_event_receiver,
         proc_event_receiver,
         ws_event_reciever,
+        rpc_clients.clone(),
+        ConnectorOptions {
+          asset_resolver: if self.config.resolve_assets {
+            Some(AssetResolver::default())
+          } else {
+            None
+          },
+          convert_external_assets: self.config.convert_external_assets,
+          activity_rate_limit: self.config.activity_rate_limit,
+          activity_priority: self.config.activity_priority.clone(),
+          allowed_origins: self.config.allowed_origins.clone(),
+          fallback_ports: self.config.connector_fallback_ports.clone(),
+          bind_address: self.config.connector_bind_address,
+          tls: self.config.connector_tls.clone(),
+          token: self.config.connector_token.clone(),
+          keepalive: self.config.connector_keepalive,
+          queue_size: self.config.connector_queue_size,
+          legacy_hello: self.config.connector_legacy_hello,
+          broadcast_all_processes: self.config.process_priority == ProcessPriority::BroadcastAll,
+          plugins,
+          on_client: self.on_connector_client.clone(),
+          on_activity_set: self.on_activity_set.clone(),
+          on_activity_cleared: self.on_activity_cleared.clone(),
+        },
+      )?)),
+      ipc_connector: Arc::new(Mutex::new(IpcConnector::new(
+        ipc_event_sender,
+        commands.clone(),
+        IpcOptions {
+          socket_count: self.config.ipc_socket_count,
+          base_name: self.config.ipc_base_name.clone(),
+          socket_dir: self.config.ipc_socket_dir.clone(),
+          sandbox_links: self.config.ipc_sandbox_links,
+          pipe_instances: self.config.ipc_pipe_instances,
+          pipe_all_users: self.config.ipc_pipe_all_users,
+          max_frame_size: self.config.ipc_max_frame_size,
+          message_limit: self.config.ipc_message_limit,
+          message_window: self.config.ipc_message_window,
+          known_client_ids: if self.config.validate_client_ids {
+            Some(Arc::new(
+              self
+                .detectable
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|detectable| detectable.id.clone())
+                .collect(),
+            ))
+          } else {
+            None
+          },
+        },
       ))),
-      ipc_connector: Arc::new(Mutex::new(IpcConnector::new(ipc_event_sender))),
-      ws_connector: Arc::new(Mutex::new(WebsocketConnector::new(ws_event_sender))),
+      ws_connector: Arc::new(Mutex::new(WebsocketConnector::new(
+        ws_event_sender,
+        commands,
+        WebsocketOptions {
+          base_port: self.config.websocket_base_port,
+          port_count: self.config.websocket_port_count,
+          bind_address: self.config.websocket_bind_address,
+          allowed_origins: self.config.allowed_origins.clone(),
+          socket_activation: self.config.systemd,
+        },
+      )?)),
+      shutdown: Shutdown::default(),
+      started: Instant::now(),
+      scanner_enabled: self.config.enable_process_scanner,
     };
 
+    #[cfg(feature = "client-connector")]
     log!(
       "[RPC Server] Starting client connector on port {}...",
       connectors.client_connector.lock().unwrap().port
@@ -219,15 +1059,161 @@ impl RPCServer {
       connectors.process_server.lock().unwrap().start();
     }
 
+    if config.watch_detectable_files && !config.detectable_files.is_empty() {
+      log!("[RPC Server] Watching detectable files for changes...");
+      self.watch_detectable_files(&connectors);
+    }
+
+    if let Some(interval) = config.detectable_update_interval {
+      log!("[RPC Server] Starting detectable updater...");
+      self.update_detectables(&connectors, interval);
+    }
+
     if config.enable_websocket_connector || config.enable_secondary_events {
-      log!("[RPC Server] Starting websocket connector...");
+      log!(
+        "[RPC Server] Starting websocket connector on port(s) {:?}...",
+        connectors.ws_connector.lock().unwrap().ports()
+      );
       connectors.ws_connector.lock().unwrap().start(
         config.enable_websocket_connector,
         config.enable_secondary_events,
       );
     }
 
+    if let Some(name) = config.control_socket.as_ref() {
+      let state_connectors = connectors.clone();
+
+      // Everything else is already running, so a second instance doesn't stop this one
+      if let Err(err) = control::serve(
+        config.ipc_socket_dir.as_deref(),
+        name,
+        connectors.shutdown.clone(),
+        move || state_connectors.state(),
+        self.on_reload_requested.clone(),
+      ) {
+        log!(
+          warn,
+          "[RPC Server] Could not start the control socket: {}",
+          err
+        );
+      }
+    }
+
+    if config.systemd {
+      systemd::notify("READY=1");
+      systemd::watchdog(connectors.shutdown.clone());
+    }
+
     log!("[RPC Server] Done! Watching for activity...");
     self.connectors = Some(connectors);
+
+    Ok(())
+  }
+
+  fn reloader(&self, connectors: &Connectors) -> DetectableReloader {
+    DetectableReloader {
+      base: self.base_detectable.clone(),
+      files: self.detectable_files.clone(),
+      detectable: self.detectable.clone(),
+      process_server: connectors.process_server.lock().unwrap().clone(),
+    }
+  }
+
+  /**
+   * Merge the detectable files over the base list again whenever one changes
+   */
+  fn watch_detectable_files(&mut self, connectors: &Connectors) {
+    let reloader = self.reloader(connectors);
+    self.watching_files = true;
+
+    watch_files(
+      self.detectable_files.clone(),
+      connectors.shutdown.clone(),
+      move || reloader.reload(),
+    );
+  }
+
+  /**
+   * Keep Discord's detectable list up to date, merging it into the base list every `interval`
+   */
+  fn update_detectables(&self, connectors: &Connectors, interval: Duration) {
+    let reloader = self.reloader(connectors);
+
+    DetectableUpdater::default().start(interval, connectors.shutdown.clone(), move |list| {
+      merge_detectables(&mut reloader.base.lock().unwrap(), list);
+      reloader.reload();
+    });
+  }
+
+  /**
+   * Stop every server and connector, disconnecting their clients and removing the IPC sockets. The websocket ports are only released once the process exits.
+   */
+  pub fn stop(&mut self) {
+    let Some(connectors) = self.connectors.take() else {
+      log!(
+        warn,
+        "[RPC Server] Cannot stop, connectors are not initialized"
+      );
+      return;
+    };
+
+    log!("[RPC Server] Stopping...");
+
+    if self.config.systemd {
+      systemd::notify("STOPPING=1");
+    }
+
+    connectors.shutdown.stop();
+    self.watching_files = false;
+    connectors.process_server.lock().unwrap().stop();
+    connectors.ws_connector.lock().unwrap().stop();
+
+    let mut ipc_connector = connectors.ipc_connector.lock().unwrap();
+    ipc_connector.stop();
+    // Shared by the IPC and websocket connectors
+    ipc_connector.rpc_clients().close_all();
+
+    connectors.client_connector.lock().unwrap().stop();
+
+    log!("[RPC Server] Stopped");
+  }
+
+  /**
+   * Like start(), but every server loop runs on the blocking pool of the current tokio runtime instead of on threads of its own (requires the `async` feature).
+   * The loops are the same blocking ones start() runs, so each holds a thread of the pool until the server stops.
+   */
+  #[cfg(feature = "async")]
+  pub async fn start_async(&mut self) -> Result<()> {
+    let tasks = TaskGroup::new(tokio::runtime::Handle::current());
+    let started = tasks.enter(|| self.start());
+
+    if started.is_ok() {
+      self.tasks = Some(tasks);
+    }
+
+    started
+  }
+
+  /**
+   * Stop like stop(), then wait for the server loops started by start_async() to finish. Loops blocked on the OS (eg. WMI events) are given up on after a few seconds.
+   * Dropping the future early is safe, the server is stopped either way.
+   */
+  #[cfg(feature = "async")]
+  pub async fn stop_async(&mut self) {
+    self.stop();
+
+    let Some(tasks) = self.tasks.take() else {
+      return;
+    };
+
+    let running = tasks.join(ASYNC_STOP_TIMEOUT).await;
+
+    if running > 0 {
+      log!(
+        warn,
+        "[RPC Server] {} tasks were still running after stopping",
+        running
+      );
+    }
   }
 }
@@ -1,17 +1,207 @@
-pub fn log(message: impl AsRef<str>) {
-  // If LOGS_ENABLED is 1, log the message with a timestamp
-  if std::env::var("RSRPC_LOGS_ENABLED").unwrap_or("0".to_string()) == "1" {
-    println!(
-      "[{}] {}",
-      chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-      message.as_ref()
-    );
+use std::sync::{Arc, RwLock};
+
+pub use tracing::Level;
+
+pub type LogSink = dyn Fn(&LogRecord) + Send + Sync;
+
+struct Sink {
+  callback: Arc<LogSink>,
+  // Keep printing to stdout as well
+  stdout: bool,
+}
+
+static SINK: RwLock<Option<Sink>> = RwLock::new(None);
+
+// Take the place of the RSRPC_LOGS_ENABLED, RSRPC_LOG_LEVEL and RSRPC_LOG_FORMAT environment variables once set,
+// since those can't be changed safely while other threads are logging
+struct Settings {
+  enabled: Option<bool>,
+  level: Option<Level>,
+  json: Option<bool>,
+}
+
+static SETTINGS: RwLock<Settings> = RwLock::new(Settings {
+  enabled: None,
+  level: None,
+  json: None,
+});
+
+/**
+ * A message rsRPC logged, as passed to the log sink
+ */
+#[derive(Clone, Debug)]
+pub struct LogRecord {
+  pub level: Level,
+  // The module it came from, eg. "rsrpc::server::process"
+  pub target: &'static str,
+  pub message: String,
+  pub timestamp: chrono::DateTime<chrono::Local>,
+}
+
+/**
+ * Send every message, of every level, to `callback`. Printing to stdout only carries on if `stdout` is set. Process wide, it replaces the previous sink.
+ */
+pub fn set_sink(callback: impl Fn(&LogRecord) + Send + Sync + 'static, stdout: bool) {
+  *SINK.write().unwrap() = Some(Sink {
+    callback: Arc::new(callback),
+    stdout,
+  });
+}
+
+pub fn clear_sink() {
+  *SINK.write().unwrap() = None;
+}
+
+/**
+ * Print messages to stdout or not, instead of going by RSRPC_LOGS_ENABLED
+ */
+pub fn set_enabled(enabled: bool) {
+  SETTINGS.write().unwrap().enabled = Some(enabled);
+}
+
+/**
+ * Only print messages up to `level`. None goes back to RSRPC_LOG_LEVEL, or info if that isn't set either.
+ */
+pub fn set_level(level: Option<Level>) {
+  SETTINGS.write().unwrap().level = level;
+}
+
+/**
+ * Print messages as JSON or as text, instead of going by RSRPC_LOG_FORMAT
+ */
+pub fn set_json(json: bool) {
+  SETTINGS.write().unwrap().json = Some(json);
+}
+
+/**
+ * The most verbose level that is printed
+ */
+pub fn max_level() -> Level {
+  SETTINGS.read().unwrap().level.unwrap_or_else(|| {
+    std::env::var("RSRPC_LOG_LEVEL")
+      .ok()
+      .and_then(|level| level.parse::<Level>().ok())
+      .unwrap_or(Level::INFO)
+  })
+}
+
+fn enabled() -> bool {
+  SETTINGS
+    .read()
+    .unwrap()
+    .enabled
+    .unwrap_or_else(|| std::env::var("RSRPC_LOGS_ENABLED").as_deref() == Ok("1"))
+}
+
+fn json() -> bool {
+  SETTINGS
+    .read()
+    .unwrap()
+    .json
+    .unwrap_or_else(|| std::env::var("RSRPC_LOG_FORMAT").as_deref() == Ok("json"))
+}
+
+/**
+ * Pass a message to the log sink, and print it to stdout if RSRPC_LOGS_ENABLED is 1 and the level isn't filtered out by RSRPC_LOG_LEVEL (info by default).
+ * RSRPC_LOG_FORMAT=json prints every message as a JSON object on a line of its own. set_enabled(), set_level() and set_json() override them.
+ */
+pub fn log(level: Level, target: &'static str, message: impl AsRef<str>) {
+  // Cloned out so a sink that logs itself doesn't deadlock
+  let sink = SINK
+    .read()
+    .unwrap()
+    .as_ref()
+    .map(|sink| (sink.callback.clone(), sink.stdout));
+
+  if let Some((callback, stdout)) = sink {
+    callback(&LogRecord {
+      level,
+      target,
+      message: message.as_ref().to_string(),
+      timestamp: chrono::Local::now(),
+    });
+
+    if !stdout {
+      return;
+    }
+  }
+
+  if !enabled() {
+    return;
+  }
+
+  // More verbose levels compare greater
+  if level > max_level() {
+    return;
+  }
+
+  let message = message.as_ref();
+
+  if json() {
+    println!("{}", json_line(level, target, message));
+    return;
   }
+
+  let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+
+  if level == Level::INFO {
+    println!("[{}] {}", timestamp, message);
+  } else {
+    println!("[{}] {} {}", timestamp, level, message);
+  }
+}
+
+/**
+ * One log message as a JSON object, for RSRPC_LOG_FORMAT=json, eg.
+ * {"timestamp": "2024-01-01T12:00:00.000+01:00", "level": "INFO", "target": "rsrpc::server::ipc", "message": "Created IPC socket: ...", "fields": {"component": "IPC"}}
+ */
+fn json_line(level: Level, target: &str, message: &str) -> String {
+  let mut fields = serde_json::Map::new();
+  let mut text = message;
+
+  // Messages start with the component they are about, eg. "[IPC] ..."
+  if let Some((component, rest)) = message
+    .strip_prefix('[')
+    .and_then(|message| message.split_once("] "))
+  {
+    fields.insert("component".to_string(), component.into());
+    text = rest;
+  }
+
+  serde_json::json!({
+    "timestamp": chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+    "level": level.to_string(),
+    "target": target,
+    "message": text,
+    "fields": fields,
+  })
+  .to_string()
 }
 
+/**
+ * Log a message as a `tracing` event, with the module it came from as the target, eg. `log!(warn, "[IPC] ...")`. Messages without a level are info.
+ * Without a tracing subscriber, messages are still printed to stdout when RSRPC_LOGS_ENABLED is 1.
+ */
 #[macro_export]
 macro_rules! log {
+  (error, $($arg:tt)*) => {
+    $crate::log!(@event ERROR, $($arg)*)
+  };
+  (warn, $($arg:tt)*) => {
+    $crate::log!(@event WARN, $($arg)*)
+  };
+  (debug, $($arg:tt)*) => {
+    $crate::log!(@event DEBUG, $($arg)*)
+  };
+  (trace, $($arg:tt)*) => {
+    $crate::log!(@event TRACE, $($arg)*)
+  };
+  (@event $level:ident, $($arg:tt)*) => {{
+    let message = format!($($arg)*);
+    tracing::event!(target: module_path!(), tracing::Level::$level, "{}", message);
+    $crate::logger::log($crate::logger::Level::$level, module_path!(), message);
+  }};
   ($($arg:tt)*) => {
-    $crate::logger::log(format!($($arg)*))
+    $crate::log!(@event INFO, $($arg)*)
   };
 }
@@ -0,0 +1,187 @@
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+  time::Instant,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivitySource {
+  // Set by the host application through RPCServer::set_activity()
+  Api,
+  Ipc,
+  Websocket,
+  Process,
+}
+
+/**
+ * What the connector clients should be sent after an activity changed
+ */
+#[derive(Clone, Debug)]
+pub enum Broadcast {
+  Activity {
+    source: ActivitySource,
+    application_id: String,
+    socket_id: String,
+    payload: String,
+  },
+  Clear {
+    source: ActivitySource,
+    application_id: String,
+    pid: u64,
+    socket_id: String,
+  },
+}
+
+#[derive(Clone)]
+struct Slot {
+  application_id: String,
+  payload: String,
+  pid: u64,
+  socket_id: String,
+  updated: Instant,
+}
+
+// (source, socket id)
+type SlotKey = (ActivitySource, String);
+
+/**
+ * Keeps the latest activity of every source/connection, and decides which one is shown. Sources earlier in `priority` win, ties go to whatever was updated last.
+ */
+#[derive(Clone)]
+pub struct ActivityArbiter {
+  priority: Vec<ActivitySource>,
+  slots: Arc<Mutex<HashMap<SlotKey, Slot>>>,
+  current: Arc<Mutex<Option<SlotKey>>>,
+}
+
+impl ActivityArbiter {
+  pub fn new(priority: Vec<ActivitySource>) -> Self {
+    Self {
+      priority,
+      slots: Arc::new(Mutex::new(HashMap::new())),
+      current: Arc::new(Mutex::new(None)),
+    }
+  }
+
+  pub fn set(
+    &self,
+    source: ActivitySource,
+    application_id: &str,
+    socket_id: String,
+    pid: u64,
+    payload: String,
+  ) -> Vec<Broadcast> {
+    let key = (source, socket_id.clone());
+    let mut slots = self.slots.lock().unwrap();
+
+    slots.insert(
+      key.clone(),
+      Slot {
+        application_id: application_id.to_string(),
+        payload,
+        pid,
+        socket_id,
+        updated: Instant::now(),
+      },
+    );
+
+    self.arbitrate(&slots, Some(&key))
+  }
+
+  pub fn clear(&self, source: ActivitySource, socket_id: &str) -> Vec<Broadcast> {
+    let key = (source, socket_id.to_string());
+    let mut slots = self.slots.lock().unwrap();
+
+    let Some(removed) = slots.remove(&key) else {
+      return vec![];
+    };
+
+    let mut current = self.current.lock().unwrap();
+
+    // Something else is shown, so nobody will notice
+    if current.as_ref() != Some(&key) {
+      return vec![];
+    }
+
+    *current = None;
+    drop(current);
+
+    let mut broadcasts = vec![Broadcast::Clear {
+      source,
+      application_id: removed.application_id,
+      pid: removed.pid,
+      socket_id: removed.socket_id,
+    }];
+    broadcasts.extend(self.arbitrate(&slots, None));
+
+    broadcasts
+  }
+
+  /**
+   * Pick the winning slot. `updated` is the slot that just changed, which needs to be sent again if it is still (or now) the winner.
+   */
+  fn arbitrate(&self, slots: &HashMap<SlotKey, Slot>, updated: Option<&SlotKey>) -> Vec<Broadcast> {
+    let winner = slots
+      .iter()
+      .min_by(|(a_key, a), (b_key, b)| {
+        self
+          .rank(a_key.0)
+          .cmp(&self.rank(b_key.0))
+          .then(b.updated.cmp(&a.updated))
+      })
+      .map(|(key, slot)| (key.clone(), slot.clone()));
+
+    let Some((winner_key, winner)) = winner else {
+      return vec![];
+    };
+
+    let mut current = self.current.lock().unwrap();
+    let mut broadcasts = vec![];
+
+    if current.as_ref() == Some(&winner_key) && updated != Some(&winner_key) {
+      return broadcasts;
+    }
+
+    if let Some(previous) = current.as_ref().filter(|previous| **previous != winner_key) {
+      if let Some(slot) = slots.get(previous) {
+        broadcasts.push(Broadcast::Clear {
+          source: previous.0,
+          application_id: slot.application_id.clone(),
+          pid: slot.pid,
+          socket_id: slot.socket_id.clone(),
+        });
+      }
+    }
+
+    broadcasts.push(Broadcast::Activity {
+      source: winner_key.0,
+      application_id: winner.application_id,
+      socket_id: winner.socket_id,
+      payload: winner.payload,
+    });
+    *current = Some(winner_key);
+
+    broadcasts
+  }
+
+  fn rank(&self, source: ActivitySource) -> usize {
+    self
+      .priority
+      .iter()
+      .position(|s| *s == source)
+      .unwrap_or(self.priority.len())
+  }
+}
+
+impl Default for ActivityArbiter {
+  fn default() -> Self {
+    Self::new(vec![
+      ActivitySource::Api,
+      ActivitySource::Ipc,
+      ActivitySource::Websocket,
+      ActivitySource::Process,
+    ])
+  }
+}
@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  path::PathBuf,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use crate::{cmd::Activity, log};
+
+// How long cached asset lists on disk are considered fresh
+const CACHE_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApplicationAsset {
+  pub id: String,
+  pub name: String,
+  #[serde(default)]
+  pub r#type: u32,
+}
+
+/**
+ * Resolves asset keys (the names set in the developer portal) to asset IDs, which is what clients actually render
+ */
+#[derive(Clone)]
+pub struct AssetResolver {
+  // application id -> (asset name -> asset id)
+  cache: Arc<Mutex<HashMap<String, HashMap<String, String>>>>,
+  cache_dir: Option<PathBuf>,
+}
+
+impl Default for AssetResolver {
+  fn default() -> Self {
+    Self::new(dirs::cache_dir().map(|dir| dir.join("rsrpc").join("assets")))
+  }
+}
+
+impl AssetResolver {
+  pub fn new(cache_dir: Option<PathBuf>) -> Self {
+    Self {
+      cache: Arc::new(Mutex::new(HashMap::new())),
+      cache_dir,
+    }
+  }
+
+  /**
+   * Rewrite the asset keys of an activity to asset IDs, if they can be resolved
+   */
+  pub fn resolve(&self, application_id: &str, activity: &mut Activity) {
+    let Some(assets) = activity.assets.as_mut() else {
+      return;
+    };
+
+    // Nothing to do
+    if !needs_resolving(&assets.large_image) && !needs_resolving(&assets.small_image) {
+      return;
+    }
+
+    let known = self.assets_for(application_id);
+
+    for key in [&mut assets.large_image, &mut assets.small_image] {
+      if !needs_resolving(key) {
+        continue;
+      }
+
+      if let Some(id) = key.as_ref().and_then(|name| known.get(name)) {
+        *key = Some(id.clone());
+      }
+    }
+  }
+
+  fn assets_for(&self, application_id: &str) -> HashMap<String, String> {
+    if let Some(assets) = self.cache.lock().unwrap().get(application_id) {
+      return assets.clone();
+    }
+
+    let list = match self.read_disk_cache(application_id) {
+      Some(list) => list,
+      None => match fetch_assets(application_id) {
+        Ok(list) => {
+          self.write_disk_cache(application_id, &list);
+          list
+        }
+        Err(err) => {
+          log!(
+            warn,
+            "[Assets] Failed to fetch assets for {}: {}",
+            application_id,
+            err
+          );
+          vec![]
+        }
+      },
+    };
+
+    let assets: HashMap<String, String> = list
+      .into_iter()
+      .map(|asset| (asset.name, asset.id))
+      .collect();
+
+    self
+      .cache
+      .lock()
+      .unwrap()
+      .insert(application_id.to_string(), assets.clone());
+
+    assets
+  }
+
+  fn cache_file(&self, application_id: &str) -> Option<PathBuf> {
+    // Application IDs are snowflakes, anything else shouldn't end up in a path
+    if !application_id.chars().all(|c| c.is_ascii_digit()) {
+      return None;
+    }
+
+    self
+      .cache_dir
+      .as_ref()
+      .map(|dir| dir.join(format!("{}.json", application_id)))
+  }
+
+  fn read_disk_cache(&self, application_id: &str) -> Option<Vec<ApplicationAsset>> {
+    let file = self.cache_file(application_id)?;
+    let age = std::fs::metadata(&file)
+      .ok()?
+      .modified()
+      .ok()?
+      .elapsed()
+      .ok()?;
+
+    if age > CACHE_MAX_AGE {
+      return None;
+    }
+
+    let contents = std::fs::read_to_string(&file).ok()?;
+    serde_json::from_str(&contents).ok()
+  }
+
+  fn write_disk_cache(&self, application_id: &str, list: &[ApplicationAsset]) {
+    let Some(file) = self.cache_file(application_id) else {
+      return;
+    };
+
+    if let Some(dir) = file.parent() {
+      std::fs::create_dir_all(dir)
+        .unwrap_or_else(|e| log!(warn, "[Assets] Error creating cache: {}", e));
+    }
+
+    match serde_json::to_string(list) {
+      Ok(contents) => std::fs::write(&file, contents)
+        .unwrap_or_else(|e| log!(warn, "[Assets] Error writing cache: {}", e)),
+      Err(err) => log!(error, "[Assets] Error serializing assets: {}", err),
+    }
+  }
+}
+
+fn fetch_assets(application_id: &str) -> Result<Vec<ApplicationAsset>, Box<dyn std::error::Error>> {
+  let url = format!(
+    "https://discord.com/api/v9/oauth2/applications/{}/assets",
+    application_id
+  );
+  let body = reqwest::blocking::get(url)?.error_for_status()?.text()?;
+
+  Ok(serde_json::from_str(&body)?)
+}
+
+/**
+ * Convert raw image URLs to the media proxy format, eg. https://example.com/image.png -> mp:external/https/example.com/image.png
+ */
+pub fn convert_external_assets(activity: &mut Activity) {
+  let Some(assets) = activity.assets.as_mut() else {
+    return;
+  };
+
+  for key in [&mut assets.large_image, &mut assets.small_image] {
+    if let Some(url) = key.as_ref().and_then(|url| external_asset_key(url)) {
+      *key = Some(url);
+    }
+  }
+}
+
+fn external_asset_key(url: &str) -> Option<String> {
+  for scheme in ["https", "http"] {
+    if let Some(rest) = url.strip_prefix(&format!("{}://", scheme)) {
+      return Some(format!("mp:external/{}/{}", scheme, rest));
+    }
+  }
+
+  None
+}
+
+/**
+ * Asset IDs, media proxy URLs, etc. can be used as-is
+ */
+fn needs_resolving(key: &Option<String>) -> bool {
+  match key {
+    Some(key) => !key.is_empty() && !key.chars().all(|c| c.is_ascii_digit()) && !key.contains(':'),
+    None => false,
+  }
+}
@@ -1,43 +1,424 @@
+#![cfg_attr(not(feature = "client-connector"), allow(dead_code))]
+
+use std::{
+  collections::{HashMap, HashSet},
+  net::IpAddr,
+  sync::{mpsc, Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+#[cfg(feature = "client-connector")]
 use std::{
-  collections::HashMap,
-  sync::{Arc, Mutex},
+  error::Error,
+  net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener},
 };
 
-use simple_websockets::{Event, EventHub, Message, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
+#[cfg(feature = "client-connector")]
+use crate::{cmd::RPC_CLOSE_INVALID_ORIGIN, url_params::get_url_params};
 use crate::{
-  cmd::{ActivityCmd, ActivityPayload},
-  log,
+  cmd::{
+    Activity, ActivityCmd, ActivityCmdArgs, ActivityPayload, ActivityRequestPayload, ActivityType,
+    Assets, InboundEvent, Metadata, TimeoutValue, Timestamps, RPC_ERROR_INVALID_COMMAND,
+    RPC_ERROR_INVALID_PAYLOAD,
+  },
+  detection::{DetectableActivity, PresenceOverride},
+  log, ActivityCallback, ConnectorCallback,
+};
+
+use super::{
+  arbitration::{ActivityArbiter, ActivitySource, Broadcast},
+  assets::{convert_external_assets, AssetResolver},
+  outgoing_queue::OutgoingQueue,
+  plugins::Plugins,
+  process_types::ProcessDetectedEvent,
+  rate_limit::ActivityRateLimiter,
+  rpc_clients::RpcClients,
+  runtime,
+  shutdown::Shutdown,
+  state::{ConnectorClientState, ShownActivity},
+  tls::TlsConfig,
+  utils::{origin_allowed, token_matches},
 };
+#[cfg(feature = "client-connector")]
+use super::{
+  shutdown::POLL_INTERVAL,
+  tls,
+  utils::launch_websocket_server,
+  ws::{self, Event, EventHub, Message, Responder},
+};
+
+// Events connector clients are allowed to dispatch back to RPC clients
+static INBOUND_EVENTS: [&str; 4] = [
+  "ACTIVITY_JOIN",
+  "ACTIVITY_SPECTATE",
+  "ACTIVITY_JOIN_REQUEST",
+  "ACTIVITY_INVITE",
+];
+
+/**
+ * Serialize a forwarded (non-activity) command for the connector clients
+ */
+fn forwarded_payload(cmd: &ActivityCmd) -> String {
+  let payload = match cmd.cmd.as_str() {
+    "SEND_ACTIVITY_JOIN_INVITE" | "CLOSE_ACTIVITY_REQUEST" => {
+      serde_json::to_string(&ActivityRequestPayload {
+        cmd: cmd.cmd.clone(),
+        application_id: cmd.application_id.clone(),
+        user_id: cmd
+          .args
+          .as_ref()
+          .and_then(|args| args.user_id.clone())
+          .unwrap_or_default(),
+        socket_id: cmd.socket_id.clone(),
+      })
+    }
+    _ => serde_json::to_string(cmd),
+  };
+
+  payload.unwrap_or_default()
+}
+
+// What clients that didn't pass the token in the query string send first
+#[derive(Deserialize)]
+struct ConnectorAuth {
+  token: String,
+}
+
+// How long they have to send it before they are disconnected
+#[cfg(feature = "client-connector")]
+const TOKEN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The one socket activities set through RPCServer::set_activity() are shown as
+const API_SOCKET_ID: &str = "api";
+
+// Bumped whenever a change to what clients are sent, or can send, could break them
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/**
+ * The first message every client gets, eg. {"cmd": "HELLO", "protocol": 1, "version": "0.1.0", "capabilities": ["subscribe", ...], "ready": {...}}
+ */
+#[derive(Serialize)]
+struct Hello {
+  cmd: &'static str,
+  protocol: u32,
+  // The rsrpc version
+  version: &'static str,
+  // Which optional parts of the protocol this server supports
+  capabilities: Vec<&'static str>,
+  // The READY event, which used to be all clients got
+  ready: Value,
+}
+
+impl Hello {
+  fn new(ready: &str, options: &ConnectorOptions) -> Self {
+    let mut capabilities = vec![
+      "replay",
+      "subscribe",
+      "get_state",
+      "clear_activity",
+      "dispatch",
+    ];
+
+    if options.keepalive.is_some() {
+      capabilities.push("keepalive");
+    }
+
+    if options.token.is_some() {
+      capabilities.push("token");
+    }
+
+    Hello {
+      cmd: "HELLO",
+      protocol: PROTOCOL_VERSION,
+      version: env!("CARGO_PKG_VERSION"),
+      capabilities,
+      ready: serde_json::from_str(ready).unwrap_or(Value::Null),
+    }
+  }
+}
+
+// Sent to clients every `keepalive` interval, which they answer with {"cmd": "PONG"}
+static PING: &str = r#"{"cmd":"PING"}"#;
+
+/**
+ * Messages clients can send once connected. Any of them can have a `nonce`, which the response echoes.
+ */
+#[derive(Deserialize)]
+#[serde(tag = "cmd", rename_all = "SCREAMING_SNAKE_CASE")]
+enum ControlMessage {
+  // Answers a PING, nothing is sent back
+  Pong,
+  // Only get sent activities from some sources or applications, see `Subscription`
+  Subscribe(Subscription),
+  // Get the activities being shown, and the client's subscription
+  GetState,
+  // Stop showing an activity, or all of them if no socket id is given
+  ClearActivity {
+    #[serde(rename = "socketId")]
+    socket_id: Option<String>,
+  },
+  // An event for the RPC client that owns `socket_id`, as an answer to eg. an ACTIVITY_JOIN_REQUEST
+  Dispatch {
+    evt: String,
+    #[serde(rename = "socketId")]
+    socket_id: String,
+    #[serde(default)]
+    data: Option<Value>,
+  },
+  #[serde(other)]
+  Unknown,
+}
+
+#[derive(Serialize)]
+struct ConnectorState<'a> {
+  activities: Vec<Value>,
+  subscription: &'a Subscription,
+}
+
+/**
+ * A payload on its way to the connector clients, along with what it is about so they can filter on it
+ */
+#[derive(Clone, Debug)]
+pub struct Outgoing {
+  pub source: ActivitySource,
+  pub application_id: Option<String>,
+  // Set for activity updates, a client only needs the latest one for every socket
+  pub socket_id: Option<String>,
+  pub data: String,
+}
+
+/**
+ * What a client wants to be sent, eg. {"cmd": "SUBSCRIBE", "sources": ["process"]}. Fields that are left out don't filter anything.
+ */
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Subscription {
+  pub sources: Option<HashSet<ActivitySource>>,
+  pub application_ids: Option<HashSet<String>>,
+}
+
+impl Subscription {
+  pub fn matches(&self, outgoing: &Outgoing) -> bool {
+    let source_matches = self
+      .sources
+      .as_ref()
+      .map(|sources| sources.contains(&outgoing.source))
+      .unwrap_or(true);
+    let application_matches = self
+      .application_ids
+      .as_ref()
+      .map(|ids| {
+        outgoing
+          .application_id
+          .as_ref()
+          .map(|id| ids.contains(id))
+          .unwrap_or(false)
+      })
+      .unwrap_or(true);
+
+    source_matches && application_matches
+  }
+}
+
+/**
+ * An activity that was set, whether or not it won arbitration and is being shown
+ */
+#[derive(Clone, Debug)]
+pub struct SocketState {
+  pub source: ActivitySource,
+  pub pid: Option<u64>,
+  // The latest activity payload for the socket
+  pub payload: String,
+}
+
+/**
+ * Passed to `RPCServer::on_connector_client` whenever a client is let in or goes away
+ */
+#[derive(Clone, Debug)]
+pub enum ConnectorClientEvent {
+  Connected {
+    client_id: u64,
+    peer_address: Option<IpAddr>,
+  },
+  Disconnected {
+    client_id: u64,
+    peer_address: Option<IpAddr>,
+  },
+}
+
+/**
+ * Passed to `RPCServer::on_activity_set` whenever an activity is shown, and to `RPCServer::on_activity_cleared` when it goes away
+ */
+#[derive(Clone, Debug)]
+pub struct ActivityEvent {
+  pub source: ActivitySource,
+  pub application_id: String,
+  pub socket_id: String,
+  pub pid: Option<u64>,
+  // None when the activity was cleared
+  pub activity: Option<Activity>,
+}
+
+pub struct ConnectorClient {
+  #[cfg(feature = "client-connector")]
+  pub responder: Responder,
+  // Where the client connected from, None without the `client-connector` feature
+  pub peer_address: Option<IpAddr>,
+  // Everything sent to the client goes through here
+  pub queue: OutgoingQueue,
+  // When the client last sent anything
+  pub last_seen: Instant,
+  pub subscription: Subscription,
+}
 
-use super::process::ProcessDetectedEvent;
+impl ConnectorClient {
+  pub fn send(&self, key: Option<&str>, data: String) -> bool {
+    self.queue.push(key, data)
+  }
+
+  pub fn close(&self) {
+    self.queue.close();
+    #[cfg(feature = "client-connector")]
+    self.responder.close();
+  }
+}
 
 fn empty_activity(pid: u64, socket_id: String) -> String {
-  format!(
-    r#"
-    {{
-      "activity": null,
-      "pid": {},
-      "socketId": "{}"
-    }}
-  "#,
-    pid, socket_id
-  )
+  serde_json::to_string(&ActivityPayload {
+    activity: None,
+    pid: Some(pid),
+    socket_id: Some(socket_id),
+  })
+  .unwrap_or_default()
+}
+
+/**
+ * The activity shown for a detected game
+ */
+fn process_activity(detected: &DetectableActivity) -> ActivityPayload {
+  let start = detected
+    .timestamp
+    .as_deref()
+    .and_then(|timestamp| timestamp.parse().ok())
+    .unwrap_or_default();
+
+  let mut activity = Activity {
+    application_id: Some(detected.id.clone()),
+    name: Some(detected.name.clone()),
+    timestamps: Some(Timestamps {
+      start: Some(TimeoutValue(start)),
+      end: None,
+    }),
+    r#type: ActivityType::Playing as u32,
+    metadata: Some(Metadata::default()),
+    flags: Some(0),
+    ..Default::default()
+  };
+
+  if let Some(presence) = detected.presence.as_ref() {
+    let render = |template: &Option<String>| {
+      template
+        .as_deref()
+        .map(|template| PresenceOverride::render(template, detected))
+    };
+
+    if let Some(name) = render(&presence.name) {
+      activity.name = Some(name);
+    }
+
+    activity.details = render(&presence.details);
+    activity.state = render(&presence.state);
+
+    if presence.has_assets() {
+      activity.assets = Some(Assets {
+        large_image: presence.large_image.clone(),
+        large_text: render(&presence.large_text),
+        small_image: presence.small_image.clone(),
+        small_text: render(&presence.small_text),
+      });
+    }
+  }
+
+  ActivityPayload {
+    activity: Some(activity),
+    pid: Some(detected.pid.unwrap_or_default()),
+    socket_id: Some(detected.id.clone()),
+  }
+}
+
+#[derive(Clone)]
+pub struct ConnectorOptions {
+  pub asset_resolver: Option<AssetResolver>,
+  pub convert_external_assets: bool,
+  pub activity_rate_limit: Duration,
+  // Which source wins when several have an activity
+  pub activity_priority: Vec<ActivitySource>,
+  // Allowed on top of Discord and local pages
+  pub allowed_origins: Vec<String>,
+  // The address to listen on, the port is passed separately
+  pub bind_address: IpAddr,
+  // Tried in order when the port is taken
+  pub fallback_ports: Vec<u16>,
+  // Serve wss:// instead of ws://
+  pub tls: Option<TlsConfig>,
+  // Clients have to present this token, either as `?token=` or in their first message
+  pub token: Option<String>,
+  // Ping clients this often, and drop the ones that haven't sent anything in twice as long
+  pub keepalive: Option<Duration>,
+  // Frames held for a client that isn't keeping up, before old ones are dropped
+  pub queue_size: usize,
+  // Greet clients with the bare READY event instead of a HELLO, for clients written before the handshake
+  pub legacy_hello: bool,
+  // Show every detected game as its own activity, instead of arbitrating them with the other sources
+  pub broadcast_all_processes: bool,
+  // Rewrite every activity before it is sent
+  pub plugins: Plugins,
+  pub on_client: Option<Arc<Mutex<ConnectorCallback>>>,
+  pub on_activity_set: Option<Arc<Mutex<ActivityCallback>>>,
+  pub on_activity_cleared: Option<Arc<Mutex<ActivityCallback>>>,
 }
 
 #[derive(Clone)]
 pub struct ClientConnector {
+  // 0 without the `client-connector` feature, since nothing listens
   pub port: u16,
+  #[cfg(feature = "client-connector")]
   server: Arc<Mutex<EventHub>>,
-  pub clients: Arc<Mutex<HashMap<u64, Responder>>>,
-  data_on_connect: String,
+  // Who is behind the connections the TLS proxy forwards
+  #[cfg(feature = "client-connector")]
+  proxied: tls::ProxiedPeers,
+  pub clients: Arc<Mutex<HashMap<u64, ConnectorClient>>>,
+  // What clients are sent once they connect
+  hello: String,
 
-  pub last_pid: Arc<Mutex<Option<u64>>>,
-  pub active_socket: Arc<Mutex<Option<String>>>,
+  // socket id -> every activity currently set, so sockets come and go independently of each other
+  pub sockets: Arc<Mutex<HashMap<String, SocketState>>>,
+  // socket id -> the activity payload clients were last sent for it, replayed to new clients
+  shown: Arc<Mutex<HashMap<String, Outgoing>>>,
 
   pub ipc_event_rec: Arc<Mutex<std::sync::mpsc::Receiver<ActivityCmd>>>,
   pub proc_event_rec: Arc<Mutex<std::sync::mpsc::Receiver<ProcessDetectedEvent>>>,
   pub ws_event_rec: Arc<Mutex<std::sync::mpsc::Receiver<ActivityCmd>>>,
+
+  inbound_event_sender: mpsc::Sender<InboundEvent>,
+  inbound_event_rec: Arc<Mutex<mpsc::Receiver<InboundEvent>>>,
+  rpc_clients: RpcClients,
+  asset_resolver: Option<AssetResolver>,
+  convert_external_assets: bool,
+  rate_limiter: ActivityRateLimiter<Outgoing>,
+  arbiter: ActivityArbiter,
+  allowed_origins: Vec<String>,
+  token: Option<String>,
+  keepalive: Option<Duration>,
+  queue_size: usize,
+  broadcast_all_processes: bool,
+  plugins: Plugins,
+  on_client: Option<Arc<Mutex<ConnectorCallback>>>,
+  on_activity_set: Option<Arc<Mutex<ActivityCallback>>>,
+  on_activity_cleared: Option<Arc<Mutex<ActivityCallback>>>,
+  shutdown: Shutdown,
 }
 
 impl ClientConnector {
@@ -47,69 +428,153 @@ impl ClientConnector {
     ipc_event_rec: std::sync::mpsc::Receiver<ActivityCmd>,
     proc_event_rec: std::sync::mpsc::Receiver<ProcessDetectedEvent>,
     ws_event_rec: std::sync::mpsc::Receiver<ActivityCmd>,
-  ) -> ClientConnector {
-    ClientConnector {
-      server: Arc::new(Mutex::new(simple_websockets::launch(port).unwrap_or_else(
-        |_| {
-          log!("[Client Connector] Failed to launch websocket server, port may already be in use");
-          std::process::exit(1);
-        },
-      ))),
+    rpc_clients: RpcClients,
+    options: ConnectorOptions,
+  ) -> crate::Result<ClientConnector> {
+    let (inbound_event_sender, inbound_event_rec) = mpsc::channel();
+    let shutdown = Shutdown::default();
+    #[cfg(feature = "client-connector")]
+    let proxied = tls::ProxiedPeers::default();
+    #[cfg(feature = "client-connector")]
+    let (server, port) = Self::launch(
+      std::iter::once(port).chain(options.fallback_ports.iter().copied()),
+      &options,
+      &proxied,
+      &shutdown,
+    )
+    .map_err(|err| crate::Error::Listen(format!("client connector: {}", err)))?;
+    #[cfg(not(feature = "client-connector"))]
+    let port = {
+      log!(
+        "[Client Connector] Built without the client-connector feature, not listening on port {}",
+        port
+      );
+      0
+    };
+    let hello = if options.legacy_hello {
+      data_on_connect
+    } else {
+      serde_json::to_string(&Hello::new(&data_on_connect, &options))?
+    };
+
+    Ok(ClientConnector {
+      #[cfg(feature = "client-connector")]
+      server: Arc::new(Mutex::new(server)),
+      #[cfg(feature = "client-connector")]
+      proxied,
       clients: Arc::new(Mutex::new(HashMap::new())),
-      data_on_connect,
+      hello,
       port,
 
-      last_pid: Arc::new(Mutex::new(None)),
-      active_socket: Arc::new(Mutex::new(None)),
+      sockets: Arc::new(Mutex::new(HashMap::new())),
+      shown: Arc::new(Mutex::new(HashMap::new())),
 
       ipc_event_rec: Arc::new(Mutex::new(ipc_event_rec)),
       proc_event_rec: Arc::new(Mutex::new(proc_event_rec)),
       ws_event_rec: Arc::new(Mutex::new(ws_event_rec)),
-    }
+
+      inbound_event_sender,
+      inbound_event_rec: Arc::new(Mutex::new(inbound_event_rec)),
+      rpc_clients,
+      asset_resolver: options.asset_resolver,
+      convert_external_assets: options.convert_external_assets,
+      rate_limiter: ActivityRateLimiter::new(options.activity_rate_limit),
+      arbiter: ActivityArbiter::new(options.activity_priority),
+      allowed_origins: options.allowed_origins,
+      token: options.token,
+      keepalive: options.keepalive,
+      queue_size: options.queue_size,
+      broadcast_all_processes: options.broadcast_all_processes,
+      plugins: options.plugins,
+      on_client: options.on_client,
+      on_activity_set: options.on_activity_set,
+      on_activity_cleared: options.on_activity_cleared,
+      shutdown,
+    })
   }
 
-  pub fn start(&self) {
-    let clone = self.clone();
-    let clients_clone = self.clients.clone();
+  /**
+   * Start the websocket server on the first of `ports` that is free
+   */
+  #[cfg(feature = "client-connector")]
+  fn launch(
+    ports: impl Iterator<Item = u16>,
+    options: &ConnectorOptions,
+    proxied: &tls::ProxiedPeers,
+    shutdown: &Shutdown,
+  ) -> Result<(EventHub, u16), Box<dyn Error>> {
+    let mut last_err: Option<Box<dyn Error>> = None;
 
-    std::thread::spawn(move || {
-      loop {
-        match clone.server.lock().unwrap().poll_event() {
-          Event::Connect(client_id, responder) => {
-            log!("[Client Connector] Client {} connected", client_id);
-            // Send initial connection data
-            responder.send(Message::Text(clone.data_on_connect.clone()));
-            clients_clone.lock().unwrap().insert(client_id, responder);
-          }
-          Event::Disconnect(client_id) => {
-            clients_clone.lock().unwrap().remove(&client_id);
-          }
-          Event::Message(client_id, message) => {
-            log!(
-              "[Client Connector] Received message from client {}: {:?}",
-              client_id,
-              message
-            );
-            let responder = clients_clone.lock().unwrap();
-            let responder = responder.get(&client_id).unwrap();
-            responder.send(message);
-          }
+    for port in ports {
+      let address = SocketAddr::new(options.bind_address, port);
+      let server = match options.tls.as_ref() {
+        Some(tls) => Self::launch_tls(address, tls, proxied, shutdown),
+        None => launch_websocket_server(address),
+      };
+
+      match server {
+        Ok(server) => return Ok((server, port)),
+        Err(err) => {
+          log!(
+            warn,
+            "[Client Connector] Failed to listen on {}, port may already be in use: {}",
+            address,
+            err
+          );
+          last_err = Some(err);
         }
       }
-    });
+    }
+
+    Err(last_err.unwrap_or_else(|| "No port to listen on".into()))
+  }
+
+  /**
+   * Run the websocket server on a local port only, with TLS connections on `address` being forwarded to it
+   */
+  #[cfg(feature = "client-connector")]
+  fn launch_tls(
+    address: SocketAddr,
+    tls: &TlsConfig,
+    proxied: &tls::ProxiedPeers,
+    shutdown: &Shutdown,
+  ) -> Result<EventHub, Box<dyn Error>> {
+    // Same family as the public address, in case that is all the host has
+    let local: IpAddr = if address.is_ipv6() {
+      Ipv6Addr::LOCALHOST.into()
+    } else {
+      Ipv4Addr::LOCALHOST.into()
+    };
+    let listener = TcpListener::bind((local, 0))?;
+    let backend = listener.local_addr()?;
+    let server = ws::launch_from_listener(listener)?;
+
+    tls::serve(address, tls, backend, proxied.clone(), shutdown.clone())?;
+
+    Ok(server)
+  }
+
+  /**
+   * Start routing activities from the other connectors and the process scanner, and serving websocket clients if built with the `client-connector` feature
+   */
+  pub fn start(&self) {
+    #[cfg(feature = "client-connector")]
+    self.listen();
 
-   
     let mut ipc_clone = self.clone();
     let mut proc_clone = self.clone();
     let mut ws_clone = self.clone();
 
-    std::thread::spawn(move || {
-      loop {
-        let mut ipc_activity = ipc_clone.ipc_event_rec.lock().unwrap().recv().unwrap();
-
-        // if there are no client, skip
-        if ipc_clone.clients.lock().unwrap().len() == 0 {
-          log!("[Client Connector] No clients connected, skipping");
+    runtime::spawn(move || {
+      while let Some(mut ipc_activity) = ipc_clone.shutdown.recv(&ipc_clone.ipc_event_rec) {
+        if ipc_activity.cmd != "SET_ACTIVITY" {
+          log!(debug, "[Client Connector] Sending payload for IPC event");
+          ipc_clone.send(&Outgoing {
+            source: ActivitySource::Ipc,
+            socket_id: None,
+            application_id: ipc_activity.application_id.clone(),
+            data: forwarded_payload(&ipc_activity),
+          });
           continue;
         }
 
@@ -118,17 +583,21 @@ impl ClientConnector {
         let mut args = match ipc_activity.args {
           Some(args) => args,
           None => {
-            log!("[Client Connector] Invalid activity command, skipping");
+            log!(
+              warn,
+              "[Client Connector] Invalid activity command, skipping"
+            );
             continue;
           }
         };
 
+        let socket_id = ipc_activity
+          .socket_id
+          .clone()
+          .unwrap_or_else(|| args.pid.unwrap_or_default().to_string());
+
         if args.activity.is_none() {
-          let pid = args.pid.unwrap_or_default();
-          // Send empty payload
-          let payload = empty_activity(pid, pid.to_string());
-          log!("[Client Connector] Sending empty payload");
-          ipc_clone.send_data(payload);
+          ipc_clone.clear_activity(ActivitySource::Ipc, &socket_id);
           continue;
         }
 
@@ -136,126 +605,135 @@ impl ClientConnector {
 
         if let Some(activity) = activity {
           activity.application_id = ipc_activity.application_id;
+          ipc_clone.resolve_assets(activity);
+          ipc_clone.plugins.transform(activity);
 
           let payload = ActivityPayload {
             activity: Some(activity.clone()),
             pid: args.pid,
-            socket_id: Some(args.pid.unwrap_or(0).to_string()),
+            socket_id: Some(socket_id.clone()),
           };
 
           match serde_json::to_string(&payload) {
             Ok(payload) => {
               log!(
+                debug,
                 "[Client Connector] Sending payload for IPC activity: {:?}",
                 payload
               );
-              ipc_clone.send_data(payload)
+              ipc_clone.set_activity(
+                ActivitySource::Ipc,
+                activity.application_id.as_deref().unwrap_or_default(),
+                socket_id,
+                args.pid,
+                payload,
+              );
             }
-            Err(err) => log!("[Client Connector] Error serializing IPC activity: {}", err),
+            Err(err) => log!(
+              error,
+              "[Client Connector] Error serializing IPC activity: {}",
+              err
+            ),
           };
         } else {
-          log!("[Client Connector] Invalid activity command, skipping");
+          log!(
+            warn,
+            "[Client Connector] Invalid activity command, skipping"
+          );
         }
       }
     });
 
-    std::thread::spawn(move || {
-      loop {
-        let proc_event = proc_clone.proc_event_rec.lock().unwrap().recv().unwrap();
+    runtime::spawn(move || {
+      // Game id -> the presence override it was last sent with, so games whose details change (eg. the ROM an emulator runs) are sent again
+      let mut sent_presence: HashMap<String, Option<PresenceOverride>> = HashMap::new();
+
+      while let Some(proc_event) = proc_clone.shutdown.recv(&proc_clone.proc_event_rec) {
         let proc_activity = proc_event.activity;
 
-        // if there are no clients, skip
-        if proc_clone.clients.lock().unwrap().len() == 0 {
-          log!("[Client Connector] No clients connected, skipping");
-          continue;
+        // Games that aren't shown anymore, or nothing is running at all
+        for socket_id in proc_clone.sockets_from(ActivitySource::Process) {
+          if !proc_event.running.contains(&socket_id) {
+            proc_clone.clear_activity(ActivitySource::Process, &socket_id);
+          }
         }
 
         if proc_activity.id == "null" {
-          // If our last socket id is empty, skip
-          if proc_clone.active_socket.lock().unwrap().is_none() {
-            continue;
-          }
-          // Send an empty payload
-          log!("[Client Connector] Sending empty payload");
-          let payload = empty_activity(
-            (*proc_clone.last_pid.lock().unwrap()).unwrap_or_default(),
-            proc_clone.active_socket.lock().unwrap().clone().unwrap_or_default(),
-          );
-          proc_clone.send_data(payload);
-          *proc_clone.active_socket.lock().unwrap() = None;
           continue;
         }
 
-        // If the active socket is different from the current socket, send an empty payload for the old socket
-        if *proc_clone.active_socket.lock().unwrap() != Some(proc_activity.id.clone()) {
-          if proc_clone.active_socket.lock().unwrap().is_some() {
-            // Send an empty payload
-            log!("[Client Connector] Sending empty payload");
-            let payload = empty_activity(
-              (*proc_clone.last_pid.lock().unwrap()).unwrap_or_default(),
-              proc_clone.active_socket.lock().unwrap().clone().unwrap_or_default(),
-            );
-            proc_clone.send_data(payload);
-          }
-        } else {
+        let already_set = proc_clone
+          .sockets
+          .lock()
+          .unwrap()
+          .get(&proc_activity.id)
+          .map(|state| state.source == ActivitySource::Process)
+          .unwrap_or(false)
+          && sent_presence.get(&proc_activity.id) == Some(&proc_activity.presence);
+
+        if already_set {
           log!(
+            debug,
             "[Client Connector] Already sent payload for activity: {}",
             proc_activity.name
           );
           continue;
         }
 
-        let payload = format!(
-          // I don't even know what half of these fields are for yet
-          r#"
-          {{
-            "activity": {{
-              "application_id": "{}",
-              "name": "{}",
-              "timestamps": {{
-                "start": {}
-              }},
-              "type": 0,
-              "metadata": {{}},
-              "flags": 0
-            }},
-            "pid": {},
-            "socketId": "{}"
-          }}
-          "#,
-          proc_activity.id,
-          proc_activity.name,
-          proc_activity.timestamp.as_ref().unwrap_or(&"0".to_string()),
-          proc_activity.pid.unwrap_or_default(),
-          proc_activity.id
-        );
+        sent_presence.insert(proc_activity.id.clone(), proc_activity.presence.clone());
+        let mut payload = process_activity(&proc_activity);
+
+        // Asset keys from a presence override are resolved like the ones clients send
+        if let Some(activity) = payload
+          .activity
+          .as_mut()
+          .filter(|activity| activity.assets.is_some())
+        {
+          proc_clone.resolve_assets(activity);
+        }
+
+        if let Some(activity) = payload.activity.as_mut() {
+          proc_clone.plugins.transform(activity);
+        }
 
-        *proc_clone.last_pid.lock().unwrap() = proc_activity.pid;
-        *proc_clone.active_socket.lock().unwrap() = Some(proc_activity.id.clone());
+        let payload = match serde_json::to_string(&payload) {
+          Ok(payload) => payload,
+          Err(err) => {
+            log!(
+              error,
+              "[Client Connector] Error serializing process activity: {}",
+              err
+            );
+            continue;
+          }
+        };
 
         log!(
+          debug,
           "[Client Connector] Sending payload for activity: {}",
           proc_activity.name
         );
 
-        proc_clone.send_data(payload);
+        proc_clone.set_activity(
+          ActivitySource::Process,
+          &proc_activity.id,
+          proc_activity.id.clone(),
+          proc_activity.pid,
+          payload,
+        );
       }
     });
 
-    std::thread::spawn(move || {
-      loop {
-        let mut ws_event = ws_clone.ws_event_rec.lock().unwrap().recv().unwrap();
-
-        // if there are no clients, skip
-        if ws_clone.clients.lock().unwrap().len() == 0 {
-          log!("[Client Connector] No clients connected, skipping");
-          continue;
-        }
-
+    runtime::spawn(move || {
+      while let Some(mut ws_event) = ws_clone.shutdown.recv(&ws_clone.ws_event_rec) {
         if ws_event.cmd != "SET_ACTIVITY" {
-          let payload = serde_json::to_string(&ws_event).unwrap_or("".to_string());
-          log!("[Client Connector] Sending payload for WS event");
-          ws_clone.send_data(payload);
+          log!(debug, "[Client Connector] Sending payload for WS event");
+          ws_clone.send(&Outgoing {
+            source: ActivitySource::Websocket,
+            socket_id: None,
+            application_id: ws_event.application_id.clone(),
+            data: forwarded_payload(&ws_event),
+          });
           continue;
         }
 
@@ -264,16 +742,21 @@ impl ClientConnector {
         let mut args = match ws_event.args {
           Some(args) => args,
           None => {
-            log!("[Client Connector] Invalid activity command, skipping");
+            log!(
+              warn,
+              "[Client Connector] Invalid activity command, skipping"
+            );
             continue;
           }
         };
 
+        let socket_id = ws_event
+          .socket_id
+          .clone()
+          .unwrap_or_else(|| args.pid.unwrap_or_default().to_string());
+
         if args.activity.is_none() {
-          let pid = args.pid.unwrap_or_default();
-          let payload = empty_activity(pid, pid.to_string());
-          log!("[Client Connector] Sending empty payload");
-          ws_clone.send_data(payload);
+          ws_clone.clear_activity(ActivitySource::Websocket, &socket_id);
           continue;
         }
 
@@ -281,38 +764,892 @@ impl ClientConnector {
 
         if let Some(activity) = activity {
           activity.application_id = ws_event.application_id;
+          ws_clone.resolve_assets(activity);
+          ws_clone.plugins.transform(activity);
 
           let payload = ActivityPayload {
             activity: Some(activity.clone()),
             pid: args.pid,
-            socket_id: Some(args.pid.unwrap_or(0).to_string()),
+            socket_id: Some(socket_id.clone()),
           };
 
           match serde_json::to_string(&payload) {
             Ok(payload) => {
               log!(
-                "[Client Connector] Sending payload for IPC activity: {:?}",
+                debug,
+                "[Client Connector] Sending payload for websocket activity: {:?}",
                 payload
               );
-              ws_clone.send_data(payload)
+              ws_clone.set_activity(
+                ActivitySource::Websocket,
+                activity.application_id.as_deref().unwrap_or_default(),
+                socket_id,
+                args.pid,
+                payload,
+              );
             }
-            Err(err) => log!("[Client Connector] Error serializing IPC activity: {}", err),
+            Err(err) => log!(
+              error,
+              "[Client Connector] Error serializing websocket activity: {}",
+              err
+            ),
           };
         } else {
-          log!("[Client Connector] Invalid activity command, skipping");
+          log!(
+            warn,
+            "[Client Connector] Invalid activity command, skipping"
+          );
+        }
+      }
+    });
+  }
+
+  /**
+   * Accept websocket clients and handle their messages, send them the activity updates the rate limiter held back, and ping them if `keepalive` is set
+   */
+  #[cfg(feature = "client-connector")]
+  fn listen(&self) {
+    let clone = self.clone();
+    let clients_clone = self.clients.clone();
+
+    runtime::spawn(move || {
+      // Clients that still have to send their token, and since when
+      let mut pending: HashMap<u64, (Responder, Instant)> = HashMap::new();
+
+      while !clone.shutdown.is_stopped() {
+        pending.retain(|client_id, (responder, since)| {
+          if since.elapsed() < TOKEN_TIMEOUT {
+            return true;
+          }
+
+          log!(
+            warn,
+            "[Client Connector] Client {} didn't send a token in time, disconnecting it",
+            client_id
+          );
+          responder.close();
+          false
+        });
+
+        let Some(event) = clone.server.lock().unwrap().next_event() else {
+          std::thread::sleep(POLL_INTERVAL);
+          continue;
+        };
+
+        match event {
+          Event::Connect(client_id, responder) => {
+            let connection = responder.connection_details();
+            let origin = connection
+              .headers
+              .get("origin")
+              .map(|origin| origin.to_str().unwrap_or_default());
+
+            if !origin_allowed(origin, &clone.allowed_origins) {
+              log!(
+                warn,
+                "[Client Connector] Rejecting client {} from origin {:?}",
+                client_id,
+                origin
+              );
+              responder.close_with(RPC_CLOSE_INVALID_ORIGIN as u16, "Invalid Origin");
+              continue;
+            }
+
+            if let Some(ref token) = clone.token {
+              let url_params = get_url_params(connection.uri.clone());
+
+              match url_params.get("token") {
+                Some(given) if token_matches(given, token) => {}
+                Some(_) => {
+                  log!(
+                    warn,
+                    "[Client Connector] Rejecting client {}, invalid token",
+                    client_id
+                  );
+                  responder.close();
+                  continue;
+                }
+                None => {
+                  log!(
+                    "[Client Connector] Client {} connected, waiting for token",
+                    client_id
+                  );
+                  pending.insert(client_id, (responder, Instant::now()));
+                  continue;
+                }
+              }
+            }
+
+            clone.accept(client_id, responder);
+          }
+          Event::Disconnect(client_id) => {
+            pending.remove(&client_id);
+
+            let removed = clients_clone.lock().unwrap().remove(&client_id);
+
+            if let Some(client) = removed {
+              clone.disconnected(client_id, client.peer_address);
+            }
+          }
+          Event::Message(client_id, message) => {
+            if let Some((responder, _)) = pending.remove(&client_id) {
+              let authenticated = match (&message, clone.token.as_ref()) {
+                (Message::Text(text), Some(token)) => serde_json::from_str::<ConnectorAuth>(text)
+                  .map(|auth| token_matches(&auth.token, token))
+                  .unwrap_or(false),
+                _ => false,
+              };
+
+              if authenticated {
+                clone.accept(client_id, responder);
+              } else {
+                log!(
+                  warn,
+                  "[Client Connector] Rejecting client {}, invalid token",
+                  client_id
+                );
+                responder.close();
+              }
+
+              continue;
+            }
+
+            log!(
+              debug,
+              "[Client Connector] Received message from client {}: {:?}",
+              client_id,
+              message
+            );
+
+            if let Some(client) = clients_clone.lock().unwrap().get_mut(&client_id) {
+              client.last_seen = Instant::now();
+            }
+
+            let Message::Text(text) = message else {
+              clone.reply(
+                client_id,
+                &ActivityCmd::error(
+                  "DISPATCH",
+                  None,
+                  RPC_ERROR_INVALID_PAYLOAD,
+                  "Messages have to be JSON text",
+                ),
+              );
+              continue;
+            };
+
+            clone.handle_message(client_id, &text);
+          }
+        }
+      }
+    });
+
+    let inbound_clone = self.clone();
+
+    runtime::spawn(move || {
+      while let Some(event) = inbound_clone
+        .shutdown
+        .recv(&inbound_clone.inbound_event_rec)
+      {
+        let dispatch = ActivityCmd {
+          cmd: event.cmd,
+          evt: Some(event.evt.clone()),
+          data: event.data,
+          ..ActivityCmd::empty()
+        };
+
+        let payload = match serde_json::to_string(&dispatch) {
+          Ok(payload) => payload,
+          Err(err) => {
+            log!(
+              error,
+              "[Client Connector] Error serializing inbound event: {}",
+              err
+            );
+            continue;
+          }
+        };
+
+        if !inbound_clone
+          .rpc_clients
+          .dispatch(&event.socket_id, &event.evt, payload)
+        {
+          log!(
+            debug,
+            "[Client Connector] Could not dispatch event to socket {}, it may not be subscribed",
+            event.socket_id
+          );
+        }
+      }
+    });
+
+    let flush_clone = self.clone();
+
+    // Send activity updates that were held back by the rate limiter
+    runtime::spawn(move || {
+      while flush_clone.shutdown.sleep(Duration::from_millis(250)) {
+        for outgoing in flush_clone.rate_limiter.take_ready() {
+          log!(debug, "[Client Connector] Sending rate limited payload");
+          flush_clone.send(&outgoing);
+        }
+      }
+    });
+
+    if let Some(keepalive) = self.keepalive {
+      let keepalive_clone = self.clone();
+
+      runtime::spawn(move || {
+        while keepalive_clone.shutdown.sleep(keepalive) {
+          keepalive_clone.evict_stale(keepalive * 2);
+          keepalive_clone.send_data(PING.to_string());
+        }
+      });
+    }
+  }
+
+  /**
+   * Stop every worker thread, disconnect all clients and free the port
+   */
+  pub fn stop(&self) {
+    self.shutdown.stop();
+    #[cfg(feature = "client-connector")]
+    self.server.lock().unwrap().close();
+
+    let clients: Vec<(u64, ConnectorClient)> = self.clients.lock().unwrap().drain().collect();
+
+    // Reported without holding the clients lock, which the callback may want
+    for (client_id, client) in clients {
+      client.close();
+      self.disconnected(client_id, client.peer_address);
+    }
+  }
+
+  /**
+   * Disconnect clients that haven't sent anything (not even a PONG) within `timeout`
+   */
+  fn evict_stale(&self, timeout: Duration) {
+    let stale: Vec<(u64, ConnectorClient)> = {
+      let mut clients = self.clients.lock().unwrap();
+      let stale_ids: Vec<u64> = clients
+        .iter()
+        .filter(|(_, client)| client.last_seen.elapsed() >= timeout)
+        .map(|(client_id, _)| *client_id)
+        .collect();
+
+      stale_ids
+        .into_iter()
+        .filter_map(|client_id| clients.remove(&client_id).map(|client| (client_id, client)))
+        .collect()
+    };
+
+    // Closed without holding the clients lock, so broadcasts don't wait on them
+    for (client_id, client) in stale {
+      log!(
+        warn,
+        "[Client Connector] Client {} stopped responding, disconnecting it",
+        client_id
+      );
+      client.close();
+      self.disconnected(client_id, client.peer_address);
+    }
+  }
+
+  fn notify(&self, event: ConnectorClientEvent) {
+    if let Some(ref callback) = self.on_client {
+      callback.lock().unwrap()(event);
+    }
+  }
+
+  /**
+   * Report a client that was removed. Must not be called with the clients lock held, since the callback may take it.
+   */
+  fn disconnected(&self, client_id: u64, peer_address: Option<IpAddr>) {
+    log!("[Client Connector] Client {} disconnected", client_id);
+
+    self.notify(ConnectorClientEvent::Disconnected {
+      client_id,
+      peer_address,
+    });
+  }
+
+  /**
+   * Start sending activities to a client that is allowed to receive them
+   */
+  #[cfg(feature = "client-connector")]
+  fn accept(&self, client_id: u64, responder: Responder) {
+    log!("[Client Connector] Client {} connected", client_id);
+
+    // Headers like X-Forwarded-For are up to the client, so only the socket is trusted
+    let peer_address = self
+      .proxied
+      .resolve(responder.connection_details().peer_address)
+      .ip();
+
+    let queue = OutgoingQueue::new(self.queue_size);
+    let writer = responder.clone();
+    queue.start_writer(move |data| writer.send(Message::Text(data)));
+
+    let client = ConnectorClient {
+      responder,
+      peer_address: Some(peer_address),
+      queue,
+      last_seen: Instant::now(),
+      subscription: Subscription::default(),
+    };
+
+    // Send initial connection data
+    client.send(None, self.hello.clone());
+
+    // Catch it up on whatever is being shown right now. The clients lock is held until it is added,
+    // so nothing sent in between is missed (taken before shown, like subscribe does)
+    let mut clients = self.clients.lock().unwrap();
+
+    for outgoing in self.shown.lock().unwrap().values() {
+      client.send(outgoing.socket_id.as_deref(), outgoing.data.clone());
+    }
+
+    clients.insert(client_id, client);
+    drop(clients);
+
+    self.notify(ConnectorClientEvent::Connected {
+      client_id,
+      peer_address,
+    });
+  }
+
+  /**
+   * Handle a message from a connected client
+   */
+  fn handle_message(&self, client_id: u64, text: &str) {
+    let message = serde_json::from_str::<Value>(text).and_then(|value| {
+      let nonce = value
+        .get("nonce")
+        .and_then(|nonce| nonce.as_str())
+        .map(|nonce| nonce.to_string());
+      let cmd = value
+        .get("cmd")
+        .and_then(|cmd| cmd.as_str())
+        .unwrap_or_default()
+        .to_string();
+
+      serde_json::from_value::<ControlMessage>(value).map(|message| (cmd, nonce, message))
+    });
+
+    let (cmd, nonce, message) = match message {
+      Ok(message) => message,
+      Err(err) => {
+        log!(
+          warn,
+          "[Client Connector] Invalid message from client {}: {}",
+          client_id,
+          err
+        );
+        self.reply(
+          client_id,
+          &ActivityCmd::error("DISPATCH", None, RPC_ERROR_INVALID_PAYLOAD, err.to_string()),
+        );
+        return;
+      }
+    };
+
+    let response = |data: Option<Value>| ActivityCmd {
+      cmd: cmd.clone(),
+      data,
+      nonce: nonce.clone(),
+      ..ActivityCmd::empty()
+    };
+
+    match message {
+      ControlMessage::Pong => {}
+      ControlMessage::Subscribe(subscription) => {
+        self.subscribe(client_id, subscription);
+        self.reply(client_id, &response(None));
+      }
+      ControlMessage::GetState => {
+        let activities = self
+          .shown
+          .lock()
+          .unwrap()
+          .values()
+          .filter_map(|outgoing| serde_json::from_str(&outgoing.data).ok())
+          .collect();
+        let clients = self.clients.lock().unwrap();
+        let Some(client) = clients.get(&client_id) else {
+          return;
+        };
+        let state = serde_json::to_value(ConnectorState {
+          activities,
+          subscription: &client.subscription,
+        })
+        .ok();
+
+        client.send(
+          None,
+          serde_json::to_string(&response(state)).unwrap_or_default(),
+        );
+      }
+      ControlMessage::ClearActivity { socket_id } => {
+        let cleared: Vec<(ActivitySource, String)> = self
+          .sockets
+          .lock()
+          .unwrap()
+          .iter()
+          .filter(|(id, _)| {
+            socket_id
+              .as_ref()
+              .map(|socket_id| socket_id == *id)
+              .unwrap_or(true)
+          })
+          .map(|(id, state)| (state.source, id.clone()))
+          .collect();
+
+        log!(
+          "[Client Connector] Client {} cleared {} activities",
+          client_id,
+          cleared.len()
+        );
+
+        for (source, socket_id) in cleared {
+          self.clear_activity(source, &socket_id);
+        }
+
+        self.reply(client_id, &response(None));
+      }
+      ControlMessage::Dispatch {
+        evt,
+        socket_id,
+        data,
+      } => {
+        let event = InboundEvent {
+          cmd,
+          evt,
+          socket_id,
+          data,
+        };
+
+        let valid = if INBOUND_EVENTS.contains(&event.evt.as_str()) {
+          event.validate()
+        } else {
+          Err(format!("{} can't be dispatched", event.evt))
+        };
+
+        if let Err(err) = valid {
+          log!(
+            warn,
+            "[Client Connector] Dropping event from client {}: {}",
+            client_id,
+            err
+          );
+          self.reply(
+            client_id,
+            &ActivityCmd::error(&event.cmd, nonce, RPC_ERROR_INVALID_PAYLOAD, err),
+          );
+          return;
+        }
+
+        self
+          .inbound_event_sender
+          .send(event)
+          .unwrap_or_else(|e| log!(warn, "[Client Connector] Error queueing event: {}", e));
+      }
+      ControlMessage::Unknown => {
+        log!(
+          warn,
+          "[Client Connector] Unknown command from client {}: {}",
+          client_id,
+          cmd
+        );
+        self.reply(
+          client_id,
+          &ActivityCmd::error(&cmd, nonce, RPC_ERROR_INVALID_COMMAND, "Unknown command"),
+        );
+      }
+    }
+  }
+
+  /**
+   * Send a response to a single client
+   */
+  fn reply(&self, client_id: u64, response: &ActivityCmd) {
+    if let Some(client) = self.clients.lock().unwrap().get(&client_id) {
+      client.send(None, serde_json::to_string(response).unwrap_or_default());
+    }
+  }
+
+  /**
+   * Only send a client what it asked for from now on, and what is being shown that it hasn't gotten yet
+   */
+  fn subscribe(&self, client_id: u64, subscription: Subscription) {
+    let mut clients = self.clients.lock().unwrap();
+
+    let Some(client) = clients.get_mut(&client_id) else {
+      return;
+    };
+
+    log!(
+      debug,
+      "[Client Connector] Client {} subscribed to {:?}",
+      client_id,
+      subscription
+    );
+
+    for outgoing in self.shown.lock().unwrap().values() {
+      // Anything matching the old subscription was already sent
+      if subscription.matches(outgoing) && !client.subscription.matches(outgoing) {
+        client.send(outgoing.socket_id.as_deref(), outgoing.data.clone());
+      }
+    }
+
+    client.subscription = subscription;
+  }
+
+  /**
+   * Show an activity set from the host application, fixed up and resolved like the ones clients send
+   */
+  pub fn set_api_activity(&self, application_id: &str, activity: Activity) {
+    let mut cmd = ActivityCmd {
+      application_id: Some(application_id.to_string()),
+      cmd: "SET_ACTIVITY".to_string(),
+      args: Some(ActivityCmdArgs {
+        activity: Some(activity),
+        ..Default::default()
+      }),
+      ..ActivityCmd::empty()
+    };
+    cmd.fix();
+
+    let Some(mut activity) = cmd.args.and_then(|args| args.activity) else {
+      return;
+    };
+
+    activity.application_id = Some(application_id.to_string());
+    self.resolve_assets(&mut activity);
+    self.plugins.transform(&mut activity);
+
+    let payload = ActivityPayload {
+      activity: Some(activity),
+      pid: None,
+      socket_id: Some(API_SOCKET_ID.to_string()),
+    };
+
+    match serde_json::to_string(&payload) {
+      Ok(payload) => {
+        log!(
+          debug,
+          "[Client Connector] Sending payload for API activity: {:?}",
+          payload
+        );
+        self.set_activity(
+          ActivitySource::Api,
+          application_id,
+          API_SOCKET_ID.to_string(),
+          None,
+          payload,
+        );
+      }
+      Err(err) => log!(
+        error,
+        "[Client Connector] Error serializing API activity: {}",
+        err
+      ),
+    }
+  }
+
+  pub fn clear_api_activity(&self) {
+    self.clear_activity(ActivitySource::Api, API_SOCKET_ID);
+  }
+
+  pub fn client_states(&self) -> Vec<ConnectorClientState> {
+    let mut clients: Vec<ConnectorClientState> = self
+      .clients
+      .lock()
+      .unwrap()
+      .iter()
+      .map(|(client_id, client)| ConnectorClientState {
+        client_id: *client_id,
+        peer_address: client.peer_address,
+        subscription: client.subscription.clone(),
+        idle_secs: client.last_seen.elapsed().as_secs(),
+      })
+      .collect();
+
+    clients.sort_by_key(|client| client.client_id);
+    clients
+  }
+
+  /**
+   * The activity payloads new clients would be sent, parsed back into activities
+   */
+  pub fn shown_activities(&self) -> Vec<ShownActivity> {
+    let mut shown: Vec<ShownActivity> = self
+      .shown
+      .lock()
+      .unwrap()
+      .iter()
+      .map(|(socket_id, outgoing)| {
+        let payload = serde_json::from_str::<ActivityPayload>(&outgoing.data).ok();
+
+        ShownActivity {
+          socket_id: socket_id.clone(),
+          source: outgoing.source,
+          application_id: outgoing.application_id.clone(),
+          pid: payload.as_ref().and_then(|payload| payload.pid),
+          activity: payload.and_then(|payload| payload.activity),
+        }
+      })
+      .collect();
+
+    shown.sort_by(|a, b| a.socket_id.cmp(&b.socket_id));
+    shown
+  }
+
+  fn resolve_assets(&self, activity: &mut Activity) {
+    if self.convert_external_assets {
+      convert_external_assets(activity);
+    }
+
+    if let (Some(resolver), Some(application_id)) = (
+      self.asset_resolver.as_ref(),
+      activity.application_id.clone(),
+    ) {
+      resolver.resolve(&application_id, activity);
+    }
+  }
+
+  /**
+   * Record the latest activity of a socket, and show it if it wins arbitration
+   */
+  fn set_activity(
+    &self,
+    source: ActivitySource,
+    application_id: &str,
+    socket_id: String,
+    pid: Option<u64>,
+    payload: String,
+  ) {
+    let previous = self.sockets.lock().unwrap().insert(
+      socket_id.clone(),
+      SocketState {
+        source,
+        pid,
+        payload: payload.clone(),
+      },
+    );
+
+    // Clients like to send the same activity over and over, there's no need to pass that on
+    if let Some(previous) = previous {
+      if previous.source == source && previous.payload == payload {
+        log!(
+          debug,
+          "[Client Connector] Activity for socket {} did not change, skipping",
+          socket_id
+        );
+        return;
+      }
+    }
+
+    if self.bypasses_arbiter(source) {
+      self.broadcast(vec![Broadcast::Activity {
+        source,
+        application_id: application_id.to_string(),
+        socket_id,
+        payload,
+      }]);
+      return;
+    }
+
+    let broadcasts = self.arbiter.set(
+      source,
+      application_id,
+      socket_id,
+      pid.unwrap_or_default(),
+      payload,
+    );
+    self.broadcast(broadcasts);
+  }
+
+  /**
+   * Forget the activity of a socket, leaving the other sockets alone
+   */
+  fn clear_activity(&self, source: ActivitySource, socket_id: &str) {
+    let removed = {
+      let mut sockets = self.sockets.lock().unwrap();
+
+      if sockets.get(socket_id).map(|state| state.source) == Some(source) {
+        sockets.remove(socket_id)
+      } else {
+        None
+      }
+    };
+
+    if self.bypasses_arbiter(source) {
+      if let Some(removed) = removed {
+        // Process sockets are named after the application
+        self.broadcast(vec![Broadcast::Clear {
+          source,
+          application_id: socket_id.to_string(),
+          pid: removed.pid.unwrap_or_default(),
+          socket_id: socket_id.to_string(),
+        }]);
+      }
+      return;
+    }
+
+    let broadcasts = self.arbiter.clear(source, socket_id);
+    self.broadcast(broadcasts);
+  }
+
+  /**
+   * Whether activities from `source` are all shown, rather than competing for a single slot
+   */
+  fn bypasses_arbiter(&self, source: ActivitySource) -> bool {
+    source == ActivitySource::Process && self.broadcast_all_processes
+  }
+
+  /**
+   * The ids of the sockets `source` has an activity set for
+   */
+  fn sockets_from(&self, source: ActivitySource) -> Vec<String> {
+    self
+      .sockets
+      .lock()
+      .unwrap()
+      .iter()
+      .filter(|(_, state)| state.source == source)
+      .map(|(socket_id, _)| socket_id.clone())
+      .collect()
+  }
+
+  /**
+   * Send whatever the arbiter decided should be shown
+   */
+  fn broadcast(&self, broadcasts: Vec<Broadcast>) {
+    for broadcast in broadcasts {
+      match broadcast {
+        Broadcast::Activity {
+          source,
+          application_id,
+          socket_id,
+          payload,
+        } => {
+          if let Some(ref callback) = self.on_activity_set {
+            let shown = serde_json::from_str::<ActivityPayload>(&payload).ok();
+
+            callback.lock().unwrap()(ActivityEvent {
+              source,
+              application_id: application_id.clone(),
+              socket_id: socket_id.clone(),
+              pid: shown.as_ref().and_then(|shown| shown.pid),
+              activity: shown.and_then(|shown| shown.activity),
+            });
+          }
+
+          let outgoing = Outgoing {
+            source,
+            application_id: Some(application_id),
+            socket_id: Some(socket_id.clone()),
+            data: payload,
+          };
+
+          // Even if it is held back, it is the latest one new clients should see
+          self
+            .shown
+            .lock()
+            .unwrap()
+            .insert(socket_id, outgoing.clone());
+          self.send_activity(outgoing);
+        }
+        Broadcast::Clear {
+          source,
+          application_id,
+          pid,
+          socket_id,
+        } => {
+          log!(debug, "[Client Connector] Sending empty payload");
+
+          if let Some(ref callback) = self.on_activity_cleared {
+            callback.lock().unwrap()(ActivityEvent {
+              source,
+              application_id: application_id.clone(),
+              socket_id: socket_id.clone(),
+              pid: Some(pid),
+              activity: None,
+            });
+          }
+
+          self.shown.lock().unwrap().remove(&socket_id);
+          // Don't let a held back update bring it back
+          self.rate_limiter.clear(&application_id);
+          self.send(&Outgoing {
+            source,
+            application_id: Some(application_id),
+            socket_id: Some(socket_id.clone()),
+            data: empty_activity(pid, socket_id),
+          });
         }
       }
+    }
+  }
+
+  /**
+   * Send an activity update, unless the application is updating too often
+   */
+  fn send_activity(&self, outgoing: Outgoing) {
+    let application_id = outgoing.application_id.clone().unwrap_or_default();
+
+    match self.rate_limiter.submit(&application_id, outgoing) {
+      Some(outgoing) => self.send(&outgoing),
+      None => log!(
+        debug,
+        "[Client Connector] Rate limited activity update for {}",
+        application_id
+      ),
+    }
+  }
+
+  /**
+   * Send a payload to every client subscribed to it
+   */
+  pub fn send(&self, outgoing: &Outgoing) {
+    self.send_where(outgoing.socket_id.as_deref(), &outgoing.data, |client| {
+      client.subscription.matches(outgoing)
     });
   }
 
   pub fn send_data(&self, data: String) {
-    // Send data to all clients
-    for (_, responder) in self.clients.lock().unwrap().iter() {
-      responder.send(Message::Text(data.clone()));
+    self.send_where(None, &data, |_| true);
+  }
+
+  fn send_where(&self, key: Option<&str>, data: &str, filter: impl Fn(&ConnectorClient) -> bool) {
+    let mut removed = vec![];
+
+    // Queue data for all clients, forgetting the ones that are gone
+    self.clients.lock().unwrap().retain(|client_id, client| {
+      let alive = if filter(client) {
+        client.send(key, data.to_string())
+      } else {
+        !client.queue.is_closed()
+      };
+
+      if !alive {
+        log!(
+          warn,
+          "[Client Connector] Could not send to client {}, removing it",
+          client_id
+        );
+        removed.push((*client_id, client.peer_address));
+      }
+
+      alive
+    });
+
+    for (client_id, peer_address) in removed {
+      self.disconnected(client_id, peer_address);
     }
   }
 }
 
+#[cfg(feature = "client-connector")]
 impl Drop for ClientConnector {
   fn drop(&mut self) {
     drop(self.server.lock().unwrap());
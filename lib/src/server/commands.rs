@@ -0,0 +1,449 @@
+#![cfg_attr(not(any(feature = "ipc", feature = "rpc-websocket")), allow(dead_code))]
+
+use serde_json::{json, Value};
+use std::{
+  collections::HashMap,
+  sync::{mpsc, Arc, Mutex},
+};
+
+use crate::{
+  cmd::{
+    ActivityCmd, UserConfig, RPC_ERROR_INVALID_COMMAND, RPC_ERROR_INVALID_EVENT,
+    RPC_ERROR_INVALID_GUILD_TEMPLATE, RPC_ERROR_INVALID_INVITE, RPC_ERROR_INVALID_PAYLOAD,
+    RPC_ERROR_OAUTH2, RPC_ERROR_UNKNOWN,
+  },
+  log,
+};
+
+use super::{lobbies::LobbyManager, rpc_clients::RpcClients};
+
+#[derive(Clone, Debug, Default)]
+pub struct CommandConfig {
+  // Answer AUTHORIZE/AUTHENTICATE with fake codes and tokens
+  pub auth_stub: bool,
+  pub user: UserConfig,
+  // Returned by GET_VOICE_SETTINGS, and updated by SET_VOICE_SETTINGS
+  pub voice_settings: Value,
+  // Keep track of GameSDK lobbies, instead of telling games they aren't supported
+  pub lobbies: bool,
+}
+
+/**
+ * Shared between the IPC and websocket connectors, so both handle commands the same way
+ */
+#[derive(Clone, Default)]
+pub struct CommandHandler {
+  pub rpc_clients: RpcClients,
+  pub config: CommandConfig,
+
+  // application id -> devices from SET_CERTIFIED_DEVICES
+  certified_devices: Arc<Mutex<HashMap<String, Vec<Value>>>>,
+  voice_settings: Arc<Mutex<Value>>,
+  lobbies: LobbyManager,
+}
+
+impl CommandHandler {
+  pub fn new(rpc_clients: RpcClients, config: CommandConfig) -> Self {
+    Self {
+      rpc_clients,
+      voice_settings: Arc::new(Mutex::new(config.voice_settings.clone())),
+      config,
+      certified_devices: Arc::new(Mutex::new(HashMap::new())),
+      lobbies: LobbyManager::default(),
+    }
+  }
+
+  pub fn certified_devices(&self) -> HashMap<String, Vec<Value>> {
+    self.certified_devices.lock().unwrap().clone()
+  }
+
+  /**
+   * Handle commands that are answered directly to the RPC client instead of being broadcast. Returns the response if the command was handled.
+   */
+  pub fn handle(
+    &self,
+    event_sender: &mpsc::Sender<ActivityCmd>,
+    cmd: &ActivityCmd,
+  ) -> Option<ActivityCmd> {
+    match cmd.cmd.as_str() {
+      "SUBSCRIBE" | "UNSUBSCRIBE" => Some(handle_subscription(&self.rpc_clients, cmd)),
+      "INVITE_BROWSER" | "GUILD_TEMPLATE_BROWSER" => Some(handle_invite(event_sender, cmd)),
+      "DEEP_LINK" => Some(handle_deep_link(event_sender, cmd)),
+      "SEND_ACTIVITY_JOIN_INVITE" | "CLOSE_ACTIVITY_REQUEST" => {
+        Some(handle_activity_request(event_sender, cmd))
+      }
+      "AUTHORIZE" => Some(self.handle_authorize(cmd)),
+      "AUTHENTICATE" => Some(self.handle_authenticate(cmd)),
+      "SET_CERTIFIED_DEVICES" => Some(self.handle_certified_devices(cmd)),
+      "GET_VOICE_SETTINGS" => Some(response(
+        cmd,
+        Some(self.voice_settings.lock().unwrap().clone()),
+      )),
+      "SET_VOICE_SETTINGS" => Some(self.handle_set_voice_settings(cmd)),
+      // We are never in a voice channel
+      "GET_SELECTED_VOICE_CHANNEL" => Some(response(cmd, Some(Value::Null))),
+      "CREATE_LOBBY"
+      | "UPDATE_LOBBY"
+      | "DELETE_LOBBY"
+      | "CONNECT_TO_LOBBY"
+      | "DISCONNECT_FROM_LOBBY"
+      | "SEND_TO_LOBBY"
+      | "SEARCH_LOBBIES"
+      | "UPDATE_LOBBY_MEMBER"
+      | "CONNECT_TO_LOBBY_VOICE"
+      | "DISCONNECT_FROM_LOBBY_VOICE" => Some(self.handle_lobby(cmd)),
+      _ => None,
+    }
+  }
+
+  /**
+   * Hardware SDKs (Logitech, SteelSeries, etc.) report their devices here. We have no use for them, but they expect an answer.
+   */
+  fn handle_certified_devices(&self, cmd: &ActivityCmd) -> ActivityCmd {
+    let Some(devices) = cmd.args.as_ref().and_then(|args| args.devices.clone()) else {
+      return ActivityCmd::error(
+        &cmd.cmd,
+        cmd.nonce.clone(),
+        RPC_ERROR_INVALID_PAYLOAD,
+        "No devices provided",
+      );
+    };
+
+    log!(
+      "[RPC] {} certified device(s) set by {}",
+      devices.len(),
+      cmd
+        .application_id
+        .as_deref()
+        .unwrap_or("unknown application")
+    );
+
+    self
+      .certified_devices
+      .lock()
+      .unwrap()
+      .insert(cmd.application_id.clone().unwrap_or_default(), devices);
+
+    response(cmd, None)
+  }
+
+  fn handle_set_voice_settings(&self, cmd: &ActivityCmd) -> ActivityCmd {
+    let mut voice_settings = self.voice_settings.lock().unwrap();
+
+    if let Some(args) = cmd.args.as_ref() {
+      let changes = Value::Object(args.extra.clone().into_iter().collect());
+      merge_json(&mut voice_settings, &changes);
+    }
+
+    response(cmd, Some(voice_settings.clone()))
+  }
+
+  fn handle_lobby(&self, cmd: &ActivityCmd) -> ActivityCmd {
+    if !self.config.lobbies {
+      return ActivityCmd::error(
+        &cmd.cmd,
+        cmd.nonce.clone(),
+        RPC_ERROR_INVALID_COMMAND,
+        "Lobbies are not supported",
+      );
+    }
+
+    let mut args = cmd.args.clone().unwrap_or_default();
+
+    // `type` is modeled for DEEP_LINK, lobbies need it back
+    if let Some(lobby_type) = args.r#type.take() {
+      args.extra.insert("type".to_string(), lobby_type);
+    }
+
+    let result = self.lobbies.handle(
+      &cmd.cmd,
+      cmd.application_id.as_deref().unwrap_or_default(),
+      &self.config.user.id,
+      &args.extra,
+    );
+
+    match result {
+      Ok(data) => response(cmd, Some(data)),
+      Err((code, message)) => ActivityCmd::error(&cmd.cmd, cmd.nonce.clone(), code, message),
+    }
+  }
+
+  fn handle_authorize(&self, cmd: &ActivityCmd) -> ActivityCmd {
+    if !self.config.auth_stub {
+      return oauth_unsupported(cmd);
+    }
+
+    response(cmd, Some(json!({ "code": fake_secret() })))
+  }
+
+  fn handle_authenticate(&self, cmd: &ActivityCmd) -> ActivityCmd {
+    if !self.config.auth_stub {
+      return oauth_unsupported(cmd);
+    }
+
+    let args = cmd.args.clone().unwrap_or_default();
+    let application_id = cmd
+      .application_id
+      .clone()
+      .or(args.client_id)
+      .unwrap_or_default();
+
+    response(
+      cmd,
+      Some(json!({
+        "application": {
+          "id": application_id,
+          "name": "",
+          "description": "",
+          "icon": null,
+          "rpc_origins": [],
+        },
+        "user": self.config.user,
+        "scopes": args.scopes.unwrap_or_else(|| vec!["rpc".to_string(), "identify".to_string()]),
+        "expires": (chrono::Utc::now() + chrono::Duration::days(7)).to_rfc3339(),
+        "access_token": args.access_token.unwrap_or_else(fake_secret),
+      })),
+    )
+  }
+}
+
+/**
+ * Create the response frame for a command, echoing its nonce
+ */
+pub fn response(cmd: &ActivityCmd, data: Option<Value>) -> ActivityCmd {
+  ActivityCmd {
+    cmd: cmd.cmd.clone(),
+    data,
+    nonce: cmd.nonce.clone(),
+    ..ActivityCmd::empty()
+  }
+}
+
+/**
+ * Respond to SET_ACTIVITY with the activity that was set (or nothing, if it was cleared)
+ */
+pub fn activity_response(cmd: &ActivityCmd) -> ActivityCmd {
+  let activity = cmd
+    .args
+    .as_ref()
+    .and_then(|args| args.activity.clone())
+    .map(|mut activity| {
+      activity.application_id = cmd.application_id.clone();
+      activity
+    });
+
+  response(
+    cmd,
+    activity.and_then(|activity| serde_json::to_value(activity).ok()),
+  )
+}
+
+pub fn unknown_command(cmd: &ActivityCmd) -> ActivityCmd {
+  ActivityCmd::error(
+    &cmd.cmd,
+    cmd.nonce.clone(),
+    RPC_ERROR_INVALID_COMMAND,
+    format!("Unknown command: {}", cmd.cmd),
+  )
+}
+
+fn handle_subscription(rpc_clients: &RpcClients, cmd: &ActivityCmd) -> ActivityCmd {
+  let Some(evt) = cmd.evt.as_ref() else {
+    return ActivityCmd::error(
+      &cmd.cmd,
+      cmd.nonce.clone(),
+      RPC_ERROR_INVALID_EVENT,
+      "No event provided",
+    );
+  };
+
+  let socket_id = cmd.socket_id.as_deref().unwrap_or_default();
+  let success = if cmd.cmd == "SUBSCRIBE" {
+    rpc_clients.subscribe(socket_id, evt)
+  } else {
+    rpc_clients.unsubscribe(socket_id, evt)
+  };
+
+  if !success {
+    return ActivityCmd::error(
+      &cmd.cmd,
+      cmd.nonce.clone(),
+      RPC_ERROR_INVALID_PAYLOAD,
+      "Client is not ready",
+    );
+  }
+
+  log!(debug, "[RPC] Socket {} {} {}", socket_id, cmd.cmd, evt);
+
+  response(cmd, Some(json!({ "evt": evt })))
+}
+
+/**
+ * Forward invites (and guild templates) to the connector clients, so they can be opened
+ */
+fn handle_invite(event_sender: &mpsc::Sender<ActivityCmd>, cmd: &ActivityCmd) -> ActivityCmd {
+  let error_code = if cmd.cmd == "INVITE_BROWSER" {
+    RPC_ERROR_INVALID_INVITE
+  } else {
+    RPC_ERROR_INVALID_GUILD_TEMPLATE
+  };
+  let raw_code = cmd
+    .args
+    .as_ref()
+    .and_then(|args| args.code.clone())
+    .unwrap_or_default();
+
+  let Some(code) = invite_code(&raw_code) else {
+    return ActivityCmd::error(
+      &cmd.cmd,
+      cmd.nonce.clone(),
+      error_code,
+      format!("Invalid invite id: {}", raw_code),
+    );
+  };
+
+  let mut forwarded = cmd.clone();
+
+  if let Some(args) = forwarded.args.as_mut() {
+    args.code = Some(code.clone());
+  }
+
+  if let Err(err) = event_sender.send(forwarded) {
+    log!(warn, "[RPC] Error forwarding {}: {}", cmd.cmd, err);
+
+    return ActivityCmd::error(
+      &cmd.cmd,
+      cmd.nonce.clone(),
+      RPC_ERROR_UNKNOWN,
+      "Could not open invite",
+    );
+  }
+
+  response(cmd, Some(json!({ "code": code })))
+}
+
+/**
+ * Forward deep links (eg. opening a channel or settings page) to the connector clients
+ */
+fn handle_deep_link(event_sender: &mpsc::Sender<ActivityCmd>, cmd: &ActivityCmd) -> ActivityCmd {
+  let link_type = cmd
+    .args
+    .as_ref()
+    .and_then(|args| args.r#type.as_ref())
+    .and_then(Value::as_str);
+
+  if link_type
+    .map(|link_type| link_type.is_empty())
+    .unwrap_or(true)
+  {
+    return ActivityCmd::error(
+      &cmd.cmd,
+      cmd.nonce.clone(),
+      RPC_ERROR_INVALID_PAYLOAD,
+      "No deep link type provided",
+    );
+  }
+
+  if let Err(err) = event_sender.send(cmd.clone()) {
+    log!(warn, "[RPC] Error forwarding {}: {}", cmd.cmd, err);
+
+    return ActivityCmd::error(
+      &cmd.cmd,
+      cmd.nonce.clone(),
+      RPC_ERROR_UNKNOWN,
+      "Could not open deep link",
+    );
+  }
+
+  response(cmd, None)
+}
+
+/**
+ * Forward answers to join requests to the connector clients, which know who actually asked
+ */
+fn handle_activity_request(
+  event_sender: &mpsc::Sender<ActivityCmd>,
+  cmd: &ActivityCmd,
+) -> ActivityCmd {
+  let has_user = cmd
+    .args
+    .as_ref()
+    .and_then(|args| args.user_id.as_ref())
+    .map(|user_id| !user_id.is_empty())
+    .unwrap_or(false);
+
+  if !has_user {
+    return ActivityCmd::error(
+      &cmd.cmd,
+      cmd.nonce.clone(),
+      RPC_ERROR_INVALID_PAYLOAD,
+      "No user id provided",
+    );
+  }
+
+  if let Err(err) = event_sender.send(cmd.clone()) {
+    log!(warn, "[RPC] Error forwarding {}: {}", cmd.cmd, err);
+
+    return ActivityCmd::error(
+      &cmd.cmd,
+      cmd.nonce.clone(),
+      RPC_ERROR_UNKNOWN,
+      "Could not answer activity request",
+    );
+  }
+
+  response(cmd, None)
+}
+
+/**
+ * Get the bare code out of an invite, which may also be a full link (eg. https://discord.gg/abc)
+ */
+fn invite_code(invite: &str) -> Option<String> {
+  let code = invite.trim().trim_end_matches('/').rsplit('/').next()?;
+
+  if code.is_empty() || !code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+    return None;
+  }
+
+  Some(code.to_string())
+}
+
+fn oauth_unsupported(cmd: &ActivityCmd) -> ActivityCmd {
+  ActivityCmd::error(
+    &cmd.cmd,
+    cmd.nonce.clone(),
+    RPC_ERROR_OAUTH2,
+    "OAuth2 is not supported",
+  )
+}
+
+/**
+ * Something that looks enough like an OAuth2 code or token. It is never checked by anything.
+ */
+fn fake_secret() -> String {
+  let nanos = std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_nanos();
+
+  format!("{:032x}", nanos)
+}
+
+/**
+ * Recursively merge `value` into `target`, keeping whatever `value` doesn't overwrite
+ */
+fn merge_json(target: &mut Value, value: &Value) {
+  match (target.as_object_mut(), value.as_object()) {
+    (Some(target), Some(value)) => {
+      for (key, value) in value {
+        match target.get_mut(key) {
+          Some(existing) => merge_json(existing, value),
+          None => {
+            target.insert(key.clone(), value.clone());
+          }
+        }
+      }
+    }
+    _ => *target = value.clone(),
+  }
+}
@@ -0,0 +1,72 @@
+use std::{
+  collections::HashSet,
+  net::IpAddr,
+  path::PathBuf,
+  sync::{mpsc, Arc},
+  time::Duration,
+};
+
+use super::{commands::CommandHandler, rpc_clients::RpcClients, shutdown::Shutdown};
+use crate::cmd::ActivityCmd;
+
+pub trait IpcFacilitator {
+  fn start(&mut self);
+
+  /**
+   * Stop accepting connections, disconnect clients and remove the sockets
+   */
+  fn stop(&mut self);
+
+  fn shutdown(&self) -> Shutdown;
+
+  fn event_sender(&mut self) -> &mut mpsc::Sender<ActivityCmd>;
+
+  fn rpc_clients(&self) -> RpcClients;
+
+  fn commands(&self) -> CommandHandler;
+
+  fn options(&self) -> IpcOptions;
+}
+
+#[derive(Clone, Debug)]
+pub struct IpcOptions {
+  // How many of the <base_name>-N sockets to bind
+  pub socket_count: u8,
+  // What the sockets are called before their index, "discord-ipc" for the ones clients look for
+  pub base_name: String,
+  // Where to create the sockets instead of the platform default (not used on Windows)
+  #[cfg_attr(target_os = "windows", allow(dead_code))]
+  pub socket_dir: Option<PathBuf>,
+  // Also make the sockets visible where Flatpak and Snap apps look for them (Linux only)
+  #[cfg_attr(target_os = "windows", allow(dead_code))]
+  pub sandbox_links: bool,
+  // Listeners per named pipe (Windows only)
+  #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+  pub pipe_instances: u8,
+  // Grant Authenticated Users read and write on the pipes (Windows only)
+  #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+  pub pipe_all_users: bool,
+  // Frames with more data than this close the connection
+  pub max_frame_size: usize,
+  // Only accept handshakes from these applications, if set
+  pub known_client_ids: Option<Arc<HashSet<String>>>,
+  // Connections sending more than this many frames per `message_window` are dropped, zero to disable
+  pub message_limit: u32,
+  pub message_window: Duration,
+}
+
+// Where Discord's local RPC server can be found, clients probe these in order
+pub const RPC_PORT_RANGE: std::ops::RangeInclusive<u16> = 6463..=6472;
+
+#[derive(Clone, Debug)]
+pub struct WebsocketOptions {
+  // The first port tried, `RPC_PORT_RANGE` is probed from its start
+  pub base_port: u16,
+  // How many ports of the 10 from `base_port` to listen on
+  pub port_count: u8,
+  pub bind_address: IpAddr,
+  // Allowed on top of Discord and local pages
+  pub allowed_origins: Vec<String>,
+  // Serve on the sockets systemd passed in instead, if there are any
+  pub socket_activation: bool,
+}
@@ -0,0 +1,213 @@
+use serde_json::Value;
+use std::{
+  path::Path,
+  sync::{Arc, Mutex},
+};
+
+use super::{shutdown::Shutdown, state::ServerState};
+use crate::{ReloadCallback, Result};
+
+/**
+ * Answer commands on the control socket `name` until stopped. Clients write a command on a line of its own and get a line of JSON back:
+ * `status` is answered with the ServerState, and `reload` calls `reload` if the host set it.
+ */
+pub fn serve(
+  socket_dir: Option<&Path>,
+  name: &str,
+  shutdown: Shutdown,
+  state: impl Fn() -> ServerState + Send + Sync + 'static,
+  reload: Option<Arc<Mutex<ReloadCallback>>>,
+) -> Result<()> {
+  platform::serve(socket_dir, name, shutdown, state, reload)
+}
+
+/**
+ * Send `command` to the control socket of a running server, and return its answer. Fails if no server is listening on `name`.
+ */
+pub fn query(socket_dir: Option<&Path>, name: &str, command: &str) -> Result<Value> {
+  platform::query(socket_dir, name, command)
+}
+
+// The control socket is a local socket like the IPC ones, so it needs the same `ipc` feature
+#[cfg(feature = "ipc")]
+mod platform {
+  use interprocess::local_socket::{
+    traits::{Listener as _, Stream as _},
+    GenericFilePath, ListenerNonblockingMode, ListenerOptions, Stream, ToFsName,
+  };
+  use serde_json::{json, Value};
+  use std::{
+    io::{BufRead, BufReader, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+  };
+
+  use crate::server::{
+    runtime,
+    shutdown::{Shutdown, POLL_INTERVAL},
+    state::ServerState,
+  };
+  use crate::{log, Error, ReloadCallback, Result};
+
+  // Commands are a single word, so longer lines are refused without reading the rest
+  const MAX_COMMAND_LEN: u64 = 4 * 1024;
+
+  /**
+   * Where the control socket `name` is, next to the IPC sockets, or a named pipe on Windows
+   */
+  #[cfg(not(target_os = "windows"))]
+  pub fn control_socket_path(socket_dir: Option<&Path>, name: &str) -> String {
+    crate::server::paths::ipc_dir(socket_dir)
+      .join(name)
+      .to_string_lossy()
+      .to_string()
+  }
+
+  #[cfg(target_os = "windows")]
+  pub fn control_socket_path(_socket_dir: Option<&Path>, name: &str) -> String {
+    format!(r"\\.\pipe\{}", name)
+  }
+
+  pub fn serve(
+    socket_dir: Option<&Path>,
+    name: &str,
+    shutdown: Shutdown,
+    state: impl Fn() -> ServerState + Send + Sync + 'static,
+    reload: Option<Arc<Mutex<ReloadCallback>>>,
+  ) -> Result<()> {
+    let path = control_socket_path(socket_dir, name);
+
+    if Stream::connect(path.clone().to_fs_name::<GenericFilePath>()?).is_ok() {
+      return Err(Error::Listen(format!("{} is already in use", path)));
+    }
+
+    // Nobody is listening on it, so it was left behind by a crash
+    #[cfg(not(target_os = "windows"))]
+    if Path::new(&path).exists() {
+      std::fs::remove_file(&path)?;
+    }
+
+    let listener = ListenerOptions::new()
+      .name(path.clone().to_fs_name::<GenericFilePath>()?)
+      .create_sync()
+      .map_err(|err| Error::Listen(format!("{}: {}", path, err)))?;
+
+    // Blocking accepts would never notice the server being stopped
+    listener.set_nonblocking(ListenerNonblockingMode::Accept)?;
+
+    log!("[Control] Listening on {}", path);
+
+    let state = Arc::new(state);
+
+    runtime::spawn(move || {
+      while !shutdown.is_stopped() {
+        match listener.accept() {
+          Ok(stream) => {
+            let state = state.clone();
+            let reload = reload.clone();
+
+            runtime::spawn(move || {
+              if let Err(err) = handle_stream(stream, state.as_ref(), reload.as_deref()) {
+                log!(debug, "[Control] Error answering a client: {}", err);
+              }
+            });
+          }
+          Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+            std::thread::sleep(POLL_INTERVAL);
+          }
+          Err(err) => {
+            log!(warn, "[Control] Error: {}", err);
+            break;
+          }
+        }
+      }
+
+      #[cfg(not(target_os = "windows"))]
+      if std::fs::remove_file(&path).is_ok() {
+        log!(debug, "[Control] Removed {}", path);
+      }
+    });
+
+    Ok(())
+  }
+
+  fn handle_stream(
+    stream: Stream,
+    state: &dyn Fn() -> ServerState,
+    reload: Option<&Mutex<ReloadCallback>>,
+  ) -> Result<()> {
+    let mut reader = BufReader::new(stream.take(MAX_COMMAND_LEN));
+    let mut command = String::new();
+    reader.read_line(&mut command)?;
+
+    let too_long = command.len() as u64 >= MAX_COMMAND_LEN && !command.ends_with('\n');
+    let response = match command.trim() {
+      _ if too_long => json!({ "error": "Command too long" }),
+      "status" => serde_json::to_value(state())?,
+      "reload" => match reload {
+        Some(reload) => {
+          log!("[Control] Reload requested");
+          (reload.lock().unwrap())();
+          json!({ "reloading": true })
+        }
+        None => json!({ "error": "This server can't be reloaded" }),
+      },
+      command => json!({ "error": format!("Unknown command: {:?}", command) }),
+    };
+
+    let mut stream = reader.into_inner().into_inner();
+    writeln!(stream, "{}", response)?;
+    stream.flush()?;
+
+    Ok(())
+  }
+
+  pub fn query(socket_dir: Option<&Path>, name: &str, command: &str) -> Result<Value> {
+    let path = control_socket_path(socket_dir, name);
+    let mut stream = Stream::connect(path.clone().to_fs_name::<GenericFilePath>()?)
+      .map_err(|err| Error::Other(format!("Could not connect to {}: {}", path, err)))?;
+
+    writeln!(stream, "{}", command)?;
+    stream.flush()?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    let response: Value = serde_json::from_str(&response)?;
+
+    if let Some(error) = response["error"].as_str() {
+      return Err(Error::Other(error.to_string()));
+    }
+
+    Ok(response)
+  }
+}
+
+#[cfg(not(feature = "ipc"))]
+mod platform {
+  use serde_json::Value;
+  use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+  };
+
+  use crate::server::{shutdown::Shutdown, state::ServerState};
+  use crate::{Error, ReloadCallback, Result};
+
+  pub fn serve(
+    _socket_dir: Option<&Path>,
+    _name: &str,
+    _shutdown: Shutdown,
+    _state: impl Fn() -> ServerState + Send + Sync + 'static,
+    _reload: Option<Arc<Mutex<ReloadCallback>>>,
+  ) -> Result<()> {
+    Err(Error::Other(
+      "rsrpc was built without the ipc feature, which the control socket needs".to_string(),
+    ))
+  }
+
+  pub fn query(_socket_dir: Option<&Path>, _name: &str, _command: &str) -> Result<Value> {
+    Err(Error::Other(
+      "rsrpc was built without the ipc feature, which the control socket needs".to_string(),
+    ))
+  }
+}
@@ -0,0 +1,164 @@
+use reqwest::{
+  header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+  StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, path::PathBuf, time::Duration};
+
+use crate::{detection::DetectableActivity, log};
+
+use super::{runtime, shutdown::Shutdown};
+
+pub const DETECTABLE_URL: &str = "https://discord.com/api/v9/applications/detectable";
+
+// What the cached list was served with, to only download it again once it changed
+#[derive(Serialize, Deserialize, Default)]
+struct CacheInfo {
+  etag: Option<String>,
+  last_modified: Option<String>,
+}
+
+/**
+ * Fetches Discord's detectable list, keeping a copy on disk so it is there even when Discord isn't reachable
+ */
+#[derive(Clone)]
+pub struct DetectableUpdater {
+  cache_dir: Option<PathBuf>,
+}
+
+impl Default for DetectableUpdater {
+  fn default() -> Self {
+    Self::new(dirs::cache_dir().map(|dir| dir.join("rsrpc").join("detectable")))
+  }
+}
+
+impl DetectableUpdater {
+  pub fn new(cache_dir: Option<PathBuf>) -> Self {
+    Self { cache_dir }
+  }
+
+  fn cache_file(&self, name: &str) -> Option<PathBuf> {
+    self.cache_dir.as_ref().map(|dir| dir.join(name))
+  }
+
+  /**
+   * The list fetched last time, if there is one
+   */
+  pub fn cached(&self) -> Option<Vec<DetectableActivity>> {
+    let contents = std::fs::read_to_string(self.cache_file("detectable.json")?).ok()?;
+    serde_json::from_str(&contents).ok()
+  }
+
+  fn cache_info(&self) -> CacheInfo {
+    self
+      .cache_file("detectable.meta.json")
+      .and_then(|file| std::fs::read_to_string(file).ok())
+      .and_then(|contents| serde_json::from_str(&contents).ok())
+      .unwrap_or_default()
+  }
+
+  fn write_cache(&self, body: &str, info: &CacheInfo) -> Result<(), Box<dyn Error>> {
+    let (Some(list), Some(meta)) = (
+      self.cache_file("detectable.json"),
+      self.cache_file("detectable.meta.json"),
+    ) else {
+      return Ok(());
+    };
+
+    if let Some(dir) = list.parent() {
+      std::fs::create_dir_all(dir)?;
+    }
+
+    std::fs::write(list, body)?;
+    std::fs::write(meta, serde_json::to_string(info)?)?;
+
+    Ok(())
+  }
+
+  /**
+   * Download the list, unless it didn't change since it was cached, in which case this is None
+   */
+  pub fn fetch(&self) -> Result<Option<Vec<DetectableActivity>>, Box<dyn Error>> {
+    let mut request = reqwest::blocking::Client::new().get(DETECTABLE_URL);
+
+    // Without a cached list there is nothing to compare against
+    if self.cached().is_some() {
+      let info = self.cache_info();
+
+      if let Some(etag) = info.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+      }
+
+      if let Some(last_modified) = info.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+      }
+    }
+
+    let response = request.send()?;
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+      return Ok(None);
+    }
+
+    let response = response.error_for_status()?;
+    let header = |name| {
+      response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+    };
+    let info = CacheInfo {
+      etag: header(ETAG),
+      last_modified: header(LAST_MODIFIED),
+    };
+
+    let body = response.text()?;
+    let list = serde_json::from_str(&body)?;
+
+    self
+      .write_cache(&body, &info)
+      .unwrap_or_else(|e| log!(warn, "[Detectable Updater] Error writing cache: {}", e));
+
+    Ok(Some(list))
+  }
+
+  /**
+   * Pass the cached list to `on_update` right away, then every list that is fetched, every `interval` until stopped
+   */
+  pub fn start(
+    self,
+    interval: Duration,
+    shutdown: Shutdown,
+    on_update: impl Fn(Vec<DetectableActivity>) + Send + 'static,
+  ) {
+    runtime::spawn(move || {
+      if let Some(cached) = self.cached() {
+        log!(
+          "[Detectable Updater] Using {} cached detectables",
+          cached.len()
+        );
+        on_update(cached);
+      }
+
+      loop {
+        match self.fetch() {
+          Ok(Some(list)) => {
+            log!("[Detectable Updater] Fetched {} detectables", list.len());
+            on_update(list);
+          }
+          Ok(None) => log!("[Detectable Updater] Detectable list is up to date"),
+          Err(err) => log!(
+            warn,
+            "[Detectable Updater] Failed to fetch detectables: {}",
+            err
+          ),
+        }
+
+        if !shutdown.sleep(interval) {
+          break;
+        }
+      }
+    });
+  }
+}
@@ -0,0 +1,53 @@
+use std::{
+  path::PathBuf,
+  sync::{Arc, Mutex},
+  time::{Duration, SystemTime},
+};
+
+use super::{runtime, shutdown::Shutdown};
+
+// How often the watched files are checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+fn modified(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+  files
+    .iter()
+    .map(|file| {
+      std::fs::metadata(file)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+    })
+    .collect()
+}
+
+/**
+ * Call `on_change` whenever one of `files` is modified, created or removed, until stopped. The list can be changed while watching,
+ * whoever changes it is expected to reload the files themselves.
+ */
+pub fn watch_files(
+  files: Arc<Mutex<Vec<PathBuf>>>,
+  shutdown: Shutdown,
+  on_change: impl Fn() + Send + 'static,
+) {
+  runtime::spawn(move || {
+    let mut watched = files.lock().unwrap().clone();
+    let mut last = modified(&watched);
+
+    while shutdown.sleep(WATCH_INTERVAL) {
+      let current_files = files.lock().unwrap().clone();
+
+      if current_files != watched {
+        watched = current_files;
+        last = modified(&watched);
+        continue;
+      }
+
+      let current = modified(&watched);
+
+      if current != last {
+        last = current;
+        on_change();
+      }
+    }
+  });
+}
@@ -0,0 +1,313 @@
+use std::{net::UdpSocket, path::Path, time::Duration};
+
+use super::process::Exec;
+
+// Where RetroArch listens for network commands, when `network_cmd_enable` is on
+const RETROARCH_COMMAND_PORT: u16 = 55355;
+const RETROARCH_COMMAND_TIMEOUT: Duration = Duration::from_millis(100);
+
+// RetroArch options that are followed by a value, which isn't the content
+static RETROARCH_VALUE_OPTIONS: [&str; 16] = [
+  "-L",
+  "--libretro",
+  "-c",
+  "--config",
+  "--appendconfig",
+  "-s",
+  "--save",
+  "-S",
+  "--savestate",
+  "-r",
+  "--record",
+  "--recordconfig",
+  "--size",
+  "--subsystem",
+  "-e",
+  "--entryslot",
+];
+
+// Libretro core names (without `_libretro` and the extension) -> the system they emulate
+static CORE_SYSTEMS: [(&str, &str); 40] = [
+  ("snes9x", "SNES"),
+  ("snes9x2010", "SNES"),
+  ("bsnes", "SNES"),
+  ("bsnes_hd_beta", "SNES"),
+  ("mesen-s", "SNES"),
+  ("fceumm", "NES"),
+  ("nestopia", "NES"),
+  ("mesen", "NES"),
+  ("quicknes", "NES"),
+  ("mgba", "GBA"),
+  ("vba_next", "GBA"),
+  ("vbam", "GBA"),
+  ("gpsp", "GBA"),
+  ("gambatte", "Game Boy"),
+  ("sameboy", "Game Boy"),
+  ("gearboy", "Game Boy"),
+  ("genesis_plus_gx", "Genesis"),
+  ("picodrive", "Genesis"),
+  ("blastem", "Genesis"),
+  ("mupen64plus_next", "N64"),
+  ("parallel_n64", "N64"),
+  ("mednafen_psx", "PlayStation"),
+  ("mednafen_psx_hw", "PlayStation"),
+  ("swanstation", "PlayStation"),
+  ("pcsx_rearmed", "PlayStation"),
+  ("melonds", "Nintendo DS"),
+  ("desmume", "Nintendo DS"),
+  ("ppsspp", "PSP"),
+  ("flycast", "Dreamcast"),
+  ("dolphin", "GameCube"),
+  ("citra", "3DS"),
+  ("mednafen_saturn", "Saturn"),
+  ("yabause", "Saturn"),
+  ("mednafen_pce", "PC Engine"),
+  ("mednafen_pce_fast", "PC Engine"),
+  ("stella", "Atari 2600"),
+  ("mame", "Arcade"),
+  ("fbneo", "Arcade"),
+  ("mednafen_wswan", "WonderSwan"),
+  ("handy", "Atari Lynx"),
+];
+
+// ROM extensions -> the system they are for, when the core doesn't say
+static EXTENSION_SYSTEMS: [(&str, &str); 14] = [
+  ("sfc", "SNES"),
+  ("smc", "SNES"),
+  ("nes", "NES"),
+  ("gba", "GBA"),
+  ("gb", "Game Boy"),
+  ("gbc", "Game Boy Color"),
+  ("md", "Genesis"),
+  ("gen", "Genesis"),
+  ("smd", "Genesis"),
+  ("n64", "N64"),
+  ("z64", "N64"),
+  ("v64", "N64"),
+  ("nds", "Nintendo DS"),
+  ("pce", "PC Engine"),
+];
+
+// RetroArch's system ids, as reported over network commands -> how they are shown
+static SYSTEM_IDS: [(&str, &str); 10] = [
+  ("super_nes", "SNES"),
+  ("nes", "NES"),
+  ("game_boy_advance", "GBA"),
+  ("game_boy", "Game Boy"),
+  ("game_boy_color", "Game Boy Color"),
+  ("mega_drive", "Genesis"),
+  ("nintendo_64", "N64"),
+  ("playstation", "PlayStation"),
+  ("nintendo_ds", "Nintendo DS"),
+  ("pc_engine", "PC Engine"),
+];
+
+/**
+ * A game an emulator is running
+ */
+#[derive(Clone, Debug)]
+pub struct EmulatorGame {
+  pub emulator: &'static str,
+  pub title: String,
+  // eg. "SNES"
+  pub system: Option<String>,
+  // The libretro core, eg. "snes9x"
+  pub core: Option<String>,
+  pub pid: u64,
+}
+
+impl EmulatorGame {
+  /**
+   * eg. "Super Metroid (SNES)"
+   */
+  pub fn display_name(&self) -> String {
+    match &self.system {
+      Some(system) => format!("{} ({})", self.title, system),
+      None => self.title.clone(),
+    }
+  }
+}
+
+/**
+ * Every game RetroArch is running right now, from its command line, or by asking it over network commands when it was started without content
+ */
+pub fn running_games(processes: &[Exec]) -> Vec<EmulatorGame> {
+  let mut games: Vec<EmulatorGame> = vec![];
+
+  for process in processes.iter().filter(|process| is_retroarch(process)) {
+    let core = retroarch_core(&process.cmd);
+    let game = match retroarch_content(&process.cmd) {
+      Some(content) => EmulatorGame {
+        emulator: "RetroArch",
+        title: clean_title(&content),
+        system: core
+          .as_deref()
+          .and_then(core_system)
+          .or_else(|| extension_system(&content))
+          .map(|system| system.to_string()),
+        core,
+        pid: process.pid,
+      },
+      None => match retroarch_status() {
+        Some((system_id, title)) => EmulatorGame {
+          emulator: "RetroArch",
+          title: clean_title(&title),
+          system: core
+            .as_deref()
+            .and_then(core_system)
+            .map(|system| system.to_string())
+            .or_else(|| system_name(&system_id)),
+          core,
+          pid: process.pid,
+        },
+        None => continue,
+      },
+    };
+
+    if !games.iter().any(|known| known.pid == game.pid) {
+      games.push(game);
+    }
+  }
+
+  games
+}
+
+fn is_retroarch(process: &Exec) -> bool {
+  Path::new(&process.path.replace('\\', "/"))
+    .file_stem()
+    .map(|stem| stem.to_string_lossy().eq_ignore_ascii_case("retroarch"))
+    .unwrap_or(false)
+}
+
+/**
+ * The core RetroArch was started with, from `-L <core>` or `--libretro=<core>`, eg. "snes9x"
+ */
+fn retroarch_core(cmd: &[String]) -> Option<String> {
+  let path = cmd.iter().enumerate().find_map(|(i, arg)| {
+    if arg == "-L" || arg == "--libretro" {
+      cmd.get(i + 1).cloned()
+    } else {
+      arg.strip_prefix("--libretro=").map(|core| core.to_string())
+    }
+  })?;
+
+  let stem = Path::new(&path.replace('\\', "/"))
+    .file_stem()?
+    .to_string_lossy()
+    .to_lowercase();
+
+  Some(stem.trim_end_matches("_libretro").to_string())
+}
+
+/**
+ * The content RetroArch was started with, the last argument that isn't an option or an option's value
+ */
+fn retroarch_content(cmd: &[String]) -> Option<String> {
+  let mut content = None;
+  let mut skip_next = false;
+
+  for arg in cmd.iter().skip(1) {
+    if skip_next {
+      skip_next = false;
+      continue;
+    }
+
+    if RETROARCH_VALUE_OPTIONS.contains(&arg.as_str()) {
+      skip_next = true;
+    } else if !arg.starts_with('-') {
+      content = Some(arg.clone());
+    }
+  }
+
+  content
+}
+
+/**
+ * Ask RetroArch what it is playing, as (system id, content name). Only works with network commands enabled.
+ */
+fn retroarch_status() -> Option<(String, String)> {
+  let socket = UdpSocket::bind(("127.0.0.1", 0)).ok()?;
+  socket
+    .set_read_timeout(Some(RETROARCH_COMMAND_TIMEOUT))
+    .ok()?;
+  socket
+    .send_to(b"GET_STATUS", ("127.0.0.1", RETROARCH_COMMAND_PORT))
+    .ok()?;
+
+  let mut buffer = [0u8; 1024];
+  let len = socket.recv(&mut buffer).ok()?;
+  let response = String::from_utf8_lossy(&buffer[..len]);
+
+  // GET_STATUS PLAYING super_nes,Super Metroid,crc32=...
+  let status = response.trim().strip_prefix("GET_STATUS PLAYING ")?;
+  let mut fields = status.splitn(3, ',');
+  let system_id = fields.next()?.to_string();
+  let title = fields.next()?.to_string();
+
+  Some((system_id, title))
+}
+
+fn core_system(core: &str) -> Option<&'static str> {
+  CORE_SYSTEMS
+    .iter()
+    .find(|(name, _)| *name == core)
+    .map(|(_, system)| *system)
+}
+
+fn extension_system(content: &str) -> Option<&'static str> {
+  let ext = Path::new(content)
+    .extension()?
+    .to_string_lossy()
+    .to_lowercase();
+
+  EXTENSION_SYSTEMS
+    .iter()
+    .find(|(known, _)| *known == ext)
+    .map(|(_, system)| *system)
+}
+
+fn system_name(system_id: &str) -> Option<String> {
+  if system_id.is_empty() {
+    return None;
+  }
+
+  SYSTEM_IDS
+    .iter()
+    .find(|(id, _)| *id == system_id)
+    .map(|(_, system)| system.to_string())
+    .or_else(|| Some(system_id.replace('_', " ")))
+}
+
+/**
+ * The name of a ROM without its directory, extension and No-Intro style tags, eg. "Super Metroid (Japan, USA) (En,Ja).sfc" -> "Super Metroid"
+ */
+fn clean_title(content: &str) -> String {
+  let content = content.replace('\\', "/");
+  let file_name = content.rsplit('/').next().unwrap_or(&content);
+  // Files in archives are `game.zip#game.sfc`
+  let file_name = file_name.rsplit('#').next().unwrap_or(file_name);
+  let stem = Path::new(file_name)
+    .file_stem()
+    .map(|stem| stem.to_string_lossy().to_string())
+    .unwrap_or_else(|| file_name.to_string());
+
+  let mut title = String::new();
+  let mut depth = 0;
+
+  for c in stem.chars() {
+    match c {
+      '(' | '[' => depth += 1,
+      ')' | ']' => depth -= 1,
+      _ if depth <= 0 => title.push(c),
+      _ => {}
+    }
+  }
+
+  let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+
+  if title.is_empty() {
+    stem
+  } else {
+    title
+  }
+}
@@ -0,0 +1,404 @@
+use serde_json::{Map, Number, Value};
+
+// https://www.erlang.org/doc/apps/erts/erl_ext_dist.html
+const VERSION: u8 = 131;
+const NEW_FLOAT_EXT: u8 = 70;
+const SMALL_INTEGER_EXT: u8 = 97;
+const INTEGER_EXT: u8 = 98;
+const FLOAT_EXT: u8 = 99;
+const ATOM_EXT: u8 = 100;
+const SMALL_TUPLE_EXT: u8 = 104;
+const LARGE_TUPLE_EXT: u8 = 105;
+const NIL_EXT: u8 = 106;
+const STRING_EXT: u8 = 107;
+const LIST_EXT: u8 = 108;
+const BINARY_EXT: u8 = 109;
+const SMALL_BIG_EXT: u8 = 110;
+const LARGE_BIG_EXT: u8 = 111;
+const SMALL_ATOM_EXT: u8 = 115;
+const MAP_EXT: u8 = 116;
+const ATOM_UTF8_EXT: u8 = 118;
+const SMALL_ATOM_UTF8_EXT: u8 = 119;
+
+// Nothing we receive is nested anywhere near this deep
+const MAX_DEPTH: usize = 64;
+
+/**
+ * Encode JSON as ETF, the same way Discord's erlpack does (strings as binaries, null as the `nil` atom)
+ */
+pub fn encode(value: &Value) -> Vec<u8> {
+  let mut buffer = vec![VERSION];
+  encode_term(value, &mut buffer);
+  buffer
+}
+
+fn encode_term(value: &Value, buffer: &mut Vec<u8>) {
+  match value {
+    Value::Null => encode_atom("nil", buffer),
+    Value::Bool(value) => encode_atom(if *value { "true" } else { "false" }, buffer),
+    Value::Number(number) => encode_number(number, buffer),
+    Value::String(string) => {
+      buffer.push(BINARY_EXT);
+      buffer.extend_from_slice(&(string.len() as u32).to_be_bytes());
+      buffer.extend_from_slice(string.as_bytes());
+    }
+    Value::Array(values) => {
+      if values.is_empty() {
+        buffer.push(NIL_EXT);
+        return;
+      }
+
+      buffer.push(LIST_EXT);
+      buffer.extend_from_slice(&(values.len() as u32).to_be_bytes());
+
+      for value in values {
+        encode_term(value, buffer);
+      }
+
+      buffer.push(NIL_EXT);
+    }
+    Value::Object(map) => {
+      buffer.push(MAP_EXT);
+      buffer.extend_from_slice(&(map.len() as u32).to_be_bytes());
+
+      for (key, value) in map {
+        encode_term(&Value::String(key.clone()), buffer);
+        encode_term(value, buffer);
+      }
+    }
+  }
+}
+
+fn encode_atom(atom: &str, buffer: &mut Vec<u8>) {
+  buffer.push(SMALL_ATOM_UTF8_EXT);
+  buffer.push(atom.len() as u8);
+  buffer.extend_from_slice(atom.as_bytes());
+}
+
+fn encode_number(number: &Number, buffer: &mut Vec<u8>) {
+  if let Some(value) = number.as_i64() {
+    if (0..=255).contains(&value) {
+      buffer.push(SMALL_INTEGER_EXT);
+      buffer.push(value as u8);
+    } else if (i32::MIN as i64..=i32::MAX as i64).contains(&value) {
+      buffer.push(INTEGER_EXT);
+      buffer.extend_from_slice(&(value as i32).to_be_bytes());
+    } else {
+      encode_big(value.unsigned_abs(), value < 0, buffer);
+    }
+  } else if let Some(value) = number.as_u64() {
+    encode_big(value, false, buffer);
+  } else {
+    buffer.push(NEW_FLOAT_EXT);
+    buffer.extend_from_slice(&number.as_f64().unwrap_or_default().to_be_bytes());
+  }
+}
+
+fn encode_big(value: u64, negative: bool, buffer: &mut Vec<u8>) {
+  let mut digits = value.to_le_bytes().to_vec();
+
+  while digits.last() == Some(&0) {
+    digits.pop();
+  }
+
+  buffer.push(SMALL_BIG_EXT);
+  buffer.push(digits.len() as u8);
+  buffer.push(negative as u8);
+  buffer.extend_from_slice(&digits);
+}
+
+/**
+ * Decode ETF into JSON. Atoms other than nil/true/false become strings, and tuples become arrays.
+ */
+pub fn decode(data: &[u8]) -> Result<Value, String> {
+  let mut reader = Reader {
+    data,
+    position: 0,
+    depth: 0,
+  };
+
+  if reader.u8()? != VERSION {
+    return Err("Unknown ETF version".to_string());
+  }
+
+  reader.term()
+}
+
+struct Reader<'a> {
+  data: &'a [u8],
+  position: usize,
+  depth: usize,
+}
+
+impl Reader<'_> {
+  fn take(&mut self, count: usize) -> Result<&[u8], String> {
+    let end = self
+      .position
+      .checked_add(count)
+      .filter(|end| *end <= self.data.len())
+      .ok_or("Unexpected end of ETF data")?;
+    let bytes = &self.data[self.position..end];
+    self.position = end;
+
+    Ok(bytes)
+  }
+
+  fn u8(&mut self) -> Result<u8, String> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u16(&mut self) -> Result<u16, String> {
+    let bytes = self.take(2)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+  }
+
+  fn u32(&mut self) -> Result<u32, String> {
+    let bytes = self.take(4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+  }
+
+  fn string(&mut self, len: usize) -> Result<String, String> {
+    Ok(String::from_utf8_lossy(self.take(len)?).to_string())
+  }
+
+  fn term(&mut self) -> Result<Value, String> {
+    self.depth += 1;
+
+    if self.depth > MAX_DEPTH {
+      return Err("ETF data nested too deep".to_string());
+    }
+
+    let value = self.term_inner();
+    self.depth -= 1;
+
+    value
+  }
+
+  fn term_inner(&mut self) -> Result<Value, String> {
+    match self.u8()? {
+      SMALL_INTEGER_EXT => Ok(Value::from(self.u8()?)),
+      INTEGER_EXT => Ok(Value::from(self.u32()? as i32)),
+      NEW_FLOAT_EXT => {
+        let bytes = self.take(8)?;
+        let mut float = [0; 8];
+        float.copy_from_slice(bytes);
+
+        Ok(float_value(f64::from_be_bytes(float)))
+      }
+      FLOAT_EXT => {
+        let float = self.string(31)?;
+        let float = float.trim_end_matches('\0').trim();
+
+        Ok(float_value(float.parse().map_err(|_| "Invalid float")?))
+      }
+      ATOM_EXT | ATOM_UTF8_EXT => {
+        let len = self.u16()? as usize;
+        Ok(atom_value(self.string(len)?))
+      }
+      SMALL_ATOM_EXT | SMALL_ATOM_UTF8_EXT => {
+        let len = self.u8()? as usize;
+        Ok(atom_value(self.string(len)?))
+      }
+      NIL_EXT => Ok(Value::Array(vec![])),
+      STRING_EXT => {
+        let len = self.u16()? as usize;
+        Ok(Value::String(self.string(len)?))
+      }
+      BINARY_EXT => {
+        let len = self.u32()? as usize;
+        Ok(Value::String(self.string(len)?))
+      }
+      LIST_EXT => {
+        let len = self.u32()? as usize;
+        let values = self.terms(len)?;
+
+        // Proper lists end with NIL_EXT, improper ones with whatever the tail is
+        let tail = self.term()?;
+
+        if tail.as_array().map(|tail| tail.is_empty()).unwrap_or(false) {
+          Ok(Value::Array(values))
+        } else {
+          let mut values = values;
+          values.push(tail);
+          Ok(Value::Array(values))
+        }
+      }
+      SMALL_TUPLE_EXT => {
+        let len = self.u8()? as usize;
+        Ok(Value::Array(self.terms(len)?))
+      }
+      LARGE_TUPLE_EXT => {
+        let len = self.u32()? as usize;
+        Ok(Value::Array(self.terms(len)?))
+      }
+      MAP_EXT => {
+        let arity = self.u32()? as usize;
+        let mut map = Map::new();
+
+        for _ in 0..arity {
+          let key = match self.term()? {
+            Value::String(key) => key,
+            key => key.to_string(),
+          };
+          map.insert(key, self.term()?);
+        }
+
+        Ok(Value::Object(map))
+      }
+      SMALL_BIG_EXT => {
+        let len = self.u8()? as usize;
+        self.big(len)
+      }
+      LARGE_BIG_EXT => {
+        let len = self.u32()? as usize;
+        self.big(len)
+      }
+      tag => Err(format!("Unsupported ETF tag: {}", tag)),
+    }
+  }
+
+  fn terms(&mut self, len: usize) -> Result<Vec<Value>, String> {
+    // Don't trust the length for the allocation, every term is at least a byte
+    let mut values = Vec::with_capacity(len.min(self.data.len() - self.position));
+
+    for _ in 0..len {
+      values.push(self.term()?);
+    }
+
+    Ok(values)
+  }
+
+  fn big(&mut self, len: usize) -> Result<Value, String> {
+    let negative = self.u8()? != 0;
+    let digits = self.take(len)?;
+
+    if digits.iter().skip(8).any(|digit| *digit != 0) {
+      return Err("ETF integer too large".to_string());
+    }
+
+    let value = digits
+      .iter()
+      .take(8)
+      .rev()
+      .fold(0u64, |value, digit| (value << 8) | *digit as u64);
+
+    if !negative {
+      return Ok(Value::from(value));
+    }
+
+    // One further than i64::MAX, for i64::MIN
+    if value > i64::MAX as u64 + 1 {
+      return Err("ETF integer too large".to_string());
+    }
+
+    Ok(Value::from((value as i64).wrapping_neg()))
+  }
+}
+
+fn atom_value(atom: String) -> Value {
+  match atom.as_str() {
+    "nil" | "null" => Value::Null,
+    "true" => Value::Bool(true),
+    "false" => Value::Bool(false),
+    _ => Value::String(atom),
+  }
+}
+
+fn float_value(float: f64) -> Value {
+  Number::from_f64(float)
+    .map(Value::Number)
+    .unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn payload() -> Value {
+    json!({
+      "cmd": "SET_ACTIVITY",
+      "nonce": null,
+      "args": {
+        "pid": 1234,
+        "activity": {
+          "state": "In a match",
+          "instance": false,
+          "timestamps": { "start": 1_700_000_000_000u64 },
+          "buttons": ["Join", "Watch"],
+        },
+      },
+      "numbers": [0, 255, 256, -1, i32::MIN, i32::MAX, u64::MAX, i64::MIN, 1.5],
+      "empty": [],
+    })
+  }
+
+  #[test]
+  fn round_trips() {
+    let value = payload();
+    assert_eq!(decode(&encode(&value)), Ok(value));
+  }
+
+  #[test]
+  fn rejects_truncated_data() {
+    let data = encode(&payload());
+
+    for len in 0..data.len() {
+      assert!(
+        decode(&data[..len]).is_err(),
+        "decoded {} of {} bytes",
+        len,
+        data.len()
+      );
+    }
+  }
+
+  #[test]
+  fn rejects_unknown_version() {
+    let mut data = encode(&json!("rsRPC"));
+    data[0] = 130;
+
+    assert!(decode(&data).is_err());
+  }
+
+  #[test]
+  fn rejects_deep_nesting() {
+    let mut data = vec![VERSION];
+
+    // Lists of one list each, one more than allowed
+    for _ in 0..MAX_DEPTH + 1 {
+      data.extend_from_slice(&[LIST_EXT, 0, 0, 0, 1]);
+    }
+
+    data.push(NIL_EXT);
+
+    assert_eq!(decode(&data), Err("ETF data nested too deep".to_string()));
+  }
+
+  #[test]
+  fn accepts_nesting_up_to_the_limit() {
+    let mut value = json!([]);
+
+    for _ in 0..MAX_DEPTH - 1 {
+      value = json!([value]);
+    }
+
+    assert_eq!(decode(&encode(&value)), Ok(value));
+  }
+
+  #[test]
+  fn rejects_lengths_past_the_end() {
+    // Claiming u32::MAX elements or bytes, with none of them there
+    assert!(decode(&[VERSION, LIST_EXT, 0xff, 0xff, 0xff, 0xff]).is_err());
+    assert!(decode(&[VERSION, BINARY_EXT, 0xff, 0xff, 0xff, 0xff, b'a']).is_err());
+    assert!(decode(&[VERSION, MAP_EXT, 0xff, 0xff, 0xff, 0xff]).is_err());
+  }
+
+  #[test]
+  fn rejects_integers_too_large() {
+    // 2^64, a digit more than fits
+    assert!(decode(&[VERSION, SMALL_BIG_EXT, 9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]).is_err());
+    // -(2^63 + 1)
+    assert!(decode(&[VERSION, SMALL_BIG_EXT, 8, 1, 1, 0, 0, 0, 0, 0, 0, 0x80]).is_err());
+  }
+}
@@ -0,0 +1,93 @@
+use sha2::{Digest, Sha256};
+use std::{
+  collections::HashMap,
+  fs::File,
+  io::{self, Read},
+  time::SystemTime,
+};
+
+// Larger files are never hashed, no game executable is anywhere near this
+const MAX_HASHED_SIZE: u64 = 256 * 1024 * 1024;
+// Forget everything once this many files were hashed, rather than tracking which ones are still around
+const MAX_CACHED: usize = 4096;
+
+struct CachedHash {
+  // The file is hashed again if either of these changed
+  len: u64,
+  modified: Option<SystemTime>,
+  hash: Option<String>,
+}
+
+/**
+ * The SHA-256 of every executable that was hashed, so processes started from the same file aren't read again
+ */
+#[derive(Default)]
+pub struct HashCache {
+  hashes: HashMap<String, CachedHash>,
+}
+
+impl HashCache {
+  /**
+   * The lowercase hex SHA-256 of the file at `path`, None if it can't be read or is too large
+   */
+  pub fn hash(&mut self, path: &str) -> Option<String> {
+    if path.is_empty() {
+      return None;
+    }
+
+    let metadata = std::fs::metadata(path).ok()?;
+    let len = metadata.len();
+    let modified = metadata.modified().ok();
+
+    if let Some(cached) = self.hashes.get(path) {
+      if cached.len == len && cached.modified == modified {
+        return cached.hash.clone();
+      }
+    }
+
+    let hash = if len > MAX_HASHED_SIZE {
+      None
+    } else {
+      hash_file(path).ok()
+    };
+
+    if self.hashes.len() >= MAX_CACHED {
+      self.hashes.clear();
+    }
+
+    self.hashes.insert(
+      path.to_string(),
+      CachedHash {
+        len,
+        modified,
+        hash: hash.clone(),
+      },
+    );
+
+    hash
+  }
+}
+
+fn hash_file(path: &str) -> io::Result<String> {
+  let mut file = File::open(path)?;
+  let mut hasher = Sha256::new();
+  let mut buffer = [0u8; 64 * 1024];
+
+  loop {
+    let read = file.read(&mut buffer)?;
+
+    if read == 0 {
+      break;
+    }
+
+    hasher.update(&buffer[..read]);
+  }
+
+  Ok(
+    hasher
+      .finalize()
+      .iter()
+      .map(|byte| format!("{:02x}", byte))
+      .collect(),
+  )
+}
@@ -0,0 +1,181 @@
+use aho_corasick::AhoCorasick;
+use std::collections::HashMap;
+
+use crate::detection::DetectableActivity;
+
+/**
+ * Where an executable is in the detectable lists the index was built from
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Entry {
+  // Which list, and where in it
+  pub list: usize,
+  pub position: usize,
+  // Which of its executables
+  pub executable: usize,
+}
+
+/**
+ * The executables of every detectable, by what they match, so a process is looked up instead of compared against each of them
+ */
+#[derive(Default)]
+pub struct ExecutableIndex {
+  // Lowercase file names, eg. "game.exe" or "game" -> every executable with that name
+  names: HashMap<String, Vec<Entry>>,
+  // Names with a slash, which match whole trailing components of the path, eg. "bin/win64/game.exe"
+  paths: Option<AhoCorasick>,
+  path_entries: Vec<Vec<Entry>>,
+  // Glob and regex names, which have to be tried one by one
+  patterns: Vec<Entry>,
+  // Lowercase SHA-256 hashes -> every executable with that hash
+  hashes: HashMap<String, Vec<Entry>>,
+}
+
+// Architecture suffixes that are dropped from file names, so eg. game64.exe matches game.exe. Longest first, only the first that matches is dropped.
+static ARCH_SUFFIXES: [&str; 12] = [
+  "_x86_64", "-x86_64", "_x64", "-x64", ".x64", "_x86", "-x86", "x64", "_64", "-64", "64", "32",
+];
+
+/**
+ * Everything before the first dot, the way the scanner always compared names without their extension
+ */
+fn name_no_ext(name: &str) -> &str {
+  name.split('.').next().unwrap_or(name)
+}
+
+/**
+ * `path`, and `path` with an architecture suffix dropped from its file name if it has one
+ */
+fn variants(path: &str) -> Vec<String> {
+  let mut variants = vec![path.to_string()];
+  let file_start = path.rfind('/').map(|slash| slash + 1).unwrap_or(0);
+  let file_name = &path[file_start..];
+  let stem_len = file_name.rfind('.').unwrap_or(file_name.len());
+  let (stem, ext) = file_name.split_at(stem_len);
+
+  if let Some(suffix) = ARCH_SUFFIXES
+    .iter()
+    .find(|suffix| stem.len() > suffix.len() && stem.ends_with(*suffix))
+  {
+    variants.push(format!(
+      "{}{}{}",
+      &path[..file_start],
+      &stem[..stem.len() - suffix.len()],
+      ext
+    ));
+  }
+
+  variants
+}
+
+impl ExecutableIndex {
+  pub fn build(lists: &[&[DetectableActivity]]) -> Self {
+    let mut index = Self::default();
+    let mut path_names: HashMap<String, usize> = HashMap::new();
+
+    for (list, detectable) in lists.iter().enumerate() {
+      for (position, activity) in detectable.iter().enumerate() {
+        for (executable, exe) in activity.executables.iter().flatten().enumerate() {
+          let entry = Entry {
+            list,
+            position,
+            executable,
+          };
+
+          if let Some(hash) = exe.sha256.as_ref() {
+            index
+              .hashes
+              .entry(hash.trim().to_lowercase())
+              .or_default()
+              .push(entry);
+            continue;
+          }
+
+          if exe.pattern.is_some() {
+            index.patterns.push(entry);
+            continue;
+          }
+
+          let name = exe.name.to_lowercase().replace('\\', "/");
+
+          if name.is_empty() {
+            continue;
+          }
+
+          if name.contains('/') {
+            let next = index.path_entries.len();
+            let id = *path_names.entry(name).or_insert(next);
+
+            if id == next {
+              index.path_entries.push(vec![]);
+            }
+
+            index.path_entries[id].push(entry);
+          } else {
+            index.names.entry(name).or_default().push(entry);
+          }
+        }
+      }
+    }
+
+    let mut paths: Vec<(String, usize)> = path_names.into_iter().collect();
+    paths.sort_by_key(|(_, id)| *id);
+    index.paths = AhoCorasick::new(paths.into_iter().map(|(path, _)| path)).ok();
+
+    index
+  }
+
+  /**
+   * The executables that could be the process at `path` (lowercase, with forward slashes), also with an architecture suffix dropped from its name. Glob and regex executables are always included, and neither OS nor arguments are checked.
+   */
+  pub fn candidates(&self, path: &str) -> Vec<Entry> {
+    if path.is_empty() {
+      return vec![];
+    }
+
+    let mut candidates: Vec<Entry> = vec![];
+
+    for path in variants(path) {
+      let file_name = path.rsplit('/').next().unwrap_or(&path);
+
+      for name in [file_name, name_no_ext(file_name)] {
+        if let Some(entries) = self.names.get(name) {
+          candidates.extend(entries);
+        }
+      }
+
+      if let Some(paths) = self.paths.as_ref() {
+        for found in paths.find_overlapping_iter(&path) {
+          // Only whole components at the end of the path, so "bin/game.exe" doesn't match "/mybin/game.exe.bak"
+          let starts_component = found.start() == 0
+            || path.as_bytes()[found.start() - 1] == b'/'
+            || path.as_bytes()[found.start()] == b'/';
+
+          if starts_component && found.end() == path.len() {
+            candidates.extend(&self.path_entries[found.pattern().as_usize()]);
+          }
+        }
+      }
+    }
+
+    candidates.extend(&self.patterns);
+    candidates.sort();
+    candidates.dedup();
+
+    candidates
+  }
+
+  /**
+   * Whether any executable is matched by its hash, otherwise processes don't need to be hashed at all
+   */
+  pub fn has_hashes(&self) -> bool {
+    !self.hashes.is_empty()
+  }
+
+  /**
+   * The executables that are the file with this SHA-256 (lowercase hex)
+   */
+  pub fn by_hash(&self, hash: &str) -> &[Entry] {
+    self.hashes.get(hash).map(Vec::as_slice).unwrap_or_default()
+  }
+}
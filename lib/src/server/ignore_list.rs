@@ -0,0 +1,54 @@
+/**
+ * Processes and applications that are never detected. Each entry is an application id, an executable name (eg. `cmd.exe`, with or without the extension) or a path (a file, or a directory ending in a slash for everything in it).
+ */
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreList {
+  // Lowercase, with forward slashes
+  entries: Vec<String>,
+}
+
+impl IgnoreList {
+  pub fn new(entries: &[String]) -> Self {
+    Self {
+      entries: entries
+        .iter()
+        .map(|entry| entry.trim().to_lowercase().replace('\\', "/"))
+        .filter(|entry| !entry.is_empty())
+        .collect(),
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  /**
+   * Whether the process running the executable at `path` is ignored
+   */
+  pub fn ignores_process(&self, path: &str) -> bool {
+    let path = path.to_lowercase().replace('\\', "/");
+    let file_name = path.rsplit('/').next().unwrap_or(&path);
+    let stem = file_name
+      .rsplit_once('.')
+      .map(|(stem, _)| stem)
+      .unwrap_or(file_name);
+
+    self.entries.iter().any(|entry| {
+      if entry.ends_with('/') {
+        path.starts_with(entry.as_str())
+      } else if entry.contains('/') {
+        path == *entry
+      } else {
+        file_name == entry || stem == entry
+      }
+    })
+  }
+
+  /**
+   * Whether the application with the id `id` is ignored
+   */
+  pub fn ignores_application(&self, id: &str) -> bool {
+    let id = id.to_lowercase();
+    self.entries.iter().any(|entry| *entry == id)
+  }
+}
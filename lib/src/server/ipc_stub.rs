@@ -0,0 +1,68 @@
+#![allow(dead_code)]
+
+use std::sync::mpsc;
+
+use super::{
+  commands::CommandHandler,
+  connector_types::{IpcFacilitator, IpcOptions},
+  rpc_clients::RpcClients,
+  shutdown::Shutdown,
+};
+use crate::{cmd::ActivityCmd, log};
+
+/**
+ * Stands in for the IPC connector when built without the `ipc` feature. No sockets are created, so only websocket clients and the host can set activities.
+ */
+#[derive(Clone)]
+pub struct IpcConnector {
+  options: IpcOptions,
+  shutdown: Shutdown,
+
+  event_sender: mpsc::Sender<ActivityCmd>,
+  commands: CommandHandler,
+}
+
+impl IpcFacilitator for IpcConnector {
+  fn start(&mut self) {
+    log!("[IPC] Built without the ipc feature, not creating any sockets");
+  }
+
+  fn stop(&mut self) {
+    self.shutdown.stop();
+  }
+
+  fn shutdown(&self) -> Shutdown {
+    self.shutdown.clone()
+  }
+
+  fn event_sender(&mut self) -> &mut mpsc::Sender<ActivityCmd> {
+    &mut self.event_sender
+  }
+
+  fn rpc_clients(&self) -> RpcClients {
+    self.commands.rpc_clients.clone()
+  }
+
+  fn commands(&self) -> CommandHandler {
+    self.commands.clone()
+  }
+
+  fn options(&self) -> IpcOptions {
+    self.options.clone()
+  }
+}
+
+impl IpcConnector {
+  pub fn new(
+    event_sender: mpsc::Sender<ActivityCmd>,
+    commands: CommandHandler,
+    options: IpcOptions,
+  ) -> Self {
+    Self {
+      options,
+      shutdown: Shutdown::default(),
+      event_sender,
+      commands,
+    }
+  }
+}
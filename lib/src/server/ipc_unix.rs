@@ -1,170 +1,212 @@
-use interprocess::local_socket::traits::ListenerExt;
 use interprocess::local_socket::Listener;
 use interprocess::local_socket::{GenericFilePath, ListenerOptions, ToFsName};
-use std::env;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 use std::sync::{mpsc, Arc, Mutex};
 
 use crate::cmd::ActivityCmd;
 use crate::log;
 
-use super::ipc_utils::{handle_stream, IpcFacilitator};
-
-fn get_socket_path() -> String {
-  let xdg_runtime_dir = env::var("XDG_RUNTIME_DIR").unwrap_or_default();
-  let tmpdir = env::var("TMPDIR").unwrap_or_default();
-  let tmp = env::var("TMP").unwrap_or_default();
-  let temp = env::var("TEMP").unwrap_or_default();
-  let tmp_dir = if !xdg_runtime_dir.is_empty() {
-    xdg_runtime_dir
-  } else if !tmpdir.is_empty() {
-    tmpdir
-  } else if !tmp.is_empty() {
-    tmp
-  } else if !temp.is_empty() {
-    temp
-  } else {
-    "/tmp".to_string()
-  };
-
-  // Append a / to the temp dir if it doesn't have one
-  let tmp_dir = if tmp_dir.ends_with('/') {
-    tmp_dir
-  } else {
-    format!("{}/", tmp_dir)
-  };
-
-  format!("{}discord-ipc", tmp_dir)
-}
+use super::commands::CommandHandler;
+use super::connector_types::{IpcFacilitator, IpcOptions};
+use super::ipc_utils::{accept_connections, bind_sockets};
+use super::paths::ipc_socket_path;
+use super::rpc_clients::RpcClients;
+use super::runtime;
+use super::shutdown::Shutdown;
+
+// Relative to $XDG_RUNTIME_DIR
+#[cfg(target_os = "linux")]
+static SANDBOX_DIRS: [&str; 3] = [
+  "app/com.discordapp.Discord",
+  "app/com.discordapp.DiscordCanary",
+  "snap.discord",
+];
 
 #[derive(Clone)]
 pub struct IpcConnector {
-  socket: Arc<Mutex<Listener>>,
-  did_handshake: bool,
-  pub client_id: String,
-  pub pid: u64,
-  pub nonce: String,
+  // Taken by the accept threads once started
+  sockets: Arc<Mutex<Vec<Listener>>>,
+  // Socket files and links to them, removed when stopped
+  paths: Vec<PathBuf>,
+  options: IpcOptions,
+  shutdown: Shutdown,
 
   event_sender: mpsc::Sender<ActivityCmd>,
+  commands: CommandHandler,
 }
 
 impl IpcFacilitator for IpcConnector {
-  fn handshake(&self) -> bool {
-    self.did_handshake
-  }
-
-  fn set_handshake(&mut self, handshake: bool) {
-    self.did_handshake = handshake;
-  }
-
-  fn client_id(&self) -> String {
-    self.client_id.clone()
-  }
+  /**
+   * Create a new thread for every socket, that will recieve messages from it
+   */
+  fn start(&mut self) {
+    for socket in self.sockets.lock().unwrap().drain(..) {
+      let connector = self.clone();
 
-  fn set_client_id(&mut self, client_id: String) {
-    self.client_id = client_id;
+      runtime::spawn(move || accept_connections(connector, socket));
+    }
   }
 
-  fn pid(&self) -> u64 {
-    self.pid
-  }
+  fn stop(&mut self) {
+    self.shutdown.stop();
 
-  fn set_pid(&mut self, pid: u64) {
-    self.pid = pid;
+    for path in &self.paths {
+      if std::fs::remove_file(path).is_ok() {
+        log!("[IPC] Removed {}", path.display());
+      }
+    }
   }
 
-  fn nonce(&self) -> String {
-    self.nonce.clone()
+  fn shutdown(&self) -> Shutdown {
+    self.shutdown.clone()
   }
 
-  fn set_nonce(&mut self, nonce: String) {
-    self.nonce = nonce;
+  fn event_sender(&mut self) -> &mut mpsc::Sender<ActivityCmd> {
+    &mut self.event_sender
   }
 
-  fn recreate_socket(&mut self) {
-    // Delete the socket, then create a new one
-    let socket = Self::create_socket(None);
-    *self.socket.lock().unwrap() = socket;
+  fn rpc_clients(&self) -> RpcClients {
+    self.commands.rpc_clients.clone()
   }
 
-  /**
-   * Create a new thread that will recieve messages from the socket
-   */
-  fn start(&mut self) {
-    let connector = self.clone();
-
-    std::thread::spawn(move || {
-      let socket = connector.socket.lock().unwrap();
-
-      for stream in socket.incoming() {
-        // Little baby delay to keep things smooth
-        std::thread::sleep(std::time::Duration::from_millis(5));
-
-        let mut clone = connector.clone();
-
-        match stream {
-          Ok(mut stream) => {
-            log!("[IPC] Incoming stream...");
-            std::thread::spawn(move || handle_stream(&mut clone, &mut stream));
-          }
-          Err(err) => {
-            log!("[IPC] Error: {}", err);
-            break;
-          }
-        }
-      }
-    });
+  fn commands(&self) -> CommandHandler {
+    self.commands.clone()
   }
 
-  fn event_sender(&mut self) -> &mut mpsc::Sender<ActivityCmd> {
-    &mut self.event_sender
+  fn options(&self) -> IpcOptions {
+    self.options.clone()
   }
 }
 
 impl IpcConnector {
   /**
-   * Create a socket and return a new IpcConnector
+   * Create up to `options.socket_count` sockets and return a new IpcConnector
    */
-  pub fn new(event_sender: mpsc::Sender<ActivityCmd>) -> Self {
-    let socket = Self::create_socket(None);
+  pub fn new(
+    event_sender: mpsc::Sender<ActivityCmd>,
+    commands: CommandHandler,
+    options: IpcOptions,
+  ) -> Self {
+    let (sockets, paths): (Vec<_>, Vec<_>) = bind_sockets(options.socket_count, |index| {
+      Self::create_socket(
+        index,
+        options.socket_dir.as_deref(),
+        &options.base_name,
+        options.sandbox_links,
+      )
+    })
+    .into_iter()
+    .unzip();
 
     Self {
-      socket: Arc::new(Mutex::new(socket)),
-      did_handshake: false,
-      client_id: "".to_string(),
-      pid: 0,
-      nonce: "".to_string(),
+      sockets: Arc::new(Mutex::new(sockets)),
+      paths: paths.into_iter().flatten().collect(),
+      options,
+      shutdown: Shutdown::default(),
       event_sender,
+      commands,
     }
   }
 
   /**
-   * ACTUALLY create a socket, and return the handle
+   * ACTUALLY create a socket, and return the handle along with every path it can be found at
    */
-  fn create_socket(tries: Option<u8>) -> Listener {
-    let socket_path = get_socket_path();
-    let tries = tries.unwrap_or(0);
-    let socket_path = format!("{}-{}", socket_path, tries);
-
-    log!("[IPC] Creating socket: {}", socket_path);
+  fn create_socket(
+    index: u8,
+    socket_dir: Option<&Path>,
+    base_name: &str,
+    sandbox_links: bool,
+  ) -> Option<(Listener, Vec<PathBuf>)> {
+    let socket_path = ipc_socket_path(socket_dir, base_name, index)
+      .to_string_lossy()
+      .to_string();
+
+    log!(debug, "[IPC] Creating socket: {}", socket_path);
+
+    // A socket file nobody is listening on was left behind by a crash, and can be replaced
+    if std::path::Path::new(&socket_path).exists() && UnixStream::connect(&socket_path).is_err() {
+      log!("[IPC] Removing stale socket: {}", socket_path);
+      std::fs::remove_file(&socket_path)
+        .unwrap_or_else(|e| log!(warn, "[IPC] Error removing stale socket: {}", e));
+    }
 
     let listener =
-      ListenerOptions::new().name(socket_path.clone().to_fs_name::<GenericFilePath>().unwrap());
+      ListenerOptions::new().name(socket_path.clone().to_fs_name::<GenericFilePath>().ok()?);
 
-    let socket = match listener.create_sync() {
-      Ok(socket) => socket,
-      Err(err) => {
-        log!("[IPC] Failed to create IPC socket: {}", err);
+    match listener.create_sync() {
+      Ok(socket) => {
+        log!("[IPC] Created IPC socket: {}", socket_path);
+
+        let mut paths = vec![PathBuf::from(&socket_path)];
 
-        if tries < 9 {
-          return Self::create_socket(Some(tries + 1));
-        } else {
-          panic!("[IPC] Failed to create socket: {}", err);
+        if sandbox_links {
+          paths.extend(link_sandbox_paths(&socket_path, base_name, index));
         }
+
+        Some((socket, paths))
+      }
+      Err(err) => {
+        log!(
+          warn,
+          "[IPC] Failed to create IPC socket {}: {}",
+          socket_path,
+          err
+        );
+        None
       }
-    };
+    }
+  }
+}
 
-    log!("[IPC] Created IPC socket: {}", socket_path);
+/**
+ * Flatpak and Snap apps can't see the regular socket, but they do look for Discord's socket in their own runtime directories. Link ours there.
+ */
+#[cfg(target_os = "linux")]
+fn link_sandbox_paths(socket_path: &str, base_name: &str, index: u8) -> Vec<PathBuf> {
+  let runtime_dir = std::env::var("XDG_RUNTIME_DIR").unwrap_or_default();
+  let mut links = vec![];
 
-    socket
+  if runtime_dir.is_empty() {
+    return links;
   }
+
+  for dir in SANDBOX_DIRS {
+    let dir = std::path::Path::new(&runtime_dir).join(dir);
+    let link = dir.join(format!("{}-{}", base_name, index));
+
+    // Something real is already there (eg. Discord itself running in a Flatpak)
+    if link.exists() && UnixStream::connect(&link).is_ok() {
+      continue;
+    }
+
+    let result = std::fs::create_dir_all(&dir).and_then(|_| {
+      // Dangling links, or leftovers from a previous run
+      if link.symlink_metadata().is_ok() {
+        std::fs::remove_file(&link)?;
+      }
+
+      std::os::unix::fs::symlink(socket_path, &link)
+    });
+
+    match result {
+      Ok(_) => {
+        log!("[IPC] Linked IPC socket to {}", link.display());
+        links.push(link);
+      }
+      Err(err) => log!(
+        warn,
+        "[IPC] Error linking IPC socket to {}: {}",
+        link.display(),
+        err
+      ),
+    }
+  }
+
+  links
+}
+
+#[cfg(not(target_os = "linux"))]
+fn link_sandbox_paths(_socket_path: &str, _base_name: &str, _index: u8) -> Vec<PathBuf> {
+  vec![]
 }
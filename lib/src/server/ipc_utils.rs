@@ -1,34 +1,162 @@
 use std::{
-  io::{Read, Write},
-  sync::mpsc,
+  collections::HashSet,
+  io::{ErrorKind, Read, Write},
+  sync::{mpsc, Arc, Mutex},
+  time::{Duration, Instant},
 };
 
-use interprocess::local_socket::Stream;
+use interprocess::local_socket::{
+  traits::{Listener as _, Stream as _},
+  Listener, ListenerNonblockingMode, SendHalf, Stream,
+};
 
 use crate::{
-  cmd::{ActivityCmd, ActivityCmdArgs},
+  cmd::{
+    ActivityCmd, ActivityCmdArgs, RPC_CLOSE_INVALID_CLIENT_ID, RPC_CLOSE_INVALID_VERSION,
+    RPC_CLOSE_NORMAL, RPC_CLOSE_RATE_LIMITED, RPC_CLOSE_TOO_LARGE, RPC_CLOSE_UNSUPPORTED,
+    RPC_ERROR_INVALID_PAYLOAD,
+  },
   log,
   server::utils,
 };
 
-pub trait IpcFacilitator {
-  fn handshake(&self) -> bool;
-  fn set_handshake(&mut self, handshake: bool);
+use super::{
+  commands::{activity_response, unknown_command, CommandHandler},
+  connector_types::IpcFacilitator,
+  rate_limit::MessageRateLimiter,
+  rpc_clients::{next_socket_id, RpcClient, RpcClients},
+  runtime,
+  shutdown::{Shutdown, POLL_INTERVAL},
+};
 
-  fn client_id(&self) -> String;
-  fn set_client_id(&mut self, client_id: String);
+/**
+ * State of a single IPC connection. Every connection gets its own, so any number of clients can be connected at once.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct IpcConnection {
+  pub did_handshake: bool,
+  pub client_id: String,
+  pub pid: u64,
+  pub nonce: String,
+}
 
-  fn pid(&self) -> u64;
-  fn set_pid(&mut self, pid: u64);
+// How long a client can leave frames unread before it is considered gone
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
 
-  fn nonce(&self) -> String;
-  fn set_nonce(&mut self, nonce: String);
+/**
+ * The writing half of an IPC connection, so frames can be sent from other threads while the connection is being read from
+ */
+#[derive(Clone)]
+pub struct IpcClient {
+  writer: Arc<Mutex<SendHalf>>,
+}
 
-  fn recreate_socket(&mut self);
+impl IpcClient {
+  pub fn send_packet(&self, r_type: PacketType, data: String) -> bool {
+    let mut writer = self.writer.lock().unwrap();
+    write_all(&mut *writer, &encode(r_type, data)).is_ok()
+  }
 
-  fn start(&mut self);
+  /**
+   * Tell the client why the connection is being closed
+   */
+  pub fn send_close(&self, code: u32, message: &str) -> bool {
+    let data = serde_json::json!({ "code": code, "message": message }).to_string();
+    self.send_packet(PacketType::Close, data)
+  }
+}
 
-  fn event_sender(&mut self) -> &mut mpsc::Sender<ActivityCmd>;
+impl RpcClient for IpcClient {
+  fn send_frame(&self, data: String) -> bool {
+    self.send_packet(PacketType::Frame, data)
+  }
+
+  fn close(&self) {
+    self.send_close(RPC_CLOSE_NORMAL, "Server stopped");
+  }
+}
+
+/**
+ * Like `Write::write_all()`, but waits up to WRITE_TIMEOUT for a non-blocking stream to have room
+ */
+fn write_all(writer: &mut impl Write, mut data: &[u8]) -> std::io::Result<()> {
+  let deadline = Instant::now() + WRITE_TIMEOUT;
+
+  while !data.is_empty() {
+    match writer.write(data) {
+      Ok(0) => return Err(ErrorKind::WriteZero.into()),
+      Ok(written) => data = &data[written..],
+      Err(err) if err.kind() == ErrorKind::Interrupted => {}
+      Err(err) if err.kind() == ErrorKind::WouldBlock && Instant::now() < deadline => {
+        std::thread::sleep(POLL_INTERVAL);
+      }
+      Err(err) => return Err(err),
+    }
+  }
+
+  Ok(())
+}
+
+// Clients try discord-ipc-0 through discord-ipc-9
+pub const MAX_SOCKET_INDEX: u8 = 9;
+
+/**
+ * Bind the first `count` free socket indices, skipping ones that are taken (eg. by Discord itself)
+ */
+pub fn bind_sockets<T>(count: u8, create: impl Fn(u8) -> Option<T>) -> Vec<T> {
+  let mut sockets = vec![];
+
+  for index in 0..=MAX_SOCKET_INDEX {
+    if sockets.len() >= count as usize {
+      break;
+    }
+
+    if let Some(socket) = create(index) {
+      sockets.push(socket);
+    }
+  }
+
+  if sockets.is_empty() {
+    log!(
+      warn,
+      "[IPC] Could not create any IPC socket, every index up to {} is taken",
+      MAX_SOCKET_INDEX
+    );
+  }
+
+  sockets
+}
+
+/**
+ * Accept connections on `socket` until the connector is stopped, handling each one on its own thread. The listener is dropped (and the socket with it) when this returns.
+ */
+pub fn accept_connections<T>(connector: T, socket: Listener)
+where
+  T: IpcFacilitator + Clone + Send + 'static,
+{
+  // Blocking accepts would never notice the connector being stopped
+  if let Err(err) = socket.set_nonblocking(ListenerNonblockingMode::Accept) {
+    log!(warn, "[IPC] Error making socket non-blocking: {}", err);
+    return;
+  }
+
+  while !connector.shutdown().is_stopped() {
+    match socket.accept() {
+      Ok(stream) => {
+        log!(debug, "[IPC] Incoming stream...");
+
+        let mut clone = connector.clone();
+        runtime::spawn(move || handle_stream(&mut clone, stream));
+      }
+      Err(err) if err.kind() == ErrorKind::WouldBlock => {
+        std::thread::sleep(POLL_INTERVAL);
+      }
+      Err(err) => {
+        log!(warn, "[IPC] Error: {}", err);
+        break;
+      }
+    }
+  }
 }
 
 #[derive(Debug)]
@@ -41,14 +169,14 @@ pub enum PacketType {
 }
 
 impl PacketType {
-  pub fn from_u32(value: u32) -> Self {
+  pub fn from_u32(value: u32) -> Option<Self> {
     match value {
-      0 => PacketType::Handshake,
-      1 => PacketType::Frame,
-      2 => PacketType::Close,
-      3 => PacketType::Ping,
-      4 => PacketType::Pong,
-      _ => PacketType::Frame,
+      0 => Some(PacketType::Handshake),
+      1 => Some(PacketType::Frame),
+      2 => Some(PacketType::Close),
+      3 => Some(PacketType::Ping),
+      4 => Some(PacketType::Pong),
+      _ => None,
     }
   }
 }
@@ -56,7 +184,128 @@ impl PacketType {
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct Handshake {
   pub v: u32,
-  pub client_id: String,
+  #[serde(default)]
+  pub client_id: Option<String>,
+}
+
+/**
+ * Check a handshake the same way Discord does, returning the client id, or the close code and reason
+ */
+pub fn validate_handshake(
+  message: &str,
+  known_client_ids: Option<&HashSet<String>>,
+) -> Result<String, (u32, &'static str)> {
+  let Ok(handshake) = serde_json::from_str::<Handshake>(message) else {
+    return Err((RPC_CLOSE_INVALID_CLIENT_ID, "Invalid Client ID"));
+  };
+
+  if handshake.v != 1 {
+    return Err((RPC_CLOSE_INVALID_VERSION, "Invalid Version"));
+  }
+
+  let client_id = handshake.client_id.unwrap_or_default();
+
+  // Application IDs are snowflakes
+  if client_id.is_empty() || !client_id.chars().all(|c| c.is_ascii_digit()) {
+    return Err((RPC_CLOSE_INVALID_CLIENT_ID, "Invalid Client ID"));
+  }
+
+  if let Some(known_client_ids) = known_client_ids {
+    if !known_client_ids.contains(&client_id) {
+      return Err((RPC_CLOSE_INVALID_CLIENT_ID, "Unknown Client ID"));
+    }
+  }
+
+  Ok(client_id)
+}
+
+// Opcode and data length, both u32 LE
+const HEADER_SIZE: usize = 8;
+
+#[derive(Debug)]
+pub enum FrameError {
+  Io(std::io::Error),
+  // The declared data length
+  TooLarge(usize),
+  // The server stopped while waiting for data
+  Stopped,
+}
+
+/**
+ * Reads whole frames off a stream, no matter how the bytes are split across reads. Anything read past the end of a frame is kept for the next one.
+ * Non-blocking streams are polled until `shutdown` is stopped, so a reader never outlives its server.
+ */
+pub struct FrameReader<R: Read> {
+  reader: R,
+  buffer: Vec<u8>,
+  max_frame_size: usize,
+  shutdown: Shutdown,
+}
+
+impl<R: Read> FrameReader<R> {
+  pub fn new(reader: R, max_frame_size: usize, shutdown: Shutdown) -> Self {
+    Self {
+      reader,
+      buffer: Vec::new(),
+      max_frame_size,
+      shutdown,
+    }
+  }
+
+  /**
+   * Get the next frame's opcode and data
+   */
+  pub fn read_frame(&mut self) -> Result<(u32, String), FrameError> {
+    self.fill(HEADER_SIZE)?;
+
+    let opcode = read_u32(&self.buffer[0..4]);
+    let size = read_u32(&self.buffer[4..8]) as usize;
+
+    if size > self.max_frame_size {
+      return Err(FrameError::TooLarge(size));
+    }
+
+    self.fill(HEADER_SIZE + size)?;
+
+    let frame: Vec<u8> = self.buffer.drain(..HEADER_SIZE + size).collect();
+    let data = String::from_utf8_lossy(&frame[HEADER_SIZE..]).to_string();
+
+    Ok((opcode, data))
+  }
+
+  /**
+   * Read until at least `size` bytes are buffered
+   */
+  fn fill(&mut self, size: usize) -> Result<(), FrameError> {
+    let mut chunk = [0; 4096];
+
+    while self.buffer.len() < size {
+      match self.reader.read(&mut chunk) {
+        Ok(0) => {
+          return Err(FrameError::Io(std::io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "stream closed",
+          )))
+        }
+        Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+        Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+        Err(err) if err.kind() == ErrorKind::WouldBlock => {
+          if self.shutdown.is_stopped() {
+            return Err(FrameError::Stopped);
+          }
+
+          std::thread::sleep(POLL_INTERVAL);
+        }
+        Err(err) => return Err(FrameError::Io(err)),
+      }
+    }
+
+    Ok(())
+  }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+  u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
 }
 
 pub fn encode(r_type: PacketType, data: String) -> Vec<u8> {
@@ -77,183 +326,332 @@ pub fn encode(r_type: PacketType, data: String) -> Vec<u8> {
 #[allow(clippy::result_large_err)]
 pub fn send_empty(
   event_sender: &mut mpsc::Sender<ActivityCmd>,
+  application_id: &str,
   pid: u64,
+  socket_id: &str,
 ) -> Result<(), mpsc::SendError<ActivityCmd>> {
-  log!("[IPC] Sending empty activity");
+  log!(debug, "[IPC] Sending empty activity");
 
   let activity = ActivityCmd {
+    application_id: Some(application_id.to_string()),
     args: Some(ActivityCmdArgs {
       activity: None,
-      code: None,
       pid: Some(pid),
+      ..Default::default()
     }),
+    cmd: "SET_ACTIVITY".to_string(),
+    socket_id: Some(socket_id.to_string()),
     ..ActivityCmd::empty()
   };
   event_sender.send(activity)
 }
 
-pub fn handle_stream(ipc: &mut dyn IpcFacilitator, stream: &mut Stream) {
-  loop {
-    let current_pid = ipc.pid();
-    // Read into buffer
-    let mut buffer = std::io::BufReader::new(&mut *stream);
+pub fn handle_stream(ipc: &mut dyn IpcFacilitator, stream: Stream) {
+  let socket_id = next_socket_id();
 
-    // Read the packet type and size
-    let mut packet_type = [0; 4];
-    let mut data_size = [0; 4];
+  // A blocking read would keep the thread, and the connection, around after the server stopped
+  if let Err(err) = stream.set_nonblocking(true) {
+    log!(warn, "[IPC] Error making stream non-blocking: {}", err);
+    return;
+  }
 
-    match buffer.by_ref().take(4).read_exact(&mut packet_type) {
-      Ok(_) => (),
-      Err(err) => {
+  let (mut reader, writer) = stream.split();
+  let client = IpcClient {
+    writer: Arc::new(Mutex::new(writer)),
+  };
+  let mut connection = IpcConnection::default();
+  // Whether this connection has an activity that has to be cleared when it goes away
+  let mut has_activity = false;
+
+  let options = ipc.options();
+  let mut frames = FrameReader::new(&mut reader, options.max_frame_size, ipc.shutdown());
+  let mut rate_limiter = MessageRateLimiter::new(options.message_limit, options.message_window);
+
+  loop {
+    let current_pid = connection.pid;
+
+    let (opcode, message) = match frames.read_frame() {
+      Ok(frame) => frame,
+      Err(FrameError::TooLarge(size)) => {
         log!(
-          "[IPC] Error reading packet type: {}, socket likely closed",
-          err
+          warn,
+          "[IPC] Frame of {} bytes is too large, closing socket {}",
+          size,
+          socket_id
         );
-
-        // Send empty activity
-        send_empty(ipc.event_sender(), current_pid)
-          .unwrap_or_else(|e| log!("[IPC] Error sending empty activity: {}", e));
+        client.send_close(RPC_CLOSE_TOO_LARGE, "Frame too large");
         break;
       }
-    }
-
-    match buffer.by_ref().take(4).read_exact(&mut data_size) {
-      Ok(_) => (),
-      Err(err) => {
-        log!("[IPC] Error reading data size: {}", err);
-
-        // Send empty activity
-        send_empty(ipc.event_sender(), current_pid)
-          .unwrap_or_else(|e| log!("[IPC] Error sending empty activity: {}", e));
+      Err(FrameError::Io(err)) => {
+        log!(
+          debug,
+          "[IPC] Error reading frame: {}, socket likely closed",
+          err
+        );
         break;
       }
-    }
+      Err(FrameError::Stopped) => break,
+    };
 
-    // Convert the rest of the buffer to a string
-    let mut message = String::new();
+    if ipc.shutdown().is_stopped() {
+      break;
+    }
 
-    match buffer
-      .by_ref()
-      .take(u32::from_le_bytes(data_size) as u64)
-      .read_to_string(&mut message)
-    {
-      Ok(_) => (),
-      Err(err) => {
-        log!("[IPC] Error reading data: {}", err);
-        break;
-      }
+    if !rate_limiter.check() {
+      log!(
+        warn,
+        "[IPC] Socket {} sent more than {} frames in {:?}, closing it",
+        socket_id,
+        options.message_limit,
+        options.message_window
+      );
+      client.send_close(RPC_CLOSE_RATE_LIMITED, "Rate limited");
+      break;
     }
 
-    let r_type = PacketType::from_u32(u32::from_le_bytes(packet_type));
+    let Some(r_type) = PacketType::from_u32(opcode) else {
+      log!(
+        warn,
+        "[IPC] Unknown opcode {}, closing socket {}",
+        opcode,
+        socket_id
+      );
+      client.send_close(RPC_CLOSE_UNSUPPORTED, "Unknown opcode");
+      break;
+    };
 
-    log!("[IPC] Recieved message: {}", message);
+    log!(debug, "[IPC] Recieved message: {}", message);
 
     match r_type {
       PacketType::Handshake => {
-        log!("[IPC] Recieved handshake");
-        let Ok(data) = serde_json::from_str::<Handshake>(&message) else {
-          log!("[IPC] Error parsing handshake");
-          continue;
+        log!(debug, "[IPC] Recieved handshake");
+
+        let client_id = match validate_handshake(&message, options.known_client_ids.as_deref()) {
+          Ok(client_id) => client_id,
+          Err((code, reason)) => {
+            log!(
+              warn,
+              "[IPC] Rejecting handshake on socket {}: {}",
+              socket_id,
+              reason
+            );
+            client.send_close(code, reason);
+            break;
+          }
         };
 
-        if data.v != 1 {
-          log!("[IPC] Invalid version: {}", data.v);
-          continue;
-        }
-
-        ipc.set_handshake(true);
-        ipc.set_client_id(data.client_id);
+        connection.did_handshake = true;
+        connection.client_id = client_id;
 
         // Send CONNECTION_RESPONSE
-        let resp = encode(PacketType::Frame, utils::CONNECTION_REPONSE.to_string());
-
-        match stream.write_all(&resp) {
-          Ok(_) => (),
-          Err(err) => log!("[IPC] Error sending connection response: {}", err),
+        if !client.send_frame(utils::connection_response(&ipc.commands().config.user)) {
+          log!(warn, "[IPC] Error sending connection response");
         }
+
+        // Now that the client is ready, it can receive events
+        ipc
+          .rpc_clients()
+          .register(socket_id.clone(), Box::new(client.clone()));
       }
       PacketType::Frame => {
-        if !ipc.handshake() {
-          log!("[IPC] Did not handshake yet, ignoring frame");
+        if !connection.did_handshake {
+          log!(warn, "[IPC] Did not handshake yet, ignoring frame");
           continue;
         }
 
         let Ok(mut activity_cmd) = serde_json::from_str::<ActivityCmd>(&message) else {
-          log!("[IPC] Error parsing activity command");
+          log!(warn, "[IPC] Error parsing activity command");
 
           // Send empty activity
-          send_empty(ipc.event_sender(), current_pid)
-            .unwrap_or_else(|e| log!("[IPC] Error sending empty activity: {}", e));
+          send_empty(
+            ipc.event_sender(),
+            &connection.client_id,
+            current_pid,
+            &socket_id,
+          )
+          .unwrap_or_else(|e| log!(warn, "[IPC] Error sending empty activity: {}", e));
+          has_activity = false;
           continue;
         };
 
+        activity_cmd.application_id = Some(connection.client_id.clone());
+        activity_cmd.socket_id = Some(socket_id.clone());
+
+        if let Some(response) = ipc.commands().handle(ipc.event_sender(), &activity_cmd) {
+          client.send_cmd(&response);
+          continue;
+        }
+
+        if activity_cmd.cmd != "SET_ACTIVITY" {
+          log!(warn, "[IPC] Unknown command: {}", activity_cmd.cmd);
+          client.send_cmd(&unknown_command(&activity_cmd));
+          continue;
+        }
+
         let args = match activity_cmd.args {
           Some(ref args) => args,
           None => {
-            log!("[IPC] Invalid activity command, skipping");
+            log!(warn, "[IPC] Invalid activity command, skipping");
 
             // Send empty activity
-            send_empty(ipc.event_sender(), current_pid)
-              .unwrap_or_else(|e| log!("[IPC] Error sending empty activity: {}", e));
+            send_empty(
+              ipc.event_sender(),
+              &connection.client_id,
+              current_pid,
+              &socket_id,
+            )
+            .unwrap_or_else(|e| log!(warn, "[IPC] Error sending empty activity: {}", e));
+            has_activity = false;
             continue;
           }
         };
 
-        activity_cmd.application_id = Some(ipc.client_id());
+        if let Err(err) = activity_cmd.validate() {
+          log!(warn, "[IPC] Invalid activity: {}", err);
+
+          client.send_cmd(&ActivityCmd::error(
+            &activity_cmd.cmd,
+            activity_cmd.nonce.clone(),
+            RPC_ERROR_INVALID_PAYLOAD,
+            err,
+          ));
+          continue;
+        }
+
+        connection.pid = args.pid.unwrap_or_default();
+        connection.nonce = activity_cmd.nonce.clone().unwrap_or_default();
+        has_activity = args.activity.is_some();
 
-        ipc.set_pid(args.pid.unwrap_or_default());
-        ipc.set_nonce(activity_cmd.nonce.clone());
+        client.send_cmd(&activity_response(&activity_cmd));
 
         match ipc.event_sender().send(activity_cmd) {
           Ok(_) => (),
-          Err(err) => log!("[IPC] Error sending activity command: {}", err),
+          Err(err) => log!(warn, "[IPC] Error sending activity command: {}", err),
         }
       }
       PacketType::Close => {
-        log!("[IPC] Recieved close");
-
-        // Send message with an empty activity
-        let activity_cmd = ActivityCmd {
-          application_id: Some(ipc.client_id()),
-          cmd: "SET_ACTIVITY".to_string(),
-          data: None,
-          evt: None,
-          args: Some(ActivityCmdArgs {
-            pid: Some(ipc.pid()),
-            activity: None,
-            code: None,
-          }),
-          nonce: ipc.nonce(),
-        };
-
-        match ipc.event_sender().send(activity_cmd) {
-          Ok(_) => (),
-          Err(err) => log!("[IPC] Error sending activity command: {}", err),
-        }
-
-        // reset values
-        ipc.set_handshake(false);
-        ipc.set_client_id("".to_string());
-        ipc.set_pid(0);
-
-        ipc.recreate_socket();
+        log!(debug, "[IPC] Recieved close");
 
+        // Acknowledge it, the activity is cleared below like for any other disconnect
+        client.send_close(RPC_CLOSE_NORMAL, "Closed by client");
         break;
       }
       PacketType::Ping => {
-        log!("[IPC] Recieved ping");
-
-        // Send a pong
-        let resp = encode(PacketType::Pong, message);
+        log!(debug, "[IPC] Recieved ping");
 
-        match stream.write_all(&resp) {
-          Ok(_) => (),
-          Err(err) => log!("[IPC] Error sending pong: {}", err),
-        };
+        // Send a pong with the same data
+        if !client.send_packet(PacketType::Pong, message) {
+          log!(warn, "[IPC] Error sending pong");
+        }
       }
       PacketType::Pong => {
-        log!("[IPC] Recieved pong");
+        log!(debug, "[IPC] Recieved pong");
       }
     }
   }
+
+  // The client closed, or went away without clearing its activity (eg. it crashed), so do it for them
+  if has_activity {
+    log!("[IPC] Socket {} closed with an activity set", socket_id);
+
+    send_empty(
+      ipc.event_sender(),
+      &connection.client_id,
+      connection.pid,
+      &socket_id,
+    )
+    .unwrap_or_else(|e| log!(warn, "[IPC] Error sending empty activity: {}", e));
+  }
+
+  ipc.rpc_clients().unregister(&socket_id);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Hands out a byte per read, like a client writing a frame in pieces
+  struct Trickle<'a>(&'a [u8]);
+
+  impl Read for Trickle<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+      let Some((first, rest)) = self.0.split_first() else {
+        return Ok(0);
+      };
+
+      buffer[0] = *first;
+      self.0 = rest;
+      Ok(1)
+    }
+  }
+
+  fn header(opcode: u32, size: u32) -> Vec<u8> {
+    [opcode.to_le_bytes(), size.to_le_bytes()].concat()
+  }
+
+  #[test]
+  fn reads_consecutive_frames() {
+    let data = [
+      encode(PacketType::Handshake, r#"{"v":1}"#.to_string()),
+      encode(PacketType::Frame, "{}".to_string()),
+    ]
+    .concat();
+    let mut frames = FrameReader::new(Trickle(&data), 1024, Shutdown::default());
+
+    assert_eq!(frames.read_frame().unwrap(), (0, r#"{"v":1}"#.to_string()));
+    assert_eq!(frames.read_frame().unwrap(), (1, "{}".to_string()));
+    assert!(matches!(frames.read_frame(), Err(FrameError::Io(_))));
+  }
+
+  #[test]
+  fn accepts_frames_up_to_the_limit() {
+    let data = encode(PacketType::Frame, "a".repeat(16));
+    let mut frames = FrameReader::new(&data[..], 16, Shutdown::default());
+
+    assert_eq!(frames.read_frame().unwrap().1.len(), 16);
+  }
+
+  #[test]
+  fn rejects_oversized_frames_before_reading_them() {
+    // Only the header is there, so reading the data would fail differently
+    let data = header(1, 1024 * 1024);
+    let mut frames = FrameReader::new(&data[..], 1024, Shutdown::default());
+
+    assert!(matches!(
+      frames.read_frame(),
+      Err(FrameError::TooLarge(size)) if size == 1024 * 1024
+    ));
+  }
+
+  #[test]
+  fn rejects_truncated_frames() {
+    let mut data = header(1, 10);
+    data.extend_from_slice(b"short");
+    let mut frames = FrameReader::new(&data[..], 1024, Shutdown::default());
+
+    assert!(matches!(frames.read_frame(), Err(FrameError::Io(_))));
+    assert!(matches!(
+      FrameReader::new(&data[..5], 1024, Shutdown::default()).read_frame(),
+      Err(FrameError::Io(_))
+    ));
+  }
+
+  // A non-blocking stream nothing was written to
+  struct Idle;
+
+  impl Read for Idle {
+    fn read(&mut self, _buffer: &mut [u8]) -> std::io::Result<usize> {
+      Err(ErrorKind::WouldBlock.into())
+    }
+  }
+
+  #[test]
+  fn stops_waiting_when_stopped() {
+    let shutdown = Shutdown::default();
+    shutdown.stop();
+
+    let mut frames = FrameReader::new(Idle, 1024, shutdown);
+
+    assert!(matches!(frames.read_frame(), Err(FrameError::Stopped)));
+  }
 }
@@ -1,142 +1,175 @@
-use interprocess::local_socket::traits::ListenerExt;
-use interprocess::local_socket::{Listener, ListenerOptions, ToFsName};
+use interprocess::local_socket::traits::Stream as _;
+use interprocess::local_socket::{Listener, ListenerOptions, Stream, ToFsName};
 use interprocess::os::windows::local_socket::{ListenerOptionsExt, NamedPipe};
 use interprocess::os::windows::security_descriptor::SecurityDescriptor;
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
+use widestring::u16cstr;
 
 use crate::cmd::ActivityCmd;
 use crate::log;
 
-use super::ipc_utils::{handle_stream, IpcFacilitator};
+use super::commands::CommandHandler;
+use super::connector_types::{IpcFacilitator, IpcOptions};
+use super::ipc_utils::{accept_connections, bind_sockets};
+use super::rpc_clients::RpcClients;
+use super::runtime;
+use super::shutdown::Shutdown;
 
 #[derive(Clone)]
 pub struct IpcConnector {
-  socket: Arc<Mutex<Listener>>,
-  did_handshake: bool,
-  pub client_id: String,
-  pub pid: u64,
-  pub nonce: String,
+  // Taken by the accept threads once started
+  sockets: Arc<Mutex<Vec<Listener>>>,
+  options: IpcOptions,
+  shutdown: Shutdown,
 
   event_sender: mpsc::Sender<ActivityCmd>,
+  commands: CommandHandler,
 }
 
 impl IpcFacilitator for IpcConnector {
-  fn handshake(&self) -> bool {
-    self.did_handshake
-  }
-
-  fn set_handshake(&mut self, handshake: bool) {
-    self.did_handshake = handshake;
-  }
-
-  fn client_id(&self) -> String {
-    self.client_id.clone()
-  }
+  /**
+   * Create a new thread for every pipe, that will recieve messages from it
+   */
+  fn start(&mut self) {
+    for socket in self.sockets.lock().unwrap().drain(..) {
+      let connector = self.clone();
 
-  fn set_client_id(&mut self, client_id: String) {
-    self.client_id = client_id;
+      runtime::spawn(move || accept_connections(connector, socket));
+    }
   }
 
-  fn pid(&self) -> u64 {
-    self.pid
+  /**
+   * Pipes go away with their last instance, which the accept threads drop when they return
+   */
+  fn stop(&mut self) {
+    self.shutdown.stop();
   }
 
-  fn set_pid(&mut self, pid: u64) {
-    self.pid = pid;
+  fn shutdown(&self) -> Shutdown {
+    self.shutdown.clone()
   }
 
-  fn nonce(&self) -> String {
-    self.nonce.clone()
+  fn event_sender(&mut self) -> &mut mpsc::Sender<ActivityCmd> {
+    &mut self.event_sender
   }
 
-  fn set_nonce(&mut self, nonce: String) {
-    self.nonce = nonce;
+  fn rpc_clients(&self) -> RpcClients {
+    self.commands.rpc_clients.clone()
   }
 
-  fn recreate_socket(&mut self) {
-    let socket = Self::create_socket(None);
-    *self.socket.lock().unwrap() = socket;
+  fn commands(&self) -> CommandHandler {
+    self.commands.clone()
   }
 
-  /**
-   * Create a new thread that will recieve messages from the socket
-   */
-  fn start(&mut self) {
-    let connector = self.clone();
-
-    std::thread::spawn(move || {
-      let socket = connector.socket.lock().unwrap();
-
-      for stream in socket.incoming() {
-        // Little baby delay to keep things smooth
-        std::thread::sleep(std::time::Duration::from_millis(5));
-
-        let mut clone = connector.clone();
-
-        match stream {
-          Ok(mut stream) => {
-            log!("[IPC] Incoming stream...");
-            std::thread::spawn(move || handle_stream(&mut clone, &mut stream));
-          }
-          Err(err) => {
-            log!("[IPC] Error: {}", err);
-            break;
-          }
-        }
-      }
-    });
-  }
-
-  fn event_sender(&mut self) -> &mut mpsc::Sender<ActivityCmd> {
-    &mut self.event_sender
+  fn options(&self) -> IpcOptions {
+    self.options.clone()
   }
 }
 
 impl IpcConnector {
   /**
-   * Create a socket and return a new IpcConnector
+   * Create up to `options.socket_count` pipes and return a new IpcConnector
    */
-  pub fn new(event_sender: mpsc::Sender<ActivityCmd>) -> Self {
+  pub fn new(
+    event_sender: mpsc::Sender<ActivityCmd>,
+    commands: CommandHandler,
+    options: IpcOptions,
+  ) -> Self {
+    let sockets = bind_sockets(options.socket_count, |index| {
+      Self::create_socket(
+        index,
+        &options.base_name,
+        options.pipe_instances,
+        options.pipe_all_users,
+      )
+    });
+
     Self {
-      socket: Arc::new(Mutex::new(Self::create_socket(None))),
-      did_handshake: false,
-      client_id: "".to_string(),
-      pid: 0,
-      nonce: "".to_string(),
+      sockets: Arc::new(Mutex::new(sockets.into_iter().flatten().collect())),
+      options,
+      shutdown: Shutdown::default(),
       event_sender,
+      commands,
     }
   }
 
-  fn create_socket(tries: Option<u8>) -> Listener {
+  /**
+   * Create `instances` listeners for the same pipe, so that many clients connecting at once don't get ERROR_PIPE_BUSY while one is being accepted
+   */
+  fn create_socket(
+    index: u8,
+    base_name: &str,
+    instances: u8,
+    all_users: bool,
+  ) -> Option<Vec<Listener>> {
     // Define the path to the named pipe
-    let pipe_path = r"\\.\pipe\discord-ipc";
-
-    // Append tried number to name if applicable
-    let pipe_path = match tries {
-      Some(tries) => format!("{}-{}", pipe_path, tries),
-      None => format!("{}-{}", pipe_path, 0),
-    };
-
-    let listener = ListenerOptions::new()
-      .name(pipe_path.clone().to_fs_name::<NamedPipe>().unwrap())
-      .security_descriptor(SecurityDescriptor::default());
-
-    let socket = match listener.create_sync() {
-      Ok(socket) => socket,
-      Err(err) => {
-        log!("[IPC] Failed to create IPC socket: {}", err);
-
-        if tries.unwrap_or(0) < 9 {
-          return Self::create_socket(Some(tries.unwrap_or(0) + 1));
-        } else {
-          panic!("[IPC] Failed to create socket: {}", err);
+    let pipe_path = format!(r"\\.\pipe\{}-{}", base_name, index);
+    let name = pipe_path.clone().to_fs_name::<NamedPipe>().ok()?;
+
+    // Pipes don't have to be the first instance, so Discord's own pipe has to be skipped explicitly
+    if Stream::connect(name.clone()).is_ok() {
+      log!(warn, "[IPC] {} is already in use", pipe_path);
+      return None;
+    }
+
+    let mut sockets = vec![];
+
+    for _ in 0..instances.max(1) {
+      let security_descriptor = match Self::security_descriptor(all_users) {
+        Ok(security_descriptor) => security_descriptor,
+        Err(err) => {
+          log!(
+            warn,
+            "[IPC] Failed to create the security descriptor for {}: {}",
+            pipe_path,
+            err
+          );
+          break;
+        }
+      };
+      let listener = ListenerOptions::new()
+        .name(name.clone())
+        .security_descriptor(security_descriptor);
+
+      match listener.create_sync() {
+        Ok(socket) => sockets.push(socket),
+        Err(err) => {
+          log!(
+            warn,
+            "[IPC] Failed to create IPC socket {}: {}",
+            pipe_path,
+            err
+          );
+          break;
         }
       }
-    };
+    }
+
+    if sockets.is_empty() {
+      return None;
+    }
+
+    log!(
+      "[IPC] Created IPC socket: {} ({} instance(s))",
+      pipe_path,
+      sockets.len()
+    );
 
-    log!("[IPC] Created IPC socket: {}", pipe_path);
+    Some(sockets)
+  }
+  /**
+   * The default only lets the user the server runs as write to the pipes, so a service running as LocalSystem
+   * can't be reached by games. With `all_users`, Authenticated Users get read and write on top of it.
+   */
+  fn security_descriptor(all_users: bool) -> std::io::Result<SecurityDescriptor> {
+    if !all_users {
+      return Ok(SecurityDescriptor::default());
+    }
 
-    socket
+    // Full control for LocalSystem, administrators and the owner, read and write for any logged-in user
+    SecurityDescriptor::deserialize(u16cstr!(
+      "D:(A;;GA;;;SY)(A;;GA;;;BA)(A;;GA;;;OW)(A;;GRGW;;;AU)"
+    ))
   }
 }
@@ -0,0 +1,164 @@
+use serde_json::Value;
+use std::path::PathBuf;
+
+use super::process::Exec;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Launcher {
+  Heroic,
+  Lutris,
+}
+
+impl Launcher {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Launcher::Heroic => "heroic",
+      Launcher::Lutris => "lutris",
+    }
+  }
+}
+
+/**
+ * A game that a launcher says is running, which usually can't be told apart by its executable (a Wine prefix, a shell script, ...)
+ */
+#[derive(Clone, Debug)]
+pub struct LauncherGame {
+  pub launcher: Launcher,
+  pub title: String,
+  pub pid: u64,
+}
+
+/**
+ * Every game Heroic or Lutris is running right now
+ */
+pub fn running_games(processes: &[Exec]) -> Vec<LauncherGame> {
+  let mut games: Vec<LauncherGame> = vec![];
+  let mut heroic_titles: Option<Vec<(String, String)>> = None;
+
+  for process in processes {
+    let game = if let Some(title) = lutris_title(&process.cmd) {
+      LauncherGame {
+        launcher: Launcher::Lutris,
+        title,
+        pid: process.pid,
+      }
+    } else if let Some(app_name) = heroic_app_name(&process.environ) {
+      // Only read the library once, and only when something is running
+      let titles = heroic_titles.get_or_insert_with(heroic_library);
+      let title = titles
+        .iter()
+        .find(|(name, _)| *name == app_name)
+        .map(|(_, title)| title.clone())
+        .unwrap_or(app_name);
+
+      LauncherGame {
+        launcher: Launcher::Heroic,
+        title,
+        pid: process.pid,
+      }
+    } else {
+      continue;
+    };
+
+    // Every child of a game is tagged the same way, the first one is enough
+    if !games
+      .iter()
+      .any(|known| known.launcher == game.launcher && known.title == game.title)
+    {
+      games.push(game);
+    }
+  }
+
+  games
+}
+
+/**
+ * Lutris runs games as `lutris-wrapper <title> ...`, sometimes through the Python interpreter
+ */
+fn lutris_title(cmd: &[String]) -> Option<String> {
+  let position = cmd
+    .iter()
+    .position(|arg| arg == "lutris-wrapper" || arg.ends_with("/lutris-wrapper"))?;
+
+  cmd
+    .get(position + 1)
+    .filter(|title| !title.is_empty())
+    .cloned()
+}
+
+/**
+ * Heroic puts the app name of the game in the environment of everything it launches
+ */
+fn heroic_app_name(environ: &[String]) -> Option<String> {
+  environ
+    .iter()
+    .find_map(|var| var.strip_prefix("HEROIC_APP_NAME="))
+    .filter(|name| !name.is_empty())
+    .map(|name| name.to_string())
+}
+
+fn heroic_config_dirs() -> Vec<PathBuf> {
+  let mut config_dirs = vec![];
+
+  if let Some(config) = dirs::config_dir() {
+    config_dirs.push(config.join("heroic"));
+  }
+
+  // Flatpak
+  if let Some(home) = dirs::home_dir() {
+    config_dirs.push(home.join(".var/app/com.heroicgameslauncher.hgl/config/heroic"));
+  }
+
+  config_dirs
+}
+
+/**
+ * (app name, title) of every game in Heroic's store caches
+ */
+fn heroic_library() -> Vec<(String, String)> {
+  let mut titles = vec![];
+
+  for dir in heroic_config_dirs() {
+    let Ok(entries) = std::fs::read_dir(dir.join("store_cache")) else {
+      continue;
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+      let Some(library) = std::fs::read_to_string(entry.path())
+        .ok()
+        .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+      else {
+        continue;
+      };
+
+      collect_titles(&library, &mut titles);
+    }
+  }
+
+  titles
+}
+
+/**
+ * The caches of every store are laid out differently, but all of them have objects with `app_name` and `title`
+ */
+fn collect_titles(value: &Value, titles: &mut Vec<(String, String)>) {
+  match value {
+    Value::Object(map) => {
+      if let (Some(Value::String(app_name)), Some(Value::String(title))) =
+        (map.get("app_name"), map.get("title"))
+      {
+        titles.push((app_name.clone(), title.clone()));
+      }
+
+      for value in map.values() {
+        collect_titles(value, titles);
+      }
+    }
+    Value::Array(values) => {
+      for value in values {
+        collect_titles(value, titles);
+      }
+    }
+    _ => {}
+  }
+}
@@ -0,0 +1,243 @@
+#![cfg_attr(not(any(feature = "ipc", feature = "rpc-websocket")), allow(dead_code))]
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+};
+
+use crate::cmd::{
+  RPC_ERROR_INVALID_LOBBY, RPC_ERROR_INVALID_LOBBY_SECRET, RPC_ERROR_INVALID_PAYLOAD,
+};
+
+pub type LobbyResult = Result<Value, (u32, String)>;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LobbyMember {
+  pub user_id: String,
+  pub metadata: Map<String, Value>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Lobby {
+  pub id: String,
+  pub application_id: String,
+  // 1 = private, 2 = public
+  pub r#type: u64,
+  pub owner_id: String,
+  pub secret: String,
+  pub capacity: u64,
+  pub locked: bool,
+  pub metadata: Map<String, Value>,
+  pub members: Vec<LobbyMember>,
+}
+
+/**
+ * Just enough of the GameSDK lobby commands for games using them to get through their connect flow. None of this ever leaves this machine.
+ */
+#[derive(Clone, Default)]
+pub struct LobbyManager {
+  lobbies: Arc<Mutex<HashMap<String, Lobby>>>,
+}
+
+impl LobbyManager {
+  pub fn handle(
+    &self,
+    cmd: &str,
+    application_id: &str,
+    user_id: &str,
+    args: &HashMap<String, Value>,
+  ) -> LobbyResult {
+    match cmd {
+      "CREATE_LOBBY" => self.create(application_id, user_id, args),
+      "UPDATE_LOBBY" => self.update(args),
+      "DELETE_LOBBY" => self.delete(args),
+      "CONNECT_TO_LOBBY" => self.connect(user_id, args),
+      "DISCONNECT_FROM_LOBBY" => self.disconnect(user_id, args),
+      "SEARCH_LOBBIES" => self.search(application_id, args),
+      "UPDATE_LOBBY_MEMBER" => self.update_member(args),
+      // There is nobody to send to, and no voice to connect to
+      "SEND_TO_LOBBY" | "CONNECT_TO_LOBBY_VOICE" | "DISCONNECT_FROM_LOBBY_VOICE" => {
+        self.get(args, "lobby_id").map(|_| Value::Null)
+      }
+      _ => Err((
+        RPC_ERROR_INVALID_PAYLOAD,
+        format!("Unknown lobby command: {}", cmd),
+      )),
+    }
+  }
+
+  fn create(
+    &self,
+    application_id: &str,
+    user_id: &str,
+    args: &HashMap<String, Value>,
+  ) -> LobbyResult {
+    let id = unique_id();
+    let lobby = Lobby {
+      id: id.clone(),
+      application_id: application_id.to_string(),
+      r#type: args.get("type").and_then(Value::as_u64).unwrap_or(1),
+      owner_id: user_id.to_string(),
+      secret: unique_id(),
+      capacity: args.get("capacity").and_then(Value::as_u64).unwrap_or(16),
+      locked: args.get("locked").and_then(Value::as_bool).unwrap_or(false),
+      metadata: metadata(args),
+      members: vec![LobbyMember {
+        user_id: user_id.to_string(),
+        metadata: Map::new(),
+      }],
+    };
+
+    self.lobbies.lock().unwrap().insert(id, lobby.clone());
+
+    Ok(json!(lobby))
+  }
+
+  fn update(&self, args: &HashMap<String, Value>) -> LobbyResult {
+    let mut lobby = self.get(args, "id")?;
+
+    if let Some(lobby_type) = args.get("type").and_then(Value::as_u64) {
+      lobby.r#type = lobby_type;
+    }
+
+    if let Some(owner_id) = args.get("owner_id").and_then(Value::as_str) {
+      lobby.owner_id = owner_id.to_string();
+    }
+
+    if let Some(capacity) = args.get("capacity").and_then(Value::as_u64) {
+      lobby.capacity = capacity;
+    }
+
+    if let Some(locked) = args.get("locked").and_then(Value::as_bool) {
+      lobby.locked = locked;
+    }
+
+    lobby.metadata.extend(metadata(args));
+
+    self.lobbies.lock().unwrap().insert(lobby.id.clone(), lobby);
+
+    Ok(Value::Null)
+  }
+
+  fn delete(&self, args: &HashMap<String, Value>) -> LobbyResult {
+    let lobby = self.get(args, "id")?;
+    self.lobbies.lock().unwrap().remove(&lobby.id);
+
+    Ok(Value::Null)
+  }
+
+  fn connect(&self, user_id: &str, args: &HashMap<String, Value>) -> LobbyResult {
+    let mut lobby = self.get(args, "id")?;
+    let secret = args
+      .get("secret")
+      .and_then(Value::as_str)
+      .unwrap_or_default();
+
+    if lobby.secret != secret {
+      return Err((
+        RPC_ERROR_INVALID_LOBBY_SECRET,
+        "Invalid lobby secret".to_string(),
+      ));
+    }
+
+    if !lobby.members.iter().any(|member| member.user_id == user_id) {
+      lobby.members.push(LobbyMember {
+        user_id: user_id.to_string(),
+        metadata: Map::new(),
+      });
+    }
+
+    self
+      .lobbies
+      .lock()
+      .unwrap()
+      .insert(lobby.id.clone(), lobby.clone());
+
+    Ok(json!(lobby))
+  }
+
+  fn disconnect(&self, user_id: &str, args: &HashMap<String, Value>) -> LobbyResult {
+    let mut lobby = self.get(args, "id")?;
+    lobby.members.retain(|member| member.user_id != user_id);
+
+    // Nobody left, so the lobby goes away
+    if lobby.members.is_empty() {
+      self.lobbies.lock().unwrap().remove(&lobby.id);
+    } else {
+      self.lobbies.lock().unwrap().insert(lobby.id.clone(), lobby);
+    }
+
+    Ok(Value::Null)
+  }
+
+  fn search(&self, application_id: &str, args: &HashMap<String, Value>) -> LobbyResult {
+    let limit = args
+      .get("limit")
+      .and_then(Value::as_u64)
+      .unwrap_or(u64::MAX) as usize;
+    let lobbies: Vec<Lobby> = self
+      .lobbies
+      .lock()
+      .unwrap()
+      .values()
+      // Only public lobbies can be found
+      .filter(|lobby| lobby.application_id == application_id && lobby.r#type == 2)
+      .take(limit)
+      .cloned()
+      .collect();
+
+    Ok(json!(lobbies))
+  }
+
+  fn update_member(&self, args: &HashMap<String, Value>) -> LobbyResult {
+    let mut lobby = self.get(args, "lobby_id")?;
+    let user_id = args
+      .get("user_id")
+      .and_then(Value::as_str)
+      .unwrap_or_default();
+
+    let Some(member) = lobby
+      .members
+      .iter_mut()
+      .find(|member| member.user_id == user_id)
+    else {
+      return Err((
+        RPC_ERROR_INVALID_PAYLOAD,
+        "Unknown lobby member".to_string(),
+      ));
+    };
+
+    member.metadata.extend(metadata(args));
+
+    self.lobbies.lock().unwrap().insert(lobby.id.clone(), lobby);
+
+    Ok(Value::Null)
+  }
+
+  fn get(&self, args: &HashMap<String, Value>, key: &str) -> Result<Lobby, (u32, String)> {
+    let id = args.get(key).and_then(Value::as_str).unwrap_or_default();
+
+    match self.lobbies.lock().unwrap().get(id) {
+      Some(lobby) => Ok(lobby.clone()),
+      None => Err((RPC_ERROR_INVALID_LOBBY, format!("Invalid lobby: {}", id))),
+    }
+  }
+}
+
+fn metadata(args: &HashMap<String, Value>) -> Map<String, Value> {
+  args
+    .get("metadata")
+    .and_then(Value::as_object)
+    .cloned()
+    .unwrap_or_default()
+}
+
+fn unique_id() -> String {
+  std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_nanos()
+    .to_string()
+}
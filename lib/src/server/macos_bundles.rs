@@ -0,0 +1,87 @@
+use std::{
+  collections::HashMap,
+  path::{Path, PathBuf},
+};
+
+use plist::Value;
+
+/**
+ * The .app bundle an executable belongs to
+ */
+#[derive(Clone, Debug)]
+pub struct Bundle {
+  pub path: PathBuf,
+  pub identifier: Option<String>,
+  pub name: Option<String>,
+  pub display_name: Option<String>,
+}
+
+impl Bundle {
+  /**
+   * Everything the bundle could be listed as in the detectable list, lowercase
+   */
+  pub fn names(&self) -> Vec<String> {
+    let stem = self
+      .path
+      .file_stem()
+      .map(|stem| stem.to_string_lossy().to_string());
+    let file_name = self
+      .path
+      .file_name()
+      .map(|name| name.to_string_lossy().to_string());
+
+    vec![
+      self.identifier.clone(),
+      self.name.clone(),
+      self.display_name.clone(),
+      stem,
+      file_name,
+    ]
+    .into_iter()
+    .flatten()
+    .map(|name| name.to_lowercase())
+    .collect()
+  }
+}
+
+/**
+ * Remembers the Info.plist of every bundle that was looked at, they don't change while an app is running
+ */
+#[derive(Default)]
+pub struct BundleCache {
+  bundles: HashMap<PathBuf, Option<Bundle>>,
+}
+
+impl BundleCache {
+  /**
+   * The bundle of the executable at `exe`, eg. /Applications/Game.app/Contents/MacOS/Game
+   */
+  pub fn bundle_for(&mut self, exe: &str) -> Option<Bundle> {
+    let index = exe.find(".app/Contents/MacOS/")?;
+    let path = PathBuf::from(&exe[..index + ".app".len()]);
+
+    self
+      .bundles
+      .entry(path.clone())
+      .or_insert_with(|| read_bundle(&path))
+      .clone()
+  }
+}
+
+fn read_bundle(path: &Path) -> Option<Bundle> {
+  let info = Value::from_file(path.join("Contents/Info.plist")).ok()?;
+  let info = info.as_dictionary()?;
+  let get = |key: &str| {
+    info
+      .get(key)
+      .and_then(|value| value.as_string())
+      .map(|value| value.to_string())
+  };
+
+  Some(Bundle {
+    path: path.to_path_buf(),
+    identifier: get("CFBundleIdentifier"),
+    name: get("CFBundleName"),
+    display_name: get("CFBundleDisplayName"),
+  })
+}
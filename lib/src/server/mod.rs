@@ -1,23 +1,88 @@
+pub mod arbitration;
+pub mod assets;
 pub mod client_connector;
+pub mod commands;
+pub mod connector_types;
+pub mod control;
+pub mod detectable_updater;
+pub mod detectable_watcher;
+#[cfg(feature = "process-scanning")]
+pub mod emulators;
+#[cfg(feature = "rpc-websocket")]
+pub mod etf;
+#[cfg(feature = "process-scanning")]
+pub mod exe_hashes;
+#[cfg(feature = "process-scanning")]
+pub mod executable_index;
+#[cfg(feature = "process-scanning")]
+pub mod ignore_list;
+#[cfg(feature = "ipc")]
 pub mod ipc_utils;
+#[cfg(feature = "process-scanning")]
+pub mod launchers;
+pub mod lobbies;
+pub mod outgoing_queue;
+#[cfg(feature = "process-scanning")]
+pub mod pid_watcher;
+pub mod plugins;
+#[cfg(feature = "process-scanning")]
+pub mod proc_roots;
+#[cfg(feature = "process-scanning")]
 pub mod process;
+#[cfg(not(feature = "process-scanning"))]
+#[path = "process_stub.rs"]
+pub mod process;
+#[cfg(feature = "process-scanning")]
+pub mod process_events;
+#[cfg(feature = "process-scanning")]
+pub mod process_snapshot;
+pub mod process_types;
+pub mod rate_limit;
+pub mod rpc_clients;
+pub mod runtime;
+pub mod shutdown;
+pub mod state;
+pub mod steam;
+pub mod systemd;
+pub mod tls;
 pub mod utils;
+#[cfg(feature = "rpc-websocket")]
+pub mod websocket;
+#[cfg(not(feature = "rpc-websocket"))]
+#[path = "websocket_stub.rs"]
 pub mod websocket;
+#[cfg(feature = "process-scanning")]
+pub mod window_titles;
+#[cfg(feature = "process-scanning")]
+pub mod wine;
+#[cfg(any(feature = "client-connector", feature = "rpc-websocket"))]
+pub mod ws;
 
-#[cfg(target_os = "windows")]
+#[cfg(all(target_os = "windows", feature = "ipc"))]
 pub mod ipc_win;
 
-#[cfg(not(target_os = "windows"))]
+#[cfg(all(not(target_os = "windows"), feature = "ipc"))]
 pub mod ipc_unix;
 
-#[cfg(target_os = "windows")]
+#[cfg(all(not(target_os = "windows"), feature = "ipc"))]
+pub mod paths;
+
+#[cfg(all(target_os = "macos", feature = "process-scanning"))]
+pub mod macos_bundles;
+
+#[cfg(all(target_os = "windows", feature = "ipc"))]
 mod platform {
   pub use super::ipc_win as ipc;
 }
 
-#[cfg(not(target_os = "windows"))]
+#[cfg(all(not(target_os = "windows"), feature = "ipc"))]
 mod platform {
   pub use super::ipc_unix as ipc;
 }
 
+#[cfg(feature = "ipc")]
 pub use platform::ipc;
+
+#[cfg(not(feature = "ipc"))]
+#[path = "ipc_stub.rs"]
+pub mod ipc;
@@ -0,0 +1,135 @@
+#![cfg_attr(not(feature = "client-connector"), allow(dead_code))]
+
+use std::{
+  collections::VecDeque,
+  sync::{Arc, Condvar, Mutex},
+};
+
+use crate::log;
+
+use super::runtime;
+
+struct QueuedFrame {
+  // Frames with the same key replace each other, only the latest one matters
+  key: Option<String>,
+  data: String,
+}
+
+#[derive(Default)]
+struct QueueState {
+  frames: VecDeque<QueuedFrame>,
+  closed: bool,
+}
+
+/**
+ * Frames waiting to be written to a single client, so a slow client only ever holds up itself. When it is full, whatever is oldest is dropped, preferring frames that a newer one makes redundant anyway.
+ */
+#[derive(Clone)]
+pub struct OutgoingQueue {
+  state: Arc<(Mutex<QueueState>, Condvar)>,
+  capacity: usize,
+}
+
+impl OutgoingQueue {
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      state: Arc::new((Mutex::new(QueueState::default()), Condvar::new())),
+      capacity: capacity.max(1),
+    }
+  }
+
+  /**
+   * Queue a frame. A queued frame with the same `key` is replaced instead. Returns false if the queue is closed.
+   */
+  pub fn push(&self, key: Option<&str>, data: String) -> bool {
+    let (state, available) = &*self.state;
+    let mut state = state.lock().unwrap();
+
+    if state.closed {
+      return false;
+    }
+
+    if let Some(key) = key {
+      if let Some(frame) = state
+        .frames
+        .iter_mut()
+        .find(|frame| frame.key.as_deref() == Some(key))
+      {
+        frame.data = data;
+        return true;
+      }
+    }
+
+    if state.frames.len() >= self.capacity {
+      // Keyed frames are activity updates, which are superseded soon enough anyway
+      let oldest = state
+        .frames
+        .iter()
+        .position(|frame| frame.key.is_some())
+        .unwrap_or(0);
+
+      state.frames.remove(oldest);
+      log!(
+        warn,
+        "[Client Connector] Client queue is full, dropped a frame"
+      );
+    }
+
+    state.frames.push_back(QueuedFrame {
+      key: key.map(|key| key.to_string()),
+      data,
+    });
+    available.notify_one();
+
+    true
+  }
+
+  /**
+   * Stop accepting frames, and let the writer return
+   */
+  pub fn close(&self) {
+    let (state, available) = &*self.state;
+
+    state.lock().unwrap().closed = true;
+    available.notify_all();
+  }
+
+  pub fn is_closed(&self) -> bool {
+    self.state.0.lock().unwrap().closed
+  }
+
+  /**
+   * Wait for the next frame, or None once closed
+   */
+  fn pop(&self) -> Option<String> {
+    let (state, available) = &*self.state;
+    let mut state = state.lock().unwrap();
+
+    loop {
+      if state.closed {
+        return None;
+      }
+
+      if let Some(frame) = state.frames.pop_front() {
+        return Some(frame.data);
+      }
+
+      state = available.wait(state).unwrap();
+    }
+  }
+
+  /**
+   * Write queued frames with `send` on a thread of their own, until the queue is closed or sending fails
+   */
+  pub fn start_writer(&self, send: impl Fn(String) -> bool + Send + 'static) {
+    let queue = self.clone();
+
+    runtime::spawn(move || {
+      while let Some(data) = queue.pop() {
+        if !send(data) {
+          queue.close();
+        }
+      }
+    });
+  }
+}
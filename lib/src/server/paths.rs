@@ -0,0 +1,82 @@
+use std::{
+  env,
+  path::{Path, PathBuf},
+};
+
+// Overrides the directory the IPC sockets are created in
+pub const IPC_DIR_ENV: &str = "RSRPC_IPC_DIR";
+
+/**
+ * Get the directory the IPC sockets go in, the same one clients look in on this platform. An explicit `dir_override` wins over the RSRPC_IPC_DIR environment variable, which wins over the platform default.
+ */
+pub fn ipc_dir(dir_override: Option<&Path>) -> PathBuf {
+  if let Some(dir) = dir_override {
+    return dir.to_path_buf();
+  }
+
+  if let Some(dir) = env_dir(IPC_DIR_ENV) {
+    return dir;
+  }
+
+  platform_ipc_dir()
+}
+
+/**
+ * Get the full path of the <base_name>-N socket, eg. discord-ipc-0
+ */
+pub fn ipc_socket_path(dir_override: Option<&Path>, base_name: &str, index: u8) -> PathBuf {
+  ipc_dir(dir_override).join(format!("{}-{}", base_name, index))
+}
+
+#[cfg(target_os = "macos")]
+fn platform_ipc_dir() -> PathBuf {
+  match env_dir("TMPDIR") {
+    // Sandboxed, the real per-user temp dir is where everyone else looks
+    Some(dir) if is_sandbox_container(&dir) => darwin_user_temp_dir()
+      .or_else(|| env_dir("TMP"))
+      .unwrap_or_else(|| PathBuf::from("/tmp")),
+    Some(dir) => dir,
+    None => darwin_user_temp_dir()
+      .or_else(|| env_dir("TMP"))
+      .or_else(|| env_dir("TEMP"))
+      .unwrap_or_else(|| PathBuf::from("/tmp")),
+  }
+}
+
+#[cfg(not(target_os = "macos"))]
+fn platform_ipc_dir() -> PathBuf {
+  ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+    .iter()
+    .find_map(|var| env_dir(var))
+    .unwrap_or_else(|| PathBuf::from("/tmp"))
+}
+
+/**
+ * Apps in the macOS App Sandbox get a TMPDIR inside ~/Library/Containers/<bundle id>
+ */
+#[cfg(target_os = "macos")]
+fn is_sandbox_container(dir: &Path) -> bool {
+  dir.to_string_lossy().contains("/Library/Containers/")
+}
+
+#[cfg(target_os = "macos")]
+fn darwin_user_temp_dir() -> Option<PathBuf> {
+  let output = std::process::Command::new("getconf")
+    .arg("DARWIN_USER_TEMP_DIR")
+    .output()
+    .ok()?;
+  let dir = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+  if !output.status.success() || dir.is_empty() {
+    return None;
+  }
+
+  Some(PathBuf::from(dir))
+}
+
+fn env_dir(var: &str) -> Option<PathBuf> {
+  env::var(var)
+    .ok()
+    .filter(|dir| !dir.is_empty())
+    .map(PathBuf::from)
+}
@@ -0,0 +1,62 @@
+/**
+ * Whether the process `pid` is still running. Processes that can't be looked at (eg. owned by another user) count as running.
+ */
+pub fn is_alive(pid: u64) -> bool {
+  platform::is_alive(pid)
+}
+
+#[cfg(unix)]
+mod platform {
+  pub fn is_alive(pid: u64) -> bool {
+    if pid == 0 || pid > libc::pid_t::MAX as u64 {
+      return false;
+    }
+
+    // Signal 0 only checks whether the process exists
+    if unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+      return true;
+    }
+
+    std::io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+  }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+  use winapi::{
+    shared::minwindef::{DWORD, FALSE},
+    um::{
+      errhandlingapi::GetLastError,
+      handleapi::CloseHandle,
+      minwinbase::STILL_ACTIVE,
+      processthreadsapi::{GetExitCodeProcess, OpenProcess},
+      winnt::PROCESS_QUERY_LIMITED_INFORMATION,
+    },
+  };
+
+  // Returned by OpenProcess for pids that don't exist
+  const ERROR_INVALID_PARAMETER: DWORD = 87;
+
+  pub fn is_alive(pid: u64) -> bool {
+    unsafe {
+      let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid as DWORD);
+
+      if handle.is_null() {
+        return GetLastError() != ERROR_INVALID_PARAMETER;
+      }
+
+      let mut exit_code: DWORD = 0;
+      let queried = GetExitCodeProcess(handle, &mut exit_code);
+      CloseHandle(handle);
+
+      queried == 0 || exit_code == STILL_ACTIVE
+    }
+  }
+}
+
+#[cfg(not(any(unix, target_os = "windows")))]
+mod platform {
+  pub fn is_alive(_pid: u64) -> bool {
+    true
+  }
+}
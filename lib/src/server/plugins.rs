@@ -0,0 +1,372 @@
+use serde::{Deserialize, Serialize};
+use std::{
+  fmt,
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+};
+
+use crate::{
+  cmd::Activity,
+  detection::{DetectableActivity, PresenceOverride},
+  log,
+};
+
+/**
+ * A running process, the way plugins are given it
+ */
+#[derive(Clone, Debug, Serialize)]
+pub struct PluginProcess {
+  pub pid: u64,
+  pub parent_pid: Option<u64>,
+  pub path: String,
+  pub cmd: Vec<String>,
+}
+
+/**
+ * A game a plugin found
+ */
+#[derive(Deserialize)]
+struct PluginDetection {
+  id: String,
+  name: String,
+  pid: Option<u64>,
+  presence: Option<PresenceOverride>,
+}
+
+struct LoadedPlugin {
+  plugin: runtime::WasmPlugin,
+  // Set once the plugin failed, it isn't called again
+  disabled: bool,
+}
+
+/**
+ * Every plugin that loaded, shared by the process scanner and the client connector. A plugin is a wasm32-unknown-unknown module exporting
+ * `memory`, `rsrpc_alloc(len) -> ptr` and `rsrpc_detect` and/or `rsrpc_transform`, which take the pointer and length of their JSON input
+ * and return `(ptr << 32) | len` of their JSON output, or 0 for nothing. See the README for what the JSON looks like.
+ */
+#[derive(Clone, Default)]
+pub struct Plugins {
+  plugins: Arc<Mutex<Vec<LoadedPlugin>>>,
+}
+
+impl fmt::Debug for Plugins {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let plugins = self.plugins.lock().unwrap();
+    f.debug_list()
+      .entries(plugins.iter().map(|loaded| loaded.plugin.name()))
+      .finish()
+  }
+}
+
+impl Plugins {
+  /**
+   * Load every plugin in `paths`, each one a .wasm file or a directory of them. Plugins that fail to load are logged and skipped.
+   */
+  pub fn load(paths: &[PathBuf]) -> Self {
+    let mut plugins = vec![];
+
+    for path in paths.iter().flat_map(|path| plugin_files(path)) {
+      match runtime::WasmPlugin::load(&path) {
+        Ok(plugin) => {
+          log!("[Plugins] Loaded {}", plugin.name());
+          plugins.push(LoadedPlugin {
+            plugin,
+            disabled: false,
+          });
+        }
+        Err(err) => log!(warn, "[Plugins] Could not load {}: {}", path.display(), err),
+      }
+    }
+
+    Plugins {
+      plugins: Arc::new(Mutex::new(plugins)),
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.plugins.lock().unwrap().is_empty()
+  }
+
+  /**
+   * Every game the plugins found among `processes`
+   */
+  pub fn detect(&self, processes: &[PluginProcess]) -> Vec<DetectableActivity> {
+    let mut plugins = self.plugins.lock().unwrap();
+
+    if plugins.is_empty() {
+      return vec![];
+    }
+
+    let input = match serde_json::to_vec(processes) {
+      Ok(input) => input,
+      Err(err) => {
+        log!(
+          error,
+          "[Plugins] Error serializing the process list: {}",
+          err
+        );
+        return vec![];
+      }
+    };
+    let mut detected = vec![];
+
+    for loaded in plugins.iter_mut().filter(|loaded| !loaded.disabled) {
+      let output = match loaded.plugin.detect(&input) {
+        Ok(Some(output)) => output,
+        Ok(None) => continue,
+        Err(err) => {
+          loaded.disable(err);
+          continue;
+        }
+      };
+
+      match serde_json::from_slice::<Vec<PluginDetection>>(&output) {
+        Ok(games) => detected.extend(games.into_iter().map(|game| {
+          let mut activity = DetectableActivity::empty(&game.id, &game.name);
+          activity.pid = game.pid;
+          activity.presence = game.presence;
+          activity
+        })),
+        Err(err) => log!(
+          warn,
+          "[Plugins] {} returned invalid detections: {}",
+          loaded.plugin.name(),
+          err
+        ),
+      }
+    }
+
+    detected
+  }
+
+  /**
+   * Let every plugin rewrite `activity` in turn, in the order they were loaded
+   */
+  pub fn transform(&self, activity: &mut Activity) {
+    let mut plugins = self.plugins.lock().unwrap();
+
+    for loaded in plugins.iter_mut().filter(|loaded| !loaded.disabled) {
+      let input = match serde_json::to_vec(&activity) {
+        Ok(input) => input,
+        Err(err) => {
+          log!(error, "[Plugins] Error serializing activity: {}", err);
+          return;
+        }
+      };
+
+      match loaded.plugin.transform(&input) {
+        Ok(Some(output)) => match serde_json::from_slice::<Activity>(&output) {
+          Ok(transformed) => *activity = transformed,
+          Err(err) => log!(
+            warn,
+            "[Plugins] {} returned an invalid activity: {}",
+            loaded.plugin.name(),
+            err
+          ),
+        },
+        Ok(None) => {}
+        Err(err) => loaded.disable(err),
+      }
+    }
+  }
+}
+
+impl LoadedPlugin {
+  fn disable(&mut self, err: Box<dyn std::error::Error>) {
+    log!(warn, "[Plugins] Disabling {}: {}", self.plugin.name(), err);
+    self.disabled = true;
+  }
+}
+
+/**
+ * `path` itself, or the .wasm files in it when it is a directory, sorted so they load in a predictable order
+ */
+fn plugin_files(path: &Path) -> Vec<PathBuf> {
+  if !path.is_dir() {
+    return vec![path.to_path_buf()];
+  }
+
+  let mut files: Vec<PathBuf> = match std::fs::read_dir(path) {
+    Ok(entries) => entries
+      .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+      .filter(|file| file.extension().map(|ext| ext == "wasm").unwrap_or(false))
+      .collect(),
+    Err(err) => {
+      log!(warn, "[Plugins] Could not read {}: {}", path.display(), err);
+      vec![]
+    }
+  };
+
+  files.sort();
+  files
+}
+
+#[cfg(feature = "wasm-plugins")]
+mod runtime {
+  use std::{error::Error, path::Path};
+  use wasmtime::{Caller, Config, Engine, Linker, Memory, Module, Store, TypedFunc};
+
+  use crate::log;
+
+  // Roughly how many instructions a plugin may run per call
+  const FUEL: u64 = 100_000_000;
+
+  pub struct WasmPlugin {
+    // The plugin's file name, also kept in the store for logging
+    name: String,
+    store: Store<String>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    detect: Option<TypedFunc<(i32, i32), i64>>,
+    transform: Option<TypedFunc<(i32, i32), i64>>,
+  }
+
+  impl WasmPlugin {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+      let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string());
+
+      let mut config = Config::new();
+      config.consume_fuel(true);
+
+      let engine = Engine::new(&config)?;
+      let module = Module::from_file(&engine, path)?;
+      let mut linker = Linker::new(&engine);
+
+      linker.func_wrap(
+        "env",
+        "rsrpc_log",
+        |mut caller: Caller<'_, String>, ptr: i32, len: i32| {
+          let memory = match caller
+            .get_export("memory")
+            .and_then(|export| export.into_memory())
+          {
+            Some(memory) => memory,
+            None => return,
+          };
+          if let Some(message) = guest_bytes(memory.data(&caller), ptr, len) {
+            log!(
+              "[Plugins] {}: {}",
+              caller.data(),
+              String::from_utf8_lossy(message)
+            );
+          }
+        },
+      )?;
+
+      let mut store = Store::new(&engine, name.clone());
+      store.set_fuel(FUEL)?;
+
+      let instance = linker.instantiate(&mut store, &module)?;
+      let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or("Plugin does not export its memory")?;
+      let alloc = instance.get_typed_func::<i32, i32>(&mut store, "rsrpc_alloc")?;
+      let detect = instance
+        .get_typed_func::<(i32, i32), i64>(&mut store, "rsrpc_detect")
+        .ok();
+      let transform = instance
+        .get_typed_func::<(i32, i32), i64>(&mut store, "rsrpc_transform")
+        .ok();
+
+      if detect.is_none() && transform.is_none() {
+        return Err("Plugin exports neither rsrpc_detect nor rsrpc_transform".into());
+      }
+
+      Ok(WasmPlugin {
+        name,
+        store,
+        memory,
+        alloc,
+        detect,
+        transform,
+      })
+    }
+
+    pub fn name(&self) -> &str {
+      &self.name
+    }
+
+    pub fn detect(&mut self, input: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+      match self.detect {
+        Some(func) => self.call(func, input),
+        None => Ok(None),
+      }
+    }
+
+    pub fn transform(&mut self, input: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+      match self.transform {
+        Some(func) => self.call(func, input),
+        None => Ok(None),
+      }
+    }
+
+    /**
+     * Copy `input` into the plugin, call `func` with it and copy out what it returned
+     */
+    fn call(
+      &mut self,
+      func: TypedFunc<(i32, i32), i64>,
+      input: &[u8],
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+      self.store.set_fuel(FUEL)?;
+
+      let len = input.len() as i32;
+      let ptr = self.alloc.call(&mut self.store, len)?;
+      self
+        .memory
+        .write(&mut self.store, ptr as u32 as usize, input)?;
+
+      let result = func.call(&mut self.store, (ptr, len))?;
+
+      if result == 0 {
+        return Ok(None);
+      }
+
+      let output = guest_bytes(
+        self.memory.data(&self.store),
+        (result >> 32) as i32,
+        result as i32,
+      )
+      .ok_or("Plugin returned output outside its memory")?;
+
+      Ok(Some(output.to_vec()))
+    }
+  }
+
+  /**
+   * The `len` bytes at `ptr` in a plugin's memory, or None if they don't fit in it.
+   * Only what is there is looked at, so a bogus length can't make the host allocate it.
+   */
+  fn guest_bytes(memory: &[u8], ptr: i32, len: i32) -> Option<&[u8]> {
+    let start = ptr as u32 as usize;
+    memory.get(start..start.checked_add(len as u32 as usize)?)
+  }
+}
+
+#[cfg(not(feature = "wasm-plugins"))]
+mod runtime {
+  use std::{error::Error, path::Path};
+
+  pub struct WasmPlugin;
+
+  impl WasmPlugin {
+    pub fn load(_path: &Path) -> Result<Self, Box<dyn Error>> {
+      Err("Built without the wasm-plugins feature".into())
+    }
+
+    pub fn name(&self) -> &str {
+      ""
+    }
+
+    pub fn detect(&mut self, _input: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+      Ok(None)
+    }
+
+    pub fn transform(&mut self, _input: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+      Ok(None)
+    }
+  }
+}
@@ -0,0 +1,130 @@
+use std::path::Path;
+
+use super::process::Exec;
+
+/**
+ * Every process in the procfs mounted at `root`, eg. a container's or the host's /proc as seen from a sandbox. The environment is only read when `environ` is set.
+ */
+pub fn list(root: &Path, environ: bool) -> Vec<Exec> {
+  platform::list(root, environ)
+}
+
+/**
+ * Whether the process `pid` is still in the procfs at `root`
+ */
+pub fn is_alive(root: &Path, pid: u64) -> bool {
+  root.join(pid.to_string()).exists()
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+  use std::{fs, path::Path};
+
+  use crate::{log, server::process::Exec};
+
+  /**
+   * When the system booted, in seconds since the epoch, from the `btime` line of /proc/stat
+   */
+  fn boot_time(root: &Path) -> Option<u64> {
+    fs::read_to_string(root.join("stat"))
+      .ok()?
+      .lines()
+      .find_map(|line| line.strip_prefix("btime "))
+      .and_then(|btime| btime.trim().parse().ok())
+  }
+
+  /**
+   * A NUL separated file, like cmdline and environ
+   */
+  fn read_list(path: &Path) -> Vec<String> {
+    fs::read(path)
+      .map(|bytes| {
+        bytes
+          .split(|byte| *byte == 0)
+          .filter(|part| !part.is_empty())
+          .map(|part| String::from_utf8_lossy(part).to_string())
+          .collect()
+      })
+      .unwrap_or_default()
+  }
+
+  /**
+   * The parent pid and start time (in clock ticks since boot) from /proc/<pid>/stat
+   */
+  fn read_stat(dir: &Path) -> Option<(u64, u64)> {
+    let stat = fs::read_to_string(dir.join("stat")).ok()?;
+    // The command name is in parentheses and can contain spaces, everything after it is space separated
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+
+    Some((fields.get(1)?.parse().ok()?, fields.get(19)?.parse().ok()?))
+  }
+
+  pub fn list(root: &Path, environ: bool) -> Vec<Exec> {
+    let entries = match fs::read_dir(root) {
+      Ok(entries) => entries,
+      Err(err) => {
+        log!(
+          warn,
+          "[Process Scanner] Could not read {}: {}",
+          root.display(),
+          err
+        );
+        return vec![];
+      }
+    };
+
+    let boot_time = boot_time(root).unwrap_or_default();
+    let ticks = match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+      ticks if ticks > 0 => ticks as u64,
+      _ => 100,
+    };
+
+    entries
+      .filter_map(|entry| entry.ok())
+      .filter_map(|entry| {
+        let pid: u64 = entry.file_name().to_str()?.parse().ok()?;
+        let dir = entry.path();
+        let cmd = read_list(&dir.join("cmdline"));
+        let path = fs::read_link(dir.join("exe"))
+          .map(|exe| exe.display().to_string())
+          .unwrap_or_default();
+
+        // Kernel threads
+        if path.is_empty() && cmd.is_empty() {
+          return None;
+        }
+
+        let (parent_pid, start_ticks) = read_stat(&dir).unwrap_or_default();
+        let start_time = if boot_time == 0 {
+          0
+        } else {
+          boot_time + start_ticks / ticks
+        };
+
+        Some(Exec::new(
+          pid,
+          path,
+          cmd,
+          if environ {
+            read_list(&dir.join("environ"))
+          } else {
+            vec![]
+          },
+          start_time,
+          Some(parent_pid).filter(|pid| *pid != 0),
+        ))
+      })
+      .collect()
+  }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+  use std::path::Path;
+
+  use crate::server::process::Exec;
+
+  pub fn list(_root: &Path, _environ: bool) -> Vec<Exec> {
+    vec![]
+  }
+}
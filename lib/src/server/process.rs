@@ -1,38 +1,149 @@
-use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 use std::sync::atomic::AtomicBool;
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::vec;
 use sysinfo::UpdateKind;
-use sysinfo::{ProcessRefreshKind, RefreshKind, System};
+use sysinfo::{Process, ProcessRefreshKind, RefreshKind, System};
 
 use crate::log;
-use crate::ProcessCallback;
 
 use super::super::DetectableActivity;
+use super::exe_hashes::HashCache;
+use super::executable_index::{Entry, ExecutableIndex};
+use super::ignore_list::IgnoreList;
+use super::plugins::PluginProcess;
+use super::process_snapshot::ProcessSnapshot;
+use super::process_types::{
+  DetectionProviders, ProcessDetectedEvent, ProcessEventListeners, ProcessOptions, ProcessPriority,
+  ProcessScanState,
+};
+use super::runtime;
+use super::shutdown::Shutdown;
+use super::steam::{is_steam_app, SteamApp, SteamLibrary};
+use super::{emulators, launchers, pid_watcher, proc_roots, process_events, window_titles, wine};
 
-#[derive(Default, Clone)]
-pub struct ProcessScanState {
-  pub obs_open: bool,
-}
+#[cfg(target_os = "macos")]
+use super::macos_bundles::BundleCache;
+use crate::detection::{compile_patterns, PresenceOverride, ProcessDetails};
 
-#[derive(Default)]
-pub struct ProcessEventListeners {
-  pub on_process_scan_complete: Option<Arc<Mutex<ProcessCallback>>>,
-}
+// How often processes are scanned anyway with process events, in case one was missed, unless the scan interval is longer
+const EVENT_POLL_INTERVAL: Duration = Duration::from_secs(60);
+// How often the focused window is checked, when following focus
+const FOCUS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// How often the detected processes are checked for having exited
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+// How long to wait after a process event for others to follow
+const EVENT_SETTLE_TIME: Duration = Duration::from_millis(500);
 
 #[derive(Clone)]
 pub struct Exec {
-  pid: u64,
-  path: String,
+  pub pid: u64,
+  pub path: String,
+  // The command line arguments, without the executable, joined by spaces
+  pub args: String,
+  pub cmd: Vec<String>,
+  // KEY=value, only read when launcher detection is on
+  pub environ: Vec<String>,
+  // Seconds since the epoch, 0 when the OS doesn't say
+  pub start_time: u64,
+  pub parent_pid: Option<u64>,
+}
+
+impl Exec {
+  pub fn from_process(pid: u64, process: &Process) -> Self {
+    Exec::new(
+      pid,
+      process.exe().unwrap_or(Path::new("")).display().to_string(),
+      process
+        .cmd()
+        .iter()
+        .map(|arg| arg.to_string_lossy().to_string())
+        .collect(),
+      process
+        .environ()
+        .iter()
+        .map(|var| var.to_string_lossy().to_string())
+        .collect(),
+      process.start_time(),
+      process.parent().map(|pid| pid.as_u32() as u64),
+    )
+  }
+
+  pub fn new(
+    pid: u64,
+    mut path: String,
+    cmd: Vec<String>,
+    environ: Vec<String>,
+    start_time: u64,
+    parent_pid: Option<u64>,
+  ) -> Self {
+    // Sandboxed processes often can't have their executable read, but their command line still says what they run
+    if path.is_empty() {
+      path = cmd.first().cloned().unwrap_or_default();
+    }
+
+    let mut args = cmd.iter().skip(1).cloned().collect::<Vec<_>>();
+
+    // Games running under Wine/Proton are matched by the Windows executable they run
+    if wine::is_loader(&path) {
+      if let Some((exe, exe_args)) = wine::windows_executable(&cmd) {
+        path = exe;
+        args = exe_args;
+      }
+    }
+
+    Exec {
+      pid,
+      path,
+      args: args.join(" "),
+      cmd,
+      environ,
+      start_time,
+      parent_pid,
+    }
+  }
+
+  /**
+   * When the process started, the way activity timestamps are sent. Now when unknown.
+   */
+  pub fn start_timestamp(&self) -> String {
+    if self.start_time == 0 {
+      return timestamp_now();
+    }
+
+    (self.start_time * 1000).to_string()
+  }
+
+  /**
+   * This process, as the one a game was detected in for `reason`, along with the `others` that also matched
+   */
+  pub fn details(&self, others: &[u64], reason: String) -> ProcessDetails {
+    let mut pids = vec![self.pid];
+    pids.extend(others.iter().filter(|pid| **pid != self.pid));
+
+    ProcessDetails {
+      path: self.path.clone(),
+      cmd: self.cmd.clone(),
+      parent_pid: self.parent_pid,
+      pids,
+      reason,
+    }
+  }
 }
 
+/**
+ * A game from an earlier scan, kept for the grace period after it stops being found
+ */
 #[derive(Clone)]
-pub struct ProcessDetectedEvent {
-  pub activity: DetectableActivity,
+struct RecentDetection {
+  activity: DetectableActivity,
+  last_seen: Instant,
+  // Not found in the last scan
+  missing: bool,
 }
 
 #[derive(Clone)]
@@ -42,6 +153,27 @@ pub struct ProcessServer {
   custom_detectables: Arc<Mutex<Vec<DetectableActivity>>>,
   thread_count: u16,
   scanning: Arc<AtomicBool>,
+  paused: Arc<AtomicBool>,
+  ignored: Arc<Mutex<IgnoreList>>,
+  // Everything detected recently, in the order it was first detected
+  recent: Arc<Mutex<Vec<RecentDetection>>>,
+  // The id of the game that had focus last
+  focused: Arc<Mutex<Option<String>>>,
+  providers: DetectionProviders,
+  snapshot: Arc<Mutex<ProcessSnapshot>>,
+  // Built on the first scan after the detectable list changed
+  index: Arc<Mutex<Option<ExecutableIndex>>>,
+  hashes: Arc<Mutex<HashCache>>,
+  shutdown: Shutdown,
+  // Wakes the scan loop early, on process events and when resumed
+  wake_sender: mpsc::Sender<()>,
+  wake: Arc<Mutex<mpsc::Receiver<()>>>,
+  options: ProcessOptions,
+  steam_library: Arc<Mutex<Option<SteamLibrary>>>,
+  // pid -> the Steam game it belongs to, as of the last scan
+  steam_processes: Arc<Mutex<HashMap<u64, SteamApp>>>,
+  #[cfg(target_os = "macos")]
+  bundles: Arc<Mutex<BundleCache>>,
 
   pub detectable_list: Vec<DetectableActivity>,
   pub event_sender: mpsc::Sender<ProcessDetectedEvent>,
@@ -57,10 +189,30 @@ impl ProcessServer {
     event_sender: mpsc::Sender<ProcessDetectedEvent>,
     thread_count: u16,
     event_listeners: ProcessEventListeners,
+    providers: DetectionProviders,
+    options: ProcessOptions,
   ) -> Self {
+    let (wake_sender, wake) = mpsc::channel();
+
     ProcessServer {
       scanning: Arc::new(AtomicBool::new(false)),
+      paused: Arc::new(AtomicBool::new(false)),
+      ignored: Arc::new(Mutex::new(IgnoreList::new(&options.ignored))),
+      recent: Arc::new(Mutex::new(vec![])),
+      focused: Arc::new(Mutex::new(None)),
+      providers,
+      snapshot: Arc::new(Mutex::new(ProcessSnapshot::default())),
+      index: Arc::new(Mutex::new(None)),
+      hashes: Arc::new(Mutex::new(HashCache::default())),
+      shutdown: Shutdown::default(),
+      wake_sender,
+      wake: Arc::new(Mutex::new(wake)),
       thread_count,
+      options,
+      steam_library: Arc::new(Mutex::new(None)),
+      steam_processes: Arc::new(Mutex::new(HashMap::new())),
+      #[cfg(target_os = "macos")]
+      bundles: Arc::new(Mutex::new(BundleCache::default())),
       detected_list: Arc::new(Mutex::new(vec![])),
       detectable_chunks: Arc::new(Mutex::new(vec![])),
       custom_detectables: Arc::new(Mutex::new(vec![])),
@@ -73,12 +225,15 @@ impl ProcessServer {
   }
 
   pub fn append_detectables(&mut self, mut detectable: Vec<DetectableActivity>) {
+    compile_patterns(&mut detectable);
+
     // Append to detectable chunks, since that's what is actually scanned
     self
       .custom_detectables
       .lock()
       .unwrap()
       .append(&mut detectable);
+    self.detectables_changed();
   }
 
   pub fn remove_detectable_by_name(&mut self, name: String) {
@@ -87,222 +242,1102 @@ impl ProcessServer {
       .lock()
       .unwrap()
       .retain(|x| x.name != name);
+    self.detectables_changed();
   }
 
-  pub fn start(&self) {
-    let wait_time = Duration::from_secs(10);
-    let clone = self.clone();
-    // Evenly split the detectable list into chunks
-    let mut chunks: Vec<Vec<DetectableActivity>> = vec![];
-
-    for _ in 0..self.thread_count {
-      chunks.push(vec![]);
+  /**
+   * Remove a detectable by id, from both the main list and the appended ones. A scan that is already running finishes first.
+   */
+  pub fn remove_detectable(&self, id: &str) {
+    for chunk in self.detectable_chunks.lock().unwrap().iter_mut() {
+      chunk.retain(|x| x.id != id);
     }
 
-    let mut i = 0;
+    self
+      .custom_detectables
+      .lock()
+      .unwrap()
+      .retain(|x| x.id != id);
+    self.detectables_changed();
+  }
+
+  /**
+   * Index and match every process again on the next scan
+   */
+  fn detectables_changed(&self) {
+    *self.index.lock().unwrap() = None;
+    self.snapshot.lock().unwrap().invalidate();
+  }
 
-    for obj in &self.detectable_list {
-      chunks[i].push(obj.clone());
+  pub fn clear_appended_detectables(&self) {
+    self.custom_detectables.lock().unwrap().clear();
+    self.detectables_changed();
+  }
 
-      i += 1;
+  /**
+   * Everything that is scanned for, the main list followed by the appended detectables
+   */
+  pub fn detectables(&self) -> Vec<DetectableActivity> {
+    let chunks = self.detectable_chunks.lock().unwrap();
+    let mut detectable: Vec<DetectableActivity> = chunks.iter().flatten().cloned().collect();
 
-      if i >= self.thread_count.into() {
-        i = 0;
+    detectable.extend(self.custom_detectables.lock().unwrap().iter().cloned());
+    detectable
+  }
+
+  /**
+   * Swap the list of detectables that is scanned for. A scan that is already running finishes with the old one.
+   */
+  pub fn replace_detectables(&self, detectable: Vec<DetectableActivity>) {
+    let chunks = chunk_detectables(&detectable, self.thread_count);
+    *self.detectable_chunks.lock().unwrap() = chunks;
+    self.detectables_changed();
+  }
+
+  pub fn start(&self) {
+    let mut wait_time = self.options.scan_interval;
+    let clone = self.clone();
+
+    *clone.detectable_chunks.lock().unwrap() =
+      chunk_detectables(&self.detectable_list, self.thread_count);
+
+    if self.options.event_driven {
+      match process_events::watch(self.wake_sender.clone(), self.shutdown.clone()) {
+        Ok(()) => {
+          log!("[Process Scanner] Watching for process events");
+          wait_time = wait_time.max(EVENT_POLL_INTERVAL);
+        }
+        Err(err) => log!(
+          warn,
+          "[Process Scanner] Process events unavailable, polling instead: {}",
+          err
+        ),
       }
     }
 
-    *clone.detectable_chunks.lock().unwrap() = chunks;
+    if self.options.follow_focus {
+      self.watch_focus();
+    }
+
+    if self.options.watch_exits {
+      self.watch_exits();
+    }
+
+    runtime::spawn(move || {
+      let wake = clone.wake.lock().unwrap();
+
+      // Run the process scan whenever a process starts or exits, or every `wait_time` regardless
+      while !clone.shutdown.is_stopped() {
+        if clone.is_paused() {
+          clone.wait_for_change(&wake, wait_time);
+          continue;
+        }
 
-    std::thread::spawn(move || {
-      // Run the process scan repeatedly (every 3 seconds)
-      loop {
         let detected = match clone.scan_for_processes() {
-          Ok(detected) => detected,
+          Ok(detected) => clone.apply_grace_period(detected),
           Err(err) => {
-            log!("[Process Scanner] Error while scanning processes: {}", err);
-            std::thread::sleep(wait_time);
+            log!(
+              warn,
+              "[Process Scanner] Error while scanning processes: {}",
+              err
+            );
+            clone.wait_for_change(&wake, wait_time);
             continue;
           }
         };
-        let mut new_game_detected = false;
-
-        // If the detected list has changed, send only the first element
-        if !detected.is_empty() {
-          let detected_list = clone.detected_list.lock().unwrap();
-
-          // If the detected list is empty, send the first element
-          if detected_list.is_empty() {
-            new_game_detected = true;
-            clone
-              .event_sender
-              .send(ProcessDetectedEvent {
-                activity: detected[0].clone(),
-              })
-              .unwrap();
-          } else {
-            // If the detected list is not empty, check if the first element is different
-            if detected[0].id != detected_list[0].id {
-              new_game_detected = true;
-            }
 
-            clone
-              .event_sender
-              .send(ProcessDetectedEvent {
-                activity: detected[0].clone(),
-              })
-              .unwrap();
-          }
+        // Paused during the scan, which already cleared the activity
+        if clone.is_paused() {
+          continue;
         }
 
-        // If there are no detected processes, send an empty message
-        if detected.is_empty() {
-          clone
-            .event_sender
-            .send(ProcessDetectedEvent {
-              activity: DetectableActivity {
-                bot_public: None,
-                bot_require_code_grant: None,
-                cover_image: None,
-                description: None,
-                developers: None,
-                executables: None,
-                flags: None,
-                guild_id: None,
-                hook: false,
-                icon: None,
-                id: "null".to_string(),
-                name: "".to_string(),
-                publishers: None,
-                rpc_origins: None,
-                splash: None,
-                third_party_skus: None,
-                type_field: None,
-                verify_key: None,
-                primary_sku_id: None,
-                slug: None,
-                aliases: None,
-                overlay: None,
-                overlay_compatibility_hook: None,
-                privacy_policy_url: None,
-                terms_of_service_url: None,
-                eula_id: None,
-                deeplink_uri: None,
-                tags: None,
-                pid: None,
-                timestamp: None,
-              },
-            })
-            .unwrap();
+        clone.publish(detected);
+
+        // Scan again when the grace period of a game that wasn't found runs out
+        let wait = clone
+          .grace_remaining()
+          .map(|remaining| remaining.min(wait_time))
+          .unwrap_or(wait_time);
+        clone.wait_for_change(&wake, wait);
+      }
+    });
+  }
+
+  /**
+   * Send the games that should be shown out of everything that was detected
+   */
+  fn publish(&self, mut detected: Vec<DetectableActivity>) {
+    self.options.priority.sort(&mut detected);
+
+    if self.options.follow_focus {
+      self.focus_first(&mut detected);
+    }
+
+    let shown = if self.options.priority == ProcessPriority::BroadcastAll {
+      detected.len()
+    } else {
+      detected.len().min(1)
+    };
+    let running: Vec<String> = detected[..shown]
+      .iter()
+      .map(|activity| activity.id.clone())
+      .collect();
+
+    for activity in &detected[..shown] {
+      self
+        .event_sender
+        .send(self.detected_event(activity.clone(), running.clone()))
+        .ok();
+    }
+
+    // If there are no detected processes, send an empty message
+    if detected.is_empty() {
+      self.event_sender.send(ProcessDetectedEvent::nothing()).ok();
+    }
+
+    let previous = std::mem::replace(&mut *self.detected_list.lock().unwrap(), detected.clone());
+    self.notify_detected(&previous, &detected);
+  }
+
+  /**
+   * Call on_process_detected for every game in `detected` that isn't in `previous`
+   */
+  fn notify_detected(&self, previous: &[DetectableActivity], detected: &[DetectableActivity]) {
+    let listeners = self.event_listeners.lock().unwrap();
+    let Some(callback) = listeners.on_process_detected.as_ref() else {
+      return;
+    };
+
+    for activity in detected
+      .iter()
+      .filter(|activity| !previous.iter().any(|known| known.id == activity.id))
+    {
+      callback.lock().unwrap()(activity.clone());
+    }
+  }
+
+  /**
+   * Check whether the detected processes are still running between scans, so games that exit are cleared right away instead of on the next scan
+   */
+  fn watch_exits(&self) {
+    let clone = self.clone();
+
+    runtime::spawn(move || {
+      while clone.shutdown.sleep(EXIT_POLL_INTERVAL) {
+        if clone.is_paused() {
+          continue;
+        }
+
+        let detected = clone.detected_list.lock().unwrap().clone();
+        let exited: Vec<u64> = detected
+          .iter()
+          .flat_map(watched_pids)
+          .filter(|pid| !clone.is_alive(*pid))
+          .collect();
+
+        if exited.is_empty() {
+          continue;
         }
 
-        if new_game_detected {
-          // Set the detected list to the new list
-          *clone.detected_list.lock().unwrap() = detected;
+        // Games with several processes are running until all of them exited
+        let is_running = |activity: &DetectableActivity| {
+          let pids = watched_pids(activity);
+          pids.is_empty() || pids.iter().any(|pid| !exited.contains(pid))
+        };
+
+        for activity in detected.iter().filter(|activity| !is_running(activity)) {
+          log!("[Process Scanner] {} exited", activity.name);
         }
 
-        std::thread::sleep(wait_time);
+        // An exit isn't a missed scan, so there's no grace period
+        clone
+          .recent
+          .lock()
+          .unwrap()
+          .retain(|known| is_running(&known.activity));
+        clone.publish(detected.into_iter().filter(is_running).collect());
       }
     });
   }
 
-  pub fn process_list() -> Vec<Exec> {
-    let mut processes = Vec::new();
-    let sys = System::new_with_specifics(
-      RefreshKind::nothing()
-        .with_processes(ProcessRefreshKind::nothing().with_exe(UpdateKind::Always)),
-    );
+  /**
+   * Move the game that has (or last had) focus to the front
+   */
+  fn focus_first(&self, detected: &mut Vec<DetectableActivity>) {
+    let mut focused = self.focused.lock().unwrap();
+
+    if let Some(pid) = window_titles::foreground_pid() {
+      if let Some(activity) = detected.iter().find(|activity| activity.pid == Some(pid)) {
+        *focused = Some(activity.id.clone());
+      }
+    }
+
+    let Some(id) = focused.as_ref() else {
+      return;
+    };
+
+    if let Some(position) = detected.iter().position(|activity| activity.id == *id) {
+      let activity = detected.remove(position);
+      detected.insert(0, activity);
+    }
+  }
+
+  /**
+   * Scan again whenever focus moves to a game that isn't the one shown. Focus changes aren't process events, so this polls.
+   */
+  fn watch_focus(&self) {
+    let clone = self.clone();
+
+    runtime::spawn(move || {
+      let mut last_pid = None;
+
+      while clone.shutdown.sleep(FOCUS_POLL_INTERVAL) {
+        let pid = window_titles::foreground_pid();
+
+        if pid == last_pid {
+          continue;
+        }
+
+        last_pid = pid;
+
+        let detected_list = clone.detected_list.lock().unwrap();
+        let Some(focused) = detected_list
+          .iter()
+          .find(|activity| pid.is_some() && activity.pid == pid)
+        else {
+          continue;
+        };
+
+        let shown = detected_list.first().map(|activity| &activity.id);
+
+        if shown != Some(&focused.id) {
+          clone.wake_sender.send(()).ok();
+        }
+      }
+    });
+  }
+
+  /**
+   * Keep the games from earlier scans that weren't found this time but are still in their grace period, and the timestamps of the ones that were
+   */
+  fn apply_grace_period(&self, detected: Vec<DetectableActivity>) -> Vec<DetectableActivity> {
+    let now = Instant::now();
+    let mut recent = self.recent.lock().unwrap();
+    let mut kept: Vec<RecentDetection> = vec![];
+
+    for previous in recent.drain(..) {
+      if let Some(found) = detected
+        .iter()
+        .find(|found| found.id == previous.activity.id)
+      {
+        let mut activity = found.clone();
+        activity.timestamp = previous.activity.timestamp.or(activity.timestamp);
+
+        kept.push(RecentDetection {
+          activity,
+          last_seen: now,
+          missing: false,
+        });
+      } else if now.duration_since(previous.last_seen) < self.options.grace_period {
+        kept.push(RecentDetection {
+          missing: true,
+          ..previous
+        });
+      } else {
+        log!(
+          "[Process Scanner] {} is no longer running",
+          previous.activity.name
+        );
+      }
+    }
+
+    for found in detected {
+      if !kept.iter().any(|known| known.activity.id == found.id) {
+        kept.push(RecentDetection {
+          activity: found,
+          last_seen: now,
+          missing: false,
+        });
+      }
+    }
+
+    let activities = kept.iter().map(|known| known.activity.clone()).collect();
+    *recent = kept;
+
+    activities
+  }
+
+  /**
+   * How long until the first game that wasn't found in the last scan is dropped
+   */
+  fn grace_remaining(&self) -> Option<Duration> {
+    let now = Instant::now();
+
+    self
+      .recent
+      .lock()
+      .unwrap()
+      .iter()
+      .filter(|known| known.missing)
+      .map(|known| (known.last_seen + self.options.grace_period).saturating_duration_since(now))
+      .min()
+  }
+
+  /**
+   * Wait until a process event comes in or `timeout` passes. Games usually start a few processes at once, so this waits for that to settle before scanning once for all of them.
+   */
+  fn wait_for_change(&self, wake: &mpsc::Receiver<()>, timeout: Duration) {
+    if self.shutdown.recv_timeout(wake, timeout).is_none() {
+      return;
+    }
+
+    self.shutdown.sleep(EVENT_SETTLE_TIME);
+    while wake.try_recv().is_ok() {}
+  }
+
+  fn detected_event(
+    &self,
+    mut activity: DetectableActivity,
+    running: Vec<String>,
+  ) -> ProcessDetectedEvent {
+    let steam_app = activity
+      .pid
+      .and_then(|pid| self.steam_processes.lock().unwrap().get(&pid).cloned());
+
+    ProcessDetectedEvent {
+      process: activity.process.take(),
+      activity,
+      steam_app,
+      running,
+    }
+  }
+
+  /**
+   * Find the running processes that are part of a Steam game, and detect the games Discord knows by their app id that weren't detected by executable
+   */
+  fn detect_steam_games(
+    &self,
+    processes: &[Exec],
+    detectable: &[Vec<DetectableActivity>],
+    detected_list: &mut Vec<DetectableActivity>,
+  ) {
+    let mut library = self.steam_library.lock().unwrap();
+
+    if library
+      .as_ref()
+      .map(|library| library.is_stale())
+      .unwrap_or(true)
+    {
+      *library = Some(SteamLibrary::load());
+    }
+
+    let Some(library) = library.as_ref() else {
+      return;
+    };
+
+    let steam_processes: HashMap<u64, SteamApp> = processes
+      .iter()
+      .filter_map(|process| {
+        library
+          .app_for(&process.path)
+          .map(|app| (process.pid, app.clone()))
+      })
+      .collect();
+
+    let custom = self.custom_detectables.lock().unwrap();
+
+    for (pid, app) in &steam_processes {
+      let already_detected = detected_list
+        .iter()
+        .any(|detected| detected.pid == Some(*pid) || is_steam_app(detected, &app.app_id));
+
+      if already_detected {
+        continue;
+      }
+
+      let found = detectable
+        .iter()
+        .flatten()
+        .chain(custom.iter())
+        .find(|detectable| is_steam_app(detectable, &app.app_id));
+
+      if let Some(found) = found {
+        log!(
+          "[Process Scanner] Detected {} by its Steam app id {}",
+          found.name,
+          app.app_id
+        );
+
+        let mut new_activity = found.clone();
+        let pids: Vec<u64> = steam_processes
+          .iter()
+          .filter(|(_, other)| other.app_id == app.app_id)
+          .map(|(pid, _)| *pid)
+          .collect();
+
+        new_activity.pid = Some(*pid);
+        new_activity.timestamp = Some(start_timestamp(processes, *pid));
+        new_activity.process = process_details(
+          processes,
+          *pid,
+          &pids,
+          format!("Steam app id {}", app.app_id),
+        );
+        detected_list.push(new_activity);
+      }
+    }
+
+    *self.steam_processes.lock().unwrap() = steam_processes;
+  }
+
+  /**
+   * Detect the games Heroic and Lutris say they are running, by title. Games Discord doesn't know get an activity with just the title.
+   */
+  fn detect_launcher_games(
+    &self,
+    processes: &[Exec],
+    detectable: &[Vec<DetectableActivity>],
+    detected_list: &mut Vec<DetectableActivity>,
+  ) {
+    let custom = self.custom_detectables.lock().unwrap();
+
+    for game in launchers::running_games(processes) {
+      let is_title = |name: &String| name.eq_ignore_ascii_case(&game.title);
+      let known = detectable
+        .iter()
+        .flatten()
+        .chain(custom.iter())
+        .find(|detectable| {
+          is_title(&detectable.name)
+            || detectable
+              .aliases
+              .as_ref()
+              .map(|aliases| aliases.iter().any(is_title))
+              .unwrap_or(false)
+        });
+
+      let mut new_activity = match known {
+        Some(known) => known.clone(),
+        None => DetectableActivity::empty(
+          &format!("{}:{}", game.launcher.name(), game.title),
+          &game.title,
+        ),
+      };
+
+      // Found by its executable already
+      if detected_list
+        .iter()
+        .any(|detected| detected.id == new_activity.id)
+      {
+        continue;
+      }
+
+      log!(
+        "[Process Scanner] Detected {} through {}",
+        game.title,
+        game.launcher.name()
+      );
+
+      new_activity.pid = Some(game.pid);
+      new_activity.timestamp = Some(start_timestamp(processes, game.pid));
+      new_activity.process = process_details(
+        processes,
+        game.pid,
+        &[],
+        format!("running in {}", game.launcher.name()),
+      );
+      detected_list.push(new_activity);
+    }
+  }
+
+  /**
+   * Show what emulators are running, as the game when Discord knows it by its title, or otherwise on top of the emulator's own activity
+   */
+  fn detect_emulator_games(
+    &self,
+    processes: &[Exec],
+    detectable: &[Vec<DetectableActivity>],
+    detected_list: &mut Vec<DetectableActivity>,
+  ) {
+    let custom = self.custom_detectables.lock().unwrap();
+
+    for game in emulators::running_games(processes) {
+      let is_title = |name: &String| name.eq_ignore_ascii_case(&game.title);
+      let known = detectable
+        .iter()
+        .flatten()
+        .chain(custom.iter())
+        .find(|detectable| {
+          is_title(&detectable.name)
+            || detectable
+              .aliases
+              .as_ref()
+              .map(|aliases| aliases.iter().any(is_title))
+              .unwrap_or(false)
+        });
+      // The emulator itself, if it was detected by its executable
+      let emulator = detected_list
+        .iter()
+        .position(|detected| detected.pid == Some(game.pid));
+
+      log!(
+        "[Process Scanner] {} is running {}",
+        game.emulator,
+        game.display_name()
+      );
+
+      let mut new_activity = match (known, emulator) {
+        (Some(known), _) => known.clone(),
+        (None, Some(emulator)) => detected_list.remove(emulator),
+        (None, None) => DetectableActivity::empty(
+          &format!("{}:{}", game.emulator.to_lowercase(), game.title),
+          game.emulator,
+        ),
+      };
+
+      if known.is_some() {
+        // The game replaces the emulator
+        detected_list.retain(|detected| detected.pid != Some(game.pid));
+
+        if detected_list
+          .iter()
+          .any(|detected| detected.id == new_activity.id)
+        {
+          continue;
+        }
+      }
 
-    for proc in sys.processes() {
-      processes.push(Exec {
-        pid: proc.0.to_string().parse::<u64>().unwrap(),
-        path: proc.1.exe().unwrap_or(Path::new("")).display().to_string(),
+      if new_activity.presence.is_none() {
+        new_activity.presence = Some(PresenceOverride {
+          name: Some(game.display_name()),
+          details: Some(game.title.clone()),
+          state: game.system.clone().or_else(|| game.core.clone()),
+          ..Default::default()
+        });
+      }
+
+      new_activity.pid = Some(game.pid);
+      new_activity.timestamp = Some(start_timestamp(processes, game.pid));
+      new_activity.process = process_details(
+        processes,
+        game.pid,
+        &[],
+        format!("running in {}", game.emulator),
+      );
+      detected_list.push(new_activity);
+    }
+  }
+
+  /**
+   * Detect apps by the identifier and names of the .app bundle they run from
+   */
+  #[cfg(target_os = "macos")]
+  fn detect_bundles(
+    &self,
+    processes: &[Exec],
+    detectable: &[Vec<DetectableActivity>],
+    detected_list: &mut Vec<DetectableActivity>,
+  ) {
+    let custom = self.custom_detectables.lock().unwrap();
+    let mut bundles = self.bundles.lock().unwrap();
+    // lowercase darwin executable or app name -> detectable
+    let mut names: HashMap<String, &DetectableActivity> = HashMap::new();
+
+    for detectable in detectable.iter().flatten().chain(custom.iter()) {
+      names.insert(detectable.name.to_lowercase(), detectable);
+
+      for executable in detectable.executables.iter().flatten() {
+        if executable.os == "darwin" {
+          names.insert(executable.name.to_lowercase(), detectable);
+        }
+      }
+    }
+
+    for process in processes {
+      let Some(bundle) = bundles.bundle_for(&process.path) else {
+        continue;
+      };
+
+      let Some(found) = bundle
+        .names()
+        .iter()
+        .find_map(|name| names.get(name).copied())
+      else {
+        continue;
+      };
+
+      if detected_list.iter().any(|detected| detected.id == found.id) {
+        continue;
+      }
+
+      log!(
+        "[Process Scanner] Detected {} by its bundle {}",
+        found.name,
+        bundle.path.display()
+      );
+
+      let mut new_activity = found.clone();
+      new_activity.pid = Some(process.pid);
+      new_activity.timestamp = Some(process.start_timestamp());
+      new_activity.process =
+        Some(process.details(&[], format!("bundle {}", bundle.path.display())));
+      detected_list.push(new_activity);
+    }
+  }
+
+  /**
+   * Add what the detection providers say is running
+   */
+  fn poll_providers(&self, detected_list: &mut Vec<DetectableActivity>) {
+    for provider in self.providers.lock().unwrap().iter_mut() {
+      for mut activity in provider.poll() {
+        if detected_list
+          .iter()
+          .any(|detected| detected.id == activity.id)
+        {
+          continue;
+        }
+
+        if activity.timestamp.is_none() {
+          activity.timestamp = Some(timestamp_now());
+        }
+
+        detected_list.push(activity);
+      }
+    }
+  }
+
+  /**
+   * Ask the plugins for games among the running processes
+   */
+  fn detect_by_plugins(&self, processes: &[Exec], detected_list: &mut Vec<DetectableActivity>) {
+    if self.options.plugins.is_empty() {
+      return;
+    }
+
+    let plugin_processes: Vec<PluginProcess> = processes
+      .iter()
+      .map(|process| PluginProcess {
+        pid: process.pid,
+        parent_pid: process.parent_pid,
+        path: process.path.clone(),
+        cmd: process.cmd.clone(),
+      })
+      .collect();
+
+    for mut activity in self.options.plugins.detect(&plugin_processes) {
+      if detected_list
+        .iter()
+        .any(|detected| detected.id == activity.id)
+      {
+        continue;
+      }
+
+      log!("[Process Scanner] Plugin detected {}", activity.name);
+
+      activity.timestamp = Some(match activity.pid {
+        Some(pid) => start_timestamp(processes, pid),
+        None => timestamp_now(),
       });
+      activity.process = activity
+        .pid
+        .and_then(|pid| process_details(processes, pid, &[], "plugin".to_string()));
+      detected_list.push(activity);
+    }
+  }
+
+  /**
+   * Detect games by the title of their window, as a last resort when nothing was found by executable
+   */
+  fn detect_by_window_title(
+    &self,
+    processes: &[Exec],
+    detectable: &[Vec<DetectableActivity>],
+    detected_list: &mut Vec<DetectableActivity>,
+  ) {
+    let windows = window_titles::list();
+
+    if windows.is_empty() {
+      return;
+    }
+
+    let custom = self.custom_detectables.lock().unwrap();
+    // lowercase name or alias -> detectable
+    let mut names: HashMap<String, &DetectableActivity> = HashMap::new();
+
+    for detectable in detectable.iter().flatten().chain(custom.iter()) {
+      names.insert(detectable.name.to_lowercase(), detectable);
+
+      for alias in detectable.aliases.iter().flatten() {
+        names.insert(alias.to_lowercase(), detectable);
+      }
+    }
+
+    for window in windows {
+      let found = window_titles::candidates(&window.title)
+        .iter()
+        .find_map(|candidate| names.get(candidate).copied());
+
+      let Some(found) = found else {
+        continue;
+      };
+
+      if detected_list.iter().any(|detected| detected.id == found.id) {
+        continue;
+      }
+
+      log!(
+        "[Process Scanner] Detected {} by the window title {:?}",
+        found.name,
+        window.title
+      );
+
+      let mut new_activity = found.clone();
+      new_activity.pid = Some(window.pid);
+      new_activity.timestamp = Some(start_timestamp(processes, window.pid));
+      new_activity.process = process_details(
+        processes,
+        window.pid,
+        &[],
+        format!("window title {:?}", window.title),
+      );
+      detected_list.push(new_activity);
+    }
+  }
+
+  /**
+   * Stop scanning. A scan that is already running is finished first.
+   */
+  pub fn stop(&self) {
+    self.shutdown.stop();
+  }
+
+  /**
+   * Stop scanning and clear the detected activity, until resumed
+   */
+  pub fn pause(&self) {
+    if self.paused.swap(true, std::sync::atomic::Ordering::SeqCst) {
+      return;
+    }
+
+    log!("[Process Scanner] Paused");
+
+    self.detected_list.lock().unwrap().clear();
+    self.recent.lock().unwrap().clear();
+    self.event_sender.send(ProcessDetectedEvent::nothing()).ok();
+  }
+
+  /**
+   * Start scanning again, right away
+   */
+  pub fn resume(&self) {
+    if !self.paused.swap(false, std::sync::atomic::Ordering::SeqCst) {
+      return;
+    }
+
+    log!("[Process Scanner] Resumed");
+    self.recent.lock().unwrap().clear();
+    self.wake_sender.send(()).ok();
+  }
+
+  /**
+   * Whether `pid` is still running here or in one of the other proc roots
+   */
+  fn is_alive(&self, pid: u64) -> bool {
+    pid_watcher::is_alive(pid)
+      || self
+        .options
+        .proc_roots
+        .iter()
+        .any(|root| proc_roots::is_alive(root, pid))
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.paused.load(std::sync::atomic::Ordering::SeqCst)
+  }
+
+  /**
+   * Every game the last scan found, the shown ones first
+   */
+  pub fn detected(&self) -> Vec<DetectableActivity> {
+    self.detected_list.lock().unwrap().clone()
+  }
+
+  /**
+   * Replace the ignore list, and scan again so newly ignored games are cleared right away
+   */
+  pub fn set_ignored(&self, ignored: &[String]) {
+    *self.ignored.lock().unwrap() = IgnoreList::new(ignored);
+    // Otherwise newly ignored games would linger for the grace period
+    self.recent.lock().unwrap().clear();
+    self.wake_sender.send(()).ok();
+  }
+
+  pub fn process_list(options: &ProcessOptions) -> Vec<Exec> {
+    let mut refresh = ProcessRefreshKind::nothing()
+      .with_exe(UpdateKind::Always)
+      .with_cmd(UpdateKind::Always);
+
+    if options.launcher_detection {
+      refresh = refresh.with_environ(UpdateKind::Always);
+    }
+
+    let sys = System::new_with_specifics(RefreshKind::nothing().with_processes(refresh));
+
+    sys
+      .processes()
+      .iter()
+      .map(|(pid, process)| Exec::from_process(pid.as_u32() as u64, process))
+      .collect()
+  }
+
+  /**
+   * Match `processes` against the executables of every detectable
+   */
+  fn match_executables(
+    &self,
+    chunks: &[Vec<DetectableActivity>],
+    processes: &[Exec],
+  ) -> Vec<DetectableActivity> {
+    let custom = self.custom_detectables.lock().unwrap();
+    let mut lists: Vec<&[DetectableActivity]> =
+      chunks.iter().map(|chunk| chunk.as_slice()).collect();
+    lists.push(&custom);
+
+    let mut index = self.index.lock().unwrap();
+    let index = index.get_or_insert_with(|| {
+      log!("[Process Scanner] Indexing detectable executables");
+      ExecutableIndex::build(&lists)
+    });
+
+    // (list, position) -> (executable, process) of every match
+    let mut matches: BTreeMap<(usize, usize), Vec<(usize, usize)>> = BTreeMap::new();
+    let mut hashes = self.hashes.lock().unwrap();
+
+    for (process_index, process) in processes.iter().enumerate() {
+      // Process path (but consistent slashes, so we can compare properly)
+      let process_path = process.path.to_lowercase().replace('\\', "/");
+      let os = executable_os(&process_path);
+      // Whether any of `entries` is this process, recording the ones that are
+      let mut try_entries = |entries: &[Entry], by_hash: bool| {
+        let mut matched = false;
+
+        for entry in entries {
+          let Some(executable) = lists
+            .get(entry.list)
+            .and_then(|list| list.get(entry.position))
+            .and_then(|detectable| detectable.executables.as_ref())
+            .and_then(|executables| executables.get(entry.executable))
+          else {
+            continue;
+          };
+
+          // Glob and regex names are matched here, the others were looked up by name
+          if !by_hash
+            && (!executable.is_for_os(os) || executable.matches_path(&process_path) == Some(false))
+          {
+            continue;
+          }
+
+          // Eg. Java games, which all run as java.exe and only differ in the jar they run
+          if let Some(arguments) = &executable.arguments {
+            if !process.args.contains(arguments.as_str()) {
+              continue;
+            }
+          }
+
+          matches
+            .entry((entry.list, entry.position))
+            .or_default()
+            .push((entry.executable, process_index));
+          matched = true;
+        }
+
+        matched
+      };
+
+      let matched = try_entries(&index.candidates(&process_path), false);
+
+      // Only processes that matched nothing by name are hashed, since reading the file is slow
+      if !matched && index.has_hashes() {
+        if let Some(hash) = hashes.hash(&process.path) {
+          try_entries(index.by_hash(&hash), true);
+        }
+      }
     }
 
-    processes
+    matches
+      .into_iter()
+      .map(|((list, position), mut found)| {
+        // The first executable of the detectable that is running wins, like it always has
+        found.sort();
+
+        let mut pids: Vec<u64> = vec![];
+        for (_, process_index) in &found {
+          let pid = processes[*process_index].pid;
+
+          if !pids.contains(&pid) {
+            pids.push(pid);
+          }
+        }
+
+        let (executable, process_index) = found[0];
+        let process = &processes[process_index];
+        let mut new_activity = lists[list][position].clone();
+        let reason = new_activity
+          .executables
+          .as_ref()
+          .and_then(|executables| executables.get(executable))
+          .map(|executable| executable.describe())
+          .unwrap_or_default();
+
+        new_activity.pid = Some(process.pid);
+        new_activity.timestamp = Some(process.start_timestamp());
+        new_activity.process = Some(process.details(&pids, reason));
+        new_activity
+      })
+      .collect()
+  }
+
+  /**
+   * The activities of the `processes` that matched a detectable in an earlier scan, the oldest process of each first
+   */
+  fn known_matches(
+    &self,
+    chunks: &[Vec<DetectableActivity>],
+    processes: &[Exec],
+    snapshot: &ProcessSnapshot,
+  ) -> Vec<DetectableActivity> {
+    let mut known: Vec<&Exec> = processes
+      .iter()
+      .filter(|process| !snapshot.matches(process).is_empty())
+      .collect();
+
+    if known.is_empty() {
+      return vec![];
+    }
+
+    known.sort_by_key(|process| process.start_time);
+
+    let custom = self.custom_detectables.lock().unwrap();
+    let mut detected_list: Vec<DetectableActivity> = vec![];
+
+    for process in known {
+      for id in snapshot.matches(process) {
+        if let Some(detected) = detected_list.iter_mut().find(|detected| detected.id == *id) {
+          if let Some(details) = detected.process.as_mut() {
+            details.pids.push(process.pid);
+          }
+          continue;
+        }
+
+        let Some(detectable) = chunks
+          .iter()
+          .flatten()
+          .chain(custom.iter())
+          .find(|detectable| detectable.id == *id)
+        else {
+          continue;
+        };
+
+        let mut new_activity = detectable.clone();
+        new_activity.pid = Some(process.pid);
+        new_activity.timestamp = Some(process.start_timestamp());
+        new_activity.process =
+          Some(process.details(&[], "executable, matched in an earlier scan".to_string()));
+        detected_list.push(new_activity);
+      }
+    }
+
+    detected_list
   }
 
   pub fn scan_for_processes(&self) -> Result<Vec<DetectableActivity>, Box<dyn std::error::Error>> {
     let chunks = self.detectable_chunks.lock().unwrap();
-    let processes = ProcessServer::process_list();
+    let ignored = self.ignored.lock().unwrap().clone();
+    let mut snapshot = self.snapshot.lock().unwrap();
+    let (ignored_processes, processes): (Vec<Exec>, Vec<Exec>) = snapshot
+      .refresh(&self.options)
+      .into_iter()
+      .partition(|process| ignored.ignores_process(&process.path));
 
-    log!("[Process Scanner] Process scan triggered");
+    log!(debug, "[Process Scanner] Process scan triggered");
 
     if self.scanning.load(std::sync::atomic::Ordering::Relaxed) {
-      log!("[Process Scanner] Scanning already in progress");
+      log!(debug, "[Process Scanner] Scanning already in progress");
       return Err("Scanning already in progress".into());
     }
 
-    let process_scan_state = Mutex::new(ProcessScanState::default());
+    let process_scan_state = ProcessScanState {
+      obs_open: processes.iter().any(|process| {
+        let process_path = process.path.to_lowercase();
+        process_path.contains("obs64") || process_path.contains("streamlabs")
+      }),
+    };
 
-    let mut detected_list: Vec<DetectableActivity> = (0..self.thread_count + 1)
-      .into_par_iter()
-      .flat_map(|i| {
-        // if this is the last thread, we are supposed to scan the custom detectables
-        let detectable_chunk: &Vec<DetectableActivity> = if self.thread_count == i {
-          &self.custom_detectables.lock().unwrap()
-        } else {
-          &chunks[i as usize]
-        };
+    // Only the processes that appeared since the last scan need to be matched, the others matched the same as before
+    let new_processes: Vec<Exec> = processes
+      .iter()
+      .filter(|process| !snapshot.is_known(process))
+      .cloned()
+      .collect();
+    let matched = if new_processes.is_empty() {
+      vec![]
+    } else {
+      self.match_executables(&chunks, &new_processes)
+    };
+
+    snapshot.record(&new_processes, &matched);
+    snapshot.retain(&processes);
+
+    let mut detected_list = self.known_matches(&chunks, &processes, &snapshot);
+    drop(snapshot);
+
+    for activity in matched {
+      match detected_list
+        .iter_mut()
+        .find(|detected| detected.id == activity.id)
+      {
+        // Running since an earlier scan, the new process is just another one of it
+        Some(detected) => {
+          if let (Some(details), Some(new)) = (detected.process.as_mut(), activity.process) {
+            details.pids.extend(new.pids);
+          }
+        }
+        None => detected_list.push(activity),
+      }
+    }
+
+    // The darwin executables in the detectable list are rarely the binary inside the bundle
+    #[cfg(target_os = "macos")]
+    self.detect_bundles(&processes, &chunks, &mut detected_list);
+
+    if self.options.steam_detection {
+      self.detect_steam_games(&processes, &chunks, &mut detected_list);
+    }
+
+    if self.options.launcher_detection {
+      self.detect_launcher_games(&processes, &chunks, &mut detected_list);
+    }
+
+    if self.options.emulator_detection {
+      self.detect_emulator_games(&processes, &chunks, &mut detected_list);
+    }
+
+    if self.options.window_title_detection && detected_list.is_empty() {
+      self.detect_by_window_title(&processes, &chunks, &mut detected_list);
+    }
+
+    self.poll_providers(&mut detected_list);
+    self.detect_by_plugins(&processes, &mut detected_list);
 
-        detectable_chunk
+    // Window titles aren't matched against processes, so they could still find ignored ones
+    detected_list.retain(|detected| {
+      !ignored.ignores_application(&detected.id)
+        && !ignored_processes
           .iter()
-          .filter_map(|obj| {
-            let mut new_activity = obj.clone();
-
-            if let Some(executables) = &obj.executables {
-              for executable in executables {
-                std::thread::sleep(Duration::from_millis(5));
-
-                let exec_path = executable.name.replace('\\', "/");
-
-                for process in &processes {
-                  // Process path (but consistent slashes, so we can compare properly)
-                  let process_path = process.path.to_lowercase().replace('\\', "/");
-
-                  //log!("[Process Scanner] Process path: {}", process_path);
-
-                  if process_path.contains("obs64") || process_path.contains("streamlabs") {
-                    process_scan_state.lock().unwrap().obs_open = true;
-                  }
-
-                  // If the exec_path is, in fact, a path, we can do a partial match
-                  let found = if exec_path.contains('/') {
-                    !process_path.is_empty()
-                      && (process_path.contains(&exec_path)
-                        || name_no_ext(&process_path).contains(&exec_path))
-                  } else {
-                    // Get the full name of the exec by getting the filename from the path
-                    let proc_exec_name = process_path
-                      .split('/')
-                      .last()
-                      .unwrap_or("UNKNOWN_GAME_PATH")
-                      .to_string();
-                    // If the exec_path is not a path, we need to do a full match, or else things like "abcd.exe" would match "cd.exe"
-                    proc_exec_name == exec_path || name_no_ext(&proc_exec_name) == exec_path
-                  };
-
-                  if !found {
-                    continue;
-                  }
-
-                  new_activity.pid = Some(process.pid);
-                  new_activity.timestamp = Some(format!(
-                    "{:?}",
-                    std::time::SystemTime::now()
-                      .duration_since(std::time::UNIX_EPOCH)
-                      .unwrap()
-                      .as_millis()
-                  ));
-                  return Some(new_activity);
-                }
-              }
-            }
-            None
-          })
-          .collect::<Vec<DetectableActivity>>()
-      })
-      .collect();
+          .any(|process| detected.pid == Some(process.pid))
+    });
 
     if let Some(callback) = self
       .event_listeners
@@ -311,17 +1346,110 @@ impl ProcessServer {
       .on_process_scan_complete
       .as_ref()
     {
-      callback.lock().unwrap()(process_scan_state.lock().unwrap().clone());
+      callback.lock().unwrap()(process_scan_state);
     }
 
     detected_list.shrink_to_fit();
 
-    log!("[Process Scanner] Process scan complete");
+    log!(debug, "[Process Scanner] Process scan complete");
 
     Ok(detected_list)
   }
 }
 
+/**
+ * Milliseconds since the epoch, the way activity timestamps are sent
+ */
+fn timestamp_now() -> String {
+  format!(
+    "{:?}",
+    std::time::SystemTime::now()
+      .duration_since(std::time::UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_millis()
+  )
+}
+
+/**
+ * When the process `pid` started, or now if it isn't in `processes`
+ */
+fn start_timestamp(processes: &[Exec], pid: u64) -> String {
+  processes
+    .iter()
+    .find(|process| process.pid == pid)
+    .map(|process| process.start_timestamp())
+    .unwrap_or_else(timestamp_now)
+}
+
+/**
+ * The processes to check, to know whether `activity` is still running
+ */
+fn watched_pids(activity: &DetectableActivity) -> Vec<u64> {
+  match activity.process.as_ref() {
+    Some(process) => process.pids.clone(),
+    None => activity.pid.into_iter().collect(),
+  }
+}
+
+/**
+ * The details of the process `pid`, if it is in `processes`
+ */
+fn process_details(
+  processes: &[Exec],
+  pid: u64,
+  others: &[u64],
+  reason: String,
+) -> Option<ProcessDetails> {
+  processes
+    .iter()
+    .find(|process| process.pid == pid)
+    .map(|process| process.details(others, reason))
+}
+
+/**
+ * Evenly split the detectable list into a chunk per scanning thread
+ */
+fn chunk_detectables(
+  detectable: &[DetectableActivity],
+  thread_count: u16,
+) -> Vec<Vec<DetectableActivity>> {
+  let mut chunks: Vec<Vec<DetectableActivity>> = vec![];
+
+  for _ in 0..thread_count {
+    chunks.push(vec![]);
+  }
+
+  let mut detectable = detectable.to_vec();
+  compile_patterns(&mut detectable);
+
+  let mut i = 0;
+
+  for obj in detectable {
+    chunks[i].push(obj);
+
+    i += 1;
+
+    if i >= thread_count.into() {
+      i = 0;
+    }
+  }
+
+  chunks
+}
+
+/**
+ * The `os` of the detectable executables the process at `path` (lowercase) can be. Windows ones for .exe files, which run under Wine or Proton elsewhere.
+ */
+fn executable_os(path: &str) -> &'static str {
+  if path.ends_with(".exe") || cfg!(target_os = "windows") {
+    "win32"
+  } else if cfg!(target_os = "macos") {
+    "darwin"
+  } else {
+    "linux"
+  }
+}
+
 pub fn name_no_ext(name: &String) -> String {
   if name.contains('.') {
     // Split the name by the dot
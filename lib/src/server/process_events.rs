@@ -0,0 +1,346 @@
+use std::{error::Error, sync::mpsc};
+
+use super::shutdown::Shutdown;
+
+/**
+ * Send to `wake` whenever a process starts (or on some platforms, exits), until stopped. Fails when the platform's event source isn't available, eg. without the privileges it needs, in which case only polling is left.
+ */
+pub fn watch(wake: mpsc::Sender<()>, shutdown: Shutdown) -> Result<(), Box<dyn Error>> {
+  platform::watch(wake, shutdown)
+}
+
+/**
+ * The netlink proc connector, which needs CAP_NET_ADMIN
+ */
+#[cfg(target_os = "linux")]
+mod platform {
+  use std::{error::Error, io, mem, sync::mpsc};
+
+  use crate::{
+    log,
+    server::{runtime, shutdown::Shutdown},
+  };
+
+  // linux/connector.h and linux/cn_proc.h
+  const CN_IDX_PROC: u32 = 1;
+  const CN_VAL_PROC: u32 = 1;
+  const PROC_CN_MCAST_LISTEN: u32 = 1;
+  const PROC_EVENT_EXEC: u32 = 0x0000_0002;
+  const PROC_EVENT_EXIT: u32 = 0x8000_0000;
+
+  // sizeof(struct nlmsghdr) and sizeof(struct cn_msg)
+  const NLMSG_HEADER_LEN: usize = 16;
+  const CN_MSG_LEN: usize = 20;
+
+  fn listen_message() -> Vec<u8> {
+    let len = NLMSG_HEADER_LEN + CN_MSG_LEN + 4;
+    let mut message = Vec::with_capacity(len);
+
+    // nlmsghdr
+    message.extend_from_slice(&(len as u32).to_ne_bytes());
+    message.extend_from_slice(&(libc::NLMSG_DONE as u16).to_ne_bytes());
+    message.extend_from_slice(&0u16.to_ne_bytes());
+    message.extend_from_slice(&0u32.to_ne_bytes());
+    message.extend_from_slice(&std::process::id().to_ne_bytes());
+
+    // cn_msg
+    message.extend_from_slice(&CN_IDX_PROC.to_ne_bytes());
+    message.extend_from_slice(&CN_VAL_PROC.to_ne_bytes());
+    message.extend_from_slice(&0u32.to_ne_bytes());
+    message.extend_from_slice(&0u32.to_ne_bytes());
+    message.extend_from_slice(&4u16.to_ne_bytes());
+    message.extend_from_slice(&0u16.to_ne_bytes());
+
+    message.extend_from_slice(&PROC_CN_MCAST_LISTEN.to_ne_bytes());
+
+    message
+  }
+
+  fn read_u32(buffer: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buffer.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+  }
+
+  /**
+   * Whether any of the netlink messages in `buffer` is about a process starting or exiting
+   */
+  fn has_process_event(buffer: &[u8]) -> bool {
+    let mut offset = 0;
+
+    while let Some(len) = read_u32(buffer, offset) {
+      let len = len as usize;
+
+      if len < NLMSG_HEADER_LEN {
+        break;
+      }
+
+      // proc_event.what comes right after the cn_msg header
+      if let Some(what) = read_u32(buffer, offset + NLMSG_HEADER_LEN + CN_MSG_LEN) {
+        if what == PROC_EVENT_EXEC || what == PROC_EVENT_EXIT {
+          return true;
+        }
+      }
+
+      // Messages are 4 byte aligned
+      offset += (len + 3) & !3;
+    }
+
+    false
+  }
+
+  pub fn watch(wake: mpsc::Sender<()>, shutdown: Shutdown) -> Result<(), Box<dyn Error>> {
+    let fd = unsafe {
+      libc::socket(
+        libc::AF_NETLINK,
+        libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+        libc::NETLINK_CONNECTOR,
+      )
+    };
+
+    if fd < 0 {
+      return Err(io::Error::last_os_error().into());
+    }
+
+    let fail = |fd: i32| -> Box<dyn Error> {
+      let err = io::Error::last_os_error();
+      unsafe { libc::close(fd) };
+      err.into()
+    };
+
+    let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    address.nl_family = libc::AF_NETLINK as u16;
+    address.nl_groups = CN_IDX_PROC;
+
+    let bound = unsafe {
+      libc::bind(
+        fd,
+        &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+        mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+      )
+    };
+
+    if bound < 0 {
+      return Err(fail(fd));
+    }
+
+    let message = listen_message();
+    let sent = unsafe {
+      libc::send(
+        fd,
+        message.as_ptr() as *const libc::c_void,
+        message.len(),
+        0,
+      )
+    };
+
+    if sent < 0 {
+      return Err(fail(fd));
+    }
+
+    // Wake up regularly to notice being stopped
+    let timeout = libc::timeval {
+      tv_sec: 0,
+      tv_usec: 250_000,
+    };
+    let set = unsafe {
+      libc::setsockopt(
+        fd,
+        libc::SOL_SOCKET,
+        libc::SO_RCVTIMEO,
+        &timeout as *const libc::timeval as *const libc::c_void,
+        mem::size_of::<libc::timeval>() as libc::socklen_t,
+      )
+    };
+
+    if set < 0 {
+      return Err(fail(fd));
+    }
+
+    runtime::spawn(move || {
+      let mut buffer = [0u8; 4096];
+
+      while !shutdown.is_stopped() {
+        let len = unsafe {
+          libc::recv(
+            fd,
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            buffer.len(),
+            0,
+          )
+        };
+
+        if len < 0 {
+          let err = io::Error::last_os_error();
+
+          match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted => {
+              continue
+            }
+            _ => {
+              log!(
+                warn,
+                "[Process Events] Stopped watching for processes: {}",
+                err
+              );
+              break;
+            }
+          }
+        }
+
+        if has_process_event(&buffer[..len as usize]) && wake.send(()).is_err() {
+          break;
+        }
+      }
+
+      unsafe { libc::close(fd) };
+    });
+
+    Ok(())
+  }
+}
+
+/**
+ * kqueue can only watch processes that already exist, so this watches launchd, which starts apps, for forks
+ */
+#[cfg(target_os = "macos")]
+mod platform {
+  use std::{error::Error, io, mem, ptr, sync::mpsc};
+
+  use crate::{
+    log,
+    server::{runtime, shutdown::Shutdown},
+  };
+
+  pub fn watch(wake: mpsc::Sender<()>, shutdown: Shutdown) -> Result<(), Box<dyn Error>> {
+    let kq = unsafe { libc::kqueue() };
+
+    if kq < 0 {
+      return Err(io::Error::last_os_error().into());
+    }
+
+    let mut change: libc::kevent = unsafe { mem::zeroed() };
+    change.ident = 1;
+    change.filter = libc::EVFILT_PROC;
+    change.flags = libc::EV_ADD | libc::EV_CLEAR;
+    change.fflags = libc::NOTE_FORK | libc::NOTE_EXEC;
+
+    let added = unsafe { libc::kevent(kq, &change, 1, ptr::null_mut(), 0, ptr::null()) };
+
+    if added < 0 {
+      let err = io::Error::last_os_error();
+      unsafe { libc::close(kq) };
+      return Err(err.into());
+    }
+
+    runtime::spawn(move || {
+      // Wake up regularly to notice being stopped
+      let timeout = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 250_000_000,
+      };
+
+      while !shutdown.is_stopped() {
+        let mut event: libc::kevent = unsafe { mem::zeroed() };
+        let count = unsafe { libc::kevent(kq, ptr::null(), 0, &mut event, 1, &timeout) };
+
+        if count < 0 {
+          let err = io::Error::last_os_error();
+
+          if err.kind() == io::ErrorKind::Interrupted {
+            continue;
+          }
+
+          log!(
+            warn,
+            "[Process Events] Stopped watching for processes: {}",
+            err
+          );
+          break;
+        }
+
+        if count > 0 && wake.send(()).is_err() {
+          break;
+        }
+      }
+
+      unsafe { libc::close(kq) };
+    });
+
+    Ok(())
+  }
+}
+
+/**
+ * WMI process creation and deletion events
+ */
+#[cfg(target_os = "windows")]
+mod platform {
+  use std::{collections::HashMap, error::Error, sync::mpsc};
+  use wmi::{COMLibrary, Variant, WMIConnection};
+
+  use crate::{
+    log,
+    server::{runtime, shutdown::Shutdown},
+  };
+
+  static QUERY: &str = "SELECT * FROM __InstanceOperationEvent WITHIN 1 \
+    WHERE (__CLASS = '__InstanceCreationEvent' OR __CLASS = '__InstanceDeletionEvent') \
+    AND TargetInstance ISA 'Win32_Process'";
+
+  pub fn watch(wake: mpsc::Sender<()>, shutdown: Shutdown) -> Result<(), Box<dyn Error>> {
+    let (ready_sender, ready) = mpsc::channel();
+
+    // COM has to be used from the thread it was set up on
+    runtime::spawn(move || {
+      let connection = match COMLibrary::new().and_then(WMIConnection::new) {
+        Ok(connection) => connection,
+        Err(err) => {
+          ready_sender.send(Err(err.to_string())).ok();
+          return;
+        }
+      };
+
+      let events = match connection.raw_notification::<HashMap<String, Variant>>(QUERY) {
+        Ok(events) => events,
+        Err(err) => {
+          ready_sender.send(Err(err.to_string())).ok();
+          return;
+        }
+      };
+
+      ready_sender.send(Ok(())).ok();
+
+      // Blocks until the next event, so stopping only takes effect after one
+      for event in events {
+        if shutdown.is_stopped() {
+          break;
+        }
+
+        match event {
+          Ok(_) => {
+            if wake.send(()).is_err() {
+              break;
+            }
+          }
+          Err(err) => log!(warn, "[Process Events] Error receiving WMI event: {}", err),
+        }
+      }
+    });
+
+    ready
+      .recv()
+      .map_err(|_| "WMI thread exited")?
+      .map_err(|err| err.into())
+  }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+mod platform {
+  use std::{error::Error, sync::mpsc};
+
+  use crate::server::shutdown::Shutdown;
+
+  pub fn watch(_wake: mpsc::Sender<()>, _shutdown: Shutdown) -> Result<(), Box<dyn Error>> {
+    Err("Process events are not supported on this platform".into())
+  }
+}
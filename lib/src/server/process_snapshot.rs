@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind};
+
+use super::{proc_roots, process::Exec, process_types::ProcessOptions};
+use crate::detection::DetectableActivity;
+
+/**
+ * The process table as of the last scan, so only the processes that appeared since then are matched against the detectable list
+ */
+#[derive(Default)]
+pub struct ProcessSnapshot {
+  // Kept between scans, so the executable and command line are only read for new processes
+  system: Option<System>,
+  // (pid, start time) -> the ids of the detectables the process matched, empty for most of them
+  matches: HashMap<(u64, u64), Vec<String>>,
+}
+
+fn key(process: &Exec) -> (u64, u64) {
+  (process.pid, process.start_time)
+}
+
+impl ProcessSnapshot {
+  /**
+   * Every running process, followed by the ones in the other proc roots that aren't in the main list
+   */
+  pub fn refresh(&mut self, options: &ProcessOptions) -> Vec<Exec> {
+    let mut refresh = ProcessRefreshKind::nothing()
+      .with_exe(UpdateKind::OnlyIfNotSet)
+      .with_cmd(UpdateKind::OnlyIfNotSet);
+
+    if options.launcher_detection {
+      refresh = refresh.with_environ(UpdateKind::OnlyIfNotSet);
+    }
+
+    let system = self.system.get_or_insert_with(System::new);
+    system.refresh_processes_specifics(ProcessesToUpdate::All, true, refresh);
+
+    let mut processes: Vec<Exec> = system
+      .processes()
+      .iter()
+      .map(|(pid, process)| Exec::from_process(pid.as_u32() as u64, process))
+      .collect();
+
+    for root in &options.proc_roots {
+      for process in proc_roots::list(root, options.launcher_detection) {
+        // The same pid namespace mounted twice, or a pid that is taken in ours too
+        if !processes.iter().any(|known| known.pid == process.pid) {
+          processes.push(process);
+        }
+      }
+    }
+
+    processes
+  }
+
+  /**
+   * Whether `process` was matched in an earlier scan
+   */
+  pub fn is_known(&self, process: &Exec) -> bool {
+    self.matches.contains_key(&key(process))
+  }
+
+  /**
+   * The ids of the detectables `process` matched in an earlier scan
+   */
+  pub fn matches(&self, process: &Exec) -> &[String] {
+    self
+      .matches
+      .get(&key(process))
+      .map(|ids| ids.as_slice())
+      .unwrap_or_default()
+  }
+
+  /**
+   * Remember what the `processes` that were just matched were detected as
+   */
+  pub fn record(&mut self, processes: &[Exec], detected: &[DetectableActivity]) {
+    for process in processes {
+      let ids = detected
+        .iter()
+        .filter(|activity| {
+          activity
+            .process
+            .as_ref()
+            .map(|details| details.pids.contains(&process.pid))
+            .unwrap_or(false)
+        })
+        .map(|activity| activity.id.clone())
+        .collect();
+
+      self.matches.insert(key(process), ids);
+    }
+  }
+
+  /**
+   * Forget the processes that aren't in `processes` anymore
+   */
+  pub fn retain(&mut self, processes: &[Exec]) {
+    let running: Vec<(u64, u64)> = processes.iter().map(key).collect();
+    self.matches.retain(|key, _| running.contains(key));
+  }
+
+  /**
+   * Match every process again on the next scan, after the detectable list changed
+   */
+  pub fn invalidate(&mut self) {
+    self.matches.clear();
+  }
+}
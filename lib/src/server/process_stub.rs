@@ -0,0 +1,102 @@
+#![allow(dead_code)]
+
+use std::sync::{mpsc, Arc, Mutex};
+
+use super::process_types::{
+  DetectionProviders, ProcessDetectedEvent, ProcessEventListeners, ProcessOptions,
+};
+use crate::{detection::DetectableActivity, log};
+
+/**
+ * Stands in for the process scanner when built without the `process-scanning` feature. The detectable list is still kept, so the rest of the server can manage it, but nothing is ever detected.
+ */
+#[derive(Clone)]
+pub struct ProcessServer {
+  detectables: Arc<Mutex<Vec<DetectableActivity>>>,
+  custom_detectables: Arc<Mutex<Vec<DetectableActivity>>>,
+
+  pub detectable_list: Vec<DetectableActivity>,
+  pub event_sender: mpsc::Sender<ProcessDetectedEvent>,
+}
+
+impl ProcessServer {
+  pub fn new(
+    detectable: Vec<DetectableActivity>,
+    event_sender: mpsc::Sender<ProcessDetectedEvent>,
+    _thread_count: u16,
+    _event_listeners: ProcessEventListeners,
+    _providers: DetectionProviders,
+    _options: ProcessOptions,
+  ) -> Self {
+    ProcessServer {
+      detectables: Arc::new(Mutex::new(detectable.clone())),
+      custom_detectables: Arc::new(Mutex::new(vec![])),
+      detectable_list: detectable,
+      event_sender,
+    }
+  }
+
+  pub fn append_detectables(&mut self, mut detectable: Vec<DetectableActivity>) {
+    self
+      .custom_detectables
+      .lock()
+      .unwrap()
+      .append(&mut detectable);
+  }
+
+  pub fn remove_detectable_by_name(&mut self, name: String) {
+    self
+      .custom_detectables
+      .lock()
+      .unwrap()
+      .retain(|x| x.name != name);
+  }
+
+  pub fn remove_detectable(&self, id: &str) {
+    self.detectables.lock().unwrap().retain(|x| x.id != id);
+    self
+      .custom_detectables
+      .lock()
+      .unwrap()
+      .retain(|x| x.id != id);
+  }
+
+  pub fn clear_appended_detectables(&self) {
+    self.custom_detectables.lock().unwrap().clear();
+  }
+
+  pub fn detectables(&self) -> Vec<DetectableActivity> {
+    let mut detectable = self.detectables.lock().unwrap().clone();
+
+    detectable.extend(self.custom_detectables.lock().unwrap().iter().cloned());
+    detectable
+  }
+
+  pub fn replace_detectables(&self, detectable: Vec<DetectableActivity>) {
+    *self.detectables.lock().unwrap() = detectable;
+  }
+
+  pub fn start(&self) {
+    log!("[Process Scanner] Built without the process-scanning feature, not scanning");
+  }
+
+  pub fn stop(&self) {}
+
+  pub fn pause(&self) {}
+
+  pub fn resume(&self) {}
+
+  pub fn is_paused(&self) -> bool {
+    false
+  }
+
+  pub fn detected(&self) -> Vec<DetectableActivity> {
+    vec![]
+  }
+
+  pub fn set_ignored(&self, _ignored: &[String]) {}
+
+  pub fn scan_for_processes(&self) -> Result<Vec<DetectableActivity>, Box<dyn std::error::Error>> {
+    Err("Built without the process-scanning feature".into())
+  }
+}
@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+use std::{
+  path::PathBuf,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use super::{plugins::Plugins, steam::SteamApp};
+use crate::{
+  detection::{DetectableActivity, DetectionProvider, ProcessDetails},
+  DetectedCallback, ProcessCallback,
+};
+
+#[derive(Default, Clone)]
+pub struct ProcessScanState {
+  pub obs_open: bool,
+}
+
+// Shared with the server, so providers can be added while scanning
+pub type DetectionProviders = Arc<Mutex<Vec<Box<dyn DetectionProvider>>>>;
+
+#[derive(Default)]
+pub struct ProcessEventListeners {
+  pub on_process_scan_complete: Option<Arc<Mutex<ProcessCallback>>>,
+  // Called once for every game that wasn't detected by the previous scan
+  pub on_process_detected: Option<Arc<Mutex<DetectedCallback>>>,
+}
+
+#[derive(Clone)]
+pub struct ProcessDetectedEvent {
+  pub activity: DetectableActivity,
+  // The Steam game the detected process belongs to, if it is installed through Steam
+  pub steam_app: Option<SteamApp>,
+  // The ids of every game shown after this scan, any other process activity is cleared
+  pub running: Vec<String>,
+  // The process the game was found in, None for detection providers that didn't say
+  pub process: Option<ProcessDetails>,
+}
+
+impl ProcessDetectedEvent {
+  /**
+   * Nothing is running
+   */
+  pub fn nothing() -> Self {
+    Self {
+      activity: DetectableActivity::empty("null", ""),
+      steam_app: None,
+      running: vec![],
+      process: None,
+    }
+  }
+}
+
+/**
+ * Which game is shown when several are detected
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessPriority {
+  // The one that was detected first
+  FirstDetected,
+  // The process that started last
+  MostRecentlyStarted,
+  // By name
+  Alphabetical,
+  // The application ids earlier in the list, and the ones that aren't in it by when they were detected
+  Ordered(Vec<String>),
+  // Every game, each as its own activity
+  BroadcastAll,
+}
+
+impl ProcessPriority {
+  /**
+   * Sort `detected` so the game that should be shown comes first
+   */
+  pub fn sort(&self, detected: &mut [DetectableActivity]) {
+    match self {
+      ProcessPriority::FirstDetected | ProcessPriority::BroadcastAll => {}
+      ProcessPriority::MostRecentlyStarted => {
+        let started = |activity: &DetectableActivity| {
+          activity
+            .timestamp
+            .as_deref()
+            .and_then(|timestamp| timestamp.parse::<u128>().ok())
+            .unwrap_or_default()
+        };
+
+        detected.sort_by(|a, b| started(b).cmp(&started(a)));
+      }
+      ProcessPriority::Alphabetical => {
+        detected.sort_by_key(|activity| activity.name.to_lowercase());
+      }
+      ProcessPriority::Ordered(ids) => {
+        detected.sort_by_key(|activity| {
+          ids
+            .iter()
+            .position(|id| *id == activity.id)
+            .unwrap_or(ids.len())
+        });
+      }
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct ProcessOptions {
+  // Detect games by the Steam library they are installed in, for games Discord only knows by their Steam app id
+  pub steam_detection: bool,
+  // Ask Heroic and Lutris which games they are running
+  pub launcher_detection: bool,
+  // Show the game RetroArch is running instead of just RetroArch
+  pub emulator_detection: bool,
+  // Match window titles against detectable names, for games that can't be told apart by their executable (requires the `window-titles` feature)
+  pub window_title_detection: bool,
+  // Scan when a process starts or exits, polling only rarely as a fallback
+  pub event_driven: bool,
+  // How often processes are scanned without process events
+  pub scan_interval: Duration,
+  // Application ids, executable names and paths that are never detected
+  pub ignored: Vec<String>,
+  // How long a game stays detected after it was last found, so a scan that misses it doesn't reset its activity
+  pub grace_period: Duration,
+  pub priority: ProcessPriority,
+  // Put the game with the focused window first, and keep it first while focus is on something that isn't a game (requires the `window-titles` feature)
+  pub follow_focus: bool,
+  // Check whether detected games are still running every few hundred milliseconds, to clear them as soon as they exit
+  pub watch_exits: bool,
+  // Asked for games on top of the detectable list, with the process list
+  pub plugins: Plugins,
+  // Other procfs mounts whose processes are scanned too, eg. a container's (Linux only)
+  pub proc_roots: Vec<PathBuf>,
+}
+
+impl Default for ProcessOptions {
+  fn default() -> Self {
+    Self {
+      steam_detection: true,
+      launcher_detection: false,
+      emulator_detection: false,
+      window_title_detection: false,
+      event_driven: true,
+      scan_interval: Duration::from_secs(10),
+      ignored: vec![],
+      grace_period: Duration::from_secs(10),
+      priority: ProcessPriority::FirstDetected,
+      follow_focus: false,
+      watch_exits: true,
+      plugins: Plugins::default(),
+      proc_roots: vec![],
+    }
+  }
+}
@@ -0,0 +1,226 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+struct RateLimitState<T> {
+  last_sent: Option<Instant>,
+  pending: Option<T>,
+}
+
+impl<T> Default for RateLimitState<T> {
+  fn default() -> Self {
+    Self {
+      last_sent: None,
+      pending: None,
+    }
+  }
+}
+
+/**
+ * Limits how often each application can update its activity. Updates that come in too fast are coalesced, so only the latest one is sent once the application is allowed to again.
+ */
+pub struct ActivityRateLimiter<T = String> {
+  interval: Duration,
+  // application id -> state
+  state: Arc<Mutex<HashMap<String, RateLimitState<T>>>>,
+}
+
+// Derived Clone would require T: Clone, but only the Arc is cloned
+impl<T> Clone for ActivityRateLimiter<T> {
+  fn clone(&self) -> Self {
+    Self {
+      interval: self.interval,
+      state: self.state.clone(),
+    }
+  }
+}
+
+impl<T> ActivityRateLimiter<T> {
+  pub fn new(interval: Duration) -> Self {
+    Self {
+      interval,
+      state: Arc::new(Mutex::new(HashMap::new())),
+    }
+  }
+
+  /**
+   * Returns the payload if it can be sent right away, otherwise it is held until `take_ready()` hands it out
+   */
+  pub fn submit(&self, application_id: &str, payload: T) -> Option<T> {
+    if self.interval.is_zero() {
+      return Some(payload);
+    }
+
+    let mut state = self.state.lock().unwrap();
+    let entry = state.entry(application_id.to_string()).or_default();
+
+    match entry.last_sent {
+      Some(last_sent) if last_sent.elapsed() < self.interval => {
+        entry.pending = Some(payload);
+        None
+      }
+      _ => {
+        entry.last_sent = Some(Instant::now());
+        entry.pending = None;
+        Some(payload)
+      }
+    }
+  }
+
+  /**
+   * Drop anything still held for an application, so a clear is not followed by a stale update
+   */
+  pub fn clear(&self, application_id: &str) {
+    if let Some(entry) = self.state.lock().unwrap().get_mut(application_id) {
+      entry.pending = None;
+    }
+  }
+
+  /**
+   * Get all held payloads whose application is allowed to update again
+   */
+  pub fn take_ready(&self) -> Vec<T> {
+    let mut ready = vec![];
+
+    for entry in self.state.lock().unwrap().values_mut() {
+      let allowed = entry
+        .last_sent
+        .map(|last_sent| last_sent.elapsed() >= self.interval)
+        .unwrap_or(true);
+
+      if !allowed {
+        continue;
+      }
+
+      if let Some(payload) = entry.pending.take() {
+        entry.last_sent = Some(Instant::now());
+        ready.push(payload);
+      }
+    }
+
+    ready
+  }
+}
+
+/**
+ * Counts messages on a single connection, to drop clients that flood it
+ */
+#[cfg(feature = "ipc")]
+pub struct MessageRateLimiter {
+  limit: u32,
+  window: Duration,
+  window_start: Instant,
+  count: u32,
+}
+
+#[cfg(feature = "ipc")]
+impl MessageRateLimiter {
+  pub fn new(limit: u32, window: Duration) -> Self {
+    Self {
+      limit,
+      window,
+      window_start: Instant::now(),
+      count: 0,
+    }
+  }
+
+  /**
+   * Count a message, returns false if the connection went over the limit. A limit of zero disables it.
+   */
+  pub fn check(&mut self) -> bool {
+    if self.limit == 0 {
+      return true;
+    }
+
+    if self.window_start.elapsed() >= self.window {
+      self.window_start = Instant::now();
+      self.count = 0;
+    }
+
+    self.count += 1;
+    self.count <= self.limit
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn activity_limit_coalesces_updates() {
+    let limiter = ActivityRateLimiter::new(Duration::from_millis(50));
+
+    assert_eq!(limiter.submit("app", "first"), Some("first"));
+    assert_eq!(limiter.submit("app", "second"), None);
+    assert_eq!(limiter.submit("app", "third"), None);
+    // Other applications have limits of their own
+    assert_eq!(limiter.submit("other", "other"), Some("other"));
+    assert!(limiter.take_ready().is_empty());
+
+    std::thread::sleep(Duration::from_millis(60));
+
+    // Only the latest one is sent, and starts the next window
+    assert_eq!(limiter.take_ready(), vec!["third"]);
+    assert!(limiter.take_ready().is_empty());
+    assert_eq!(limiter.submit("app", "fourth"), None);
+  }
+
+  #[test]
+  fn activity_limit_clear_drops_pending() {
+    let limiter = ActivityRateLimiter::new(Duration::from_millis(50));
+
+    limiter.submit("app", "first");
+    limiter.submit("app", "second");
+    limiter.clear("app");
+
+    std::thread::sleep(Duration::from_millis(60));
+
+    assert!(limiter.take_ready().is_empty());
+    assert_eq!(limiter.submit("app", "third"), Some("third"));
+  }
+
+  #[test]
+  fn zero_activity_interval_is_disabled() {
+    let limiter = ActivityRateLimiter::new(Duration::ZERO);
+
+    assert!((0..100).all(|update| limiter.submit("app", update) == Some(update)));
+  }
+
+  #[cfg(feature = "ipc")]
+  #[test]
+  fn message_limit_drops_floods() {
+    let mut limiter = MessageRateLimiter::new(3, Duration::from_secs(60));
+
+    assert!(limiter.check());
+    assert!(limiter.check());
+    assert!(limiter.check());
+    assert!(!limiter.check());
+    assert!(!limiter.check());
+  }
+
+  #[cfg(feature = "ipc")]
+  #[test]
+  fn message_limit_rolls_over() {
+    let mut limiter = MessageRateLimiter::new(2, Duration::from_millis(50));
+
+    assert!(limiter.check());
+    assert!(limiter.check());
+    assert!(!limiter.check());
+
+    std::thread::sleep(Duration::from_millis(60));
+
+    assert!(limiter.check());
+    assert!(limiter.check());
+    assert!(!limiter.check());
+  }
+
+  #[cfg(feature = "ipc")]
+  #[test]
+  fn zero_message_limit_is_disabled() {
+    let mut limiter = MessageRateLimiter::new(0, Duration::from_secs(60));
+
+    assert!((0..1000).all(|_| limiter.check()));
+  }
+}
@@ -0,0 +1,147 @@
+#![cfg_attr(
+  not(all(
+    feature = "client-connector",
+    feature = "rpc-websocket",
+    feature = "ipc"
+  )),
+  allow(dead_code)
+)]
+
+use std::{
+  collections::{HashMap, HashSet},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+};
+
+use super::state::RpcClientState;
+use crate::cmd::ActivityCmd;
+
+static NEXT_SOCKET_ID: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * Get a new unique socket id. These are shared between the IPC and websocket connectors so they never collide.
+ */
+pub fn next_socket_id() -> String {
+  NEXT_SOCKET_ID.fetch_add(1, Ordering::Relaxed).to_string()
+}
+
+/**
+ * Anything that can deliver a (JSON) frame back to a connected RPC client
+ */
+pub trait RpcClient: Send {
+  fn send_frame(&self, data: String) -> bool;
+
+  /**
+   * Disconnect the client, eg. because the server is stopping
+   */
+  fn close(&self);
+
+  fn send_cmd(&self, cmd: &ActivityCmd) -> bool {
+    match serde_json::to_string(cmd) {
+      Ok(data) => self.send_frame(data),
+      Err(_) => false,
+    }
+  }
+}
+
+struct RpcClientEntry {
+  client: Box<dyn RpcClient>,
+  // Events the client SUBSCRIBEd to
+  subscriptions: HashSet<String>,
+}
+
+#[derive(Clone, Default)]
+pub struct RpcClients {
+  clients: Arc<Mutex<HashMap<String, RpcClientEntry>>>,
+}
+
+impl RpcClients {
+  pub fn register(&self, socket_id: String, client: Box<dyn RpcClient>) {
+    self.clients.lock().unwrap().insert(
+      socket_id,
+      RpcClientEntry {
+        client,
+        subscriptions: HashSet::new(),
+      },
+    );
+  }
+
+  pub fn unregister(&self, socket_id: &str) {
+    self.clients.lock().unwrap().remove(socket_id);
+  }
+
+  /**
+   * Every connected client and what it is subscribed to
+   */
+  pub fn list(&self) -> Vec<RpcClientState> {
+    let mut clients: Vec<RpcClientState> = self
+      .clients
+      .lock()
+      .unwrap()
+      .iter()
+      .map(|(socket_id, entry)| {
+        let mut subscriptions: Vec<String> = entry.subscriptions.iter().cloned().collect();
+        subscriptions.sort();
+
+        RpcClientState {
+          socket_id: socket_id.clone(),
+          subscriptions,
+        }
+      })
+      .collect();
+
+    clients.sort_by(|a, b| a.socket_id.cmp(&b.socket_id));
+    clients
+  }
+
+  /**
+   * Disconnect and forget every client
+   */
+  pub fn close_all(&self) {
+    for (_, entry) in self.clients.lock().unwrap().drain() {
+      entry.client.close();
+    }
+  }
+
+  /**
+   * Send a frame to the RPC client that owns the socket id. Returns false if there is no such client, or sending failed.
+   */
+  pub fn send(&self, socket_id: &str, data: String) -> bool {
+    match self.clients.lock().unwrap().get(socket_id) {
+      Some(entry) => entry.client.send_frame(data),
+      None => false,
+    }
+  }
+
+  /**
+   * Like `send()`, but only if the client is subscribed to the event
+   */
+  pub fn dispatch(&self, socket_id: &str, evt: &str, data: String) -> bool {
+    match self.clients.lock().unwrap().get(socket_id) {
+      Some(entry) if entry.subscriptions.contains(evt) => entry.client.send_frame(data),
+      _ => false,
+    }
+  }
+
+  pub fn subscribe(&self, socket_id: &str, evt: &str) -> bool {
+    match self.clients.lock().unwrap().get_mut(socket_id) {
+      Some(entry) => {
+        entry.subscriptions.insert(evt.to_string());
+        true
+      }
+      None => false,
+    }
+  }
+
+  pub fn unsubscribe(&self, socket_id: &str, evt: &str) -> bool {
+    match self.clients.lock().unwrap().get_mut(socket_id) {
+      Some(entry) => {
+        entry.subscriptions.remove(evt);
+        true
+      }
+      None => false,
+    }
+  }
+}
@@ -0,0 +1,144 @@
+use std::{
+  cell::RefCell,
+  panic::{self, AssertUnwindSafe},
+  sync::mpsc,
+};
+
+use crate::{log, Error};
+
+thread_local! {
+  // Where the tasks spawned from this thread report their errors
+  static ERRORS: RefCell<Option<mpsc::Sender<Error>>> = RefCell::new(None);
+}
+
+/**
+ * Run `task` in the background. Every server loop is started through here, so with the `async` feature they run on the
+ * blocking pool of the tokio runtime the server was started from (see `RPCServer::start_async()`), instead of on threads of their own.
+ * A task that panics is reported as `Error::Worker` to the server it belongs to, instead of taking anything else down with it.
+ */
+pub fn spawn(task: impl FnOnce() + Send + 'static) {
+  let errors = ERRORS.with(|errors| errors.borrow().clone());
+
+  platform::spawn(move || {
+    let errors = match errors {
+      Some(errors) => errors,
+      None => return task(),
+    };
+
+    with_errors(errors.clone(), || {
+      if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(task)) {
+        let message = panic
+          .downcast_ref::<&str>()
+          .map(|message| message.to_string())
+          .or_else(|| panic.downcast_ref::<String>().cloned())
+          .unwrap_or_else(|| "unknown panic".to_string());
+
+        log!(error, "[RPC Server] A worker stopped: {}", message);
+        errors.send(Error::Worker(message)).ok();
+      }
+    })
+  })
+}
+
+/**
+ * Run `f` with every task it spawns, and the ones they spawn, reporting errors to `errors`
+ */
+pub fn with_errors<T>(errors: mpsc::Sender<Error>, f: impl FnOnce() -> T) -> T {
+  let previous = ERRORS.with(|current| current.replace(Some(errors)));
+  let result = f();
+  ERRORS.with(|current| *current.borrow_mut() = previous);
+
+  result
+}
+
+#[cfg(feature = "async")]
+pub use platform::TaskGroup;
+
+#[cfg(not(feature = "async"))]
+mod platform {
+  pub fn spawn(task: impl FnOnce() + Send + 'static) {
+    std::thread::spawn(task);
+  }
+}
+
+#[cfg(feature = "async")]
+mod platform {
+  use std::{
+    cell::RefCell,
+    sync::{Arc, Mutex},
+    time::Duration,
+  };
+  use tokio::{runtime::Handle, task::JoinHandle};
+
+  thread_local! {
+    // The group tasks spawned from this thread belong to
+    static CURRENT: RefCell<Option<TaskGroup>> = RefCell::new(None);
+  }
+
+  /**
+   * Every task a server spawned on a tokio runtime, including the ones its tasks spawned, so they can be waited on when it stops
+   */
+  #[derive(Clone)]
+  pub struct TaskGroup {
+    handle: Handle,
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+  }
+
+  impl TaskGroup {
+    pub fn new(handle: Handle) -> Self {
+      TaskGroup {
+        handle,
+        tasks: Arc::new(Mutex::new(vec![])),
+      }
+    }
+
+    /**
+     * Run `f` with every task it spawns added to this group
+     */
+    pub fn enter<T>(&self, f: impl FnOnce() -> T) -> T {
+      let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+      let result = f();
+      CURRENT.with(|current| *current.borrow_mut() = previous);
+
+      result
+    }
+
+    fn spawn(&self, task: impl FnOnce() + Send + 'static) {
+      let group = self.clone();
+      let handle = self.handle.spawn_blocking(move || group.enter(task));
+      let mut tasks = self.tasks.lock().unwrap();
+
+      // Connections come and go, there's no need to remember the ones that are done
+      tasks.retain(|task| !task.is_finished());
+      tasks.push(handle);
+    }
+
+    /**
+     * Wait up to `timeout` for every task to finish, returning how many are still running. Safe to cancel, the tasks carry on either way.
+     */
+    pub async fn join(&self, timeout: Duration) -> usize {
+      let tasks: Vec<JoinHandle<()>> = self.tasks.lock().unwrap().drain(..).collect();
+      let count = tasks.len();
+      let mut finished = 0;
+
+      let wait = async {
+        for task in tasks {
+          task.await.ok();
+          finished += 1;
+        }
+      };
+
+      tokio::time::timeout(timeout, wait).await.ok();
+      count - finished
+    }
+  }
+
+  pub fn spawn(task: impl FnOnce() + Send + 'static) {
+    match CURRENT.with(|current| current.borrow().clone()) {
+      Some(group) => group.spawn(task),
+      None => {
+        std::thread::spawn(task);
+      }
+    }
+  }
+}
@@ -0,0 +1,91 @@
+use std::{
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{Receiver, RecvTimeoutError},
+    Arc, Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+// How often blocking loops check whether they should return
+pub const POLL_INTERVAL: Duration = Duration::from_millis(25);
+
+/**
+ * Tells every worker thread of a server to return. Clones share the same flag.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown {
+  stopped: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+  pub fn stop(&self) {
+    self.stopped.store(true, Ordering::SeqCst);
+  }
+
+  pub fn is_stopped(&self) -> bool {
+    self.stopped.load(Ordering::SeqCst)
+  }
+
+  /**
+   * Sleep for `duration`, or until stopped. Returns false if stopped.
+   */
+  pub fn sleep(&self, duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+
+    while !self.is_stopped() {
+      let now = Instant::now();
+
+      if now >= deadline {
+        return true;
+      }
+
+      std::thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+
+    false
+  }
+
+  /**
+   * Wait for the next message on a channel, or None once stopped or every sender is gone
+   */
+  pub fn recv<T>(&self, receiver: &Mutex<Receiver<T>>) -> Option<T> {
+    let receiver = receiver.lock().unwrap();
+
+    while !self.is_stopped() {
+      match receiver.recv_timeout(POLL_INTERVAL) {
+        Ok(message) => return Some(message),
+        Err(RecvTimeoutError::Timeout) => continue,
+        Err(RecvTimeoutError::Disconnected) => return None,
+      }
+    }
+
+    None
+  }
+
+  /**
+   * Wait up to `timeout` for the next message on a channel. None if it times out or is stopped first, and if every sender is gone, after waiting out the rest of `timeout`.
+   */
+  pub fn recv_timeout<T>(&self, receiver: &Receiver<T>, timeout: Duration) -> Option<T> {
+    let deadline = Instant::now() + timeout;
+
+    while !self.is_stopped() {
+      let now = Instant::now();
+
+      if now >= deadline {
+        return None;
+      }
+
+      match receiver.recv_timeout(POLL_INTERVAL.min(deadline - now)) {
+        Ok(message) => return Some(message),
+        Err(RecvTimeoutError::Timeout) => continue,
+        Err(RecvTimeoutError::Disconnected) => {
+          self.sleep(deadline - now);
+          return None;
+        }
+      }
+    }
+
+    None
+  }
+}
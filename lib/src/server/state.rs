@@ -0,0 +1,61 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::HashMap, net::IpAddr};
+
+use super::{arbitration::ActivitySource, client_connector::Subscription};
+use crate::{cmd::Activity, detection::DetectableActivity};
+
+/**
+ * A snapshot of a running RPCServer, returned by `RPCServer::state()`
+ */
+#[derive(Clone, Debug, Serialize)]
+pub struct ServerState {
+  // Seconds since the server was started
+  pub uptime_secs: u64,
+  // The port the client connector ended up listening on
+  pub connector_port: u16,
+  pub connector_clients: Vec<ConnectorClientState>,
+  // Everything connected over IPC or the RPC websocket
+  pub rpc_clients: Vec<RpcClientState>,
+  // What the connector clients were last sent, one per socket id
+  pub activities: Vec<ShownActivity>,
+  pub scanner: ScannerState,
+  // application id -> devices from SET_CERTIFIED_DEVICES
+  pub certified_devices: HashMap<String, Vec<Value>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ConnectorClientState {
+  pub client_id: u64,
+  pub peer_address: Option<IpAddr>,
+  pub subscription: Subscription,
+  // Seconds since the client last sent anything
+  pub idle_secs: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RpcClientState {
+  pub socket_id: String,
+  // Events the client SUBSCRIBEd to
+  pub subscriptions: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ShownActivity {
+  pub socket_id: String,
+  pub source: ActivitySource,
+  pub application_id: Option<String>,
+  pub pid: Option<u64>,
+  // None when the socket's activity was cleared
+  pub activity: Option<Activity>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ScannerState {
+  pub enabled: bool,
+  pub paused: bool,
+  // How many detectables are being scanned for
+  pub detectable_count: usize,
+  // Every game the last scan found, the shown ones first
+  pub detected: Vec<DetectableActivity>,
+}
@@ -0,0 +1,215 @@
+#![cfg_attr(not(feature = "process-scanning"), allow(dead_code))]
+
+use std::{
+  path::{Path, PathBuf},
+  time::SystemTime,
+};
+
+use crate::{detection::DetectableActivity, log};
+
+/**
+ * A game installed through Steam
+ */
+#[derive(Clone, Debug)]
+pub struct SteamApp {
+  pub app_id: String,
+  pub name: String,
+  pub install_dir: PathBuf,
+  // `install_dir` the way process paths are compared, lowercase with forward slashes and a trailing one
+  install_prefix: String,
+}
+
+/**
+ * Every game in every Steam library on this machine, to find which game a running executable belongs to
+ */
+#[derive(Clone, Debug, Default)]
+pub struct SteamLibrary {
+  pub apps: Vec<SteamApp>,
+  // (steamapps dir, when it last changed), to notice games being installed or removed
+  library_dirs: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+/**
+ * Where Steam is usually installed
+ */
+fn steam_roots() -> Vec<PathBuf> {
+  let mut roots = vec![];
+
+  #[cfg(target_os = "windows")]
+  {
+    for var in ["ProgramFiles(x86)", "ProgramFiles"] {
+      if let Some(dir) = std::env::var_os(var) {
+        roots.push(PathBuf::from(dir).join("Steam"));
+      }
+    }
+  }
+
+  #[cfg(target_os = "macos")]
+  {
+    if let Some(home) = dirs::home_dir() {
+      roots.push(home.join("Library/Application Support/Steam"));
+    }
+  }
+
+  #[cfg(target_os = "linux")]
+  {
+    if let Some(home) = dirs::home_dir() {
+      roots.push(home.join(".steam/steam"));
+      roots.push(home.join(".local/share/Steam"));
+      // Flatpak
+      roots.push(home.join(".var/app/com.valvesoftware.Steam/.local/share/Steam"));
+    }
+  }
+
+  roots.into_iter().filter(|root| root.is_dir()).collect()
+}
+
+/**
+ * The quoted key/value pairs of a VDF (KeyValues) file, in order. Nesting isn't needed for what is read from them.
+ */
+fn vdf_pairs(contents: &str) -> Vec<(String, String)> {
+  contents
+    .lines()
+    .filter_map(|line| {
+      let mut parts = line.trim().split('"').skip(1).step_by(2);
+      let key = parts.next()?;
+      let value = parts.next()?;
+
+      Some((key.to_lowercase(), value.replace("\\\\", "\\")))
+    })
+    .collect()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+  std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/**
+ * Compare paths the way the process scanner does
+ */
+fn normalize(path: &str) -> String {
+  path.to_lowercase().replace('\\', "/")
+}
+
+impl SteamLibrary {
+  pub fn load() -> Self {
+    let mut library_dirs: Vec<PathBuf> = vec![];
+
+    for root in steam_roots() {
+      let steamapps = root.join("steamapps");
+      let contents =
+        std::fs::read_to_string(steamapps.join("libraryfolders.vdf")).unwrap_or_default();
+
+      library_dirs.push(steamapps);
+      library_dirs.extend(
+        vdf_pairs(&contents)
+          .into_iter()
+          .filter(|(key, _)| key == "path")
+          .map(|(_, path)| PathBuf::from(path).join("steamapps")),
+      );
+    }
+
+    // ~/.steam/steam is usually a link to ~/.local/share/Steam
+    let mut seen = vec![];
+    library_dirs.retain(|dir| {
+      let canonical = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+      let new = dir.is_dir() && !seen.contains(&canonical);
+      seen.push(canonical);
+      new
+    });
+
+    let apps: Vec<SteamApp> = library_dirs
+      .iter()
+      .flat_map(|dir| read_manifests(dir))
+      .collect();
+
+    log!(
+      "[Steam] Found {} games in {} libraries",
+      apps.len(),
+      library_dirs.len()
+    );
+
+    Self {
+      apps,
+      library_dirs: library_dirs
+        .into_iter()
+        .map(|dir| {
+          let modified = modified(&dir);
+          (dir, modified)
+        })
+        .collect(),
+    }
+  }
+
+  /**
+   * Whether a game was installed or removed since the library was loaded
+   */
+  pub fn is_stale(&self) -> bool {
+    self
+      .library_dirs
+      .iter()
+      .any(|(dir, last_modified)| modified(dir) != *last_modified)
+  }
+
+  /**
+   * The game that the executable at `path` is part of
+   */
+  pub fn app_for(&self, path: &str) -> Option<&SteamApp> {
+    let path = normalize(path);
+
+    self
+      .apps
+      .iter()
+      .find(|app| path.starts_with(&app.install_prefix))
+  }
+}
+
+fn read_manifests(steamapps: &Path) -> Vec<SteamApp> {
+  let Ok(entries) = std::fs::read_dir(steamapps) else {
+    return vec![];
+  };
+
+  entries
+    .filter_map(|entry| entry.ok())
+    .filter(|entry| {
+      let name = entry.file_name().to_string_lossy().to_string();
+      name.starts_with("appmanifest_") && name.ends_with(".acf")
+    })
+    .filter_map(|entry| {
+      let contents = std::fs::read_to_string(entry.path()).ok()?;
+      let pairs = vdf_pairs(&contents);
+      let get = |key: &str| {
+        pairs
+          .iter()
+          .find(|(k, _)| k == key)
+          .map(|(_, value)| value.clone())
+      };
+
+      let install_dir = steamapps.join("common").join(get("installdir")?);
+      let mut install_prefix = normalize(&install_dir.display().to_string());
+      install_prefix.push('/');
+
+      Some(SteamApp {
+        app_id: get("appid")?,
+        name: get("name").unwrap_or_default(),
+        install_dir,
+        install_prefix,
+      })
+    })
+    .collect()
+}
+
+/**
+ * Whether Discord knows `detectable` as the Steam game `app_id`
+ */
+pub fn is_steam_app(detectable: &DetectableActivity, app_id: &str) -> bool {
+  detectable
+    .third_party_skus
+    .as_ref()
+    .map(|skus| {
+      skus
+        .iter()
+        .any(|sku| sku.distributor == "steam" && sku.id.as_deref() == Some(app_id))
+    })
+    .unwrap_or(false)
+}
@@ -0,0 +1,163 @@
+#![cfg_attr(not(feature = "rpc-websocket"), allow(dead_code))]
+
+use std::net::TcpListener;
+
+use super::{runtime, shutdown::Shutdown};
+use crate::log;
+
+/**
+ * The TCP listeners systemd passed in for socket activation (see systemd.socket), in the order the unit lists them. Empty when the server
+ * wasn't socket activated, or not on Linux. The sockets can only be taken once, later calls return nothing.
+ */
+pub fn listeners() -> Vec<TcpListener> {
+  platform::listeners()
+}
+
+/**
+ * Tell systemd about the server's state, eg. "READY=1", if it is running as a Type=notify service (see sd_notify(3)). Does nothing otherwise.
+ */
+pub fn notify(state: &str) {
+  platform::notify(state)
+}
+
+/**
+ * Ping systemd's watchdog at half the interval the service asks for with WatchdogSec=, until stopped
+ */
+pub fn watchdog(shutdown: Shutdown) {
+  let Some(interval) = platform::watchdog_interval() else {
+    return;
+  };
+
+  log!(
+    debug,
+    "[systemd] Pinging the watchdog every {:?}",
+    interval / 2
+  );
+
+  runtime::spawn(move || {
+    while shutdown.sleep(interval / 2) {
+      notify("WATCHDOG=1");
+    }
+  });
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+  use socket2::{Socket, Type};
+  use std::{
+    env,
+    net::TcpListener,
+    os::{
+      linux::net::SocketAddrExt,
+      unix::{
+        io::FromRawFd,
+        net::{SocketAddr, UnixDatagram},
+      },
+    },
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+  };
+
+  use crate::log;
+
+  // The first file descriptor systemd passes, after stdin, stdout and stderr
+  const LISTEN_FDS_START: i32 = 3;
+
+  // Set once the sockets were taken, so nothing takes them twice. The environment is left alone, since other threads may be reading it.
+  static LISTENERS_TAKEN: AtomicBool = AtomicBool::new(false);
+
+  /**
+   * Whether `pid_var` says systemd's variables are meant for this process, and not a parent they were inherited from
+   */
+  fn is_own(pid_var: &str) -> bool {
+    env::var(pid_var)
+      .ok()
+      .and_then(|pid| pid.parse::<u32>().ok())
+      == Some(std::process::id())
+  }
+
+  pub fn listeners() -> Vec<TcpListener> {
+    if !is_own("LISTEN_PID") || LISTENERS_TAKEN.swap(true, Ordering::SeqCst) {
+      return vec![];
+    }
+
+    let count = env::var("LISTEN_FDS")
+      .ok()
+      .and_then(|count| count.parse::<i32>().ok())
+      .unwrap_or(0);
+
+    let mut listeners = vec![];
+
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+      let socket = unsafe { Socket::from_raw_fd(fd) };
+      let is_tcp = socket.r#type().ok() == Some(Type::STREAM)
+        && socket
+          .local_addr()
+          .ok()
+          .and_then(|address| address.as_socket())
+          .is_some();
+
+      if !is_tcp {
+        log!(
+          warn,
+          "[systemd] Ignoring socket {}, only TCP sockets are supported",
+          fd
+        );
+        continue;
+      }
+
+      listeners.push(socket.into());
+    }
+
+    listeners
+  }
+
+  pub fn notify(state: &str) {
+    let Ok(path) = env::var("NOTIFY_SOCKET") else {
+      return;
+    };
+
+    // Sockets starting with @ are in the abstract namespace
+    let address = match path.strip_prefix('@') {
+      Some(name) => SocketAddr::from_abstract_name(name),
+      None => SocketAddr::from_pathname(&path),
+    };
+
+    let result = address.and_then(|address| {
+      let socket = UnixDatagram::unbound()?;
+      socket.send_to_addr(state.as_bytes(), &address)
+    });
+
+    if let Err(err) = result {
+      log!(warn, "[systemd] Failed to notify {}: {}", path, err);
+    }
+  }
+
+  pub fn watchdog_interval() -> Option<Duration> {
+    // WATCHDOG_PID is optional
+    if env::var("WATCHDOG_PID").is_ok() && !is_own("WATCHDOG_PID") {
+      return None;
+    }
+
+    env::var("WATCHDOG_USEC")
+      .ok()
+      .and_then(|usec| usec.parse::<u64>().ok())
+      .filter(|usec| *usec > 0)
+      .map(Duration::from_micros)
+  }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+  use std::{net::TcpListener, time::Duration};
+
+  pub fn listeners() -> Vec<TcpListener> {
+    vec![]
+  }
+
+  pub fn notify(_state: &str) {}
+
+  pub fn watchdog_interval() -> Option<Duration> {
+    None
+  }
+}
@@ -0,0 +1,230 @@
+use std::{
+  collections::HashMap,
+  error::Error,
+  net::SocketAddr,
+  path::PathBuf,
+  sync::{Arc, Mutex},
+};
+
+#[cfg(feature = "tls")]
+use std::{
+  fs::File,
+  io::{BufReader, ErrorKind, Read, Write},
+  net::TcpStream,
+  time::Duration,
+};
+
+#[cfg(feature = "tls")]
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+#[cfg(feature = "tls")]
+use crate::{
+  log,
+  server::{runtime, utils::bind_listener},
+};
+
+use super::shutdown::Shutdown;
+
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+  // PEM certificate chain, leaf first
+  pub cert_path: PathBuf,
+  // PEM private key (PKCS#1, PKCS#8 or SEC1)
+  pub key_path: PathBuf,
+}
+
+/**
+ * Where the connections `serve()` makes to the websocket server really come from, since the server only sees the proxy
+ */
+#[derive(Clone, Debug, Default)]
+pub struct ProxiedPeers {
+  // The proxy's end of the connection to the websocket server -> the address the TLS connection was accepted from
+  peers: Arc<Mutex<HashMap<SocketAddr, SocketAddr>>>,
+}
+
+impl ProxiedPeers {
+  /**
+   * The address of the client behind a connection from `address`, which is `address` itself if it wasn't proxied
+   */
+  pub fn resolve(&self, address: SocketAddr) -> SocketAddr {
+    self
+      .peers
+      .lock()
+      .unwrap()
+      .get(&address)
+      .copied()
+      .unwrap_or(address)
+  }
+}
+
+// How long either side of a proxied connection is waited on before checking the other
+#[cfg(feature = "tls")]
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+// How long a client has to finish the TLS handshake
+#[cfg(feature = "tls")]
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/**
+ * Accept TLS connections on `address`, and forward the decrypted stream to the plain websocket server at `backend`, until stopped
+ */
+#[cfg(feature = "tls")]
+pub fn serve(
+  address: SocketAddr,
+  config: &TlsConfig,
+  backend: SocketAddr,
+  peers: ProxiedPeers,
+  shutdown: Shutdown,
+) -> Result<(), Box<dyn Error>> {
+  let server_config = Arc::new(server_config(config)?);
+  let listener = bind_listener(address)?;
+  listener.set_nonblocking(true)?;
+
+  log!("[TLS] Listening on {}", address);
+
+  runtime::spawn(move || {
+    while !shutdown.is_stopped() {
+      let (stream, peer_address) = match listener.accept() {
+        Ok(accepted) => accepted,
+        Err(err) if is_timeout(&err) => {
+          std::thread::sleep(POLL_INTERVAL);
+          continue;
+        }
+        Err(err) => {
+          log!(warn, "[TLS] Error accepting connection: {}", err);
+          std::thread::sleep(POLL_INTERVAL);
+          continue;
+        }
+      };
+
+      let server_config = server_config.clone();
+      let peers = peers.clone();
+      let shutdown = shutdown.clone();
+
+      runtime::spawn(move || {
+        let proxied = proxy(
+          stream,
+          peer_address,
+          server_config,
+          backend,
+          &peers,
+          &shutdown,
+        );
+
+        if let Err(err) = proxied {
+          log!(debug, "[TLS] Connection closed: {}", err);
+        }
+      });
+    }
+  });
+
+  Ok(())
+}
+
+#[cfg(not(feature = "tls"))]
+pub fn serve(
+  _address: SocketAddr,
+  _config: &TlsConfig,
+  _backend: SocketAddr,
+  _peers: ProxiedPeers,
+  _shutdown: Shutdown,
+) -> Result<(), Box<dyn Error>> {
+  Err("rsrpc was built without the tls feature".into())
+}
+
+#[cfg(feature = "tls")]
+fn server_config(config: &TlsConfig) -> Result<ServerConfig, Box<dyn Error>> {
+  let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&config.cert_path)?))
+    .collect::<Result<Vec<_>, _>>()?;
+  let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(&config.key_path)?))?
+    .ok_or("No private key found")?;
+
+  let server_config =
+    ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+      .with_safe_default_protocol_versions()?
+      .with_no_client_auth()
+      .with_single_cert(certs, key)?;
+
+  Ok(server_config)
+}
+
+#[cfg(feature = "tls")]
+fn proxy(
+  stream: TcpStream,
+  peer_address: SocketAddr,
+  server_config: Arc<ServerConfig>,
+  backend: SocketAddr,
+  peers: &ProxiedPeers,
+  shutdown: &Shutdown,
+) -> Result<(), Box<dyn Error>> {
+  // Accepted sockets inherit the listener's non-blocking mode on some platforms
+  stream.set_nonblocking(false)?;
+  // A client that stops halfway through the handshake would hold on to the thread otherwise
+  stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+  let mut tls = StreamOwned::new(ServerConnection::new(server_config)?, stream);
+
+  // Don't bother the websocket server with clients that can't finish a handshake
+  while tls.conn.is_handshaking() {
+    tls.conn.complete_io(&mut tls.sock)?;
+  }
+
+  let mut plain = TcpStream::connect(backend)?;
+  let proxied_from = plain.local_addr()?;
+
+  // Before anything is forwarded, so it is known by the time the websocket server sees the connection
+  peers
+    .peers
+    .lock()
+    .unwrap()
+    .insert(proxied_from, peer_address);
+
+  let result = forward(&mut tls, &mut plain, shutdown);
+
+  peers.peers.lock().unwrap().remove(&proxied_from);
+
+  result
+}
+
+#[cfg(feature = "tls")]
+fn forward(
+  tls: &mut StreamOwned<ServerConnection, TcpStream>,
+  plain: &mut TcpStream,
+  shutdown: &Shutdown,
+) -> Result<(), Box<dyn Error>> {
+  tls.sock.set_read_timeout(Some(POLL_INTERVAL))?;
+  plain.set_read_timeout(Some(POLL_INTERVAL))?;
+
+  let mut buffer = [0; 16 * 1024];
+
+  while !shutdown.is_stopped() {
+    match tls.read(&mut buffer) {
+      Ok(0) => break,
+      Ok(len) => plain.write_all(&buffer[..len])?,
+      Err(err) if is_timeout(&err) => {}
+      Err(err) => return Err(err.into()),
+    }
+
+    match plain.read(&mut buffer) {
+      Ok(0) => break,
+      Ok(len) => {
+        tls.write_all(&buffer[..len])?;
+        tls.flush()?;
+      }
+      Err(err) if is_timeout(&err) => {}
+      Err(err) => return Err(err.into()),
+    }
+  }
+
+  tls.conn.send_close_notify();
+  tls.flush().ok();
+
+  Ok(())
+}
+
+/**
+ * Read timeouts are WouldBlock on Unix and TimedOut on Windows
+ */
+#[cfg(feature = "tls")]
+fn is_timeout(err: &std::io::Error) -> bool {
+  matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
@@ -1,22 +1,114 @@
-pub static CONNECTION_REPONSE: &str = r#"
-{
-  "cmd": "DISPATCH",
-  "evt": "READY",
-  "data": {
-    "v": 1,
-    "user": {
-      "id": "1045800378228281345",
-      "username": "arRPC",
-      "discriminator": "0000",
-      "avatar": "cfefa4d9839fb4bdf030f91c2a13e95c",
-      "flags": 0,
-      "premium_type": 0
-    },
-    "config": {
-      "api_endpoint": "//discord.com/api",
-      "cdn_host": "cdn.discordapp.com",
-      "environment": "production"
+#![cfg_attr(
+  not(all(feature = "client-connector", feature = "rpc-websocket")),
+  allow(dead_code)
+)]
+
+use std::net::{SocketAddr, TcpListener};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+#[cfg(any(feature = "client-connector", feature = "rpc-websocket"))]
+use super::ws::{self, EventHub};
+use crate::cmd::UserConfig;
+
+/**
+ * The READY event sent to clients once they connect, presenting `user` as the logged in user
+ */
+pub fn connection_response(user: &UserConfig) -> String {
+  serde_json::json!({
+    "cmd": "DISPATCH",
+    "evt": "READY",
+    "data": {
+      "v": 1,
+      "user": user,
+      "config": {
+        "api_endpoint": "//discord.com/api",
+        "cdn_host": "cdn.discordapp.com",
+        "environment": "production"
+      }
     }
+  })
+  .to_string()
+}
+
+// Origins that are always allowed to connect to the websocket servers
+static DEFAULT_ORIGINS: [&str; 3] = [
+  "https://discord.com",
+  "https://canary.discord.com",
+  "https://ptb.discord.com",
+];
+
+/**
+ * Check the Origin header of a websocket connection. Anything without one isn't a browser, so it isn't subject to this.
+ */
+pub fn origin_allowed(origin: Option<&str>, extra_origins: &[String]) -> bool {
+  let Some(origin) = origin else {
+    return true;
+  };
+  let origin = origin.trim_end_matches('/');
+
+  if DEFAULT_ORIGINS.contains(&origin) || extra_origins.iter().any(|extra| extra == origin) {
+    return true;
+  }
+
+  // Local pages, on any port
+  ["http://localhost", "http://127.0.0.1", "http://[::1]"]
+    .iter()
+    .any(|local| {
+      origin == *local
+        || origin
+          .strip_prefix(local)
+          .and_then(|rest| rest.strip_prefix(':'))
+          .map(|port| !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()))
+          .unwrap_or(false)
+    })
+}
+
+/**
+ * Compare a token a client sent against the configured one, in time that doesn't depend on where they differ
+ */
+pub fn token_matches(given: &str, expected: &str) -> bool {
+  given.len() == expected.len()
+    && given
+      .bytes()
+      .zip(expected.bytes())
+      .fold(0, |diff, (a, b)| diff | (a ^ b))
+      == 0
+}
+
+/**
+ * Start a websocket server listening on `address`
+ */
+#[cfg(any(feature = "client-connector", feature = "rpc-websocket"))]
+pub fn launch_websocket_server(
+  address: SocketAddr,
+) -> Result<EventHub, Box<dyn std::error::Error>> {
+  let listener = bind_listener(address)?;
+
+  ws::launch_from_listener(listener)
+    .map_err(|_| format!("Failed to launch websocket server on {}", address).into())
+}
+
+/**
+ * Bind a TCP listener. The unspecified IPv6 address (`::`) accepts IPv4 connections too, which is only the default on some platforms.
+ */
+pub fn bind_listener(address: SocketAddr) -> std::io::Result<TcpListener> {
+  let socket = Socket::new(
+    Domain::for_address(address),
+    Type::STREAM,
+    Some(Protocol::TCP),
+  )?;
+
+  if address.is_ipv6() && address.ip().is_unspecified() {
+    socket.set_only_v6(false)?;
   }
+
+  // Let a restarted server take the port back while old connections are in TIME_WAIT
+  #[cfg(not(target_os = "windows"))]
+  socket.set_reuse_address(true)?;
+
+  socket.bind(&address.into())?;
+  socket.listen(128)?;
+
+  Ok(socket.into())
 }
-"#;
@@ -1,208 +1,418 @@
 use std::{
   collections::HashMap,
+  net::SocketAddr,
   sync::{mpsc, Arc, Mutex},
 };
 
-use simple_websockets::{Event, EventHub, Message, Responder};
-
 use crate::{
-  cmd::{ActivityCmd, ActivityCmdArgs},
+  cmd::{
+    ActivityCmd, ActivityCmdArgs, RPC_CLOSE_INVALID_CLIENT_ID, RPC_CLOSE_INVALID_ENCODING,
+    RPC_CLOSE_INVALID_ORIGIN, RPC_CLOSE_INVALID_VERSION, RPC_ERROR_INVALID_PAYLOAD,
+  },
   log,
-  server::utils::CONNECTION_REPONSE,
+  server::utils::{connection_response, launch_websocket_server, origin_allowed},
   url_params::get_url_params,
 };
 
-type ActivityResponder = (Option<ActivityCmd>, Responder);
+use super::{
+  commands::{activity_response, unknown_command, CommandHandler},
+  connector_types::WebsocketOptions,
+  etf,
+  rpc_clients::{next_socket_id, RpcClient},
+  runtime,
+  shutdown::{Shutdown, POLL_INTERVAL},
+  systemd,
+  ws::{self, Event, EventHub, Message, Responder},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+  Json,
+  Etf,
+}
+
+impl Encoding {
+  pub fn from_param(encoding: &str) -> Option<Self> {
+    match encoding {
+      "json" => Some(Encoding::Json),
+      "etf" => Some(Encoding::Etf),
+      _ => None,
+    }
+  }
+}
+
+/**
+ * A websocket client, which gets frames in the encoding it asked for
+ */
+#[derive(Clone)]
+pub struct WsResponder {
+  pub responder: Responder,
+  pub encoding: Encoding,
+}
+
+impl WsResponder {
+  /**
+   * Decode a message from the client, in whichever encoding it uses
+   */
+  fn decode(&self, message: Message) -> Result<ActivityCmd, String> {
+    let value = match (self.encoding, message) {
+      (Encoding::Json, Message::Text(text)) => {
+        serde_json::from_str(&text).map_err(|e| e.to_string())?
+      }
+      (Encoding::Etf, Message::Binary(data)) => etf::decode(&data)?,
+      _ => return Err("Message does not match the connection's encoding".to_string()),
+    };
+
+    serde_json::from_value(value).map_err(|e| e.to_string())
+  }
+}
+
+impl RpcClient for WsResponder {
+  fn send_frame(&self, data: String) -> bool {
+    match self.encoding {
+      Encoding::Json => self.responder.send(Message::Text(data)),
+      Encoding::Etf => match serde_json::from_str(&data) {
+        Ok(value) => self.responder.send(Message::Binary(etf::encode(&value))),
+        Err(_) => false,
+      },
+    }
+  }
+
+  fn close(&self) {
+    self.responder.close();
+  }
+}
+
+pub struct WsClient {
+  pub socket_id: String,
+  // The client_id the connection was opened with
+  pub application_id: String,
+  pub last_activity: Option<ActivityCmd>,
+  pub responder: WsResponder,
+}
 
 #[derive(Clone)]
 pub struct WebsocketConnector {
-  server: Arc<Mutex<EventHub>>,
-  pub clients: Arc<Mutex<HashMap<u64, ActivityResponder>>>,
+  // (port, server)
+  servers: Vec<(u16, Arc<Mutex<EventHub>>)>,
+  allowed_origins: Vec<String>,
+  shutdown: Shutdown,
 
   event_sender: mpsc::Sender<ActivityCmd>,
+  commands: CommandHandler,
 }
 
 impl WebsocketConnector {
-  pub fn new(event_sender: mpsc::Sender<ActivityCmd>) -> Self {
-    // Try starting websocket server on ports 6463 - 6472
-    for port in 6463..6472 {
-      match simple_websockets::launch(port) {
+  /**
+   * Start websocket servers on the first `port_count` free ports of the 10 from `base_port`, 6463 - 6472 by default
+   */
+  pub fn new(
+    event_sender: mpsc::Sender<ActivityCmd>,
+    commands: CommandHandler,
+    options: WebsocketOptions,
+  ) -> crate::Result<Self> {
+    let port_count = options.port_count;
+    let mut servers = vec![];
+
+    if options.socket_activation {
+      for listener in systemd::listeners() {
+        let port = listener.local_addr()?.port();
+
+        match ws::launch_from_listener(listener) {
+          Ok(server) => {
+            log!("[Websocket] Server started on port {} from systemd", port);
+            servers.push((port, Arc::new(Mutex::new(server))));
+          }
+          Err(_) => {
+            log!(
+              warn,
+              "[Websocket] Failed to start server on port {} from systemd",
+              port
+            );
+          }
+        }
+      }
+
+      if !servers.is_empty() {
+        return Ok(Self {
+          servers,
+          allowed_origins: options.allowed_origins,
+          shutdown: Shutdown::default(),
+          event_sender,
+          commands,
+        });
+      }
+    }
+
+    let ports = options.base_port..=options.base_port.saturating_add(9);
+
+    for port in ports {
+      if servers.len() >= port_count.max(1) as usize {
+        break;
+      }
+
+      match launch_websocket_server(SocketAddr::new(options.bind_address, port)) {
         Ok(server) => {
           log!("[Websocket] Server started on port {}", port);
-          return Self {
-            server: Arc::new(Mutex::new(server)),
-            clients: Arc::new(Mutex::new(HashMap::new())),
-            event_sender,
-          };
+          servers.push((port, Arc::new(Mutex::new(server))));
         }
         Err(_) => {
-          log!("[Websocket] Failed to start server on port {}", port);
+          log!(warn, "[Websocket] Failed to start server on port {}", port);
         }
       }
     }
 
-    log!("[Websocket] Failed to start server on any port");
-    std::process::exit(1);
+    if servers.is_empty() {
+      return Err(crate::Error::Listen(
+        "Failed to start the RPC websocket server on any port".to_string(),
+      ));
+    }
+
+    Ok(Self {
+      servers,
+      allowed_origins: options.allowed_origins,
+      shutdown: Shutdown::default(),
+      event_sender,
+      commands,
+    })
+  }
+
+  pub fn ports(&self) -> Vec<u16> {
+    self.servers.iter().map(|(port, _)| *port).collect()
   }
 
   pub fn start(&self, set_activity: bool, secondary_events: bool) {
-    let server = self.server.clone();
-    let clients = self.clients.clone();
-    let event_sender = self.event_sender.clone();
+    for (_, server) in self.servers.clone() {
+      self.serve(server, set_activity, secondary_events);
+    }
+  }
 
-    std::thread::spawn(move || {
-      let server = server.lock().unwrap();
-      let mut clients = clients.lock().unwrap();
+  /**
+   * Disconnect every client, stop handling events and free the ports
+   */
+  pub fn stop(&self) {
+    self.shutdown.stop();
 
-      loop {
-        log!("[Websocket] Polling for events...");
+    for (_, server) in &self.servers {
+      server.lock().unwrap().close();
+    }
+  }
 
-        match server.poll_event() {
+  fn serve(&self, server: Arc<Mutex<EventHub>>, set_activity: bool, secondary_events: bool) {
+    let event_sender = self.event_sender.clone();
+    let commands = self.commands.clone();
+    let rpc_clients = commands.rpc_clients.clone();
+    let allowed_origins = self.allowed_origins.clone();
+    let shutdown = self.shutdown.clone();
+
+    runtime::spawn(move || {
+      // Client ids are only unique per server
+      let mut clients: HashMap<u64, WsClient> = HashMap::new();
+
+      while !shutdown.is_stopped() {
+        let Some(event) = server.lock().unwrap().next_event() else {
+          std::thread::sleep(POLL_INTERVAL);
+          continue;
+        };
+
+        match event {
           Event::Connect(client_id, responder) => {
             let connection = responder.connection_details();
             let url_params = get_url_params(connection.uri.clone());
-            let version = url_params.get("v").unwrap_or(&"0".to_string()).clone();
-            let encoding = url_params
-              .get("encoding")
-              .unwrap_or(&"json".to_string())
-              .clone();
 
-            log!("[Websocket] Client {} connected", client_id);
+            log!(debug, "[Websocket] Client {} connected", client_id);
 
-            if version != "1" || encoding != "json" {
-              log!("[Websocket] Invalid connection from client {}", client_id);
-              continue;
-            }
+            let origin = connection
+              .headers
+              .get("origin")
+              .map(|origin| origin.to_str().unwrap_or_default());
+
+            let validated = if origin_allowed(origin, &allowed_origins) {
+              validate_params(&url_params)
+            } else {
+              Err((RPC_CLOSE_INVALID_ORIGIN, "Invalid Origin"))
+            };
+
+            let (encoding, application_id) = match validated {
+              Ok(validated) => validated,
+              Err((code, reason)) => {
+                log!(
+                  warn,
+                  "[Websocket] Rejecting client {} (origin {:?}): {}",
+                  client_id,
+                  origin,
+                  reason
+                );
+                reject(&responder, code, reason);
+                continue;
+              }
+            };
 
-            responder.send(Message::Text(CONNECTION_REPONSE.to_string()));
+            let responder = WsResponder {
+              responder,
+              encoding,
+            };
+            responder.send_frame(connection_response(&commands.config.user));
 
-            clients.insert(client_id, (None, responder));
+            let socket_id = next_socket_id();
+            rpc_clients.register(socket_id.clone(), Box::new(responder.clone()));
+
+            clients.insert(
+              client_id,
+              WsClient {
+                socket_id,
+                application_id,
+                last_activity: None,
+                responder,
+              },
+            );
           }
           Event::Disconnect(client_id) => {
-            log!("[Websocket] Client {} disconnected", client_id);
-            let responder = clients.remove(&client_id).unwrap();
+            log!(debug, "[Websocket] Client {} disconnected", client_id);
+
+            // Invalid connections were never stored
+            let Some(client) = clients.remove(&client_id) else {
+              continue;
+            };
 
-            handle_disconnect(client_id, &event_sender, &responder);
+            rpc_clients.unregister(&client.socket_id);
+            handle_disconnect(client_id, &event_sender, &client);
           }
           Event::Message(client_id, message) => {
             log!(
+              debug,
               "[Websocket] Received message from client {}: {:?}",
               client_id,
               message
             );
 
-            let responder = clients.get_mut(&client_id).unwrap();
-            let message = match message {
-              Message::Text(text) => text,
-              _ => "".to_string(),
+            let Some(client) = clients.get_mut(&client_id) else {
+              continue;
             };
 
             // If not ActivityCmd, ignore
-            let event: ActivityCmd = match serde_json::from_str(&message) {
+            let mut event = match client.responder.decode(message) {
               Ok(event) => event,
               Err(e) => {
-                log!("[Websocket] Invalid message from client {}", client_id);
-                log!("[Websocket] Error: {}", e);
+                log!(
+                  warn,
+                  "[Websocket] Invalid message from client {}",
+                  client_id
+                );
+                log!(warn, "[Websocket] Error: {}", e);
                 continue;
               }
             };
 
-            // If origin isn't a Discord URL, ignore
-            let origin = responder.1.connection_details().headers.get("origin");
+            // Always the connection's client_id, like IPC, so a message can't claim another application (and its rate limit)
+            event.socket_id = Some(client.socket_id.clone());
+            event.application_id = Some(client.application_id.clone());
 
-            if let Some(origin) = origin {
-              let value = origin.to_str().unwrap_or_default();
-              let valid = [
-                "https://discord.com",
-                "https://canary.discord.com",
-                "https://ptb.discord.com",
-              ];
+            // Anything besides activities is a secondary event
+            if !secondary_events && event.cmd != "SET_ACTIVITY" {
+              continue;
+            }
 
-              if !valid.contains(&value) {
-                log!("[Websocket] Invalid origin from client {}", client_id);
-                continue;
-              }
+            if let Some(response) = commands.handle(&event_sender, &event) {
+              client.responder.send_cmd(&response);
+              continue;
             }
 
             match event.cmd.as_str() {
-              "INVITE_BROWSER" => {
-                if !secondary_events {
-                  continue;
-                }
-
-                handle_invite(&event, &event_sender, &responder.1)
-              }
               "SET_ACTIVITY" => {
                 if !set_activity {
                   continue;
                 }
 
-                handle_set_activity(&event, &event_sender, responder)
-              }
-              "DEEP_LINK" => {
-                log!("[Websocket] Deep link unimplemented. PRs are open!");
+                handle_set_activity(&event, &event_sender, client)
               }
               _ => {
-                log!("[Websocket] Unknown command: {}", event.cmd);
+                log!(warn, "[Websocket] Unknown command: {}", event.cmd);
+                client.responder.send_cmd(&unknown_command(&event));
               }
             }
           }
         }
       }
+
+      for (_, client) in clients.drain() {
+        rpc_clients.unregister(&client.socket_id);
+        client.responder.close();
+      }
     });
   }
 }
 
-fn event_args_as_hashmap(args: Option<ActivityCmdArgs>) -> HashMap<String, String> {
-  // Serde serialize the args
-  let args = match args {
-    Some(args) => serde_json::to_string(&args).unwrap_or("".to_string()),
-    None => "{}".to_string(),
+/**
+ * Check the query string of a new connection the same way Discord does, returning the encoding and client id, or the close code and reason
+ */
+fn validate_params(
+  params: &HashMap<String, String>,
+) -> Result<(Encoding, String), (u32, &'static str)> {
+  if params.get("v").map(|v| v.as_str()) != Some("1") {
+    return Err((RPC_CLOSE_INVALID_VERSION, "Invalid Version"));
+  }
+
+  let Some(encoding) = Encoding::from_param(
+    params
+      .get("encoding")
+      .map(|encoding| encoding.as_str())
+      .unwrap_or("json"),
+  ) else {
+    return Err((RPC_CLOSE_INVALID_ENCODING, "Invalid Encoding"));
   };
 
-  // Re-deserialize the args as a hashmap
-  serde_json::from_str::<HashMap<String, String>>(&args).unwrap_or_default()
-}
+  let client_id = params.get("client_id").cloned().unwrap_or_default();
 
-fn handle_invite(
-  event: &ActivityCmd,
-  event_sender: &mpsc::Sender<ActivityCmd>,
-  responder: &Responder,
-) {
-  // Let's just assume this went well I don't care
-  let response = ActivityCmd {
-    application_id: event.application_id.clone(),
-    cmd: event.cmd.clone(),
-    args: None,
-    data: Some(event_args_as_hashmap(event.args.clone())),
-    evt: None,
-    nonce: event.nonce.clone(),
-  };
+  // Application IDs are snowflakes
+  if client_id.is_empty() || !client_id.chars().all(|c| c.is_ascii_digit()) {
+    return Err((RPC_CLOSE_INVALID_CLIENT_ID, "Invalid Client ID"));
+  }
 
-  // Send the event away!
-  event_sender.send(event.clone()).unwrap();
+  Ok((encoding, client_id))
+}
 
-  // Respond
-  responder.send(Message::Text(serde_json::to_string(&response).unwrap()));
+/**
+ * Tell the client why it is being disconnected, then close the connection with the same code. The encoding may be what is wrong, so the
+ * error is always JSON.
+ */
+fn reject(responder: &Responder, code: u32, reason: &str) {
+  let error = ActivityCmd::error("DISPATCH", None, code, reason);
+
+  responder.send(Message::Text(
+    serde_json::to_string(&error).unwrap_or_default(),
+  ));
+  responder.close_with(code as u16, reason);
 }
 
 fn handle_set_activity(
   event: &ActivityCmd,
   event_sender: &mpsc::Sender<ActivityCmd>,
-  responder: &mut ActivityResponder,
+  client: &mut WsClient,
 ) {
+  if let Err(err) = event.validate() {
+    log!(warn, "[Websocket] Invalid activity: {}", err);
+
+    client.responder.send_cmd(&ActivityCmd::error(
+      &event.cmd,
+      event.nonce.clone(),
+      RPC_ERROR_INVALID_PAYLOAD,
+      err,
+    ));
+    return;
+  }
+
   // Set the last activity for the client
-  responder.0 = Some(event.clone());
+  client.last_activity = Some(event.clone());
+
+  // The client connector only goes away when the server stops
+  event_sender.send(event.clone()).ok();
 
-  event_sender.send(event.clone()).unwrap();
+  client.responder.send_cmd(&activity_response(event));
 }
 
-fn handle_disconnect(
-  _client_id: u64,
-  event_sender: &mpsc::Sender<ActivityCmd>,
-  responder: &ActivityResponder,
-) {
-  if let Some(ref activity_cmd) = responder.0 {
+fn handle_disconnect(_client_id: u64, event_sender: &mpsc::Sender<ActivityCmd>, client: &WsClient) {
+  if let Some(ref activity_cmd) = client.last_activity {
     // Send empty activity
     let activity_cmd = ActivityCmd {
       application_id: activity_cmd.application_id.clone(),
@@ -210,13 +420,20 @@ fn handle_disconnect(
       data: None,
       evt: None,
       args: Some(ActivityCmdArgs {
-        pid: Some(activity_cmd.args.as_ref().unwrap().pid.unwrap_or_default()),
+        pid: Some(
+          activity_cmd
+            .args
+            .as_ref()
+            .and_then(|args| args.pid)
+            .unwrap_or_default(),
+        ),
         activity: None,
-        code: None,
+        ..Default::default()
       }),
       nonce: activity_cmd.nonce.clone(),
+      socket_id: Some(client.socket_id.clone()),
     };
 
-    event_sender.send(activity_cmd).unwrap();
+    event_sender.send(activity_cmd).ok();
   }
 }
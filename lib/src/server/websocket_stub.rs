@@ -0,0 +1,38 @@
+#![allow(dead_code)]
+
+use std::sync::mpsc;
+
+use super::{commands::CommandHandler, connector_types::WebsocketOptions};
+use crate::{cmd::ActivityCmd, log};
+
+/**
+ * Stands in for the RPC websocket server when built without the `rpc-websocket` feature. Nothing listens, so only IPC clients and the host can set activities.
+ */
+#[derive(Clone)]
+pub struct WebsocketConnector {
+  event_sender: mpsc::Sender<ActivityCmd>,
+  commands: CommandHandler,
+}
+
+impl WebsocketConnector {
+  pub fn new(
+    event_sender: mpsc::Sender<ActivityCmd>,
+    commands: CommandHandler,
+    _options: WebsocketOptions,
+  ) -> crate::Result<Self> {
+    Ok(Self {
+      event_sender,
+      commands,
+    })
+  }
+
+  pub fn ports(&self) -> Vec<u16> {
+    vec![]
+  }
+
+  pub fn start(&self, _set_activity: bool, _secondary_events: bool) {
+    log!("[Websocket] Built without the rpc-websocket feature, not listening");
+  }
+
+  pub fn stop(&self) {}
+}
@@ -0,0 +1,280 @@
+/**
+ * A top-level window, and the process it belongs to
+ */
+#[derive(Clone, Debug)]
+pub struct Window {
+  pub pid: u64,
+  pub title: String,
+}
+
+/**
+ * The visible top-level windows with a title. Empty when built without the `window-titles` feature, or when the platform can't tell (Wayland only exposes XWayland windows, through X11).
+ */
+pub fn list() -> Vec<Window> {
+  platform::list()
+    .into_iter()
+    .filter(|window| !window.title.trim().is_empty())
+    .collect()
+}
+
+/**
+ * The process that owns the focused window. None without the `window-titles` feature, or when the platform can't tell.
+ */
+pub fn foreground_pid() -> Option<u64> {
+  platform::foreground_pid()
+}
+
+/**
+ * The parts of a window title that could be the name of a game. Browsers and some games put more after the name, eg. "Wordle - Google Chrome".
+ */
+pub fn candidates(title: &str) -> Vec<String> {
+  let title = title.trim();
+  let mut candidates = vec![title.to_lowercase()];
+
+  for separator in [" - ", " — ", " – ", " | "] {
+    if let Some((name, _)) = title.split_once(separator) {
+      candidates.push(name.trim().to_lowercase());
+    }
+  }
+
+  candidates
+}
+
+#[cfg(not(feature = "window-titles"))]
+mod platform {
+  pub fn list() -> Vec<super::Window> {
+    vec![]
+  }
+
+  pub fn foreground_pid() -> Option<u64> {
+    None
+  }
+}
+
+#[cfg(all(feature = "window-titles", target_os = "windows"))]
+mod platform {
+  use super::Window;
+  use winapi::{
+    shared::{
+      minwindef::{BOOL, DWORD, LPARAM, TRUE},
+      windef::HWND,
+    },
+    um::winuser::{
+      EnumWindows, GetForegroundWindow, GetWindowTextLengthW, GetWindowTextW,
+      GetWindowThreadProcessId, IsWindowVisible,
+    },
+  };
+
+  unsafe extern "system" fn collect(hwnd: HWND, windows: LPARAM) -> BOOL {
+    let windows = &mut *(windows as *mut Vec<Window>);
+
+    if IsWindowVisible(hwnd) == 0 {
+      return TRUE;
+    }
+
+    let len = GetWindowTextLengthW(hwnd);
+
+    if len <= 0 {
+      return TRUE;
+    }
+
+    let mut title = vec![0u16; len as usize + 1];
+    let copied = GetWindowTextW(hwnd, title.as_mut_ptr(), title.len() as i32);
+    let mut pid: DWORD = 0;
+    GetWindowThreadProcessId(hwnd, &mut pid);
+
+    windows.push(Window {
+      pid: pid as u64,
+      title: String::from_utf16_lossy(&title[..copied.max(0) as usize]),
+    });
+
+    TRUE
+  }
+
+  pub fn list() -> Vec<Window> {
+    let mut windows: Vec<Window> = vec![];
+
+    unsafe {
+      EnumWindows(Some(collect), &mut windows as *mut Vec<Window> as LPARAM);
+    }
+
+    windows
+  }
+
+  pub fn foreground_pid() -> Option<u64> {
+    let hwnd = unsafe { GetForegroundWindow() };
+
+    if hwnd.is_null() {
+      return None;
+    }
+
+    let mut pid: DWORD = 0;
+    unsafe { GetWindowThreadProcessId(hwnd, &mut pid) };
+
+    Some(pid as u64).filter(|pid| *pid != 0)
+  }
+}
+
+#[cfg(all(feature = "window-titles", target_os = "linux"))]
+mod platform {
+  use super::Window;
+  use x11rb::{
+    connection::Connection,
+    protocol::xproto::{AtomEnum, ConnectionExt},
+  };
+
+  pub fn list() -> Vec<Window> {
+    list_x11().unwrap_or_default()
+  }
+
+  fn list_x11() -> Result<Vec<Window>, Box<dyn std::error::Error>> {
+    let (conn, screen) = x11rb::connect(None)?;
+    let root = conn.setup().roots[screen].root;
+    let atom = |name: &str| -> Result<u32, Box<dyn std::error::Error>> {
+      Ok(conn.intern_atom(false, name.as_bytes())?.reply()?.atom)
+    };
+
+    let client_list = atom("_NET_CLIENT_LIST")?;
+    let wm_name = atom("_NET_WM_NAME")?;
+    let wm_pid = atom("_NET_WM_PID")?;
+    let utf8_string = atom("UTF8_STRING")?;
+
+    let clients = conn
+      .get_property(false, root, client_list, AtomEnum::WINDOW, 0, u32::MAX)?
+      .reply()?;
+    let mut windows = vec![];
+
+    for window in clients.value32().into_iter().flatten() {
+      let title = conn
+        .get_property(false, window, wm_name, utf8_string, 0, 1024)?
+        .reply()?;
+      let pid = conn
+        .get_property(false, window, wm_pid, AtomEnum::CARDINAL, 0, 1)?
+        .reply()?;
+      let Some(pid) = pid.value32().and_then(|mut value| value.next()) else {
+        continue;
+      };
+
+      windows.push(Window {
+        pid: pid as u64,
+        title: String::from_utf8_lossy(&title.value).to_string(),
+      });
+    }
+
+    Ok(windows)
+  }
+
+  pub fn foreground_pid() -> Option<u64> {
+    foreground_pid_x11().ok().flatten()
+  }
+
+  fn foreground_pid_x11() -> Result<Option<u64>, Box<dyn std::error::Error>> {
+    let (conn, screen) = x11rb::connect(None)?;
+    let root = conn.setup().roots[screen].root;
+    let atom = |name: &str| -> Result<u32, Box<dyn std::error::Error>> {
+      Ok(conn.intern_atom(false, name.as_bytes())?.reply()?.atom)
+    };
+
+    let active_window = atom("_NET_ACTIVE_WINDOW")?;
+    let wm_pid = atom("_NET_WM_PID")?;
+
+    let active = conn
+      .get_property(false, root, active_window, AtomEnum::WINDOW, 0, 1)?
+      .reply()?;
+    let Some(window) = active.value32().and_then(|mut value| value.next()) else {
+      return Ok(None);
+    };
+
+    let pid = conn
+      .get_property(false, window, wm_pid, AtomEnum::CARDINAL, 0, 1)?
+      .reply()?;
+
+    Ok(
+      pid
+        .value32()
+        .and_then(|mut value| value.next())
+        .map(|pid| pid as u64),
+    )
+  }
+}
+
+#[cfg(all(feature = "window-titles", target_os = "macos"))]
+mod platform {
+  use super::Window;
+  use core_foundation::{
+    base::{CFType, TCFType},
+    dictionary::{CFDictionary, CFDictionaryRef},
+    number::CFNumber,
+    string::CFString,
+  };
+  use core_graphics::window::{
+    copy_window_info, kCGNullWindowID, kCGWindowLayer, kCGWindowListExcludeDesktopElements,
+    kCGWindowListOptionOnScreenOnly, kCGWindowName, kCGWindowOwnerPID,
+  };
+
+  // Window titles are only there with the Screen Recording permission, otherwise this finds nothing
+  pub fn list() -> Vec<Window> {
+    let Some(info) = copy_window_info(
+      kCGWindowListOptionOnScreenOnly | kCGWindowListExcludeDesktopElements,
+      kCGNullWindowID,
+    ) else {
+      return vec![];
+    };
+
+    let name_key = unsafe { CFString::wrap_under_get_rule(kCGWindowName) };
+    let pid_key = unsafe { CFString::wrap_under_get_rule(kCGWindowOwnerPID) };
+
+    info
+      .iter()
+      .filter_map(|window| {
+        let window: CFDictionary<CFString, CFType> =
+          unsafe { CFDictionary::wrap_under_get_rule(*window as CFDictionaryRef) };
+        let title = window.find(&name_key)?.downcast::<CFString>()?.to_string();
+        let pid = window.find(&pid_key)?.downcast::<CFNumber>()?.to_i64()?;
+
+        Some(Window {
+          pid: pid as u64,
+          title,
+        })
+      })
+      .collect()
+  }
+
+  // Windows are listed front to back, and the frontmost normal one (layer 0) belongs to the focused app. Unlike titles, this works without any permission.
+  pub fn foreground_pid() -> Option<u64> {
+    let info = copy_window_info(
+      kCGWindowListOptionOnScreenOnly | kCGWindowListExcludeDesktopElements,
+      kCGNullWindowID,
+    )?;
+
+    let layer_key = unsafe { CFString::wrap_under_get_rule(kCGWindowLayer) };
+    let pid_key = unsafe { CFString::wrap_under_get_rule(kCGWindowOwnerPID) };
+
+    info.iter().find_map(|window| {
+      let window: CFDictionary<CFString, CFType> =
+        unsafe { CFDictionary::wrap_under_get_rule(*window as CFDictionaryRef) };
+      let layer = window.find(&layer_key)?.downcast::<CFNumber>()?.to_i64()?;
+
+      if layer != 0 {
+        return None;
+      }
+
+      let pid = window.find(&pid_key)?.downcast::<CFNumber>()?.to_i64()?;
+      Some(pid as u64)
+    })
+  }
+}
+
+#[cfg(all(
+  feature = "window-titles",
+  not(any(target_os = "windows", target_os = "linux", target_os = "macos"))
+))]
+mod platform {
+  pub fn list() -> Vec<super::Window> {
+    vec![]
+  }
+
+  pub fn foreground_pid() -> Option<u64> {
+    None
+  }
+}
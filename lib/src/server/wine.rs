@@ -0,0 +1,43 @@
+// What Wine and Proton processes run as, the Windows executable is in their command line instead
+static LOADERS: [&str; 6] = [
+  "wine",
+  "wine64",
+  "wine-preloader",
+  "wine64-preloader",
+  "wineloader",
+  "pressure-vessel-wrap",
+];
+
+/**
+ * Whether `exe` is a Wine/Proton loader, rather than a game itself
+ */
+pub fn is_loader(exe: &str) -> bool {
+  let name = exe.replace('\\', "/");
+  let name = name.split('/').last().unwrap_or_default();
+
+  LOADERS.contains(&name)
+}
+
+/**
+ * Turn a Windows path the way Wine sees it into something the detectable list can be matched against. Z: is the root of the host filesystem, other drives keep their letter, eg. "Z:\home\user\game.exe" -> "/home/user/game.exe".
+ */
+pub fn normalize_path(path: &str) -> String {
+  let path = path.replace('\\', "/");
+  let mut chars = path.chars();
+
+  match (chars.next(), chars.next()) {
+    (Some(drive), Some(':')) if drive.eq_ignore_ascii_case(&'z') => path[2..].to_string(),
+    _ => path,
+  }
+}
+
+/**
+ * The Windows executable a Wine/Proton process with the command line `cmd` runs, and the arguments it was given
+ */
+pub fn windows_executable(cmd: &[String]) -> Option<(String, Vec<String>)> {
+  let position = cmd
+    .iter()
+    .position(|arg| arg.to_lowercase().ends_with(".exe"))?;
+
+  Some((normalize_path(&cmd[position]), cmd[position + 1..].to_vec()))
+}
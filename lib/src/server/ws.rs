@@ -0,0 +1,326 @@
+use std::{
+  error::Error,
+  io::{self, ErrorKind},
+  net::{SocketAddr, TcpListener, TcpStream},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{self, TryRecvError},
+    Arc,
+  },
+  time::{Duration, Instant},
+};
+
+use tungstenite::{
+  handshake::server::{ErrorResponse, Request, Response},
+  http::HeaderMap,
+  protocol::{frame::coding::CloseCode, CloseFrame},
+  WebSocket,
+};
+
+use super::{runtime, shutdown::POLL_INTERVAL};
+use crate::log;
+
+// How long a new connection has to finish the websocket handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// How long a client may keep a write waiting before it is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+// How long a client has to answer a close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+  Text(String),
+  Binary(Vec<u8>),
+}
+
+impl From<Message> for tungstenite::Message {
+  fn from(message: Message) -> Self {
+    match message {
+      Message::Text(text) => tungstenite::Message::Text(text),
+      Message::Binary(data) => tungstenite::Message::Binary(data),
+    }
+  }
+}
+
+pub enum Event {
+  Connect(u64, Responder),
+  Disconnect(u64),
+  Message(u64, Message),
+}
+
+#[derive(Debug)]
+pub struct ConnectionDetails {
+  // Path and query string the client connected with
+  pub uri: String,
+  pub headers: HeaderMap,
+  // Where the connection was accepted from
+  pub peer_address: SocketAddr,
+}
+
+enum Outgoing {
+  Message(Message),
+  // (code, reason)
+  Close(u16, String),
+}
+
+/**
+ * Sends to a single client. Clones share the same connection, which is closed once every clone is dropped.
+ */
+#[derive(Clone)]
+pub struct Responder {
+  sender: mpsc::Sender<Outgoing>,
+  details: Arc<ConnectionDetails>,
+}
+
+impl Responder {
+  /**
+   * Queue a message, returning false if the client is gone
+   */
+  pub fn send(&self, message: Message) -> bool {
+    self.sender.send(Outgoing::Message(message)).is_ok()
+  }
+
+  pub fn close(&self) {
+    self.close_with(CloseCode::Normal.into(), "");
+  }
+
+  /**
+   * Close the connection with a close frame carrying `code` and `reason`, after everything queued before it was sent
+   */
+  pub fn close_with(&self, code: u16, reason: &str) {
+    self
+      .sender
+      .send(Outgoing::Close(code, reason.to_string()))
+      .ok();
+  }
+
+  pub fn connection_details(&self) -> &ConnectionDetails {
+    &self.details
+  }
+}
+
+/**
+ * The events of every connection to a websocket server. Closing it, or dropping it, stops the server and frees its port.
+ */
+pub struct EventHub {
+  events: mpsc::Receiver<Event>,
+  closed: Arc<AtomicBool>,
+}
+
+impl EventHub {
+  /**
+   * The next event, if there is one. Doesn't block.
+   */
+  pub fn next_event(&self) -> Option<Event> {
+    self.events.try_recv().ok()
+  }
+
+  /**
+   * Stop accepting connections, closing the listener, and disconnect every client with 1001 (going away)
+   */
+  pub fn close(&self) {
+    self.closed.store(true, Ordering::SeqCst);
+  }
+}
+
+impl Drop for EventHub {
+  fn drop(&mut self) {
+    self.close();
+  }
+}
+
+/**
+ * Serve websocket connections accepted on `listener`, until the returned hub is closed
+ */
+pub fn launch_from_listener(listener: TcpListener) -> io::Result<EventHub> {
+  listener.set_nonblocking(true)?;
+
+  let (sender, events) = mpsc::channel();
+  let closed = Arc::new(AtomicBool::new(false));
+  let hub = EventHub {
+    events,
+    closed: closed.clone(),
+  };
+
+  runtime::spawn(move || {
+    let mut next_client_id = 0;
+
+    // The listener is dropped, and the port freed, once this returns
+    while !closed.load(Ordering::SeqCst) {
+      let (stream, peer_address) = match listener.accept() {
+        Ok(accepted) => accepted,
+        Err(err) if is_timeout(&err) => {
+          std::thread::sleep(POLL_INTERVAL);
+          continue;
+        }
+        Err(err) => {
+          log!(warn, "[Websocket] Error accepting connection: {}", err);
+          std::thread::sleep(POLL_INTERVAL);
+          continue;
+        }
+      };
+
+      let client_id = next_client_id;
+      next_client_id += 1;
+
+      let sender = sender.clone();
+      let closed = closed.clone();
+
+      runtime::spawn(move || {
+        handle_connection(client_id, stream, peer_address, &sender, &closed);
+      });
+    }
+  });
+
+  Ok(hub)
+}
+
+fn handle_connection(
+  client_id: u64,
+  stream: TcpStream,
+  peer_address: SocketAddr,
+  events: &mpsc::Sender<Event>,
+  closed: &AtomicBool,
+) {
+  let (mut socket, details) = match handshake(stream, peer_address) {
+    Ok(connection) => connection,
+    Err(err) => {
+      log!(
+        debug,
+        "[Websocket] Handshake with {} failed: {}",
+        peer_address,
+        err
+      );
+      return;
+    }
+  };
+
+  let (sender, outgoing) = mpsc::channel();
+  let responder = Responder {
+    sender,
+    details: Arc::new(details),
+  };
+
+  if events.send(Event::Connect(client_id, responder)).is_err() {
+    return;
+  }
+
+  match serve(client_id, &mut socket, &outgoing, events, closed) {
+    Ok(()) | Err(tungstenite::Error::ConnectionClosed) => {}
+    Err(err) => log!(
+      debug,
+      "[Websocket] Connection {} closed: {}",
+      client_id,
+      err
+    ),
+  }
+
+  events.send(Event::Disconnect(client_id)).ok();
+}
+
+fn handshake(
+  stream: TcpStream,
+  peer_address: SocketAddr,
+) -> Result<(WebSocket<TcpStream>, ConnectionDetails), Box<dyn Error>> {
+  // Accepted sockets inherit the listener's non-blocking mode on some platforms
+  stream.set_nonblocking(false)?;
+  // So a client that never finishes the handshake doesn't hold on to the thread
+  stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+  stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+  let mut request = None;
+  let socket = tungstenite::accept_hdr(
+    stream,
+    |req: &Request, response: Response| -> Result<Response, ErrorResponse> {
+      request = Some((req.uri().to_string(), req.headers().clone()));
+      Ok(response)
+    },
+  )
+  .map_err(|err| err.to_string())?;
+
+  let (uri, headers) = request.ok_or("Handshake finished without a request")?;
+
+  // From here on reads only wait long enough to check for queued messages in between
+  socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+
+  Ok((
+    socket,
+    ConnectionDetails {
+      uri,
+      headers,
+      peer_address,
+    },
+  ))
+}
+
+/**
+ * Pass messages both ways until either side closes the connection, or the hub is closed
+ */
+fn serve(
+  client_id: u64,
+  socket: &mut WebSocket<TcpStream>,
+  outgoing: &mpsc::Receiver<Outgoing>,
+  events: &mpsc::Sender<Event>,
+  closed: &AtomicBool,
+) -> tungstenite::Result<()> {
+  loop {
+    if closed.load(Ordering::SeqCst) {
+      return close(socket, CloseCode::Away, "");
+    }
+
+    loop {
+      match outgoing.try_recv() {
+        Ok(Outgoing::Message(message)) => socket.send(message.into())?,
+        Ok(Outgoing::Close(code, reason)) => return close(socket, code.into(), &reason),
+        Err(TryRecvError::Empty) => break,
+        // Every responder was dropped, nothing can send to this client anymore
+        Err(TryRecvError::Disconnected) => return close(socket, CloseCode::Normal, ""),
+      }
+    }
+
+    let message = match socket.read() {
+      Ok(tungstenite::Message::Text(text)) => Message::Text(text),
+      Ok(tungstenite::Message::Binary(data)) => Message::Binary(data),
+      // Pings are answered by tungstenite, and a close on the next read
+      Ok(_) => continue,
+      Err(tungstenite::Error::Io(err)) if is_timeout(&err) => continue,
+      Err(err) => return Err(err),
+    };
+
+    events.send(Event::Message(client_id, message)).ok();
+  }
+}
+
+/**
+ * Send a close frame, then wait a little for the client to answer it, so it gets the code before the connection goes away
+ */
+fn close(
+  socket: &mut WebSocket<TcpStream>,
+  code: CloseCode,
+  reason: &str,
+) -> tungstenite::Result<()> {
+  socket.close(Some(CloseFrame {
+    code,
+    reason: reason.to_string().into(),
+  }))?;
+
+  let deadline = Instant::now() + CLOSE_TIMEOUT;
+
+  while Instant::now() < deadline {
+    match socket.read() {
+      Ok(_) => {}
+      Err(tungstenite::Error::Io(err)) if is_timeout(&err) => {}
+      Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+      Err(err) => return Err(err),
+    }
+  }
+
+  Ok(())
+}
+
+/**
+ * Read timeouts are WouldBlock on Unix and TimedOut on Windows
+ */
+fn is_timeout(err: &io::Error) -> bool {
+  matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}